@@ -0,0 +1,323 @@
+//! Encoding and decoding of frames on the bit level.
+//!
+//! Bits are `true` when recessive and `false` when dominant.
+
+use super::{Error, Frame, Id};
+
+/// Role of a bit sent by a transmitter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Kind {
+	/// A bit in the arbitration field, where reading back a dominant level
+	/// instead of a recessive one means the arbitration was lost.
+	Arbitration,
+	/// The acknowledgement slot, which is sent recessive and overwritten by
+	/// receivers.
+	Ack,
+	Other,
+}
+
+/// Number of bits of a standard frame from the start of frame to the DLC.
+const STANDARD_HEADER: usize = 19;
+/// Number of bits of an extended frame from the start of frame to the DLC.
+const EXTENDED_HEADER: usize = 39;
+
+fn crc15(bits: &[bool]) -> u16 {
+	let mut crc = 0u16;
+	for bit in bits {
+		let next = *bit ^ (crc & 0x4000 != 0);
+		crc = (crc << 1) & 0x7fff;
+		if next {
+			crc ^= 0x4599;
+		}
+	}
+	crc
+}
+
+fn push_bits(bits: &mut Vec<(bool, Kind)>, value: u32, count: u32, kind: Kind) {
+	bits.extend((0..count).rev().map(|i| (value & (1 << i) != 0, kind)));
+}
+
+fn to_u32(bits: &[bool]) -> u32 {
+	bits.iter().fold(0, |value, bit| (value << 1) | *bit as u32)
+}
+
+/// Returns all bits of `frame` as sent on the bus, from the start of frame to
+/// the end of frame.
+pub(crate) fn encode(frame: &Frame) -> Vec<(bool, Kind)> {
+	use Kind::*;
+	let mut bits = vec![(false, Other)];
+	match frame.id {
+		Id::Standard(id) => {
+			push_bits(&mut bits, id as u32, 11, Arbitration);
+			bits.push((frame.remote, Arbitration));
+			// IDE and r0
+			bits.push((false, Arbitration));
+			bits.push((false, Other));
+		}
+		Id::Extended(id) => {
+			push_bits(&mut bits, id >> 18, 11, Arbitration);
+			// SRR and IDE
+			bits.push((true, Arbitration));
+			bits.push((true, Arbitration));
+			push_bits(&mut bits, id, 18, Arbitration);
+			bits.push((frame.remote, Arbitration));
+			// r1 and r0
+			bits.push((false, Other));
+			bits.push((false, Other));
+		}
+	}
+	push_bits(&mut bits, frame.dlc as u32, 4, Other);
+	for byte in &frame.data {
+		push_bits(&mut bits, *byte as u32, 8, Other);
+	}
+	let levels: Vec<bool> = bits.iter().map(|bit| bit.0).collect();
+	push_bits(&mut bits, crc15(&levels) as u32, 15, Other);
+	let mut stuffed = vec![];
+	let mut run = (true, 0);
+	for (level, kind) in bits {
+		stuffed.push((level, kind));
+		run = if level == run.0 {
+			(level, run.1 + 1)
+		} else {
+			(level, 1)
+		};
+		if run.1 == 5 {
+			stuffed.push((!level, kind));
+			run = (!level, 1);
+		}
+	}
+	// CRC delimiter, ACK slot, ACK delimiter and end of frame
+	stuffed.push((true, Other));
+	stuffed.push((true, Ack));
+	stuffed.extend(std::iter::repeat_n((true, Other), 8));
+	stuffed
+}
+
+/// Something decoded by a [`Decoder`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Decoded {
+	/// The CRC delimiter was received, the acknowledgement slot follows.
+	CrcChecked(bool),
+	/// The acknowledgement slot was received.
+	Ack(bool),
+	/// The end of frame was received.
+	Frame(Frame),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+	Stuffed,
+	CrcDelimiter,
+	AckSlot,
+	AckDelimiter,
+	EndOfFrame(u8),
+}
+
+/// Decodes a frame from the bits following a hard synchronization.
+#[derive(Clone, Debug)]
+pub(crate) struct Decoder {
+	bits: Vec<bool>,
+	field: Field,
+	run: (bool, u8),
+	crc_ok: bool,
+}
+
+impl Default for Decoder {
+	fn default() -> Self {
+		Decoder {
+			bits: vec![],
+			field: Field::Stuffed,
+			run: (true, 0),
+			crc_ok: false,
+		}
+	}
+}
+
+impl Decoder {
+	/// Returns the number of destuffed bits up to the end of the CRC, once
+	/// the DLC has been received.
+	fn length(&self) -> Option<usize> {
+		let bits = &self.bits;
+		let extended = *bits.get(13)?;
+		let (header, remote) = if extended {
+			(EXTENDED_HEADER, *bits.get(32)?)
+		} else {
+			(STANDARD_HEADER, bits[12])
+		};
+		if bits.len() < header {
+			return None;
+		}
+		let dlc = to_u32(&bits[header - 4..header]) as usize;
+		let data = if remote { 0 } else { dlc.min(8) };
+		Some(header + 8 * data + 15)
+	}
+
+	fn frame(&self) -> Frame {
+		let bits = &self.bits;
+		let (id, header, remote) = if bits[13] {
+			let id = (to_u32(&bits[1..12]) << 18) | to_u32(&bits[14..32]);
+			(Id::Extended(id), EXTENDED_HEADER, bits[32])
+		} else {
+			(
+				Id::Standard(to_u32(&bits[1..12]) as u16),
+				STANDARD_HEADER,
+				bits[12],
+			)
+		};
+		let dlc = to_u32(&bits[header - 4..header]) as u8;
+		let data = if remote { 0 } else { dlc.min(8) as usize };
+		Frame {
+			id,
+			remote,
+			dlc,
+			data: (0..data)
+				.map(|i| to_u32(&bits[header + 8 * i..header + 8 * i + 8]) as u8)
+				.collect(),
+		}
+	}
+
+	/// Processes the next bit sampled from the bus.
+	pub(crate) fn push(&mut self, bit: bool) -> Result<Option<Decoded>, Error> {
+		match self.field {
+			Field::Stuffed => {
+				let complete = Some(self.bits.len()) == self.length();
+				if self.run.1 == 5 {
+					if bit == self.run.0 {
+						return Err(Error::Stuff);
+					}
+					self.run = (bit, 1);
+				} else {
+					if complete {
+						self.field = Field::CrcDelimiter;
+						return self.push(bit);
+					}
+					self.run = if bit == self.run.0 {
+						(bit, self.run.1 + 1)
+					} else {
+						(bit, 1)
+					};
+					self.bits.push(bit);
+					if let Some(length) = self.length() {
+						if self.bits.len() == length {
+							let (data, crc) = self.bits.split_at(length - 15);
+							self.crc_ok = crc15(data) == to_u32(crc) as u16;
+						}
+					}
+				}
+				Ok(None)
+			}
+			Field::CrcDelimiter => {
+				if !bit {
+					return Err(Error::Form);
+				}
+				self.field = Field::AckSlot;
+				Ok(Some(Decoded::CrcChecked(self.crc_ok)))
+			}
+			Field::AckSlot => {
+				self.field = Field::AckDelimiter;
+				Ok(Some(Decoded::Ack(!bit)))
+			}
+			Field::AckDelimiter => {
+				if !bit {
+					return Err(Error::Form);
+				}
+				if !self.crc_ok {
+					return Err(Error::Crc);
+				}
+				self.field = Field::EndOfFrame(0);
+				Ok(None)
+			}
+			Field::EndOfFrame(n) => {
+				if !bit {
+					return Err(Error::Form);
+				}
+				if n == 6 {
+					return Ok(Some(Decoded::Frame(self.frame())));
+				}
+				self.field = Field::EndOfFrame(n + 1);
+				Ok(None)
+			}
+		}
+	}
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::*;
+
+	fn decode(bits: &[(bool, Kind)]) -> Result<Frame, Error> {
+		let mut decoder = Decoder::default();
+		for (bit, kind) in bits {
+			// acknowledge the frame
+			let bit = *bit && *kind != Kind::Ack;
+			if let Some(Decoded::Frame(frame)) = decoder.push(bit)? {
+				return Ok(frame);
+			}
+		}
+		panic!("incomplete frame");
+	}
+
+	#[test]
+	fn roundtrip() {
+		let frames = [
+			Frame::new(Id::Standard(0x000), &[]).unwrap(),
+			Frame::new(Id::Standard(0x7ff), &[0xff; 8]).unwrap(),
+			Frame::new(Id::Extended(0x1fff_ffff), &[0x00; 8]).unwrap(),
+			Frame::new(Id::Extended(0x0123_4567), &[1, 2, 3]).unwrap(),
+			Frame::new_remote(Id::Standard(0x123), 15).unwrap(),
+			Frame::new_remote(Id::Extended(0x42), 4).unwrap(),
+		];
+		for frame in frames.iter() {
+			let bits = encode(frame);
+			assert_eq!(Ok(frame.clone()), decode(&bits));
+			// no more than five equal bits up to the CRC delimiter
+			let levels: Vec<bool> = bits.iter().map(|bit| bit.0).collect();
+			let stuffed = &levels[..levels.len() - 10];
+			assert!(stuffed.windows(6).all(|w| w.iter().any(|b| *b != w[0])));
+		}
+	}
+
+	#[test]
+	fn known_frame() {
+		// standard frame 0x123 with one data byte 0x55
+		let frame = Frame::new(Id::Standard(0x123), &[0x55]).unwrap();
+		let levels: String = encode(&frame)
+			.iter()
+			.map(|(bit, _)| if *bit { '1' } else { '0' })
+			.collect();
+		// SOF, identifier, RTR, IDE, r0 and DLC with a stuff bit after five
+		// dominant bits, followed by the data
+		assert!(levels.starts_with(concat!(
+			"0",
+			"00100100011",
+			"000",
+			"00",
+			"1",
+			"01",
+			"01010101"
+		)));
+		assert!(levels.ends_with("1111111111"));
+	}
+
+	#[test]
+	fn errors() {
+		let frame = Frame::new(Id::Standard(0x123), &[0x55]).unwrap();
+		let mut bits = encode(&frame);
+		// six dominant bits
+		let mut decoder = Decoder::default();
+		assert_eq!(Ok(None), decoder.push(false));
+		assert_eq!(Ok(None), decoder.push(false));
+		assert_eq!(Ok(None), decoder.push(false));
+		assert_eq!(Ok(None), decoder.push(false));
+		assert_eq!(Ok(None), decoder.push(false));
+		assert_eq!(Err(Error::Stuff), decoder.push(false));
+		// a flipped data bit
+		bits[22].0 = !bits[22].0;
+		assert!(decode(&bits).is_err());
+		// a dominant end of frame bit
+		let mut bits = encode(&frame);
+		let n = bits.len();
+		bits[n - 2].0 = false;
+		assert_eq!(Err(Error::Form), decode(&bits));
+	}
+}
@@ -0,0 +1,273 @@
+use super::bits::{encode, Decoded, Decoder, Kind};
+use super::{Error, Event, Frame};
+use crate::clock::VirtualClock;
+use crate::net::PinBackend;
+use crate::sync::{Arc, Mutex};
+use crate::wire::{Driver, InputOnlyPin, Wire, WireState};
+use embedded_hal::digital::InputPin;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Transmit error count at which a node goes bus off.
+const BUS_OFF: u16 = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Mode {
+	/// Waiting for a start of frame.
+	Idle,
+	/// Sending or receiving a frame.
+	Frame,
+	/// Sending the given number of remaining dominant error flag bits.
+	ErrorFlag(u8),
+	/// Waiting for the given number of consecutive recessive bits before the
+	/// bus is idle.
+	Intermission { recessive: u8, needed: u8 },
+	/// Not taking part in bus communication anymore.
+	BusOff,
+}
+
+/// The protocol engine shared by nodes and monitors.
+#[derive(Debug)]
+pub(crate) struct Controller {
+	bit_time: Duration,
+	bus: InputOnlyPin,
+	/// The driver on the bus, `None` when only listening.
+	output: Option<Arc<Driver>>,
+	mode: Mode,
+	/// Time of the last hard synchronization.
+	start: Duration,
+	decoder: Decoder,
+	/// Bits of the frame being sent, while not having lost the arbitration.
+	sending: Option<Vec<(bool, Kind)>>,
+	/// Whether to acknowledge in the next bit.
+	ack: bool,
+	pub(crate) pending: VecDeque<Frame>,
+	pub(crate) received: VecDeque<Frame>,
+	pub(crate) events: Vec<Event>,
+	pub(crate) transmit_errors: u16,
+	pub(crate) receive_errors: u16,
+}
+
+impl Controller {
+	/// Attaches a new controller to `bus`, which drives the bus unless
+	/// `listen_only` is set.
+	pub(crate) fn attach(
+		bus: &Wire,
+		clock: VirtualClock,
+		bitrate: u32,
+		listen_only: bool,
+	) -> Arc<Mutex<Controller>> {
+		assert!(bitrate > 0, "bitrate must not be zero");
+		let output = if listen_only {
+			None
+		} else {
+			Some(Arc::new(bus.connect_driver()))
+		};
+		let controller = Arc::new(Mutex::new(Controller {
+			bit_time: Duration::from_nanos(1_000_000_000 / bitrate as u64),
+			bus: bus.connect_input_pin(),
+			output,
+			mode: Mode::Idle,
+			start: Duration::ZERO,
+			decoder: Decoder::default(),
+			sending: None,
+			ack: false,
+			pending: VecDeque::new(),
+			received: VecDeque::new(),
+			events: vec![],
+			transmit_errors: 0,
+			receive_errors: 0,
+		}));
+		let c = controller.clone();
+		bus.on_change(move |state| {
+			if state == WireState::Low {
+				Controller::hard_sync(&c, &clock);
+			}
+		});
+		controller
+	}
+
+	pub(crate) fn is_bus_off(&self) -> bool {
+		self.mode == Mode::BusOff
+	}
+
+	/// Queues `frame` and starts sending it if the bus is idle.
+	pub(crate) fn transmit(
+		controller: &Arc<Mutex<Controller>>,
+		clock: &VirtualClock,
+		frame: Frame,
+	) {
+		controller.lock().unwrap().pending.push_back(frame);
+		// started by the clock, so nodes queueing frames at the same time
+		// arbitrate for the bus
+		Controller::start_at(controller, clock, clock.now());
+	}
+
+	fn start_at(controller: &Arc<Mutex<Controller>>, clock: &VirtualClock, at: Duration) {
+		let c = controller.clone();
+		clock.schedule_at(at, move || {
+			let output = {
+				let controller = c.lock().unwrap();
+				if controller.mode != Mode::Idle || controller.pending.is_empty() {
+					return;
+				}
+				match &controller.output {
+					Some(output) => output.clone(),
+					None => return,
+				}
+			};
+			// the falling edge hard synchronizes all controllers including
+			// this one
+			output.drive(WireState::Low);
+		});
+	}
+
+	fn hard_sync(controller: &Arc<Mutex<Controller>>, clock: &VirtualClock) {
+		let now = clock.now();
+		{
+			let mut c = controller.lock().unwrap();
+			if c.mode != Mode::Idle {
+				return;
+			}
+			c.mode = Mode::Frame;
+			c.start = now;
+			c.decoder = Decoder::default();
+			c.ack = false;
+			c.sending = match c.output {
+				Some(_) => c.pending.front().map(encode),
+				None => None,
+			};
+		}
+		Controller::schedule(controller, clock, 0);
+	}
+
+	/// Schedules driving and sampling bit `n` after the last hard
+	/// synchronization. Bits are sampled at 75 % of the bit time.
+	fn schedule(controller: &Arc<Mutex<Controller>>, clock: &VirtualClock, n: u32) {
+		let (start, sample, output) = {
+			let c = controller.lock().unwrap();
+			let start = c.start + c.bit_time * n;
+			(start, start + c.bit_time * 3 / 4, c.output.clone())
+		};
+		if let Some(output) = output {
+			let c = controller.clone();
+			clock.schedule_at(start, move || {
+				let recessive = c.lock().unwrap().level(n);
+				output.drive(if recessive {
+					WireState::Floating
+				} else {
+					WireState::Low
+				});
+			});
+		}
+		let (c, clk) = (controller.clone(), clock.clone());
+		clock.schedule_at(sample, move || {
+			let (mode, next, output) = {
+				let mut controller = c.lock().unwrap();
+				controller.sample(n);
+				let next = controller.start + controller.bit_time * (n + 1);
+				(controller.mode, next, controller.output.clone())
+			};
+			match (mode, output) {
+				(Mode::Idle, _) => Controller::start_at(&c, &clk, next),
+				(Mode::BusOff, Some(output)) => output.release(),
+				(Mode::BusOff, None) => {}
+				_ => Controller::schedule(&c, &clk, n + 1),
+			}
+		});
+	}
+
+	/// Returns the level to drive in bit `n`, `true` being recessive.
+	fn level(&mut self, n: u32) -> bool {
+		match self.mode {
+			Mode::Frame => match &self.sending {
+				Some(bits) => bits.get(n as usize).map(|bit| bit.0).unwrap_or(true),
+				None => !std::mem::take(&mut self.ack),
+			},
+			Mode::ErrorFlag(_) => false,
+			_ => true,
+		}
+	}
+
+	fn sample(&mut self, n: u32) {
+		let level = self.bus.is_high().unwrap_or(true);
+		match self.mode {
+			Mode::Frame => self.receive(n, level),
+			Mode::ErrorFlag(1) => {
+				self.mode = Mode::Intermission {
+					recessive: 0,
+					needed: 11,
+				}
+			}
+			Mode::ErrorFlag(remaining) => self.mode = Mode::ErrorFlag(remaining - 1),
+			Mode::Intermission { recessive, needed } => {
+				let recessive = if level { recessive + 1 } else { 0 };
+				self.mode = if recessive == needed {
+					Mode::Idle
+				} else {
+					Mode::Intermission { recessive, needed }
+				};
+			}
+			Mode::Idle | Mode::BusOff => {}
+		}
+	}
+
+	fn receive(&mut self, n: u32, level: bool) {
+		if let Some(&(sent, kind)) = self.sending.as_ref().and_then(|bits| bits.get(n as usize)) {
+			match kind {
+				Kind::Arbitration if sent && !level => self.sending = None,
+				Kind::Ack if level => return self.error(Error::Ack),
+				Kind::Ack => {}
+				_ if sent != level => return self.error(Error::Bit),
+				_ => {}
+			}
+		}
+		match self.decoder.push(level) {
+			Ok(Some(Decoded::CrcChecked(ok))) => {
+				self.ack = ok && self.sending.is_none() && self.output.is_some()
+			}
+			Ok(Some(Decoded::Ack(false))) if self.output.is_none() => self.error(Error::Ack),
+			Ok(Some(Decoded::Frame(frame))) => {
+				if self.sending.take().is_some() {
+					self.pending.pop_front();
+					self.transmit_errors = self.transmit_errors.saturating_sub(1);
+				} else if self.output.is_some() {
+					self.received.push_back(frame);
+					self.receive_errors = self.receive_errors.saturating_sub(1);
+				} else {
+					self.events.push(Event::Frame(frame));
+				}
+				self.mode = Mode::Intermission {
+					recessive: 0,
+					needed: 3,
+				};
+			}
+			Ok(_) => {}
+			Err(error) => self.error(error),
+		}
+	}
+
+	/// Handles an error detected in the current bit. The frame being sent is
+	/// kept for retransmission.
+	fn error(&mut self, error: Error) {
+		if self.output.is_none() {
+			self.events.push(Event::Error(error));
+			self.mode = Mode::Intermission {
+				recessive: 0,
+				needed: 11,
+			};
+			return;
+		}
+		if self.sending.take().is_some() {
+			self.transmit_errors += 8;
+		} else {
+			self.receive_errors = self.receive_errors.saturating_add(1);
+		}
+		self.ack = false;
+		self.mode = if self.transmit_errors >= BUS_OFF {
+			Mode::BusOff
+		} else {
+			Mode::ErrorFlag(6)
+		};
+	}
+}
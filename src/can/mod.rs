@@ -0,0 +1,163 @@
+//! Simulated CAN buses
+//!
+//! CAN uses a single line (a differential pair in hardware) that is dominant
+//! when any node drives it and recessive otherwise. This maps directly onto a
+//! [`Wire`](`crate::wire::Wire`) created with
+//! [`Wire::new_with_pull(WireState::High)`](`crate::wire::Wire::new_with_pull`),
+//! where low is dominant and high is recessive.
+//!
+//! [`CanNode`] transmits frames bit by bit through a
+//! [`Driver`](`crate::wire::Driver`) that only pulls the bus low, including
+//! bit stuffing and CRC. It reads back every bit it sends, so several nodes starting at the
+//! same time arbitrate for the bus: a node sending a recessive identifier bit
+//! while another sends a dominant one stops sending and retries once the bus
+//! is idle again. Nodes acknowledge frames they received correctly and signal
+//! errors with error frames. [`CanMonitor`] passively decodes all frames and
+//! errors on the bus.
+//!
+//! All nodes time their bits on a [`VirtualClock`](`crate::clock::VirtualClock`)
+//! and synchronize on the start of each frame.
+//!
+//! # Examples
+//!
+//! ```
+//! use embedded_hal_sync_pins::can::{CanMonitor, CanNode, Event, Frame, Id};
+//! use embedded_hal_sync_pins::clock::VirtualClock;
+//! use embedded_hal_sync_pins::wire::{Wire, WireState};
+//!
+//! let clock = VirtualClock::new();
+//! let bus = Wire::new_with_pull(WireState::High);
+//! let monitor = CanMonitor::new(&bus, clock.clone(), 500_000);
+//! let a = CanNode::new(&bus, clock.clone(), 500_000);
+//! let b = CanNode::new(&bus, clock.clone(), 500_000);
+//! let c = CanNode::new(&bus, clock.clone(), 500_000);
+//! let low = Frame::new(Id::Standard(0x200), &[1]).unwrap();
+//! let high = Frame::new(Id::Standard(0x100), &[2]).unwrap();
+//! // both nodes start at the same time, the lower identifier wins
+//! a.transmit(low.clone());
+//! b.transmit(high.clone());
+//! clock.run();
+//! assert_eq!(vec![Event::Frame(high.clone()), Event::Frame(low.clone())], monitor.events());
+//! assert_eq!(Some(high), c.receive());
+//! assert_eq!(Some(low), c.receive());
+//! ```
+
+mod bits;
+mod controller;
+mod monitor;
+mod node;
+
+pub use monitor::{CanMonitor, Event};
+pub use node::CanNode;
+
+use std::fmt;
+
+/// Identifier of a CAN frame.
+///
+/// Lower identifiers have a higher priority. A standard identifier wins the
+/// arbitration against an extended identifier with the same 11 base bits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Id {
+	/// An 11-bit identifier.
+	Standard(u16),
+	/// A 29-bit identifier.
+	Extended(u32),
+}
+
+impl Id {
+	fn is_valid(&self) -> bool {
+		match *self {
+			Id::Standard(id) => id < 0x800,
+			Id::Extended(id) => id < 0x2000_0000,
+		}
+	}
+}
+
+/// A CAN data or remote frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+	id: Id,
+	remote: bool,
+	dlc: u8,
+	data: Vec<u8>,
+}
+
+impl Frame {
+	/// Creates a data frame.
+	///
+	/// Returns `None` if the identifier is out of range or there are more than
+	/// 8 bytes of data.
+	pub fn new(id: Id, data: &[u8]) -> Option<Self> {
+		if !id.is_valid() || data.len() > 8 {
+			return None;
+		}
+		Some(Frame {
+			id,
+			remote: false,
+			dlc: data.len() as u8,
+			data: data.to_vec(),
+		})
+	}
+
+	/// Creates a remote frame requesting `dlc` bytes.
+	///
+	/// Returns `None` if the identifier is out of range or `dlc` is greater
+	/// than 15.
+	pub fn new_remote(id: Id, dlc: u8) -> Option<Self> {
+		if !id.is_valid() || dlc > 15 {
+			return None;
+		}
+		Some(Frame {
+			id,
+			remote: true,
+			dlc,
+			data: vec![],
+		})
+	}
+
+	pub fn id(&self) -> Id {
+		self.id
+	}
+
+	pub fn is_remote(&self) -> bool {
+		self.remote
+	}
+
+	/// Returns the data length code, which may be greater than 8 for remote frames.
+	pub fn dlc(&self) -> u8 {
+		self.dlc
+	}
+
+	pub fn data(&self) -> &[u8] {
+		&self.data
+	}
+}
+
+/// An error detected on the bus.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+	/// A transmitter read back a different level than it sent.
+	Bit,
+	/// Six consecutive bits had the same level.
+	Stuff,
+	/// The received CRC did not match the frame.
+	Crc,
+	/// A fixed format bit had the wrong level.
+	Form,
+	/// No node acknowledged a frame.
+	Ack,
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Error::Bit => write!(f, "bit error"),
+			Error::Stuff => write!(f, "stuff error"),
+			Error::Crc => write!(f, "CRC error"),
+			Error::Form => write!(f, "form error"),
+			Error::Ack => write!(f, "acknowledgement error"),
+		}
+	}
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,63 @@
+use super::controller::Controller;
+use super::{Error, Frame};
+use crate::clock::VirtualClock;
+use crate::sync::{Arc, Mutex};
+use crate::wire::Wire;
+
+/// Something observed on a CAN bus by a [`CanMonitor`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+	/// A frame was sent and acknowledged successfully.
+	Frame(Frame),
+	/// An error was detected, followed by error frames of the nodes.
+	Error(Error),
+}
+
+/// A passive CAN bus monitor.
+///
+/// The monitor decodes all traffic on the bus like a CAN controller in listen
+/// only mode: it never drives the bus, neither to acknowledge frames nor to
+/// send error flags. An error is recorded once per frame, until the bus is
+/// idle again. Bit errors are only detected by transmitters, so they show up
+/// as the errors caused by the error flag of the transmitter.
+#[derive(Clone, Debug)]
+pub struct CanMonitor {
+	controller: Arc<Mutex<Controller>>,
+}
+
+impl CanMonitor {
+	/// Attaches a new monitor to `bus` with the given bitrate in bit/s.
+	///
+	/// # Panics
+	///
+	/// Panics if `bitrate` is zero.
+	pub fn new(bus: &Wire, clock: VirtualClock, bitrate: u32) -> Self {
+		CanMonitor {
+			controller: Controller::attach(bus, clock, bitrate, true),
+		}
+	}
+
+	/// Returns all events observed so far.
+	pub fn events(&self) -> Vec<Event> {
+		self.controller.lock().unwrap().events.clone()
+	}
+
+	/// Returns all frames observed so far.
+	pub fn frames(&self) -> Vec<Frame> {
+		self.controller
+			.lock()
+			.unwrap()
+			.events
+			.iter()
+			.filter_map(|event| match event {
+				Event::Frame(frame) => Some(frame.clone()),
+				Event::Error(_) => None,
+			})
+			.collect()
+	}
+
+	/// Clears the observed events.
+	pub fn clear(&self) {
+		self.controller.lock().unwrap().events.clear();
+	}
+}
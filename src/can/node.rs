@@ -0,0 +1,198 @@
+use super::controller::Controller;
+use super::Frame;
+use crate::clock::VirtualClock;
+use crate::sync::{Arc, Mutex};
+use crate::wire::Wire;
+
+/// A simulated CAN controller attached to a bus.
+///
+/// Frames are queued with [`transmit`](`CanNode::transmit`) and sent in order
+/// once the bus is idle, retrying after lost arbitration or errors. Frames sent
+/// by other nodes are acknowledged and can be taken with
+/// [`receive`](`CanNode::receive`).
+///
+/// Every error increases the transmit or receive error counter as specified
+/// by CAN, successful frames decrease them. A node whose transmit error
+/// counter reaches 256 goes bus off and stops taking part in communication.
+/// Error passive nodes are not modelled, all nodes send active error flags.
+#[derive(Clone, Debug)]
+pub struct CanNode {
+	controller: Arc<Mutex<Controller>>,
+	clock: VirtualClock,
+}
+
+impl CanNode {
+	/// Attaches a new node to `bus` with the given bitrate in bit/s.
+	///
+	/// # Panics
+	///
+	/// Panics if `bitrate` is zero.
+	pub fn new(bus: &Wire, clock: VirtualClock, bitrate: u32) -> Self {
+		CanNode {
+			controller: Controller::attach(bus, clock.clone(), bitrate, false),
+			clock,
+		}
+	}
+
+	/// Queues `frame` for transmission.
+	///
+	/// If the bus is idle, the frame is started at the current time of the
+	/// clock, so frames queued by several nodes at the same time arbitrate for
+	/// the bus.
+	pub fn transmit(&self, frame: Frame) {
+		Controller::transmit(&self.controller, &self.clock, frame);
+	}
+
+	/// Returns the number of frames waiting to be sent, including the one
+	/// being sent.
+	pub fn pending(&self) -> usize {
+		self.controller.lock().unwrap().pending.len()
+	}
+
+	/// Takes the oldest frame received from other nodes.
+	pub fn receive(&self) -> Option<Frame> {
+		self.controller.lock().unwrap().received.pop_front()
+	}
+
+	pub fn transmit_error_count(&self) -> u16 {
+		self.controller.lock().unwrap().transmit_errors
+	}
+
+	pub fn receive_error_count(&self) -> u16 {
+		self.controller.lock().unwrap().receive_errors
+	}
+
+	pub fn is_bus_off(&self) -> bool {
+		self.controller.lock().unwrap().is_bus_off()
+	}
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::*;
+	use crate::can::{CanMonitor, Error, Event, Id};
+	use crate::wire::WireState;
+	use embedded_hal::digital::OutputPin;
+	use std::time::Duration;
+
+	const BITRATE: u32 = 500_000;
+
+	fn bus(nodes: usize) -> (VirtualClock, Wire, CanMonitor, Vec<CanNode>) {
+		let clock = VirtualClock::new();
+		let bus = Wire::new_with_pull(WireState::High);
+		let monitor = CanMonitor::new(&bus, clock.clone(), BITRATE);
+		let nodes = (0..nodes)
+			.map(|_| CanNode::new(&bus, clock.clone(), BITRATE))
+			.collect();
+		(clock, bus, monitor, nodes)
+	}
+
+	fn frame(id: Id, data: &[u8]) -> Frame {
+		Frame::new(id, data).unwrap()
+	}
+
+	#[test]
+	fn single_frame() {
+		let (clock, _, monitor, nodes) = bus(2);
+		let f = frame(Id::Standard(0x123), &[0xde, 0xad]);
+		nodes[0].transmit(f.clone());
+		assert_eq!(1, nodes[0].pending());
+		clock.run();
+		assert_eq!(0, nodes[0].pending());
+		assert_eq!(vec![Event::Frame(f.clone())], monitor.events());
+		assert_eq!(Some(f), nodes[1].receive());
+		assert_eq!(None, nodes[1].receive());
+		assert_eq!(None, nodes[0].receive());
+	}
+
+	#[test]
+	fn arbitration() {
+		let (clock, _, monitor, nodes) = bus(4);
+		let frames = [
+			frame(Id::Extended(0x0400_0000), &[1]),
+			frame(Id::Standard(0x101), &[2]),
+			Frame::new_remote(Id::Standard(0x100), 2).unwrap(),
+			frame(Id::Standard(0x100), &[4]),
+		];
+		for (node, frame) in nodes.iter().zip(frames.iter()) {
+			node.transmit(frame.clone());
+		}
+		clock.run();
+		// lower identifiers first, data before remote frames and standard before
+		// extended frames with the same base identifier
+		let order = [3, 2, 0, 1];
+		assert_eq!(
+			order
+				.iter()
+				.map(|i| Event::Frame(frames[*i].clone()))
+				.collect::<Vec<_>>(),
+			monitor.events()
+		);
+		// nodes receive all frames they did not send
+		for (i, node) in nodes.iter().enumerate() {
+			let received: Vec<Frame> = std::iter::from_fn(|| node.receive()).collect();
+			let expected: Vec<Frame> = order
+				.iter()
+				.filter(|j| **j != i)
+				.map(|j| frames[*j].clone())
+				.collect();
+			assert_eq!(expected, received);
+			assert_eq!(0, node.transmit_error_count());
+		}
+	}
+
+	#[test]
+	fn queued_frames() {
+		let (clock, _, monitor, nodes) = bus(2);
+		nodes[0].transmit(frame(Id::Standard(0x300), &[1]));
+		nodes[0].transmit(frame(Id::Standard(0x301), &[2]));
+		clock.advance(Duration::from_micros(10));
+		// the bus is busy, so this frame waits for the next arbitration
+		nodes[1].transmit(frame(Id::Standard(0x001), &[3]));
+		clock.run();
+		let ids: Vec<Id> = monitor.frames().iter().map(|frame| frame.id()).collect();
+		assert_eq!(
+			vec![
+				Id::Standard(0x300),
+				Id::Standard(0x001),
+				Id::Standard(0x301)
+			],
+			ids
+		);
+	}
+
+	#[test]
+	fn no_acknowledgement() {
+		let (clock, _, monitor, nodes) = bus(1);
+		nodes[0].transmit(frame(Id::Standard(0x7ff), &[]));
+		clock.run();
+		assert!(nodes[0].is_bus_off());
+		assert_eq!(256, nodes[0].transmit_error_count());
+		assert_eq!(1, nodes[0].pending());
+		let events = monitor.events();
+		assert_eq!(32, events.len());
+		assert!(events.iter().all(|e| *e == Event::Error(Error::Ack)));
+	}
+
+	#[test]
+	fn error_frame() {
+		let (clock, bus, monitor, nodes) = bus(2);
+		let f = frame(Id::Standard(0x555), &[0xff, 0x00]);
+		nodes[0].transmit(f.clone());
+		// disturb the first data bit, making the sender see a bit error
+		let mut disturber = bus.connect_open_drain_pin();
+		clock.advance(Duration::from_micros(2 * 20));
+		disturber.set_high().unwrap();
+		clock.advance(Duration::from_micros(2));
+		disturber.set_low().unwrap();
+		clock.run();
+		// the frame is sent again after the error frame
+		let events = monitor.events();
+		assert_eq!(2, events.len());
+		assert!(matches!(events[0], Event::Error(_)));
+		assert_eq!(Event::Frame(f.clone()), events[1]);
+		assert_eq!(Some(f), nodes[1].receive());
+		assert_eq!(7, nodes[0].transmit_error_count());
+		assert_eq!(0, nodes[1].receive_error_count());
+	}
+}
@@ -0,0 +1,374 @@
+//! Virtual time
+//!
+//! Bit-banged protocols depend on timing. Running them against wall clock time
+//! makes tests slow and flaky, so components in this crate use a
+//! [`VirtualClock`] instead. Time only advances when someone waits on the clock,
+//! e.g. a driver calling [`DelayUs::delay_us`], and actions scheduled by
+//! simulated devices are run in order as soon as their time is reached.
+//!
+//! Several blocking drivers, e.g. two bus masters, can share a clock by running
+//! in their own threads, each waiting on its own [`Participant`].
+//!
+//! # Examples
+//!
+//! ```
+//! use embedded_hal_sync_pins::clock::VirtualClock;
+//! use embedded_hal::delay::DelayUs;
+//! use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+//! use std::time::Duration;
+//!
+//! let mut clock = VirtualClock::new();
+//! let fired = Arc::new(AtomicBool::new(false));
+//! let f = fired.clone();
+//! clock.schedule_in(Duration::from_micros(10), move || f.store(true, Ordering::SeqCst));
+//! clock.delay_us(5).unwrap();
+//! assert!(!fired.load(Ordering::SeqCst));
+//! clock.delay_us(5).unwrap();
+//! assert!(fired.load(Ordering::SeqCst));
+//! assert_eq!(Duration::from_micros(10), clock.now());
+//! ```
+
+use crate::sync::{Arc, Condvar, Mutex};
+use core::convert::Infallible;
+use embedded_hal::delay::DelayUs;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::time::Duration;
+
+type Action = Box<dyn FnOnce() + Send>;
+
+#[derive(Default)]
+struct ClockWrapper {
+	now: Duration,
+	seq: u64,
+	events: BTreeMap<(Duration, u64), Action>,
+	participants: usize,
+	/// Wake-up times of the waiting participants.
+	sleeping: BTreeSet<(Duration, u64)>,
+	/// Whether a participant is advancing the clock.
+	advancing: bool,
+}
+
+impl fmt::Debug for ClockWrapper {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("ClockWrapper")
+			.field("now", &self.now)
+			.field("events", &self.events.len())
+			.field("participants", &self.participants)
+			.finish()
+	}
+}
+
+/// A clock whose time only advances on request.
+///
+/// Clones of a clock share the same time and schedule.
+#[derive(Clone, Debug, Default)]
+pub struct VirtualClock {
+	clock: Arc<Mutex<ClockWrapper>>,
+	wakeup: Arc<Condvar>,
+}
+
+impl VirtualClock {
+	/// Creates a new clock starting at zero.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Returns the time elapsed since the clock was created.
+	pub fn now(&self) -> Duration {
+		self.clock.lock().unwrap().now
+	}
+
+	/// Schedules `f` to be run once the clock reaches `at`.
+	///
+	/// Actions scheduled for the same time are run in the order they were
+	/// scheduled. Actions scheduled in the past are run on the next advance.
+	pub fn schedule_at<F>(&self, at: Duration, f: F)
+	where
+		F: FnOnce() + Send + 'static,
+	{
+		let mut clock = self.clock.lock().unwrap();
+		let seq = clock.seq;
+		clock.seq += 1;
+		clock.events.insert((at, seq), Box::new(f));
+	}
+
+	/// Schedules `f` to be run after `delay` has elapsed.
+	pub fn schedule_in<F>(&self, delay: Duration, f: F)
+	where
+		F: FnOnce() + Send + 'static,
+	{
+		let at = self.now() + delay;
+		self.schedule_at(at, f);
+	}
+
+	/// Returns the time of the next scheduled action, if any.
+	pub fn next_event(&self) -> Option<Duration> {
+		self.clock.lock().unwrap().events.keys().next().map(|k| k.0)
+	}
+
+	/// Advances the clock to `time`, running all actions scheduled up to it.
+	///
+	/// The clock never runs backwards, so advancing to a time in the past
+	/// only runs pending actions.
+	pub fn advance_to(&self, time: Duration) {
+		loop {
+			let action = {
+				let mut clock = self.clock.lock().unwrap();
+				match clock.events.keys().next() {
+					Some(&(at, seq)) if at <= time => {
+						clock.now = clock.now.max(at);
+						clock.events.remove(&(at, seq))
+					}
+					_ => {
+						clock.now = clock.now.max(time);
+						None
+					}
+				}
+			};
+			match action {
+				Some(action) => action(),
+				None => break,
+			}
+		}
+	}
+
+	/// Advances the clock by `delay`, running all actions scheduled until then.
+	pub fn advance(&self, delay: Duration) {
+		let time = self.now() + delay;
+		self.advance_to(time);
+	}
+
+	/// Advances the clock by `ns` nanoseconds.
+	pub fn delay_ns(&self, ns: u64) {
+		self.advance(Duration::from_nanos(ns));
+	}
+
+	/// Runs all scheduled actions, advancing the clock to the last of them.
+	///
+	/// Actions that keep scheduling new actions make this run forever.
+	pub fn run(&self) {
+		while let Some(at) = self.next_event() {
+			self.advance_to(at);
+		}
+	}
+
+	/// Adds a participant waiting on this clock from its own thread.
+	///
+	/// All participants have to be created before any of them starts waiting.
+	pub fn participant(&self) -> Participant {
+		self.clock.lock().unwrap().participants += 1;
+		Participant {
+			clock: self.clone(),
+		}
+	}
+}
+
+/// A delay for one of several threads sharing a [`VirtualClock`].
+///
+/// Participants run in lockstep: time only advances once all participants
+/// are waiting, and then only up to the earliest time one of them waits for.
+/// That participant is woken up and runs alone until it waits again, so a
+/// simulation with several threads is as deterministic as one with a single
+/// thread. Participants waiting for the same time are woken up in the order
+/// they started waiting. While participants exist, time should not be
+/// advanced otherwise.
+///
+/// # Examples
+///
+/// ```
+/// use embedded_hal_sync_pins::clock::VirtualClock;
+/// use embedded_hal::delay::DelayUs;
+/// use std::sync::{Arc, Mutex};
+/// use std::thread;
+///
+/// let clock = VirtualClock::new();
+/// let log = Arc::new(Mutex::new(vec![]));
+/// let delays = vec![("a", 3, clock.participant()), ("b", 2, clock.participant())];
+/// let threads: Vec<_> = delays
+///     .into_iter()
+///     .map(|(name, us, mut delay)| {
+///         let log = log.clone();
+///         thread::spawn(move || {
+///             for _ in 0..2 {
+///                 delay.delay_us(us).unwrap();
+///                 log.lock().unwrap().push((name, delay.now().as_micros()));
+///             }
+///         })
+///     })
+///     .collect();
+/// for thread in threads {
+///     thread.join().unwrap();
+/// }
+/// assert_eq!(vec![("b", 2), ("a", 3), ("b", 4), ("a", 6)], *log.lock().unwrap());
+/// ```
+#[derive(Debug)]
+pub struct Participant {
+	clock: VirtualClock,
+}
+
+impl Participant {
+	pub fn now(&self) -> Duration {
+		self.clock.now()
+	}
+
+	/// Returns the shared clock.
+	pub fn clock(&self) -> &VirtualClock {
+		&self.clock
+	}
+
+	/// Waits until the clock reached `time`.
+	pub fn wait_until(&self, time: Duration) {
+		let VirtualClock { clock, wakeup } = &self.clock;
+		let mut c = clock.lock().unwrap();
+		let key = (time, c.seq);
+		c.seq += 1;
+		c.sleeping.insert(key);
+		while c.sleeping.contains(&key) {
+			if c.advancing || c.sleeping.len() < c.participants {
+				c = wakeup.wait(c).unwrap();
+				continue;
+			}
+			// all participants are waiting, wake up the first one
+			let first = *c.sleeping.iter().next().unwrap();
+			c.advancing = true;
+			drop(c);
+			self.clock.advance_to(first.0);
+			c = clock.lock().unwrap();
+			c.advancing = false;
+			c.sleeping.remove(&first);
+			wakeup.notify_all();
+		}
+	}
+}
+
+impl Drop for Participant {
+	fn drop(&mut self) {
+		self.clock.clock.lock().unwrap().participants -= 1;
+		// the remaining participants might all be waiting
+		self.clock.wakeup.notify_all();
+	}
+}
+
+impl DelayUs for Participant {
+	type Error = Infallible;
+
+	fn delay_us(&mut self, us: u32) -> Result<(), Self::Error> {
+		self.wait_until(self.now() + Duration::from_micros(us.into()));
+		Ok(())
+	}
+}
+
+impl DelayUs for VirtualClock {
+	type Error = Infallible;
+
+	fn delay_us(&mut self, us: u32) -> Result<(), Self::Error> {
+		self.advance(Duration::from_micros(us.into()));
+		Ok(())
+	}
+}
+
+/// Returns the half period in µs of a bit-banged clock at `hz`.
+///
+/// Delays have a resolution of one microsecond, so the frequency is rounded
+/// down to the next achievable one, but at most 500 kHz.
+pub(crate) fn half_period_us(hz: u32) -> u32 {
+	500_000u32.div_ceil(hz.max(1))
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::*;
+	use std::sync::Mutex;
+
+	#[test]
+	fn order() {
+		let clock = VirtualClock::new();
+		let log = Arc::new(Mutex::new(vec![]));
+		for (t, name) in [(20, "c"), (10, "a"), (10, "b")] {
+			let log = log.clone();
+			clock.schedule_at(Duration::from_micros(t), move || {
+				log.lock().unwrap().push(name)
+			});
+		}
+		assert_eq!(Some(Duration::from_micros(10)), clock.next_event());
+		clock.advance(Duration::from_micros(15));
+		assert_eq!(vec!["a", "b"], *log.lock().unwrap());
+		assert_eq!(Duration::from_micros(15), clock.now());
+		clock.run();
+		assert_eq!(vec!["a", "b", "c"], *log.lock().unwrap());
+		assert_eq!(Duration::from_micros(20), clock.now());
+		assert_eq!(None, clock.next_event());
+	}
+
+	#[test]
+	fn participants() {
+		let clock = VirtualClock::new();
+		let log = Arc::new(Mutex::new(vec![]));
+		let fired = log.clone();
+		clock.schedule_at(Duration::from_micros(5), move || {
+			fired.lock().unwrap().push(("action", 5))
+		});
+		let delays = vec![
+			("a", 4, 1, clock.participant()),
+			("b", 3, 3, clock.participant()),
+		];
+		let threads: Vec<_> = delays
+			.into_iter()
+			.map(|(name, us, n, mut delay)| {
+				let log = log.clone();
+				std::thread::spawn(move || {
+					for _ in 0..n {
+						delay.delay_us(us).unwrap();
+						log.lock().unwrap().push((name, delay.now().as_micros()));
+					}
+				})
+			})
+			.collect();
+		for thread in threads {
+			thread.join().unwrap();
+		}
+		// b keeps running after a finished
+		assert_eq!(
+			vec![("b", 3), ("a", 4), ("action", 5), ("b", 6), ("b", 9)],
+			*log.lock().unwrap()
+		);
+		assert_eq!(Duration::from_micros(9), clock.now());
+	}
+
+	#[test]
+	fn reschedule_from_action() {
+		let mut clock = VirtualClock::new();
+		let times = Arc::new(Mutex::new(vec![]));
+		fn tick(clock: VirtualClock, times: Arc<Mutex<Vec<Duration>>>, n: u32) {
+			times.lock().unwrap().push(clock.now());
+			if n > 0 {
+				let c = clock.clone();
+				clock.schedule_in(Duration::from_micros(3), move || tick(c, times, n - 1));
+			}
+		}
+		let c = clock.clone();
+		let t = times.clone();
+		clock.schedule_in(Duration::from_micros(1), move || tick(c, t, 2));
+		clock.delay_us(100).unwrap();
+		assert_eq!(
+			vec![
+				Duration::from_micros(1),
+				Duration::from_micros(4),
+				Duration::from_micros(7)
+			],
+			*times.lock().unwrap()
+		);
+		assert_eq!(Duration::from_micros(100), clock.now());
+	}
+
+	#[test]
+	fn half_period() {
+		assert_eq!(5, half_period_us(100_000));
+		// never faster than requested
+		assert_eq!(2, half_period_us(300_000));
+		assert_eq!(1, half_period_us(500_000));
+		assert_eq!(1, half_period_us(1_000_000));
+		assert_eq!(500_000, half_period_us(0));
+	}
+}
@@ -0,0 +1,274 @@
+//! Critical-section based wires
+//!
+//! This module provides a variant of [`Wire`](`crate::wire::Wire`) that does not
+//! need an allocator or an operating system. The state of all drivers is kept in a
+//! fixed-capacity table, whose size is given as a const generic parameter, and
+//! access to it is synchronized using the [`critical_section`] crate.
+//!
+//! As a [`Wire`] can be created in a `const` context, it can be placed in a
+//! `static` and shared between firmware tasks or interrupt handlers running on
+//! the same chip, e.g. to wire up a loopback between two drivers.
+//!
+//! # Examples
+//!
+//! ```
+//! use embedded_hal_sync_pins::cs_wire::{Wire, WireState};
+//! use embedded_hal::digital::{InputPin, OutputPin};
+//!
+//! static LOOPBACK: Wire<2> = Wire::new_with_pull(WireState::High);
+//!
+//! let mut tx = LOOPBACK.connect_open_drain_pin();
+//! let rx = LOOPBACK.connect_input_pin();
+//! assert_eq!(Ok(true), rx.is_high());
+//! tx.set_high().unwrap();
+//! assert_eq!(Ok(true), rx.is_low());
+//! ```
+
+use crate::net::{Net, PinBackend};
+use crate::pins;
+use core::cell::RefCell;
+use critical_section::Mutex;
+
+pub use crate::state::WireState;
+
+type PinId = usize;
+
+#[derive(Debug)]
+struct WireWrapper<const N: usize> {
+	state: [WireState; N],
+	connected: usize,
+	pull: WireState,
+}
+
+/// A wire with room for up to `N` output pins that can be shared between
+/// tasks and interrupt handlers.
+///
+/// Input pins do not drive the wire, so they do not count towards `N`.
+#[derive(Debug)]
+pub struct Wire<const N: usize> {
+	wire: Mutex<RefCell<WireWrapper<N>>>,
+}
+
+impl<const N: usize> Wire<N> {
+	/// Creates a new floating wire.
+	pub const fn new() -> Self {
+		Self::new_with_pull(WireState::Floating)
+	}
+
+	/// Creates a new wire that is pulled to `pull` if no pin drives it.
+	pub const fn new_with_pull(pull: WireState) -> Self {
+		Self {
+			wire: Mutex::new(RefCell::new(WireWrapper {
+				state: [WireState::Floating; N],
+				connected: 0,
+				pull,
+			})),
+		}
+	}
+
+	pub fn set_state(&self, id: PinId, state: WireState) {
+		self.update_pin_state(id, |_| state);
+	}
+
+	pub fn get_pin_state(&self, id: PinId) -> WireState {
+		critical_section::with(|cs| self.wire.borrow_ref(cs).state[id])
+	}
+
+	pub fn update_pin_state<F>(&self, id: PinId, mut f: F)
+	where
+		F: FnMut(WireState) -> WireState,
+	{
+		critical_section::with(|cs| {
+			let mut wire = self.wire.borrow_ref_mut(cs);
+			wire.state[id] = f(wire.state[id]);
+			// check for short circuit
+			let _ = Self::wire_state(&wire);
+		})
+	}
+
+	pub fn get_state(&self) -> WireState {
+		critical_section::with(|cs| Self::wire_state(&self.wire.borrow_ref(cs)))
+	}
+
+	fn wire_state(wire: &WireWrapper<N>) -> WireState {
+		crate::state::resolve(&wire.state[..wire.connected], wire.pull)
+	}
+
+	fn connect(&self) -> PinId {
+		critical_section::with(|cs| {
+			let mut wire = self.wire.borrow_ref_mut(cs);
+			let id = wire.connected;
+			if id >= N {
+				panic!("wire capacity of {} output pins exceeded", N);
+			}
+			wire.connected += 1;
+			id
+		})
+	}
+
+	/// Connects a new driver to this wire.
+	///
+	/// The returned [`Driver`] is a [`PinBackend`] that can be used with any of
+	/// the generic pin types in [`pins`](`crate::pins`).
+	///
+	/// # Panics
+	///
+	/// Panics if `N` output pins are already connected.
+	pub fn connect_driver(&self) -> Driver<'_, N> {
+		Driver {
+			id: self.connect(),
+			wire: self,
+		}
+	}
+
+	/// Connects a new push-pull output pin to this wire.
+	///
+	/// # Panics
+	///
+	/// Panics if `N` output pins are already connected.
+	pub fn connect_push_pull_pin(&self) -> PushPullPin<'_, N> {
+		PushPullPin::new(self.connect_driver())
+	}
+
+	/// Connects a new open drain output pin to this wire.
+	///
+	/// # Panics
+	///
+	/// Panics if `N` output pins are already connected.
+	pub fn connect_open_drain_pin(&self) -> OpenDrainPin<'_, N> {
+		OpenDrainPin::new(self.connect_driver())
+	}
+
+	pub fn connect_input_pin(&self) -> InputOnlyPin<'_, N> {
+		InputOnlyPin::new(self)
+	}
+}
+
+impl<const N: usize> Default for Wire<N> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<const N: usize> Net for Wire<N> {
+	fn level(&self) -> WireState {
+		self.get_state()
+	}
+}
+
+/// A single driver connected to a [`Wire`].
+#[derive(Debug)]
+pub struct Driver<'a, const N: usize> {
+	wire: &'a Wire<N>,
+	id: PinId,
+}
+
+impl<const N: usize> Net for Driver<'_, N> {
+	fn level(&self) -> WireState {
+		self.wire.get_state()
+	}
+}
+
+impl<const N: usize> PinBackend for Driver<'_, N> {
+	fn driven(&self) -> WireState {
+		self.wire.get_pin_state(self.id)
+	}
+
+	fn drive(&self, state: WireState) {
+		self.wire.set_state(self.id, state);
+	}
+
+	fn update_driven<F>(&self, f: F)
+	where
+		F: FnMut(WireState) -> WireState,
+	{
+		self.wire.update_pin_state(self.id, f);
+	}
+}
+
+pub type InputOnlyPin<'a, const N: usize> = pins::InputPin<&'a Wire<N>>;
+
+pub type PushPullPin<'a, const N: usize> = pins::PushPullPin<Driver<'a, N>>;
+
+pub type OpenDrainPin<'a, const N: usize> = pins::OpenDrainPin<Driver<'a, N>>;
+
+#[cfg(test)]
+mod tests {
+	extern crate std;
+
+	use super::*;
+	use embedded_hal::digital::{InputPin, OutputPin, StatefulOutputPin, ToggleableOutputPin};
+	use WireState::*;
+
+	#[test]
+	fn init() {
+		let wire: Wire<1> = Wire::new();
+		let wire2: Wire<1> = Wire::default();
+		assert_eq!(wire.get_state(), wire2.get_state());
+		assert_eq!(Floating, wire.get_state());
+		let wire: Wire<1> = Wire::new_with_pull(High);
+		assert_eq!(High, wire.get_state());
+	}
+
+	#[test]
+	fn pull_up() {
+		let wire: Wire<1> = Wire::new_with_pull(High);
+		let mut pin = wire.connect_open_drain_pin();
+		assert_eq!(High, wire.get_state());
+		assert_eq!(Ok(()), pin.set_high());
+		assert_eq!(Low, wire.get_state());
+		assert_eq!(Ok(false), pin.is_set_low());
+		assert_eq!(Ok(true), pin.is_set_high());
+		assert_eq!(Ok(()), pin.toggle());
+		assert_eq!(High, wire.get_state());
+		assert_eq!(Ok(()), pin.toggle());
+		assert_eq!(Low, wire.get_state());
+	}
+
+	#[test]
+	fn pull_down() {
+		let wire: Wire<1> = Wire::new_with_pull(Low);
+		let mut pin = wire.connect_push_pull_pin();
+		assert_eq!(Low, wire.get_state());
+		assert_eq!(Ok(()), pin.set_high());
+		assert_eq!(Ok(false), pin.is_set_low());
+		assert_eq!(Ok(true), pin.is_set_high());
+		assert_eq!(High, wire.get_state());
+		assert_eq!(Ok(()), pin.toggle());
+		assert_eq!(Low, wire.get_state());
+		assert_eq!(Ok(true), pin.is_set_low());
+		assert_eq!(Ok(false), pin.is_set_high());
+	}
+
+	#[test]
+	fn static_wire_between_threads() {
+		static WIRE: Wire<2> = Wire::new();
+		let pin_in = WIRE.connect_input_pin();
+		let handle = std::thread::spawn(|| {
+			let mut pin_out = WIRE.connect_push_pull_pin();
+			pin_out.set_high().unwrap();
+		});
+		handle.join().unwrap();
+		assert_eq!(Ok(true), pin_in.is_high());
+	}
+
+	#[test]
+	#[should_panic]
+	fn short_circuit() {
+		let wire: Wire<2> = Wire::new();
+		let mut pin1 = wire.connect_push_pull_pin();
+		let mut pin2 = wire.connect_push_pull_pin();
+		assert_eq!(Ok(()), pin1.set_high());
+		// this will cause a short circuit and panic
+		assert_eq!(Ok(()), pin2.set_low());
+	}
+
+	#[test]
+	#[should_panic]
+	fn capacity_exceeded() {
+		let wire: Wire<1> = Wire::new();
+		let _pin1 = wire.connect_push_pull_pin();
+		// there is no room for a second driver
+		let _pin2 = wire.connect_open_drain_pin();
+	}
+}
@@ -0,0 +1,324 @@
+//! Simulated DHT11 and DHT22 humidity and temperature sensors
+//!
+//! DHT sensors talk to the host over a single open drain line pulled high. The
+//! host requests a measurement by pulling the line low for at least 1 ms, or
+//! 18 ms for a DHT11, and releasing it. About 30 µs later the sensor answers
+//! with a presence pulse, 80 µs low and 80 µs high, followed by 40 bits. Every
+//! bit starts with 50 µs low, the length of the following high pulse encodes
+//! its value, 27 µs for a zero and 70 µs for a one. The frame ends with
+//! another 50 µs low pulse.
+//!
+//! The bits are five bytes, MSB first: the humidity, the temperature and a
+//! checksum, the lowest byte of the sum of the other four. A DHT22 sends the
+//! humidity and the temperature in tenths as 16 bit values, with the MSB of
+//! the temperature set for negative values. A DHT11 sends the integral and the
+//! decimal part of each value in a byte, with the MSB of the decimal part of
+//! the temperature set for negative values like newer DHT11 revisions do.
+//!
+//! # Examples
+//!
+//! ```
+//! use embedded_hal_sync_pins::clock::VirtualClock;
+//! use embedded_hal_sync_pins::dht::{Dht, Model};
+//! use embedded_hal_sync_pins::wire::{Wire, WireState};
+//!
+//! let clock = VirtualClock::new();
+//! let line = Wire::new_with_pull(WireState::High);
+//! let sensor = Dht::new(&line, clock.clone(), Model::Dht22);
+//! sensor.set_humidity(65.2);
+//! sensor.set_temperature(-10.1);
+//! assert_eq!([0x02, 0x8c, 0x80, 0x65, 0x73], sensor.frame());
+//! ```
+
+use crate::clock::VirtualClock;
+use crate::net::PinBackend;
+use crate::sync::{Arc, Mutex};
+use crate::wire::{Wire, WireState};
+use std::time::Duration;
+
+/// Time from the end of the start signal to the presence pulse.
+const RESPONSE_DELAY: Duration = Duration::from_micros(30);
+/// Length of both halves of the presence pulse.
+const PRESENCE: Duration = Duration::from_micros(80);
+/// Low time before every bit and at the end of the frame.
+const BIT_LOW: Duration = Duration::from_micros(50);
+/// High time of a zero.
+const ZERO_HIGH: Duration = Duration::from_micros(27);
+/// High time of a one.
+const ONE_HIGH: Duration = Duration::from_micros(70);
+
+/// The type of a DHT sensor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Model {
+	Dht11,
+	Dht22,
+}
+
+impl Model {
+	/// Minimum length of the start signal.
+	fn start(&self) -> Duration {
+		match self {
+			Model::Dht11 => Duration::from_millis(18),
+			Model::Dht22 => Duration::from_millis(1),
+		}
+	}
+}
+
+/// A fault to simulate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+	/// The sensor ignores start signals, as if it was not connected.
+	NoResponse,
+	/// The checksum of all frames is off by one.
+	BadChecksum,
+}
+
+#[derive(Debug)]
+struct Engine {
+	model: Model,
+	humidity: f32,
+	temperature: f32,
+	fault: Option<Fault>,
+	/// Start of the current start signal.
+	fell_at: Option<Duration>,
+	/// End of the last response.
+	busy_until: Option<Duration>,
+	reads: usize,
+}
+
+impl Engine {
+	fn frame(&self) -> [u8; 5] {
+		let tenths = |value: f32| (value.abs() * 10.0).round() as u16;
+		let (humidity, temperature) = match self.model {
+			Model::Dht11 => {
+				// the integral part saturates at 255
+				let split = |tenths: u16| {
+					let tenths = tenths.min(2559);
+					((tenths / 10) as u8, (tenths % 10) as u8)
+				};
+				let (h, mut t) = (
+					split(tenths(self.humidity)),
+					split(tenths(self.temperature)),
+				);
+				if self.temperature < 0.0 {
+					t.1 |= 0x80;
+				}
+				([h.0, h.1], [t.0, t.1])
+			}
+			Model::Dht22 => {
+				let mut t = tenths(self.temperature).min(0x7fff);
+				if self.temperature < 0.0 {
+					t |= 0x8000;
+				}
+				(tenths(self.humidity).to_be_bytes(), t.to_be_bytes())
+			}
+		};
+		let mut frame = [humidity[0], humidity[1], temperature[0], temperature[1], 0];
+		frame[4] = frame[..4]
+			.iter()
+			.fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+		if self.fault == Some(Fault::BadChecksum) {
+			frame[4] = frame[4].wrapping_add(1);
+		}
+		frame
+	}
+}
+
+/// A simulated DHT sensor attached to a wire.
+///
+/// The sensor answers every start signal that is long enough for its
+/// [`Model`] with a frame of the current humidity and temperature, pulling
+/// the line low through its own [`Driver`](`crate::wire::Driver`). Edges on the line are
+/// ignored while a frame is sent. The sensor starts at 50 % and 25 °C.
+///
+/// See the [module documentation](`self`) for an example.
+#[derive(Clone, Debug)]
+pub struct Dht {
+	engine: Arc<Mutex<Engine>>,
+}
+
+impl Dht {
+	/// Attaches a new sensor to the given wire.
+	pub fn new(line: &Wire, clock: VirtualClock, model: Model) -> Self {
+		let engine = Arc::new(Mutex::new(Engine {
+			model,
+			humidity: 50.0,
+			temperature: 25.0,
+			fault: None,
+			fell_at: None,
+			busy_until: None,
+			reads: 0,
+		}));
+		let output = Arc::new(line.connect_driver());
+		let e = engine.clone();
+		line.on_change(move |state| {
+			let now = clock.now();
+			let mut engine = e.lock().unwrap();
+			if engine.busy_until.is_some_and(|until| now < until) {
+				return;
+			}
+			if state == WireState::Low {
+				engine.fell_at = Some(now);
+				return;
+			}
+			match engine.fell_at.take() {
+				Some(fell_at) if now - fell_at >= engine.model.start() => {}
+				_ => return,
+			}
+			if engine.fault == Some(Fault::NoResponse) {
+				return;
+			}
+			let drive_at = |at, state| {
+				let output = output.clone();
+				clock.schedule_at(at, move || output.drive(state));
+			};
+			let mut at = now + RESPONSE_DELAY;
+			drive_at(at, WireState::Low);
+			at += PRESENCE;
+			drive_at(at, WireState::Floating);
+			at += PRESENCE;
+			for byte in engine.frame().iter() {
+				for i in (0..8).rev() {
+					drive_at(at, WireState::Low);
+					at += BIT_LOW;
+					drive_at(at, WireState::Floating);
+					at += if byte & (1 << i) != 0 {
+						ONE_HIGH
+					} else {
+						ZERO_HIGH
+					};
+				}
+			}
+			drive_at(at, WireState::Low);
+			at += BIT_LOW;
+			drive_at(at, WireState::Floating);
+			engine.busy_until = Some(at);
+			engine.reads += 1;
+		});
+		Dht { engine }
+	}
+
+	/// Sets the relative humidity sent in the next frames in percent.
+	pub fn set_humidity(&self, percent: f32) {
+		self.engine.lock().unwrap().humidity = percent.clamp(0.0, 100.0);
+	}
+
+	/// Sets the temperature sent in the next frames in °C.
+	///
+	/// Values beyond the range of the frame format, e.g. above 255.9 °C for a
+	/// DHT11, are sent as the largest value it can carry.
+	pub fn set_temperature(&self, celsius: f32) {
+		self.engine.lock().unwrap().temperature = celsius;
+	}
+
+	/// Simulates `fault`, or normal operation for `None`.
+	pub fn set_fault(&self, fault: Option<Fault>) {
+		self.engine.lock().unwrap().fault = fault;
+	}
+
+	/// Returns the frame sent for the current values.
+	pub fn frame(&self) -> [u8; 5] {
+		self.engine.lock().unwrap().frame()
+	}
+
+	/// Returns the number of frames sent.
+	pub fn reads(&self) -> usize {
+		self.engine.lock().unwrap().reads
+	}
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::*;
+	use embedded_hal::digital::OutputPin;
+
+	/// Reads a frame like a bit-banging driver, sampling the line every µs.
+	fn read(line: &Wire, clock: &VirtualClock, start: Duration) -> Option<[u8; 5]> {
+		let mut pin = line.connect_open_drain_pin();
+		pin.set_high().unwrap();
+		clock.advance(start);
+		pin.set_low().unwrap();
+		let mut highs = vec![];
+		let (mut high, mut since) = (true, clock.now());
+		let timeout = clock.now() + Duration::from_millis(10);
+		// the response delay, the presence pulse and 40 bits
+		while highs.len() < 42 {
+			if clock.now() > timeout {
+				return None;
+			}
+			clock.advance(Duration::from_micros(1));
+			let level = line.get_state() == WireState::High;
+			if high && !level {
+				highs.push(clock.now() - since);
+			} else if !high && level {
+				since = clock.now();
+			}
+			high = level;
+		}
+		let mut frame = [0; 5];
+		for (i, high) in highs[2..].iter().enumerate() {
+			frame[i / 8] = (frame[i / 8] << 1) | (*high > Duration::from_micros(50)) as u8;
+		}
+		clock.run();
+		Some(frame)
+	}
+
+	#[test]
+	fn dht22() {
+		let clock = VirtualClock::new();
+		let line = Wire::new_with_pull(WireState::High);
+		let sensor = Dht::new(&line, clock.clone(), Model::Dht22);
+		let start = Duration::from_millis(1);
+		assert_eq!(
+			Some([0x01, 0xf4, 0x00, 0xfa, 0xef]),
+			read(&line, &clock, start)
+		);
+		sensor.set_humidity(65.2);
+		sensor.set_temperature(-10.1);
+		assert_eq!(
+			Some([0x02, 0x8c, 0x80, 0x65, 0x73]),
+			read(&line, &clock, start)
+		);
+		assert_eq!(2, sensor.reads());
+		assert_eq!(WireState::High, line.get_state());
+	}
+
+	#[test]
+	fn dht11() {
+		let clock = VirtualClock::new();
+		let line = Wire::new_with_pull(WireState::High);
+		let sensor = Dht::new(&line, clock.clone(), Model::Dht11);
+		sensor.set_humidity(40.0);
+		sensor.set_temperature(23.4);
+		// too short for a DHT11
+		assert_eq!(None, read(&line, &clock, Duration::from_millis(1)));
+		let frame = read(&line, &clock, Duration::from_millis(18));
+		assert_eq!(Some([40, 0, 23, 4, 67]), frame);
+		assert_eq!(1, sensor.reads());
+		sensor.set_temperature(-5.3);
+		assert_eq!([40, 0, 5, 0x83, 0xb0], sensor.frame());
+		sensor.set_temperature(300.0);
+		assert_eq!([40, 0, 255, 9, 0x30], sensor.frame());
+	}
+
+	#[test]
+	fn faults() {
+		let clock = VirtualClock::new();
+		let line = Wire::new_with_pull(WireState::High);
+		let sensor = Dht::new(&line, clock.clone(), Model::Dht22);
+		let start = Duration::from_millis(2);
+		sensor.set_fault(Some(Fault::NoResponse));
+		assert_eq!(None, read(&line, &clock, start));
+		sensor.set_fault(Some(Fault::BadChecksum));
+		assert_eq!(
+			Some([0x01, 0xf4, 0x00, 0xfa, 0xf0]),
+			read(&line, &clock, start)
+		);
+		sensor.set_fault(None);
+		assert_eq!(
+			Some([0x01, 0xf4, 0x00, 0xfa, 0xef]),
+			read(&line, &clock, start)
+		);
+		assert_eq!(2, sensor.reads());
+	}
+}
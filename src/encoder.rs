@@ -0,0 +1,400 @@
+//! Simulated rotary encoders
+//!
+//! A quadrature encoder has two channels A and B, which go through the Gray
+//! code sequence low/low, high/low, high/high, low/high when turned clockwise
+//! and through the reverse sequence when turned counterclockwise, so A leads B
+//! in the clockwise direction. Every change of either channel is a step.
+//! Mechanical encoders have detents where the knob rests, one every one, two
+//! or four steps depending on the type, and the contacts bounce on every
+//! change. Some encoders also have a push button, optical ones often an index
+//! channel that is high once per revolution.
+//!
+//! [`RotaryEncoder`] drives the channels on its own pins, scheduling the steps
+//! of rotation commands on a [`VirtualClock`]. For a fixed sequence of moves,
+//! [`stimulus::quadrature`](`crate::stimulus::quadrature`) returns the same
+//! signals as a waveform.
+//!
+//! # Examples
+//!
+//! ```
+//! use embedded_hal_sync_pins::clock::VirtualClock;
+//! use embedded_hal_sync_pins::encoder::{Config, RotaryEncoder};
+//! use embedded_hal_sync_pins::wire::{Wire, WireState};
+//!
+//! let clock = VirtualClock::new();
+//! let (a, b) = (Wire::new(), Wire::new());
+//! let mut encoder = RotaryEncoder::new(
+//!     a.connect_push_pull_pin(),
+//!     b.connect_push_pull_pin(),
+//!     clock.clone(),
+//!     Config::default(),
+//! );
+//! encoder.rotate(2);
+//! encoder.rotate(-1);
+//! encoder.flush();
+//! assert_eq!(4, encoder.position());
+//! assert_eq!((WireState::Low, WireState::Low), (a.get_state(), b.get_state()));
+//! ```
+
+use crate::clock::VirtualClock;
+use crate::sync::{Arc, Mutex};
+use crate::wire::PushPullPin;
+use core::convert::Infallible;
+use embedded_hal::digital::OutputPin;
+use std::time::Duration;
+
+/// Levels of A and B in the four states of a cycle.
+const STATES: [(bool, bool); 4] = [(false, false), (true, false), (true, true), (false, true)];
+
+/// Mechanics and speed of an encoder.
+///
+/// The default is an encoder with 20 detents per revolution, four steps per
+/// detent and clean edges, turned at 1 ms per step.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+	/// Steps from one detent to the next, 1, 2 or 4.
+	pub steps_per_detent: u32,
+	/// Detents in a full revolution, setting the period of the index channel.
+	pub detents_per_revolution: u32,
+	/// Time of a single step while turning.
+	pub step: Duration,
+	/// Time the contacts bounce after every change, less than a step. A
+	/// bouncing channel falls back to its previous level after half of this
+	/// time and settles at its new level at the end of it.
+	pub bounce: Duration,
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Config {
+			steps_per_detent: 4,
+			detents_per_revolution: 20,
+			step: Duration::from_millis(1),
+			bounce: Duration::ZERO,
+		}
+	}
+}
+
+impl Config {
+	fn validate(&self) {
+		assert!(
+			matches!(self.steps_per_detent, 1 | 2 | 4),
+			"invalid steps per detent: {}",
+			self.steps_per_detent
+		);
+		assert!(
+			self.detents_per_revolution > 0,
+			"invalid detents per revolution: {}",
+			self.detents_per_revolution
+		);
+		assert!(
+			self.bounce < self.step,
+			"bounce too long: {:?}",
+			self.bounce
+		);
+	}
+}
+
+type Pin = Arc<Mutex<PushPullPin>>;
+
+/// A rotary encoder driving its channels onto wires.
+///
+/// Rotation commands are queued: each one starts once the previous ones have
+/// finished, or immediately if the encoder is at rest. All steps are scheduled
+/// on the clock when the command is issued, so [`position`](`Self::position`)
+/// already includes them. The channels start at the first state of the
+/// sequence, both low, at position zero. The index channel is high while the
+/// position is a multiple of a revolution and the button is active low.
+#[derive(Debug)]
+pub struct RotaryEncoder {
+	a: Pin,
+	b: Pin,
+	index: Option<Pin>,
+	button: Option<Pin>,
+	clock: VirtualClock,
+	config: Config,
+	position: i64,
+	busy_until: Duration,
+}
+
+impl RotaryEncoder {
+	/// Creates a new encoder at rest and drives its channels.
+	///
+	/// # Panics
+	///
+	/// Panics if the configuration is invalid.
+	pub fn new(
+		mut a: PushPullPin,
+		mut b: PushPullPin,
+		clock: VirtualClock,
+		config: Config,
+	) -> Self {
+		config.validate();
+		let _ = a.set_low();
+		let _ = b.set_low();
+		RotaryEncoder {
+			a: Arc::new(Mutex::new(a)),
+			b: Arc::new(Mutex::new(b)),
+			index: None,
+			button: None,
+			busy_until: clock.now(),
+			clock,
+			config,
+			position: 0,
+		}
+	}
+
+	/// Adds an index channel, driven high at the current position.
+	pub fn with_index(mut self, mut index: PushPullPin) -> Self {
+		let _ = index.set_state(self.at_index().into());
+		self.index = Some(Arc::new(Mutex::new(index)));
+		self
+	}
+
+	/// Adds a push button, driven high as it is released.
+	pub fn with_button(mut self, mut button: PushPullPin) -> Self {
+		let _ = button.set_high();
+		self.button = Some(Arc::new(Mutex::new(button)));
+		self
+	}
+
+	pub fn config(&self) -> Config {
+		self.config
+	}
+
+	/// Sets the time of a single step for the following commands, i.e. the
+	/// speed at which the knob is turned.
+	///
+	/// # Panics
+	///
+	/// Panics if the bounce time is not less than `step`.
+	pub fn set_step(&mut self, step: Duration) {
+		let config = Config {
+			step,
+			..self.config
+		};
+		config.validate();
+		self.config = config;
+	}
+
+	/// Returns the position in steps after all queued commands, positive in
+	/// the clockwise direction.
+	pub fn position(&self) -> i64 {
+		self.position
+	}
+
+	fn at_index(&self) -> bool {
+		let revolution = self.config.steps_per_detent * self.config.detents_per_revolution;
+		self.position.rem_euclid(revolution as i64) == 0
+	}
+
+	/// Schedules `pin` to change to `high` at `at`.
+	fn schedule(&self, pin: &Pin, at: Duration, high: bool) {
+		let pin = pin.clone();
+		self.clock.schedule_at(at, move || {
+			let _ = pin.lock().unwrap().set_state(high.into());
+		});
+	}
+
+	/// Schedules the bounce of a contact that changed to `high` at `at`.
+	fn bounce(&self, pin: &Pin, at: Duration, high: bool) {
+		let bounce = self.config.bounce;
+		if !bounce.is_zero() {
+			self.schedule(pin, at + bounce / 2, !high);
+			self.schedule(pin, at + bounce, high);
+		}
+	}
+
+	/// Queues a rotation by `steps` single steps, clockwise for positive
+	/// values, which may leave the knob between detents.
+	pub fn step(&mut self, steps: i64) {
+		let mut at = self.busy_until.max(self.clock.now());
+		for _ in 0..steps.unsigned_abs() {
+			at += self.config.step;
+			let was_at_index = self.at_index();
+			let (a, _) = STATES[self.position.rem_euclid(4) as usize];
+			self.position += steps.signum();
+			let (next_a, next_b) = STATES[self.position.rem_euclid(4) as usize];
+			let (pin, high) = if a != next_a {
+				(&self.a, next_a)
+			} else {
+				(&self.b, next_b)
+			};
+			self.schedule(pin, at, high);
+			self.bounce(pin, at, high);
+			if let Some(index) = &self.index {
+				if self.at_index() != was_at_index {
+					self.schedule(index, at, self.at_index());
+				}
+			}
+		}
+		self.busy_until = at;
+	}
+
+	/// Queues a rotation by `detents` detents, clockwise for positive values.
+	pub fn rotate(&mut self, detents: i32) {
+		self.step(detents as i64 * self.config.steps_per_detent as i64);
+	}
+
+	/// Presses the push button now, with the configured bounce.
+	///
+	/// # Panics
+	///
+	/// Panics if the encoder has no button.
+	pub fn press(&mut self) {
+		self.set_button(false);
+	}
+
+	/// Releases the push button now, with the configured bounce.
+	///
+	/// # Panics
+	///
+	/// Panics if the encoder has no button.
+	pub fn release(&mut self) {
+		self.set_button(true);
+	}
+
+	fn set_button(&mut self, high: bool) {
+		let button = self.button.as_ref().expect("encoder without button");
+		let _ = button.lock().unwrap().set_state(high.into());
+		self.bounce(button, self.clock.now(), high);
+	}
+
+	/// Returns `Ok` once all queued steps have been driven and the contacts
+	/// have settled.
+	pub fn try_flush(&mut self) -> nb::Result<(), Infallible> {
+		if self.clock.now() >= self.busy_until + self.config.bounce {
+			Ok(())
+		} else {
+			Err(nb::Error::WouldBlock)
+		}
+	}
+
+	/// Advances the clock until all queued steps have been driven and the
+	/// contacts have settled.
+	pub fn flush(&mut self) {
+		self.clock.advance_to(self.busy_until + self.config.bounce);
+	}
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::*;
+	use crate::wire::{Wire, WireState};
+
+	#[derive(Debug, Default)]
+	struct Decoder {
+		state: (bool, bool),
+		count: i64,
+		edges: usize,
+	}
+
+	/// Counts the steps on A and B like an interrupt driven decoder.
+	fn decoder(a: &Wire, b: &Wire) -> Arc<Mutex<Decoder>> {
+		let decoder = Arc::new(Mutex::new(Decoder::default()));
+		for wire in [a, b] {
+			let (d, a, b) = (decoder.clone(), a.clone(), b.clone());
+			wire.on_change(move |_| {
+				let state = (
+					a.get_state() == WireState::High,
+					b.get_state() == WireState::High,
+				);
+				let index = |state| STATES.iter().position(|s| *s == state).unwrap() as i64;
+				let mut d = d.lock().unwrap();
+				d.count += match (index(state) - index(d.state)).rem_euclid(4) {
+					1 => 1,
+					3 => -1,
+					_ => panic!("invalid transition"),
+				};
+				d.state = state;
+				d.edges += 1;
+			});
+		}
+		decoder
+	}
+
+	fn encoder(a: &Wire, b: &Wire, clock: &VirtualClock, config: Config) -> RotaryEncoder {
+		let (pa, pb) = (a.connect_push_pull_pin(), b.connect_push_pull_pin());
+		RotaryEncoder::new(pa, pb, clock.clone(), config)
+	}
+
+	#[test]
+	fn rotate() {
+		let clock = VirtualClock::new();
+		let (a, b) = (Wire::new(), Wire::new());
+		let mut encoder = encoder(&a, &b, &clock, Config::default());
+		let decoder = decoder(&a, &b);
+		encoder.rotate(3);
+		encoder.set_step(Duration::from_micros(100));
+		encoder.rotate(-5);
+		assert_eq!(Err(nb::Error::WouldBlock), encoder.try_flush());
+		encoder.flush();
+		assert_eq!(Duration::from_micros(14_000), clock.now());
+		assert_eq!(Ok(()), encoder.try_flush());
+		assert_eq!(-8, encoder.position());
+		assert_eq!(-8, decoder.lock().unwrap().count);
+		encoder.step(-1);
+		encoder.flush();
+		assert_eq!(
+			(WireState::Low, WireState::High),
+			(a.get_state(), b.get_state())
+		);
+		assert_eq!(-9, decoder.lock().unwrap().count);
+	}
+
+	#[test]
+	fn bounce() {
+		let clock = VirtualClock::new();
+		let (a, b) = (Wire::new(), Wire::new());
+		let config = Config {
+			steps_per_detent: 2,
+			bounce: Duration::from_micros(200),
+			..Default::default()
+		};
+		let mut encoder = encoder(&a, &b, &clock, config);
+		let decoder = decoder(&a, &b);
+		encoder.rotate(2);
+		encoder.rotate(-1);
+		encoder.flush();
+		let decoder = decoder.lock().unwrap();
+		assert_eq!((2, 18), (decoder.count, decoder.edges));
+		assert_eq!(
+			(WireState::High, WireState::High),
+			(a.get_state(), b.get_state())
+		);
+	}
+
+	#[test]
+	fn index_and_button() {
+		let clock = VirtualClock::new();
+		let (a, b, index, button) = (Wire::new(), Wire::new(), Wire::new(), Wire::new());
+		let config = Config {
+			steps_per_detent: 1,
+			detents_per_revolution: 8,
+			bounce: Duration::from_micros(200),
+			..Default::default()
+		};
+		let mut encoder = encoder(&a, &b, &clock, config)
+			.with_index(index.connect_push_pull_pin())
+			.with_button(button.connect_push_pull_pin());
+		assert_eq!(WireState::High, index.get_state());
+		assert_eq!(WireState::High, button.get_state());
+		encoder.step(1);
+		encoder.flush();
+		assert_eq!(WireState::Low, index.get_state());
+		encoder.step(7);
+		encoder.flush();
+		assert_eq!(WireState::High, index.get_state());
+		encoder.step(-9);
+		encoder.flush();
+		assert_eq!(WireState::Low, index.get_state());
+		encoder.press();
+		assert_eq!(WireState::Low, button.get_state());
+		clock.advance(Duration::from_micros(100));
+		assert_eq!(WireState::High, button.get_state());
+		clock.advance(Duration::from_micros(100));
+		assert_eq!(WireState::Low, button.get_state());
+		encoder.release();
+		assert_eq!(WireState::High, button.get_state());
+	}
+}
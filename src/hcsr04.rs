@@ -0,0 +1,255 @@
+//! Simulated HC-SR04 ultrasonic rangefinders
+//!
+//! The host starts a measurement with a high pulse of at least 10 µs on the
+//! trigger line. After the falling edge the sensor sends a burst of eight
+//! 40 kHz cycles and then raises the echo line until the reflection comes back,
+//! so the echo pulse lasts as long as the sound needs to travel to the object
+//! and back, about 58 µs per cm. Without an object within 4 m the echo ends
+//! after 38 ms. Triggers are ignored while a measurement is running.
+//!
+//! # Examples
+//!
+//! ```
+//! use embedded_hal_sync_pins::clock::VirtualClock;
+//! use embedded_hal_sync_pins::hcsr04::HcSr04;
+//! use embedded_hal_sync_pins::wire::{Wire, WireState};
+//! use embedded_hal::delay::DelayUs;
+//! use embedded_hal::digital::OutputPin;
+//!
+//! let mut clock = VirtualClock::new();
+//! let (trigger, echo) = (Wire::new(), Wire::new());
+//! let sensor = HcSr04::new(&trigger, &echo, clock.clone());
+//! sensor.set_distance(0.5);
+//! let mut trigger = trigger.connect_push_pull_pin();
+//! trigger.set_high().unwrap();
+//! clock.delay_us(10);
+//! trigger.set_low().unwrap();
+//! // the burst
+//! clock.delay_us(250);
+//! assert_eq!(WireState::High, echo.get_state());
+//! // 2 × 0.5 m at 343 m/s
+//! clock.delay_us(2900);
+//! assert_eq!(WireState::Low, echo.get_state());
+//! ```
+
+use crate::clock::VirtualClock;
+use crate::net::PinBackend;
+use crate::sync::{Arc, Mutex};
+use crate::wire::{Wire, WireState};
+use std::fmt;
+use std::time::Duration;
+
+/// Minimum length of the trigger pulse.
+const TRIGGER: Duration = Duration::from_micros(10);
+/// Length of the ultrasonic burst, eight cycles at 40 kHz.
+const BURST: Duration = Duration::from_micros(200);
+/// Length of the echo pulse without an object in range.
+const NO_ECHO: Duration = Duration::from_millis(38);
+/// Maximum distance of a detected object in m.
+const RANGE: f32 = 4.0;
+/// Speed of sound in air at 20 °C in m/s.
+const SPEED_OF_SOUND: f64 = 343.0;
+
+type Distance = Box<dyn FnMut(Duration) -> f32 + Send>;
+
+struct Engine {
+	/// Distance of the object in m at the time of a measurement.
+	distance: Distance,
+	/// Start of the current trigger pulse.
+	rose_at: Option<Duration>,
+	/// End of the last measurement.
+	busy_until: Option<Duration>,
+	measurements: usize,
+}
+
+impl fmt::Debug for Engine {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Engine")
+			.field("rose_at", &self.rose_at)
+			.field("busy_until", &self.busy_until)
+			.field("measurements", &self.measurements)
+			.finish()
+	}
+}
+
+/// Returns the length of the echo pulse for an object at `distance` m.
+fn echo_width(distance: f32) -> Duration {
+	if (0.0..=RANGE).contains(&distance) {
+		Duration::from_secs_f64(2.0 * distance as f64 / SPEED_OF_SOUND)
+	} else {
+		NO_ECHO
+	}
+}
+
+/// A simulated HC-SR04 attached to a trigger and an echo wire.
+///
+/// The sensor drives the echo wire low while idle. The distance to the object
+/// is sampled when the burst has been sent, either a fixed value set with
+/// [`set_distance`](`Self::set_distance`) or the result of a function of the
+/// time set with [`set_distance_with`](`Self::set_distance_with`). It starts
+/// at 1 m.
+///
+/// See the [module documentation](`self`) for an example.
+#[derive(Clone, Debug)]
+pub struct HcSr04 {
+	engine: Arc<Mutex<Engine>>,
+}
+
+impl HcSr04 {
+	/// Attaches a new sensor to the given wires.
+	pub fn new(trigger: &Wire, echo: &Wire, clock: VirtualClock) -> Self {
+		let engine = Arc::new(Mutex::new(Engine {
+			distance: Box::new(|_| 1.0),
+			rose_at: None,
+			busy_until: None,
+			measurements: 0,
+		}));
+		let output = Arc::new(echo.connect_driver());
+		output.drive(WireState::Low);
+		let e = engine.clone();
+		trigger.on_change(move |state| {
+			let now = clock.now();
+			let mut engine = e.lock().unwrap();
+			if engine.busy_until.is_some_and(|until| now < until) {
+				return;
+			}
+			if state == WireState::High {
+				engine.rose_at = Some(now);
+				return;
+			}
+			match engine.rose_at.take() {
+				Some(rose_at) if now - rose_at >= TRIGGER => {}
+				_ => return,
+			}
+			let start = now + BURST;
+			engine.busy_until = Some(start);
+			engine.measurements += 1;
+			let (e, output, c) = (e.clone(), output.clone(), clock.clone());
+			clock.schedule_at(start, move || {
+				let width = {
+					let mut engine = e.lock().unwrap();
+					let width = echo_width((engine.distance)(start));
+					engine.busy_until = Some(start + width);
+					width
+				};
+				output.drive(WireState::High);
+				c.schedule_in(width, move || output.drive(WireState::Low));
+			});
+		});
+		HcSr04 { engine }
+	}
+
+	/// Sets a fixed distance to the object in m.
+	///
+	/// Distances beyond 4 m or below zero are out of range.
+	pub fn set_distance(&self, meters: f32) {
+		self.set_distance_with(move |_| meters);
+	}
+
+	/// Sets the distance to the object in m as a function of the time of the
+	/// measurement, e.g. to simulate a moving object.
+	pub fn set_distance_with<F>(&self, distance: F)
+	where
+		F: FnMut(Duration) -> f32 + Send + 'static,
+	{
+		self.engine.lock().unwrap().distance = Box::new(distance);
+	}
+
+	/// Returns the number of measurements started.
+	pub fn measurements(&self) -> usize {
+		self.engine.lock().unwrap().measurements
+	}
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::*;
+	use crate::wire::PushPullPin;
+	use embedded_hal::digital::OutputPin;
+
+	/// Triggers a measurement and returns the delay and the width of the echo
+	/// pulse.
+	fn measure(
+		trigger: &mut PushPullPin,
+		echo: &Wire,
+		clock: &VirtualClock,
+		pulse: Duration,
+	) -> Option<(Duration, Duration)> {
+		let edges = Arc::new(Mutex::new(vec![]));
+		let e = edges.clone();
+		let c = clock.clone();
+		echo.on_change(move |_| e.lock().unwrap().push(c.now()));
+		let fell_at = clock.now() + pulse;
+		trigger.set_high().unwrap();
+		clock.advance(pulse);
+		trigger.set_low().unwrap();
+		clock.advance(Duration::from_millis(60));
+		let edges = edges.lock().unwrap();
+		match edges[..] {
+			[] => None,
+			[rose, fell] => Some((rose - fell_at, fell - rose)),
+			_ => panic!("unexpected edges: {:?}", edges),
+		}
+	}
+
+	#[test]
+	fn distance() {
+		let clock = VirtualClock::new();
+		let (trigger, echo) = (Wire::new(), Wire::new());
+		let sensor = HcSr04::new(&trigger, &echo, clock.clone());
+		let mut trigger = trigger.connect_push_pull_pin();
+		assert_eq!(WireState::Low, echo.get_state());
+		let measured = measure(&mut trigger, &echo, &clock, TRIGGER);
+		assert_eq!(Some((BURST, Duration::from_nanos(5_830_904))), measured);
+		sensor.set_distance(0.02);
+		let measured = measure(&mut trigger, &echo, &clock, TRIGGER);
+		assert_eq!(Some((BURST, Duration::from_nanos(116_618))), measured);
+		// out of range
+		sensor.set_distance(4.5);
+		let measured = measure(&mut trigger, &echo, &clock, TRIGGER);
+		assert_eq!(Some((BURST, NO_ECHO)), measured);
+		// too short
+		let measured = measure(&mut trigger, &echo, &clock, Duration::from_micros(9));
+		assert_eq!(None, measured);
+		assert_eq!(3, sensor.measurements());
+	}
+
+	#[test]
+	fn moving() {
+		let clock = VirtualClock::new();
+		let (trigger, echo) = (Wire::new(), Wire::new());
+		let sensor = HcSr04::new(&trigger, &echo, clock.clone());
+		// approaching at 1 m/s
+		sensor.set_distance_with(|t| 2.0 - t.as_secs_f32());
+		let mut trigger = trigger.connect_push_pull_pin();
+		let mut widths = vec![];
+		for _ in 0..3 {
+			clock.advance(Duration::from_millis(440));
+			let (_, width) = measure(&mut trigger, &echo, &clock, TRIGGER).unwrap();
+			widths.push((width.as_secs_f64() * SPEED_OF_SOUND * 50.0).round() as u32);
+		}
+		// in cm
+		assert_eq!(vec![156, 106, 56], widths);
+	}
+
+	#[test]
+	fn busy() {
+		let clock = VirtualClock::new();
+		let (trigger, echo) = (Wire::new(), Wire::new());
+		let sensor = HcSr04::new(&trigger, &echo, clock.clone());
+		let mut trigger = trigger.connect_push_pull_pin();
+		trigger.set_high().unwrap();
+		clock.advance(TRIGGER);
+		trigger.set_low().unwrap();
+		// retriggering during the burst and the echo
+		for delay in [100, 1000] {
+			clock.advance(Duration::from_micros(delay));
+			trigger.set_high().unwrap();
+			clock.advance(TRIGGER);
+			trigger.set_low().unwrap();
+		}
+		clock.run();
+		assert_eq!(1, sensor.measurements());
+		assert_eq!(WireState::Low, echo.get_state());
+	}
+}
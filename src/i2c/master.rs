@@ -0,0 +1,793 @@
+use super::{Address, Direction};
+use crate::clock::half_period_us;
+use crate::wire::OpenDrainPin;
+use embedded_hal::delay::DelayUs;
+use embedded_hal::digital::{InputPin, OutputPin};
+use embedded_hal::i2c::{
+	ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation, SevenBitAddress, TenBitAddress,
+};
+
+enum Op<'a, B> {
+	Read(&'a mut [u8]),
+	Write(B),
+}
+
+/// A bit-banged I2C master.
+///
+/// The master drives SDA and SCL through two [`OpenDrainPin`]s, which should be
+/// connected to wires that are pulled high. Note that an [`OpenDrainPin`] pulls
+/// its wire low when it is set high, and releases it when it is set low.
+///
+/// All timing is done using the given delay, which usually is a
+/// [`VirtualClock`](`crate::clock::VirtualClock`). Slaves may stretch the clock
+/// by holding SCL low, the master waits for SCL to be released for up to
+/// [`stretch_timeout_us`](`I2cMaster::set_stretch_timeout_us`) microseconds.
+///
+/// Several masters can share a bus. Their clocks are synchronized by the
+/// wired-AND of SCL: the low period lasts as long as the slowest master holds
+/// SCL low, and a master ends its high period as soon as another one pulls SCL
+/// low. A master that reads back a low SDA where it sent a high bit has lost
+/// the arbitration, releases the bus and returns
+/// [`ErrorKind::ArbitrationLoss`]. Before a start condition, a master waits
+/// until the bus has been idle for a full SCL period. To run masters
+/// concurrently on a [`VirtualClock`](`crate::clock::VirtualClock`), give each
+/// of them its own [`Participant`](`crate::clock::Participant`) as delay.
+///
+/// # Examples
+///
+/// ```
+/// use embedded_hal_sync_pins::clock::VirtualClock;
+/// use embedded_hal_sync_pins::i2c::I2cMaster;
+/// use embedded_hal_sync_pins::wire::{Wire, WireState};
+/// use embedded_hal::i2c::{ErrorKind, I2c, NoAcknowledgeSource};
+///
+/// let sda = Wire::new_with_pull(WireState::High);
+/// let scl = Wire::new_with_pull(WireState::High);
+/// let mut i2c = I2cMaster::new(
+///     sda.connect_open_drain_pin(),
+///     scl.connect_open_drain_pin(),
+///     VirtualClock::new(),
+/// );
+/// // there is no device on the bus
+/// assert_eq!(
+///     Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address)),
+///     i2c.write(0x50u8, &[0x00]),
+/// );
+/// ```
+pub struct I2cMaster<D> {
+	sda: OpenDrainPin,
+	scl: OpenDrainPin,
+	delay: D,
+	half_period_us: u32,
+	stretch_timeout_us: u32,
+}
+
+impl<D: DelayUs> I2cMaster<D> {
+	/// Creates a new master running at 100 kHz.
+	pub fn new(sda: OpenDrainPin, scl: OpenDrainPin, delay: D) -> Self {
+		let mut master = I2cMaster {
+			sda,
+			scl,
+			delay,
+			half_period_us: 5,
+			stretch_timeout_us: 25_000,
+		};
+		master.release_sda();
+		let _ = master.scl.set_low();
+		master
+	}
+
+	/// Sets the SCL frequency.
+	///
+	/// As the delay has a resolution of one microsecond, the frequency is
+	/// rounded down to the next achievable frequency, but at most 500 kHz.
+	pub fn set_frequency(&mut self, hz: u32) {
+		self.half_period_us = half_period_us(hz);
+	}
+
+	/// Sets the time a slave is allowed to stretch the clock.
+	///
+	/// If SCL is still low after this time, the transaction is aborted with
+	/// [`ErrorKind::Bus`].
+	pub fn set_stretch_timeout_us(&mut self, us: u32) {
+		self.stretch_timeout_us = us;
+	}
+
+	/// Releases the pins and the delay.
+	pub fn free(self) -> (OpenDrainPin, OpenDrainPin, D) {
+		(self.sda, self.scl, self.delay)
+	}
+
+	fn release_sda(&mut self) {
+		let _ = self.sda.set_low();
+	}
+
+	fn pull_sda(&mut self) {
+		let _ = self.sda.set_high();
+	}
+
+	fn pull_scl(&mut self) {
+		let _ = self.scl.set_high();
+	}
+
+	/// Releases both lines after an aborted transaction.
+	fn release_bus(&mut self) {
+		self.release_sda();
+		let _ = self.scl.set_low();
+	}
+
+	fn sda(&self) -> bool {
+		self.sda.is_high().unwrap_or(false)
+	}
+
+	fn scl(&self) -> bool {
+		self.scl.is_high().unwrap_or(false)
+	}
+
+	fn wait(&mut self, us: u32) -> Result<(), ErrorKind> {
+		self.delay.delay_us(us).map_err(|_| ErrorKind::Other)
+	}
+
+	fn half_period(&mut self) -> Result<(), ErrorKind> {
+		self.wait(self.half_period_us)
+	}
+
+	/// Waits for the high half of an SCL period, which ends early if another
+	/// master pulls SCL low.
+	fn high_period(&mut self) -> Result<(), ErrorKind> {
+		for _ in 0..self.half_period_us {
+			if !self.scl() {
+				break;
+			}
+			self.wait(1)?;
+		}
+		Ok(())
+	}
+
+	/// Releases SCL and waits until no slave stretches the clock anymore and
+	/// no other master holds it low.
+	fn release_scl(&mut self) -> Result<(), ErrorKind> {
+		let _ = self.scl.set_low();
+		let mut waited = 0;
+		while !self.scl() {
+			if waited >= self.stretch_timeout_us {
+				return Err(ErrorKind::Bus);
+			}
+			self.wait(1)?;
+			waited += 1;
+		}
+		Ok(())
+	}
+
+	fn is_idle(&self) -> bool {
+		self.sda() && self.scl()
+	}
+
+	/// Waits until the bus is idle. If it is busy, e.g. because another master
+	/// runs a transaction, both lines have to be high for a full SCL period.
+	fn wait_idle(&mut self) -> Result<(), ErrorKind> {
+		if self.is_idle() {
+			// let other masters starting at the same time see the idle bus, so
+			// they all start and arbitrate
+			return self.wait(0);
+		}
+		let mut waited = 0;
+		let mut idle = 0;
+		while idle < 2 * self.half_period_us {
+			if waited >= self.stretch_timeout_us {
+				return Err(ErrorKind::Bus);
+			}
+			self.wait(1)?;
+			waited += 1;
+			idle = if self.is_idle() { idle + 1 } else { 0 };
+		}
+		Ok(())
+	}
+
+	fn start(&mut self) -> Result<(), ErrorKind> {
+		self.wait_idle()?;
+		self.pull_sda();
+		self.half_period()?;
+		self.pull_scl();
+		self.half_period()
+	}
+
+	fn repeated_start(&mut self) -> Result<(), ErrorKind> {
+		self.release_sda();
+		self.half_period()?;
+		self.release_scl()?;
+		self.half_period()?;
+		self.start()
+	}
+
+	fn stop(&mut self) -> Result<(), ErrorKind> {
+		self.pull_sda();
+		self.half_period()?;
+		self.release_scl()?;
+		self.half_period()?;
+		self.release_sda();
+		self.half_period()
+	}
+
+	fn write_bit(&mut self, bit: bool) -> Result<(), ErrorKind> {
+		if bit {
+			self.release_sda();
+		} else {
+			self.pull_sda();
+		}
+		self.half_period()?;
+		self.release_scl()?;
+		if bit && !self.sda() {
+			// SCL and SDA are both released
+			return Err(ErrorKind::ArbitrationLoss);
+		}
+		self.high_period()?;
+		self.pull_scl();
+		Ok(())
+	}
+
+	fn read_bit(&mut self) -> Result<bool, ErrorKind> {
+		self.release_sda();
+		self.half_period()?;
+		self.release_scl()?;
+		let bit = self.sda();
+		self.high_period()?;
+		self.pull_scl();
+		Ok(bit)
+	}
+
+	/// Writes a byte and returns `true` if it was acknowledged.
+	fn write_byte(&mut self, byte: u8) -> Result<bool, ErrorKind> {
+		for i in (0..8).rev() {
+			self.write_bit(byte & (1 << i) != 0)?;
+		}
+		Ok(!self.read_bit()?)
+	}
+
+	fn read_byte(&mut self, ack: bool) -> Result<u8, ErrorKind> {
+		let mut byte = 0;
+		for _ in 0..8 {
+			byte = (byte << 1) | self.read_bit()? as u8;
+		}
+		self.write_bit(!ack)?;
+		Ok(byte)
+	}
+
+	fn write_address_byte(&mut self, byte: u8) -> Result<(), ErrorKind> {
+		if self.write_byte(byte)? {
+			Ok(())
+		} else {
+			Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address))
+		}
+	}
+
+	/// Sends a (repeated) start condition followed by the address.
+	fn address(
+		&mut self,
+		address: Address,
+		direction: Direction,
+		previous: Option<Direction>,
+	) -> Result<(), ErrorKind> {
+		if previous.is_some() {
+			self.repeated_start()?;
+		} else {
+			self.start()?;
+		}
+		let read = (direction == Direction::Read) as u8;
+		match address {
+			Address::Seven(address) => self.write_address_byte((address << 1) | read),
+			Address::Ten(address) => {
+				let header = Address::ten_bit_header(address);
+				if direction == Direction::Read && previous == Some(Direction::Write) {
+					// the slave is still addressed from the previous write
+					return self.write_address_byte(header | 1);
+				}
+				self.write_address_byte(header)?;
+				self.write_address_byte(address as u8)?;
+				if direction == Direction::Read {
+					self.repeated_start()?;
+					self.write_address_byte(header | 1)?;
+				}
+				Ok(())
+			}
+		}
+	}
+
+	fn execute<'a, B, O>(&mut self, address: Address, operations: O) -> Result<(), ErrorKind>
+	where
+		B: Iterator<Item = u8>,
+		O: IntoIterator<Item = Op<'a, B>>,
+	{
+		let mut operations = operations.into_iter().peekable();
+		let mut previous = None;
+		while let Some(op) = operations.next() {
+			match op {
+				Op::Write(bytes) => {
+					if previous != Some(Direction::Write) {
+						self.address(address, Direction::Write, previous)?;
+					}
+					for byte in bytes {
+						if !self.write_byte(byte)? {
+							return Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Data));
+						}
+					}
+					previous = Some(Direction::Write);
+				}
+				Op::Read(buffer) => {
+					if previous != Some(Direction::Read) {
+						self.address(address, Direction::Read, previous)?;
+					}
+					// the last byte before a repeated start or stop is not acknowledged
+					let last = !matches!(operations.peek(), Some(Op::Read(_)));
+					let len = buffer.len();
+					for (i, byte) in buffer.iter_mut().enumerate() {
+						*byte = self.read_byte(!(last && i + 1 == len))?;
+					}
+					previous = Some(Direction::Read);
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Runs a transaction and always finishes it with a stop condition.
+	///
+	/// Invalid addresses are rejected with [`ErrorKind::Other`] and empty
+	/// transactions succeed, both without touching the bus.
+	fn transact<'a, B, O>(&mut self, address: Address, operations: O) -> Result<(), ErrorKind>
+	where
+		B: Iterator<Item = u8>,
+		O: IntoIterator<Item = Op<'a, B>>,
+	{
+		if !address.is_valid() {
+			return Err(ErrorKind::Other);
+		}
+		let mut operations = operations.into_iter().peekable();
+		if operations.peek().is_none() {
+			// a stop condition without a start would be a bus violation
+			return Ok(());
+		}
+		let mut result = self.execute(address, operations);
+		if !matches!(result, Err(ErrorKind::Bus | ErrorKind::ArbitrationLoss)) {
+			let stop = self.stop();
+			result = result.and(stop);
+		}
+		if let Err(ErrorKind::Bus | ErrorKind::ArbitrationLoss) = result {
+			// the bus is stuck or owned by another master, so there is no way
+			// to send a stop condition, but the lines must not stay pulled low
+			self.release_bus();
+		}
+		result
+	}
+}
+
+impl<D> ErrorType for I2cMaster<D> {
+	type Error = ErrorKind;
+}
+
+macro_rules! impl_i2c {
+	($address:ty, $variant:ident) => {
+		impl<D: DelayUs> I2c<$address> for I2cMaster<D> {
+			fn read(&mut self, address: $address, buffer: &mut [u8]) -> Result<(), Self::Error> {
+				self.transact(
+					Address::$variant(address),
+					[Op::<core::iter::Empty<u8>>::Read(buffer)],
+				)
+			}
+
+			fn write(&mut self, address: $address, bytes: &[u8]) -> Result<(), Self::Error> {
+				self.transact(
+					Address::$variant(address),
+					[Op::Write(bytes.iter().copied())],
+				)
+			}
+
+			fn write_iter<B>(&mut self, address: $address, bytes: B) -> Result<(), Self::Error>
+			where
+				B: IntoIterator<Item = u8>,
+			{
+				self.transact(Address::$variant(address), [Op::Write(bytes.into_iter())])
+			}
+
+			fn write_read(
+				&mut self,
+				address: $address,
+				bytes: &[u8],
+				buffer: &mut [u8],
+			) -> Result<(), Self::Error> {
+				self.transact(
+					Address::$variant(address),
+					[Op::Write(bytes.iter().copied()), Op::Read(buffer)],
+				)
+			}
+
+			fn write_iter_read<B>(
+				&mut self,
+				address: $address,
+				bytes: B,
+				buffer: &mut [u8],
+			) -> Result<(), Self::Error>
+			where
+				B: IntoIterator<Item = u8>,
+			{
+				self.transact(
+					Address::$variant(address),
+					[Op::Write(bytes.into_iter()), Op::Read(buffer)],
+				)
+			}
+
+			fn transaction<'a>(
+				&mut self,
+				address: $address,
+				operations: &mut [Operation<'a>],
+			) -> Result<(), Self::Error> {
+				let operations = operations.iter_mut().map(|op| match op {
+					Operation::Read(buffer) => Op::Read(&mut **buffer),
+					Operation::Write(bytes) => Op::Write(bytes.iter().copied()),
+				});
+				self.transact(Address::$variant(address), operations)
+			}
+
+			fn transaction_iter<'a, O>(
+				&mut self,
+				address: $address,
+				operations: O,
+			) -> Result<(), Self::Error>
+			where
+				O: IntoIterator<Item = Operation<'a>>,
+			{
+				let operations = operations.into_iter().map(|op| match op {
+					Operation::Read(buffer) => Op::Read(buffer),
+					Operation::Write(bytes) => Op::Write(bytes.iter().copied()),
+				});
+				self.transact(Address::$variant(address), operations)
+			}
+		}
+	};
+}
+
+impl_i2c!(SevenBitAddress, Seven);
+impl_i2c!(TenBitAddress, Ten);
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::*;
+	use crate::clock::{Participant, VirtualClock};
+	use crate::i2c::{I2cMonitor, I2cTarget, RegisterMap, Transaction, Transfer};
+	use crate::wire::{Wire, WireState};
+	use std::sync::{Arc, Mutex};
+	use std::thread;
+	use std::time::Duration;
+	use WireState::*;
+
+	fn bus() -> (Wire, Wire, I2cMaster<VirtualClock>, VirtualClock) {
+		let sda = Wire::new_with_pull(High);
+		let scl = Wire::new_with_pull(High);
+		let clock = VirtualClock::new();
+		let master = I2cMaster::new(
+			sda.connect_open_drain_pin(),
+			scl.connect_open_drain_pin(),
+			clock.clone(),
+		);
+		(sda, scl, master, clock)
+	}
+
+	/// Records bus conditions and bits as a string, e.g. `S101000000P`.
+	///
+	/// A bit is sampled on the rising edge of SCL and recorded on the falling
+	/// edge, unless a start or stop condition occurred in between.
+	fn sniff(sda: &Wire, scl: &Wire) -> Arc<Mutex<String>> {
+		let log = Arc::new(Mutex::new(String::new()));
+		let pending = Arc::new(Mutex::new(None));
+		let (l, p, s) = (log.clone(), pending.clone(), scl.clone());
+		sda.on_change(move |state| {
+			if s.get_state() == High {
+				*p.lock().unwrap() = None;
+				l.lock().unwrap().push(if state == Low { 'S' } else { 'P' });
+			}
+		});
+		let (l, d) = (log.clone(), sda.clone());
+		scl.on_change(move |state| {
+			let mut pending = pending.lock().unwrap();
+			if state == High {
+				*pending = Some(if d.get_state() == High { '1' } else { '0' });
+			} else if let Some(bit) = pending.take() {
+				l.lock().unwrap().push(bit);
+			}
+		});
+		log
+	}
+
+	#[derive(Default)]
+	struct Responder {
+		clocks: usize,
+		read: bool,
+	}
+
+	/// A device acknowledging its address and, if `ack_data` is set, all data
+	/// written to it. Reads return `0xff`.
+	fn responder(sda: &Wire, scl: &Wire, ack_data: bool) {
+		let responder = Arc::new(Mutex::new(Responder::default()));
+		let (r, s) = (responder.clone(), scl.clone());
+		sda.on_change(move |state| {
+			if state == Low && s.get_state() == High {
+				*r.lock().unwrap() = Responder::default();
+			}
+		});
+		let d = sda.clone();
+		let mut pin = sda.connect_open_drain_pin();
+		scl.on_change(move |state| {
+			let mut r = responder.lock().unwrap();
+			if state == High {
+				if r.clocks == 8 {
+					r.read = d.get_state() == High;
+				}
+				return;
+			}
+			// the first falling edge belongs to the start condition
+			r.clocks += 1;
+			if r.clocks % 9 == 0 && (r.clocks == 9 || (ack_data && !r.read)) {
+				pin.set_high().unwrap();
+			} else if r.clocks % 9 == 1 {
+				pin.set_low().unwrap();
+			}
+		});
+	}
+
+	#[test]
+	fn write() {
+		let (sda, scl, mut i2c, _) = bus();
+		let log = sniff(&sda, &scl);
+		responder(&sda, &scl, true);
+		assert_eq!(Ok(()), i2c.write(0x50u8, &[0xa5]));
+		assert_eq!("S101000000101001010P", *log.lock().unwrap());
+		assert_eq!(High, sda.get_state());
+		assert_eq!(High, scl.get_state());
+	}
+
+	#[test]
+	fn frequency() {
+		let (sda, scl, mut i2c, clock) = bus();
+		let rising = Arc::new(Mutex::new(vec![]));
+		let (r, c) = (rising.clone(), clock.clone());
+		scl.on_change(move |state| {
+			if state == High {
+				r.lock().unwrap().push(c.now());
+			}
+		});
+		responder(&sda, &scl, true);
+		// 2 µs half periods, as 1 µs would be too fast
+		i2c.set_frequency(300_000);
+		assert_eq!(Ok(()), i2c.write(0x50u8, &[0xa5]));
+		let rising = rising.lock().unwrap();
+		let period = rising.windows(2).map(|w| w[1] - w[0]).min().unwrap();
+		assert_eq!(Duration::from_micros(4), period);
+	}
+
+	#[test]
+	fn empty_transaction() {
+		let (sda, scl, mut i2c, _) = bus();
+		let log = sniff(&sda, &scl);
+		assert_eq!(Ok(()), i2c.transaction(0x50u8, &mut []));
+		// an empty write still addresses the device
+		assert_eq!(
+			Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address)),
+			i2c.transaction(0x50u8, &mut [Operation::Write(&[])])
+		);
+		assert_eq!("S101000001P", *log.lock().unwrap());
+	}
+
+	#[test]
+	fn invalid_address() {
+		let (sda, scl, mut i2c, _) = bus();
+		let log = sniff(&sda, &scl);
+		responder(&sda, &scl, true);
+		assert_eq!(Err(ErrorKind::Other), i2c.write(0x80u8, &[0xa5]));
+		assert_eq!(Err(ErrorKind::Other), i2c.write(0x400u16, &[0xa5]));
+		assert_eq!("", *log.lock().unwrap());
+	}
+
+	#[test]
+	fn no_acknowledge() {
+		let (sda, scl, mut i2c, _) = bus();
+		let log = sniff(&sda, &scl);
+		assert_eq!(
+			Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address)),
+			i2c.write(0x50u8, &[0xa5])
+		);
+		assert_eq!("S101000001P", *log.lock().unwrap());
+		responder(&sda, &scl, false);
+		assert_eq!(
+			Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Data)),
+			i2c.write(0x50u8, &[0xa5])
+		);
+	}
+
+	#[test]
+	fn read() {
+		let (sda, scl, mut i2c, _) = bus();
+		let log = sniff(&sda, &scl);
+		responder(&sda, &scl, false);
+		let mut buffer = [0; 2];
+		assert_eq!(Ok(()), i2c.read(0x50u8, &mut buffer));
+		assert_eq!([0xff, 0xff], buffer);
+		// the master acknowledges all but the last byte
+		assert_eq!("S101000010111111110111111111P", *log.lock().unwrap());
+	}
+
+	#[test]
+	fn ten_bit_write_read() {
+		let (sda, scl, mut i2c, _) = bus();
+		let log = sniff(&sda, &scl);
+		responder(&sda, &scl, true);
+		let mut buffer = [0; 1];
+		assert_eq!(Ok(()), i2c.write_read(0x2a5u16, &[0x01], &mut buffer));
+		assert_eq!(
+			concat!(
+				"S111101000", // header, write
+				"101001010",  // lower address byte
+				"000000010",  // data
+				"S111101010", // header, read
+				"111111111P", // data, not acknowledged
+			),
+			*log.lock().unwrap()
+		);
+	}
+
+	#[test]
+	fn clock_stretching() {
+		let (sda, scl, mut i2c, clock) = bus();
+		responder(&sda, &scl, true);
+		// hold SCL low for 50us after every falling edge
+		let pin = Arc::new(Mutex::new(scl.connect_open_drain_pin()));
+		let c = clock.clone();
+		scl.on_change(move |state| {
+			if state == Low {
+				pin.lock().unwrap().set_high().unwrap();
+				let pin = pin.clone();
+				c.schedule_in(Duration::from_micros(50), move || {
+					pin.lock().unwrap().set_low().unwrap();
+				});
+			}
+		});
+		assert_eq!(Ok(()), i2c.write(0x50u8, &[0x00]));
+		assert!(clock.now() >= Duration::from_micros(18 * 50));
+	}
+
+	#[test]
+	fn stuck_clock() {
+		let (_, scl, mut i2c, clock) = bus();
+		i2c.set_stretch_timeout_us(100);
+		// never release SCL after the first falling edge
+		let mut pin = scl.connect_open_drain_pin();
+		scl.on_change(move |state| {
+			if state == Low {
+				pin.set_high().unwrap();
+			}
+		});
+		assert_eq!(Err(ErrorKind::Bus), i2c.write(0x50u8, &[0x00]));
+		assert!(clock.now() >= Duration::from_micros(100));
+		assert_eq!(Low, scl.get_state());
+		// a stuck bus can not be used to start a new transaction
+		assert_eq!(Err(ErrorKind::Bus), i2c.write(0x50u8, &[0x00]));
+	}
+
+	#[test]
+	fn stretch_timeout_releases_bus() {
+		let (sda, scl, mut i2c, clock) = bus();
+		responder(&sda, &scl, true);
+		i2c.set_stretch_timeout_us(100);
+		// stretch the first clock for 500 µs
+		let pin = Arc::new(Mutex::new(scl.connect_open_drain_pin()));
+		let (p, c) = (pin.clone(), clock.clone());
+		let mut stretched = false;
+		scl.on_change(move |state| {
+			if state == Low && !stretched {
+				stretched = true;
+				p.lock().unwrap().set_high().unwrap();
+				let p = p.clone();
+				c.schedule_in(Duration::from_micros(500), move || {
+					p.lock().unwrap().set_low().unwrap();
+				});
+			}
+		});
+		// the first address bit is a zero, so SDA is pulled low on the timeout
+		assert_eq!(Err(ErrorKind::Bus), i2c.write(0x20u8, &[0x00]));
+		clock.advance(Duration::from_micros(500));
+		assert_eq!(High, sda.get_state());
+		assert_eq!(High, scl.get_state());
+		assert_eq!(Ok(()), i2c.write(0x20u8, &[0x00]));
+	}
+
+	fn multi_master(
+		sda: &Wire,
+		scl: &Wire,
+		clock: &VirtualClock,
+		hz: u32,
+	) -> I2cMaster<Participant> {
+		let mut master = I2cMaster::new(
+			sda.connect_open_drain_pin(),
+			scl.connect_open_drain_pin(),
+			clock.participant(),
+		);
+		master.set_frequency(hz);
+		master
+	}
+
+	#[test]
+	fn arbitration() {
+		let (sda, scl, _, clock) = bus();
+		let monitor = I2cMonitor::new(&sda, &scl);
+		let target = I2cTarget::new(&sda, &scl, 0x50, RegisterMap::new(4));
+		let mut a = multi_master(&sda, &scl, &clock, 100_000);
+		let mut b = multi_master(&sda, &scl, &clock, 100_000);
+		// both masters write to the same register, a loses at the third bit
+		// of 0x10, where b sends a low
+		let a = thread::spawn(move || {
+			let mut losses = 0;
+			loop {
+				match a.write(0x50u8, &[0x01, 0x10]) {
+					Err(ErrorKind::ArbitrationLoss) => losses += 1,
+					result => return (result, losses),
+				}
+			}
+		});
+		let b = thread::spawn(move || b.write(0x50u8, &[0x01, 0x08]));
+		assert_eq!(Ok(()), b.join().unwrap());
+		assert_eq!((Ok(()), 1), a.join().unwrap());
+		let transfer = |data: Vec<u8>| Transaction {
+			transfers: vec![Transfer {
+				address: 0x50,
+				direction: Direction::Write,
+				ack: true,
+				data,
+			}],
+		};
+		// the transaction of b was not disturbed, a retried after it
+		assert_eq!(
+			vec![transfer(vec![0x01, 0x08]), transfer(vec![0x01, 0x10])],
+			monitor.transactions()
+		);
+		assert!(monitor.violations().is_empty());
+		assert_eq!(0x10, target.with_handler(|r| r.registers()[1]));
+		assert_eq!(High, sda.get_state());
+		assert_eq!(High, scl.get_state());
+	}
+
+	#[test]
+	fn clock_synchronization() {
+		let (sda, scl, _, clock) = bus();
+		let monitor = I2cMonitor::new(&sda, &scl);
+		I2cTarget::new(&sda, &scl, 0x50, RegisterMap::new(4));
+		let periods = Arc::new(Mutex::new(vec![]));
+		let (p, c) = (periods.clone(), clock.clone());
+		let changed = Mutex::new(Duration::ZERO);
+		scl.on_change(move |state| {
+			let mut changed = changed.lock().unwrap();
+			p.lock()
+				.unwrap()
+				.push((state, (c.now() - *changed).as_micros()));
+			*changed = c.now();
+		});
+		let mut slow = multi_master(&sda, &scl, &clock, 100_000);
+		let mut fast = multi_master(&sda, &scl, &clock, 250_000);
+		let slow = thread::spawn(move || slow.write(0x50u8, &[0x01]));
+		let fast = thread::spawn(move || fast.write(0x50u8, &[0x02]));
+		assert_eq!(Err(ErrorKind::ArbitrationLoss), fast.join().unwrap());
+		assert_eq!(Ok(()), slow.join().unwrap());
+		assert_eq!(0x01, monitor.transactions()[0].transfers[0].data[0]);
+		// each entry is the new level of SCL and the time it had the previous
+		// one, the first two belong to the start condition
+		let periods = periods.lock().unwrap();
+		let (synchronized, alone) = periods[2..].split_at(2 * 15);
+		// until the fast master loses at the 7th data bit, it determines the
+		// high period and the slow master the low period, which it starts up to
+		// a microsecond late as it polls SCL
+		for period in synchronized.chunks(2) {
+			assert_eq!([(Low, 2), (High, 6)], period);
+		}
+		for period in alone.chunks(2) {
+			assert_eq!([(Low, 5), (High, 5)], period);
+		}
+	}
+}
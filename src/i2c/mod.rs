@@ -0,0 +1,53 @@
+//! Simulated I2C buses
+//!
+//! I2C uses two open drain lines, SDA and SCL, that are pulled high. These map
+//! directly onto two [`Wire`](`crate::wire::Wire`)s created with
+//! [`Wire::new_with_pull(WireState::High)`](`crate::wire::Wire::new_with_pull`).
+//!
+//! [`I2cMaster`] implements [`embedded_hal::i2c::I2c`] by bit-banging both lines
+//! through [`OpenDrainPin`](`crate::wire::OpenDrainPin`)s, so real device drivers
+//! can be run against simulated hardware. [`I2cTarget`] is the other side of the
+//! bus: it listens on both wires and passes transactions addressed to it on to a
+//! [`TargetHandler`], e.g. a [`RegisterMap`]. [`I2cMonitor`] passively decodes
+//! all traffic on the bus, so tests can assert on transactions instead of levels.
+//!
+//! Several [`I2cMaster`]s can share the same wires to test multi-master setups,
+//! see its documentation for clock synchronization and arbitration.
+
+mod master;
+mod monitor;
+mod target;
+
+pub use master::I2cMaster;
+pub use monitor::{Event, I2cMonitor, Transaction, Transfer, Violation};
+pub use target::{I2cTarget, RegisterMap, TargetHandler};
+
+/// Direction of an I2C transfer, as given by the R/W bit of the address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+	/// The master reads from the target.
+	Read,
+	/// The master writes to the target.
+	Write,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Address {
+	Seven(u8),
+	Ten(u16),
+}
+
+impl Address {
+	/// Returns whether the address fits into 7 or 10 bits respectively.
+	pub(crate) fn is_valid(self) -> bool {
+		match self {
+			Address::Seven(address) => address <= 0x7f,
+			Address::Ten(address) => address <= 0x3ff,
+		}
+	}
+
+	/// Returns the first byte of the 10-bit address header without R/W bit.
+	pub(crate) fn ten_bit_header(address: u16) -> u8 {
+		0xf0 | ((address >> 7) as u8 & 0x06)
+	}
+}
@@ -0,0 +1,369 @@
+use super::Direction;
+use crate::sync::{Arc, Mutex};
+use crate::wire::{InputOnlyPin, Wire, WireState};
+use embedded_hal::digital::InputPin;
+
+/// A decoded I2C bus event.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+	Start,
+	RepeatedStart,
+	Stop,
+	/// The first byte after a (repeated) start condition.
+	///
+	/// For 10-bit addresses this is the raw header byte, so `address` is in the
+	/// reserved range `0x78..=0x7b` and the lower address bits follow as data.
+	Address {
+		address: u8,
+		direction: Direction,
+		ack: bool,
+	},
+	/// A data byte and whether the receiver acknowledged it.
+	Data {
+		byte: u8,
+		ack: bool,
+	},
+	Violation(Violation),
+}
+
+/// A violation of the I2C protocol.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Violation {
+	/// SDA changed while SCL was high in the middle of a byte, i.e. after
+	/// `bits` of the 9 clock pulses of a byte. This is decoded as a start or
+	/// stop condition, which must only occur between bytes.
+	MisplacedCondition { bits: u8 },
+	/// SCL was clocked without a preceding start condition.
+	ClockWithoutStart,
+}
+
+/// A transfer to or from a single address within a [`Transaction`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Transfer {
+	pub address: u8,
+	pub direction: Direction,
+	/// Whether the address was acknowledged.
+	pub ack: bool,
+	pub data: Vec<u8>,
+}
+
+/// All transfers between a start and a stop condition.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Transaction {
+	pub transfers: Vec<Transfer>,
+}
+
+#[derive(Debug, Default)]
+struct Decoder {
+	events: Vec<Event>,
+	active: bool,
+	/// A bit sampled on the rising edge of SCL, not yet confirmed by a falling edge.
+	pending: Option<bool>,
+	shift: u16,
+	bits: u8,
+	bytes: usize,
+	clock_reported: bool,
+}
+
+impl Decoder {
+	fn condition(&mut self, sda: WireState) {
+		self.pending = None;
+		if self.bits != 0 {
+			self.events
+				.push(Event::Violation(Violation::MisplacedCondition {
+					bits: self.bits,
+				}));
+		}
+		self.bits = 0;
+		self.shift = 0;
+		if sda == WireState::Low {
+			self.events.push(if self.active {
+				Event::RepeatedStart
+			} else {
+				Event::Start
+			});
+			self.active = true;
+			self.bytes = 0;
+			self.clock_reported = false;
+		} else if self.active {
+			self.events.push(Event::Stop);
+			self.active = false;
+		}
+	}
+
+	fn rising(&mut self, sda: bool) {
+		self.pending = Some(sda);
+	}
+
+	fn falling(&mut self) {
+		let bit = match self.pending.take() {
+			Some(bit) => bit,
+			None => return,
+		};
+		if !self.active {
+			if !self.clock_reported {
+				self.clock_reported = true;
+				self.events
+					.push(Event::Violation(Violation::ClockWithoutStart));
+			}
+			return;
+		}
+		self.shift = (self.shift << 1) | bit as u16;
+		self.bits += 1;
+		if self.bits < 9 {
+			return;
+		}
+		let byte = (self.shift >> 1) as u8;
+		let ack = self.shift & 1 == 0;
+		self.events.push(if self.bytes == 0 {
+			Event::Address {
+				address: byte >> 1,
+				direction: if byte & 1 == 1 {
+					Direction::Read
+				} else {
+					Direction::Write
+				},
+				ack,
+			}
+		} else {
+			Event::Data { byte, ack }
+		});
+		self.bytes += 1;
+		self.bits = 0;
+		self.shift = 0;
+	}
+}
+
+/// A passive I2C bus monitor.
+///
+/// The monitor attaches input pins to SDA and SCL and decodes all traffic on
+/// the bus into [`Event`]s. Protocol violations are recorded as
+/// [`Event::Violation`]s. Tests can assert on the decoded [`Transaction`]s
+/// instead of raw wire levels.
+///
+/// # Examples
+///
+/// ```
+/// use embedded_hal_sync_pins::clock::VirtualClock;
+/// use embedded_hal_sync_pins::i2c::{Direction, I2cMaster, I2cMonitor, Transaction, Transfer};
+/// use embedded_hal_sync_pins::wire::{Wire, WireState};
+/// use embedded_hal::i2c::I2c;
+///
+/// let sda = Wire::new_with_pull(WireState::High);
+/// let scl = Wire::new_with_pull(WireState::High);
+/// let monitor = I2cMonitor::new(&sda, &scl);
+/// let mut i2c = I2cMaster::new(
+///     sda.connect_open_drain_pin(),
+///     scl.connect_open_drain_pin(),
+///     VirtualClock::new(),
+/// );
+/// let _ = i2c.write(0x42u8, &[0x01]);
+/// assert_eq!(
+///     vec![Transaction {
+///         transfers: vec![Transfer {
+///             address: 0x42,
+///             direction: Direction::Write,
+///             ack: false,
+///             data: vec![],
+///         }],
+///     }],
+///     monitor.transactions(),
+/// );
+/// ```
+#[derive(Clone, Debug)]
+pub struct I2cMonitor {
+	decoder: Arc<Mutex<Decoder>>,
+}
+
+impl I2cMonitor {
+	/// Attaches a new monitor to the given wires.
+	pub fn new(sda: &Wire, scl: &Wire) -> Self {
+		let decoder = Arc::new(Mutex::new(Decoder::default()));
+		let d = decoder.clone();
+		let scl_in: InputOnlyPin = scl.connect_input_pin();
+		sda.on_change(move |state| {
+			if scl_in.is_high().unwrap_or(false) {
+				d.lock().unwrap().condition(state);
+			}
+		});
+		let d = decoder.clone();
+		let sda_in: InputOnlyPin = sda.connect_input_pin();
+		scl.on_change(move |state| {
+			let mut decoder = d.lock().unwrap();
+			if state == WireState::High {
+				decoder.rising(sda_in.is_high().unwrap_or(false));
+			} else {
+				decoder.falling();
+			}
+		});
+		I2cMonitor { decoder }
+	}
+
+	/// Returns all events decoded so far.
+	pub fn events(&self) -> Vec<Event> {
+		self.decoder.lock().unwrap().events.clone()
+	}
+
+	/// Returns all protocol violations detected so far.
+	pub fn violations(&self) -> Vec<Violation> {
+		self.events()
+			.into_iter()
+			.filter_map(|event| match event {
+				Event::Violation(violation) => Some(violation),
+				_ => None,
+			})
+			.collect()
+	}
+
+	/// Returns all transactions that were finished with a stop condition.
+	pub fn transactions(&self) -> Vec<Transaction> {
+		let mut transactions = vec![];
+		let mut current: Option<Transaction> = None;
+		for event in self.events() {
+			match event {
+				Event::Start => current = Some(Transaction::default()),
+				Event::Address {
+					address,
+					direction,
+					ack,
+				} => {
+					if let Some(transaction) = current.as_mut() {
+						transaction.transfers.push(Transfer {
+							address,
+							direction,
+							ack,
+							data: vec![],
+						});
+					}
+				}
+				Event::Data { byte, .. } => {
+					if let Some(transfer) = current
+						.as_mut()
+						.and_then(|transaction| transaction.transfers.last_mut())
+					{
+						transfer.data.push(byte);
+					}
+				}
+				Event::Stop => transactions.extend(current.take()),
+				Event::RepeatedStart | Event::Violation(_) => {}
+			}
+		}
+		transactions
+	}
+
+	/// Removes all events decoded so far.
+	pub fn clear(&self) {
+		self.decoder.lock().unwrap().events.clear();
+	}
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::*;
+	use crate::clock::VirtualClock;
+	use crate::i2c::{I2cMaster, I2cTarget, RegisterMap};
+	use embedded_hal::digital::OutputPin;
+	use embedded_hal::i2c::I2c;
+	use WireState::*;
+
+	#[test]
+	fn decode() {
+		let sda = Wire::new_with_pull(High);
+		let scl = Wire::new_with_pull(High);
+		let monitor = I2cMonitor::new(&sda, &scl);
+		let _target = I2cTarget::new(&sda, &scl, 0x50, RegisterMap::new(16));
+		let mut i2c = I2cMaster::new(
+			sda.connect_open_drain_pin(),
+			scl.connect_open_drain_pin(),
+			VirtualClock::new(),
+		);
+		let mut buffer = [0; 2];
+		assert_eq!(Ok(()), i2c.write_read(0x50u8, &[0x03], &mut buffer));
+		assert_eq!(
+			vec![
+				Event::Start,
+				Event::Address {
+					address: 0x50,
+					direction: Direction::Write,
+					ack: true
+				},
+				Event::Data {
+					byte: 0x03,
+					ack: true
+				},
+				Event::RepeatedStart,
+				Event::Address {
+					address: 0x50,
+					direction: Direction::Read,
+					ack: true
+				},
+				Event::Data {
+					byte: 0xff,
+					ack: true
+				},
+				Event::Data {
+					byte: 0xff,
+					ack: false
+				},
+				Event::Stop,
+			],
+			monitor.events()
+		);
+		assert_eq!(
+			vec![Transaction {
+				transfers: vec![
+					Transfer {
+						address: 0x50,
+						direction: Direction::Write,
+						ack: true,
+						data: vec![0x03],
+					},
+					Transfer {
+						address: 0x50,
+						direction: Direction::Read,
+						ack: true,
+						data: vec![0xff, 0xff],
+					},
+				],
+			}],
+			monitor.transactions()
+		);
+		assert!(monitor.violations().is_empty());
+		monitor.clear();
+		assert!(monitor.events().is_empty());
+	}
+
+	#[test]
+	fn violations() {
+		let sda = Wire::new_with_pull(High);
+		let scl = Wire::new_with_pull(High);
+		let monitor = I2cMonitor::new(&sda, &scl);
+		let mut sda_out = sda.connect_open_drain_pin();
+		let mut scl_out = scl.connect_open_drain_pin();
+		// clock a bit without a start condition
+		scl_out.set_high().unwrap();
+		scl_out.set_low().unwrap();
+		scl_out.set_high().unwrap();
+		// start condition followed by three bits
+		scl_out.set_low().unwrap();
+		sda_out.set_high().unwrap();
+		scl_out.set_high().unwrap();
+		for _ in 0..3 {
+			scl_out.set_low().unwrap();
+			scl_out.set_high().unwrap();
+		}
+		// SDA rises while SCL is high
+		scl_out.set_low().unwrap();
+		sda_out.set_low().unwrap();
+		assert_eq!(
+			vec![
+				Violation::ClockWithoutStart,
+				Violation::MisplacedCondition { bits: 3 },
+			],
+			monitor.violations()
+		);
+		assert_eq!(Some(Event::Stop), monitor.events().pop());
+		// the aborted transaction has no transfers
+		assert_eq!(vec![Transaction::default()], monitor.transactions());
+	}
+}
@@ -0,0 +1,539 @@
+use super::{Address, Direction};
+use crate::net::PinBackend;
+use crate::sync::{Arc, Mutex};
+use crate::wire::{Driver, Wire, WireState};
+
+/// Behaviour of a simulated I2C device.
+///
+/// The methods are called by an [`I2cTarget`] while the master clocks the bus,
+/// so they should return quickly and must not wait for other bus activity.
+pub trait TargetHandler {
+	/// Called when the target was addressed after a (repeated) start condition.
+	fn start(&mut self, direction: Direction) {
+		let _ = direction;
+	}
+
+	/// Called for every byte written by the master.
+	///
+	/// Returns `true` to acknowledge the byte.
+	fn write(&mut self, byte: u8) -> bool;
+
+	/// Called for every byte read by the master.
+	fn read(&mut self) -> u8;
+
+	/// Called when a transaction addressed to the target ends with a stop condition.
+	fn stop(&mut self) {}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+	/// Not addressed, waiting for a start condition.
+	Idle,
+	/// Receiving the first address byte.
+	Address,
+	/// Receiving the second byte of a 10-bit address.
+	AddressLow,
+	/// Acknowledging an address byte, the transfer continues in the given direction.
+	AddressAck(Option<Direction>),
+	/// Receiving a data byte.
+	Write,
+	/// Acknowledging a received data byte.
+	WriteAck,
+	/// Sending a data byte.
+	Read,
+	/// Waiting for the master to acknowledge a sent byte.
+	ReadAck,
+}
+
+#[derive(Debug)]
+struct Engine<H> {
+	address: Address,
+	handler: H,
+	sda: Driver,
+	state: State,
+	shift: u8,
+	bits: u8,
+	/// A 10-bit target stays selected for reads after a write until a stop.
+	selected: bool,
+	/// Whether the handler has seen a start since the last stop.
+	active: bool,
+	acked: bool,
+}
+
+impl<H: TargetHandler> Engine<H> {
+	fn start(&mut self) {
+		self.sda.release();
+		self.state = State::Address;
+		self.shift = 0;
+		self.bits = 0;
+	}
+
+	fn stop(&mut self) {
+		self.sda.release();
+		self.state = State::Idle;
+		self.selected = false;
+		if self.active {
+			self.active = false;
+			self.handler.stop();
+		}
+	}
+
+	fn select(&mut self, direction: Direction) {
+		self.active = true;
+		self.handler.start(direction);
+	}
+
+	fn rising(&mut self, sda: bool) {
+		match self.state {
+			State::Address | State::AddressLow | State::Write => {
+				self.shift = (self.shift << 1) | sda as u8;
+				self.bits += 1;
+			}
+			State::ReadAck => self.acked = !sda,
+			_ => {}
+		}
+	}
+
+	/// Matches a completed address byte and returns the state to continue with.
+	fn match_address(&mut self) -> State {
+		let byte = self.shift;
+		let read = byte & 1 == 1;
+		let direction = if read {
+			Direction::Read
+		} else {
+			Direction::Write
+		};
+		match (self.state, self.address) {
+			(State::Address, Address::Seven(address)) if byte >> 1 == address => {
+				self.select(direction);
+				State::AddressAck(Some(direction))
+			}
+			(State::Address, Address::Ten(address))
+				if byte & 0xfe == Address::ten_bit_header(address) =>
+			{
+				if !read {
+					State::AddressAck(None)
+				} else if self.selected {
+					self.select(Direction::Read);
+					State::AddressAck(Some(Direction::Read))
+				} else {
+					State::Idle
+				}
+			}
+			(State::AddressLow, Address::Ten(address)) if byte == address as u8 => {
+				self.selected = true;
+				self.select(Direction::Write);
+				State::AddressAck(Some(Direction::Write))
+			}
+			_ => State::Idle,
+		}
+	}
+
+	fn next_read_byte(&mut self) {
+		self.shift = self.handler.read();
+		self.bits = 0;
+		self.state = State::Read;
+		self.send_bit();
+	}
+
+	fn send_bit(&mut self) {
+		let bit = self.shift & 0x80 != 0;
+		self.sda.drive(if bit {
+			WireState::Floating
+		} else {
+			WireState::Low
+		});
+		self.shift <<= 1;
+		self.bits += 1;
+	}
+
+	fn falling(&mut self) {
+		match self.state {
+			State::Address | State::AddressLow if self.bits == 8 => {
+				self.state = self.match_address();
+				if let State::AddressAck(_) = self.state {
+					self.sda.drive(WireState::Low);
+				}
+			}
+			State::AddressAck(direction) => {
+				self.sda.release();
+				match direction {
+					None => {
+						self.state = State::AddressLow;
+						self.shift = 0;
+						self.bits = 0;
+					}
+					Some(Direction::Write) => {
+						self.state = State::Write;
+						self.shift = 0;
+						self.bits = 0;
+					}
+					Some(Direction::Read) => self.next_read_byte(),
+				}
+			}
+			State::Write if self.bits == 8 => {
+				if self.handler.write(self.shift) {
+					self.sda.drive(WireState::Low);
+					self.state = State::WriteAck;
+				} else {
+					self.state = State::Idle;
+				}
+			}
+			State::WriteAck => {
+				self.sda.release();
+				self.state = State::Write;
+				self.shift = 0;
+				self.bits = 0;
+			}
+			State::Read if self.bits == 8 => {
+				self.sda.release();
+				self.state = State::ReadAck;
+			}
+			State::Read => self.send_bit(),
+			State::ReadAck => {
+				if self.acked {
+					self.next_read_byte();
+				} else {
+					self.state = State::Idle;
+				}
+			}
+			_ => {}
+		}
+	}
+}
+
+/// A simulated I2C device attached to SDA and SCL wires.
+///
+/// The target listens for changes on both wires, decodes start and stop
+/// conditions, matches its address and passes all transfers addressed to it on
+/// to a [`TargetHandler`]. Acknowledges and data bits are driven onto SDA
+/// through its own [`Driver`].
+///
+/// # Examples
+///
+/// ```
+/// use embedded_hal_sync_pins::clock::VirtualClock;
+/// use embedded_hal_sync_pins::i2c::{I2cMaster, I2cTarget, RegisterMap};
+/// use embedded_hal_sync_pins::wire::{Wire, WireState};
+/// use embedded_hal::i2c::I2c;
+///
+/// let sda = Wire::new_with_pull(WireState::High);
+/// let scl = Wire::new_with_pull(WireState::High);
+/// // a 24C02 EEPROM
+/// let eeprom = I2cTarget::new(&sda, &scl, 0x50, RegisterMap::new(256));
+/// let mut i2c = I2cMaster::new(
+///     sda.connect_open_drain_pin(),
+///     scl.connect_open_drain_pin(),
+///     VirtualClock::new(),
+/// );
+/// i2c.write(0x50u8, &[0x10, 0xca, 0xfe]).unwrap();
+/// let mut buffer = [0; 2];
+/// i2c.write_read(0x50u8, &[0x10], &mut buffer).unwrap();
+/// assert_eq!([0xca, 0xfe], buffer);
+/// let stored = eeprom.with_handler(|map| map.registers()[0x10..0x12].to_vec());
+/// assert_eq!(vec![0xca, 0xfe], stored);
+/// ```
+#[derive(Debug)]
+pub struct I2cTarget<H> {
+	engine: Arc<Mutex<Engine<H>>>,
+}
+
+impl<H> Clone for I2cTarget<H> {
+	fn clone(&self) -> Self {
+		I2cTarget {
+			engine: self.engine.clone(),
+		}
+	}
+}
+
+impl<H: TargetHandler + Send + 'static> I2cTarget<H> {
+	/// Attaches a new target with a 7-bit address to the given wires.
+	pub fn new(sda: &Wire, scl: &Wire, address: u8, handler: H) -> Self {
+		Self::attach(sda, scl, Address::Seven(address), handler)
+	}
+
+	/// Attaches a new target with a 10-bit address to the given wires.
+	pub fn new_ten_bit(sda: &Wire, scl: &Wire, address: u16, handler: H) -> Self {
+		Self::attach(sda, scl, Address::Ten(address), handler)
+	}
+
+	fn attach(sda: &Wire, scl: &Wire, address: Address, handler: H) -> Self {
+		let engine = Arc::new(Mutex::new(Engine {
+			address,
+			handler,
+			sda: sda.connect_driver(),
+			state: State::Idle,
+			shift: 0,
+			bits: 0,
+			selected: false,
+			active: false,
+			acked: false,
+		}));
+		let (e, s) = (engine.clone(), scl.clone());
+		sda.on_change(move |state| {
+			if s.get_state() != WireState::High {
+				return;
+			}
+			let mut engine = e.lock().unwrap();
+			match state {
+				WireState::Low => engine.start(),
+				_ => engine.stop(),
+			}
+		});
+		let (e, d) = (engine.clone(), sda.clone());
+		scl.on_change(move |state| {
+			let mut engine = e.lock().unwrap();
+			match state {
+				WireState::High => engine.rising(d.get_state() == WireState::High),
+				_ => engine.falling(),
+			}
+		});
+		I2cTarget { engine }
+	}
+}
+
+impl<H> I2cTarget<H> {
+	/// Calls `f` with the handler of this target.
+	///
+	/// This is useful to inspect or change the state of a simulated device
+	/// between transactions.
+	pub fn with_handler<F, R>(&self, f: F) -> R
+	where
+		F: FnOnce(&mut H) -> R,
+	{
+		f(&mut self.engine.lock().unwrap().handler)
+	}
+}
+
+/// A [`TargetHandler`] modelling a device with a linear register space.
+///
+/// This is the typical interface of EEPROMs and many sensors: the first bytes of
+/// a write select a register, all following bytes are written to consecutive
+/// registers. Reads start at the selected register and also increment it. The
+/// register pointer wraps around at the end of the register space.
+#[derive(Clone, Debug)]
+pub struct RegisterMap {
+	registers: Vec<u8>,
+	pointer: usize,
+	pointer_bytes: usize,
+	received: usize,
+}
+
+impl RegisterMap {
+	/// Creates a new register map with `size` registers and a one byte register
+	/// pointer. All registers are initialized to `0xff`.
+	///
+	/// # Panics
+	///
+	/// Panics if `size` is zero.
+	pub fn new(size: usize) -> Self {
+		Self::new_with_pointer_bytes(size, 1)
+	}
+
+	/// Creates a new register map with `size` registers, selected by a register
+	/// pointer of `pointer_bytes` bytes sent MSB first, like in larger EEPROMs.
+	///
+	/// # Panics
+	///
+	/// Panics if `size` is zero.
+	pub fn new_with_pointer_bytes(size: usize, pointer_bytes: usize) -> Self {
+		assert!(size > 0, "a register map needs at least one register");
+		RegisterMap {
+			registers: vec![0xff; size],
+			pointer: 0,
+			pointer_bytes,
+			received: 0,
+		}
+	}
+
+	pub fn registers(&self) -> &[u8] {
+		&self.registers
+	}
+
+	pub fn registers_mut(&mut self) -> &mut [u8] {
+		&mut self.registers
+	}
+
+	/// Returns the currently selected register.
+	pub fn pointer(&self) -> usize {
+		self.pointer
+	}
+
+	fn advance(&mut self) {
+		self.pointer = (self.pointer + 1) % self.registers.len();
+	}
+}
+
+impl TargetHandler for RegisterMap {
+	fn start(&mut self, _direction: Direction) {
+		self.received = 0;
+	}
+
+	fn write(&mut self, byte: u8) -> bool {
+		if self.received < self.pointer_bytes {
+			let pointer = if self.received == 0 { 0 } else { self.pointer };
+			self.pointer = ((pointer << 8) | byte as usize) % self.registers.len();
+		} else {
+			self.registers[self.pointer] = byte;
+			self.advance();
+		}
+		self.received += 1;
+		true
+	}
+
+	fn read(&mut self) -> u8 {
+		let byte = self.registers[self.pointer];
+		self.advance();
+		byte
+	}
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::*;
+	use crate::clock::VirtualClock;
+	use crate::i2c::I2cMaster;
+	use embedded_hal::i2c::{ErrorKind, I2c, NoAcknowledgeSource, Operation};
+	use WireState::*;
+
+	fn bus() -> (Wire, Wire, I2cMaster<VirtualClock>) {
+		let sda = Wire::new_with_pull(High);
+		let scl = Wire::new_with_pull(High);
+		let master = I2cMaster::new(
+			sda.connect_open_drain_pin(),
+			scl.connect_open_drain_pin(),
+			VirtualClock::new(),
+		);
+		(sda, scl, master)
+	}
+
+	#[test]
+	fn register_map() {
+		let (sda, scl, mut i2c) = bus();
+		let sensor = I2cTarget::new(&sda, &scl, 0x1d, RegisterMap::new(4));
+		sensor.with_handler(|map| map.registers_mut().copy_from_slice(&[0, 1, 2, 3]));
+		let mut buffer = [0; 3];
+		assert_eq!(Ok(()), i2c.write_read(0x1du8, &[0x02], &mut buffer));
+		// the register pointer wraps around
+		assert_eq!([2, 3, 0], buffer);
+		assert_eq!(1, sensor.with_handler(|map| map.pointer()));
+		assert_eq!(Ok(()), i2c.write(0x1du8, &[0x03, 0xaa, 0xbb]));
+		assert_eq!(
+			vec![0xbb, 1, 2, 0xaa],
+			sensor.with_handler(|map| map.registers().to_vec())
+		);
+		let (mut first, mut second) = ([0; 1], [0; 1]);
+		assert_eq!(
+			Ok(()),
+			i2c.transaction(
+				0x1du8,
+				&mut [
+					Operation::Write(&[0x00]),
+					Operation::Read(&mut first),
+					Operation::Read(&mut second),
+				]
+			)
+		);
+		assert_eq!(([0xbb], [1]), (first, second));
+		assert_eq!(High, sda.get_state());
+	}
+
+	#[test]
+	fn two_byte_pointer() {
+		let (sda, scl, mut i2c) = bus();
+		let eeprom = I2cTarget::new(
+			&sda,
+			&scl,
+			0x50,
+			RegisterMap::new_with_pointer_bytes(4096, 2),
+		);
+		assert_eq!(Ok(()), i2c.write(0x50u8, &[0x01, 0x23, 0x42]));
+		assert_eq!(0x42, eeprom.with_handler(|map| map.registers()[0x123]));
+	}
+
+	#[test]
+	#[should_panic(expected = "at least one register")]
+	fn empty_register_map() {
+		RegisterMap::new(0);
+	}
+
+	#[test]
+	fn address_mismatch() {
+		let (sda, scl, mut i2c) = bus();
+		let _target = I2cTarget::new(&sda, &scl, 0x50, RegisterMap::new(16));
+		assert_eq!(
+			Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address)),
+			i2c.write(0x51u8, &[0x00])
+		);
+		assert_eq!(Ok(()), i2c.write(0x50u8, &[0x00]));
+	}
+
+	#[test]
+	fn ten_bit_address() {
+		let (sda, scl, mut i2c) = bus();
+		let target = I2cTarget::new_ten_bit(&sda, &scl, 0x2a5, RegisterMap::new(16));
+		let _other = I2cTarget::new_ten_bit(&sda, &scl, 0x1a5, RegisterMap::new(16));
+		assert_eq!(Ok(()), i2c.write(0x2a5u16, &[0x04, 0x11, 0x22]));
+		let mut buffer = [0; 2];
+		assert_eq!(Ok(()), i2c.write_read(0x2a5u16, &[0x04], &mut buffer));
+		assert_eq!([0x11, 0x22], buffer);
+		// a read without a preceding write addresses the target as well
+		assert_eq!(Ok(()), i2c.read(0x2a5u16, &mut buffer));
+		assert_eq!([0xff, 0xff], buffer);
+		assert_eq!(8, target.with_handler(|map| map.pointer()));
+		assert_eq!(
+			Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address)),
+			i2c.write(0x0a5u16, &[0x00])
+		);
+	}
+
+	#[derive(Default)]
+	struct Fifo {
+		events: Vec<String>,
+		capacity: usize,
+	}
+
+	impl TargetHandler for Fifo {
+		fn start(&mut self, direction: Direction) {
+			self.events.push(format!("{:?}", direction));
+		}
+
+		fn write(&mut self, byte: u8) -> bool {
+			self.events.push(format!("{:02x}", byte));
+			self.events.len() < self.capacity
+		}
+
+		fn read(&mut self) -> u8 {
+			0x5a
+		}
+
+		fn stop(&mut self) {
+			self.events.push("Stop".into());
+		}
+	}
+
+	#[test]
+	fn handler() {
+		let (sda, scl, mut i2c) = bus();
+		let fifo = I2cTarget::new(
+			&sda,
+			&scl,
+			0x20,
+			Fifo {
+				capacity: 3,
+				..Default::default()
+			},
+		);
+		let mut buffer = [0; 1];
+		assert_eq!(
+			Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Data)),
+			i2c.write_read(0x20u8, &[0x01, 0x02, 0x03], &mut buffer)
+		);
+		assert_eq!(Ok(()), i2c.read(0x20u8, &mut buffer));
+		assert_eq!([0x5a], buffer);
+		assert_eq!(
+			vec!["Write", "01", "02", "Stop", "Read", "Stop"],
+			fifo.with_handler(|fifo| fifo.events.clone())
+		);
+	}
+}
@@ -0,0 +1,137 @@
+//! Active-low pin adapters
+//!
+//! Many signals are active low, e.g. chip selects, resets or interrupt lines.
+//! [`Inverted`] wraps any [`embedded_hal`] input or output pin and inverts its
+//! logical level, so a driver can assert such a signal by setting the pin high.
+//!
+//! # Examples
+//!
+//! ```
+//! use embedded_hal_sync_pins::inverted::Inverted;
+//! use embedded_hal_sync_pins::pins::{self, AtomicPinState, PinState};
+//! use embedded_hal::digital::{InputPin, OutputPin};
+//! use core::sync::atomic::Ordering;
+//!
+//! let reset = AtomicPinState::new_with_state(PinState::High);
+//! let mut pin = Inverted::new(pins::PushPullPin::new(&reset));
+//! let sense = Inverted::new(pins::InputPin::new(&reset));
+//! pin.set_high().unwrap();
+//! assert_eq!(PinState::Low, reset.load(Ordering::SeqCst));
+//! assert_eq!(Ok(true), sense.is_high());
+//! ```
+
+use embedded_hal::digital::{
+	ErrorType, InputPin, OutputPin, StatefulOutputPin, ToggleableOutputPin,
+};
+
+/// A pin adapter that inverts the logical level of the wrapped pin.
+#[derive(Clone, Debug)]
+pub struct Inverted<P> {
+	pin: P,
+}
+
+impl<P> Inverted<P> {
+	/// Wraps `pin`, inverting its logical level.
+	pub fn new(pin: P) -> Self {
+		Inverted { pin }
+	}
+
+	/// Returns the wrapped pin.
+	pub fn into_inner(self) -> P {
+		self.pin
+	}
+}
+
+impl<P: ErrorType> ErrorType for Inverted<P> {
+	type Error = P::Error;
+}
+
+impl<P: InputPin> InputPin for Inverted<P> {
+	fn is_high(&self) -> Result<bool, Self::Error> {
+		self.pin.is_low()
+	}
+
+	fn is_low(&self) -> Result<bool, Self::Error> {
+		self.pin.is_high()
+	}
+}
+
+impl<P: OutputPin> OutputPin for Inverted<P> {
+	fn set_low(&mut self) -> Result<(), Self::Error> {
+		self.pin.set_high()
+	}
+
+	fn set_high(&mut self) -> Result<(), Self::Error> {
+		self.pin.set_low()
+	}
+}
+
+impl<P: StatefulOutputPin> StatefulOutputPin for Inverted<P> {
+	fn is_set_high(&self) -> Result<bool, Self::Error> {
+		self.pin.is_set_low()
+	}
+
+	fn is_set_low(&self) -> Result<bool, Self::Error> {
+		self.pin.is_set_high()
+	}
+}
+
+impl<P: ToggleableOutputPin> ToggleableOutputPin for Inverted<P> {
+	fn toggle(&mut self) -> Result<(), Self::Error> {
+		self.pin.toggle()
+	}
+}
+
+#[cfg(all(test, feature = "std", not(loom)))]
+mod tests {
+	use super::*;
+	use crate::pins::{AtomicPinState, InputPin as SyncInputPin, PinState, PushPullPin};
+	use crate::sync::{Arc, Ordering};
+	use crate::wire::{Wire, WireState};
+
+	#[test]
+	fn input() {
+		let state = Arc::new(AtomicPinState::new());
+		let pin = Inverted::new(SyncInputPin::new(state.clone()));
+		assert_eq!(Ok(false), pin.is_high());
+		assert_eq!(Ok(false), pin.is_low());
+		state.store(PinState::Low, Ordering::SeqCst);
+		assert_eq!(Ok(true), pin.is_high());
+		state.store(PinState::High, Ordering::SeqCst);
+		assert_eq!(Ok(true), pin.is_low());
+	}
+
+	#[test]
+	fn output() {
+		let state = Arc::new(AtomicPinState::new());
+		let mut pin = Inverted::new(PushPullPin::new(state.clone()));
+		assert_eq!(Ok(()), pin.set_high());
+		assert_eq!(PinState::Low, state.load(Ordering::SeqCst));
+		assert_eq!(Ok(true), pin.is_set_high());
+		assert_eq!(Ok(false), pin.is_set_low());
+		assert_eq!(Ok(true), pin.is_high());
+		assert_eq!(Ok(()), pin.set_low());
+		assert_eq!(PinState::High, state.load(Ordering::SeqCst));
+		assert_eq!(Ok(true), pin.is_set_low());
+		assert_eq!(Ok(()), pin.toggle());
+		assert_eq!(PinState::Low, state.load(Ordering::SeqCst));
+		let mut pin = pin.into_inner();
+		assert_eq!(Ok(()), OutputPin::set_high(&mut pin));
+		assert_eq!(PinState::High, state.load(Ordering::SeqCst));
+	}
+
+	#[test]
+	fn open_drain_chip_select() {
+		let cs = Wire::new_with_pull(WireState::High);
+		cs.set_active_low(true);
+		let mut pin = Inverted::new(cs.connect_open_drain_pin());
+		assert!(!cs.is_asserted());
+		// asserting an inverted open drain pin releases the line
+		assert_eq!(Ok(()), pin.set_high());
+		assert_eq!(WireState::High, cs.get_state());
+		assert!(!cs.is_asserted());
+		assert_eq!(Ok(()), pin.set_low());
+		assert_eq!(WireState::Low, cs.get_state());
+		assert!(cs.is_asserted());
+	}
+}
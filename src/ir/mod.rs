@@ -0,0 +1,354 @@
+//! Simulated infrared remote controls
+//!
+//! An IR remote sends bursts of a modulated carrier, the marks, separated by
+//! spaces without carrier. A demodulating IR receiver module strips the
+//! carrier and outputs the envelope, low during marks and high otherwise,
+//! which is what the components in this module put on and read from a single
+//! [`Wire`](`crate::wire::Wire`).
+//!
+//! Two protocols are supported:
+//!
+//! - NEC encodes bits in the length of the spaces after 562.5 µs marks. A
+//!   frame starts with a 9 ms mark and a 4.5 ms space, followed by an 8 bit
+//!   address and its inverse, or a 16 bit extended address, then an 8 bit
+//!   command and its inverse, all LSB first. While a key is held, a short
+//!   repeat code follows every 108 ms.
+//! - RC5 uses Manchester coding with 1.778 ms bits, a one being a space
+//!   followed by a mark. A frame has two start bits, a toggle bit that
+//!   changes with every key press, a 5 bit address and a 6 bit command, MSB
+//!   first. The second start bit is the inverted seventh bit of the command,
+//!   as in the extended RC5 protocol. Frames are repeated every 113.778 ms.
+//!
+//! [`IrTransmitter`] sends [`Event`]s and [`IrReceiver`] decodes them, both
+//! timed on a [`VirtualClock`](`crate::clock::VirtualClock`). [`Event::waveform`]
+//! returns the envelope of a frame, e.g. to perturb it with the functions in
+//! [`stimulus`](`crate::stimulus`).
+//!
+//! # Examples
+//!
+//! ```
+//! use embedded_hal_sync_pins::clock::VirtualClock;
+//! use embedded_hal_sync_pins::ir::{Event, IrReceiver, IrTransmitter};
+//! use embedded_hal_sync_pins::wire::Wire;
+//!
+//! let clock = VirtualClock::new();
+//! let line = Wire::new();
+//! let mut rx = IrReceiver::new(&line, clock.clone());
+//! let mut tx = IrTransmitter::new(line.connect_push_pull_pin(), clock.clone());
+//! // press and hold a key
+//! tx.send(Event::Nec {
+//!     address: 0x04,
+//!     command: 0x08,
+//! });
+//! tx.send(Event::NecRepeat);
+//! tx.flush();
+//! assert_eq!(
+//!     Ok(Event::Nec {
+//!         address: 0x04,
+//!         command: 0x08
+//!     }),
+//!     rx.try_read()
+//! );
+//! assert_eq!(Ok(Event::NecRepeat), rx.try_read());
+//! ```
+
+mod rx;
+mod tx;
+
+pub use rx::IrReceiver;
+pub use tx::IrTransmitter;
+
+use crate::stimulus::Waveform;
+use crate::wire::WireState;
+use std::fmt;
+use std::time::Duration;
+
+/// Length of a NEC mark and the unit of all NEC timings.
+const NEC_UNIT: Duration = Duration::from_nanos(562_500);
+/// Time from the start of a NEC frame or repeat code to the next one.
+const NEC_PERIOD: Duration = Duration::from_millis(108);
+/// Half an RC5 bit, 32 periods of the 36 kHz carrier.
+const RC5_HALF_BIT: Duration = Duration::from_nanos(888_889);
+/// Time from the start of an RC5 frame to the next one.
+const RC5_PERIOD: Duration = Duration::from_micros(113_778);
+/// Time without an edge after which a frame is complete.
+pub(crate) const GAP: Duration = Duration::from_millis(10);
+
+/// A frame sent by a remote control.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+	/// A NEC frame. Addresses up to `0xff` are sent with their inverse,
+	/// larger ones as extended 16 bit addresses. An extended address whose
+	/// high byte is the inverse of its low byte is received as the low byte.
+	Nec { address: u16, command: u8 },
+	/// A NEC repeat code, sent while the key of the last frame is held.
+	NecRepeat,
+	/// An RC5 frame with a 5 bit address and a 7 bit command.
+	Rc5 {
+		address: u8,
+		command: u8,
+		toggle: bool,
+	},
+}
+
+impl Event {
+	/// Returns the envelope of this frame, low during marks.
+	///
+	/// The waveform lasts until the next frame may start.
+	///
+	/// # Panics
+	///
+	/// Panics if the address or command of an RC5 frame is out of range.
+	pub fn waveform(&self) -> Waveform {
+		match *self {
+			Event::Nec { address, command } => {
+				let address = match address {
+					0..=0xff => address | ((!address << 8) & 0xff00),
+					_ => address,
+				};
+				let command = command as u16 | (!command as u16) << 8;
+				nec(address as u32 | (command as u32) << 16)
+			}
+			Event::NecRepeat => envelope(
+				&[
+					(true, 16 * NEC_UNIT),
+					(false, 4 * NEC_UNIT),
+					(true, NEC_UNIT),
+				],
+				NEC_PERIOD,
+			),
+			Event::Rc5 {
+				address,
+				command,
+				toggle,
+			} => {
+				assert!(address < 0x20, "invalid RC5 address: {}", address);
+				assert!(command < 0x80, "invalid RC5 command: {}", command);
+				let mut bits = vec![true, command & 0x40 == 0, toggle];
+				bits.extend((0..5).rev().map(|i| address & (1 << i) != 0));
+				bits.extend((0..6).rev().map(|i| command & (1 << i) != 0));
+				let halves: Vec<_> = bits
+					.iter()
+					.flat_map(|bit| vec![(!bit, RC5_HALF_BIT), (*bit, RC5_HALF_BIT)])
+					.collect();
+				envelope(&halves, RC5_PERIOD)
+			}
+		}
+	}
+}
+
+/// Returns the envelope of a NEC frame with the given 32 bits.
+fn nec(bits: u32) -> Waveform {
+	let mut pulses = vec![(true, 16 * NEC_UNIT), (false, 8 * NEC_UNIT)];
+	for i in 0..32 {
+		let space = if bits & (1 << i) != 0 { 3 } else { 1 };
+		pulses.push((true, NEC_UNIT));
+		pulses.push((false, space * NEC_UNIT));
+	}
+	pulses.push((true, NEC_UNIT));
+	envelope(&pulses, NEC_PERIOD)
+}
+
+/// Returns a waveform with the given marks and spaces, lasting `period`.
+fn envelope(pulses: &[(bool, Duration)], period: Duration) -> Waveform {
+	let mut waveform = Waveform::new(1);
+	let mut at = Duration::ZERO;
+	for (mark, duration) in pulses {
+		let state = if *mark {
+			WireState::Low
+		} else {
+			WireState::High
+		};
+		waveform.set(at, 0, state);
+		at += *duration;
+	}
+	waveform.set(at, 0, WireState::High);
+	waveform.hold(period);
+	waveform
+}
+
+/// An error while receiving a frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+	/// The lengths of the marks and spaces did not match any protocol.
+	Timing,
+	/// The command of a NEC frame did not match its inverse.
+	Checksum,
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Error::Timing => write!(f, "invalid pulse timing"),
+			Error::Checksum => write!(f, "command checksum mismatch"),
+		}
+	}
+}
+
+impl std::error::Error for Error {}
+
+/// Returns whether `duration` is within 25 % of `nominal`.
+fn near(duration: Duration, nominal: Duration) -> bool {
+	duration >= nominal * 3 / 4 && duration <= nominal * 5 / 4
+}
+
+/// Decodes a frame from the lengths of its alternating marks and spaces,
+/// starting with a mark.
+pub(crate) fn decode(pulses: &[Duration]) -> Result<Event, Error> {
+	match pulses.first() {
+		Some(mark) if near(*mark, 16 * NEC_UNIT) => decode_nec(pulses),
+		_ => decode_rc5(pulses),
+	}
+}
+
+fn decode_nec(pulses: &[Duration]) -> Result<Event, Error> {
+	if pulses.len() == 3 && near(pulses[1], 4 * NEC_UNIT) && near(pulses[2], NEC_UNIT) {
+		return Ok(Event::NecRepeat);
+	}
+	if pulses.len() != 67 || !near(pulses[1], 8 * NEC_UNIT) {
+		return Err(Error::Timing);
+	}
+	let mut bits = 0u32;
+	for (i, bit) in pulses[2..66].chunks(2).enumerate() {
+		if !near(bit[0], NEC_UNIT) {
+			return Err(Error::Timing);
+		}
+		if near(bit[1], 3 * NEC_UNIT) {
+			bits |= 1 << i;
+		} else if !near(bit[1], NEC_UNIT) {
+			return Err(Error::Timing);
+		}
+	}
+	if !near(pulses[66], NEC_UNIT) {
+		return Err(Error::Timing);
+	}
+	let [address, inverted, command, check] = bits.to_le_bytes();
+	if command != !check {
+		return Err(Error::Checksum);
+	}
+	let address = if address == !inverted {
+		address as u16
+	} else {
+		u16::from_le_bytes([address, inverted])
+	};
+	Ok(Event::Nec { address, command })
+}
+
+fn decode_rc5(pulses: &[Duration]) -> Result<Event, Error> {
+	// the first half of the first start bit is a space
+	let mut halves = vec![false];
+	for (i, pulse) in pulses.iter().enumerate() {
+		let count = if near(*pulse, RC5_HALF_BIT) {
+			1
+		} else if near(*pulse, 2 * RC5_HALF_BIT) {
+			2
+		} else {
+			return Err(Error::Timing);
+		};
+		halves.resize(halves.len() + count, i % 2 == 0);
+	}
+	// a trailing space is not terminated by an edge
+	if halves.len() == 27 {
+		halves.push(false);
+	}
+	if halves.len() != 28 {
+		return Err(Error::Timing);
+	}
+	let mut bits = vec![];
+	for half in halves.chunks(2) {
+		match half {
+			[false, true] => bits.push(true),
+			[true, false] => bits.push(false),
+			_ => return Err(Error::Timing),
+		}
+	}
+	let value = |bits: &[bool]| bits.iter().fold(0, |value, bit| (value << 1) | *bit as u8);
+	Ok(Event::Rc5 {
+		address: value(&bits[3..8]),
+		command: value(&bits[8..14]) | (!bits[1] as u8) << 6,
+		toggle: bits[2],
+	})
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::*;
+
+	/// Returns the lengths of the marks and spaces of a waveform.
+	fn pulses(waveform: &Waveform) -> Vec<Duration> {
+		let edges: Vec<_> = waveform
+			.edges()
+			.iter()
+			.skip_while(|edge| edge.state != WireState::Low)
+			.collect();
+		edges
+			.windows(2)
+			.map(|edges| edges[1].at - edges[0].at)
+			.collect()
+	}
+
+	#[test]
+	fn nec_timing() {
+		let waveform = Event::Nec {
+			address: 0x00,
+			command: 0xff,
+		}
+		.waveform();
+		let edges = waveform.edges();
+		assert_eq!(68, edges.len());
+		assert_eq!(WireState::Low, edges[0].state);
+		assert_eq!(Duration::from_millis(9), edges[1].at);
+		assert_eq!(Duration::from_micros(13_500), edges[2].at);
+		// eight zeros, eight ones, eight ones, eight zeros and a stop mark
+		assert_eq!(Duration::from_nanos(68_062_500), edges[67].at);
+		assert_eq!(NEC_PERIOD, waveform.duration());
+		let repeat = Event::NecRepeat.waveform();
+		assert_eq!(
+			vec![
+				Duration::from_millis(9),
+				Duration::from_micros(2250),
+				NEC_UNIT
+			],
+			pulses(&repeat)
+		);
+	}
+
+	#[test]
+	fn nec_decode() {
+		for (address, command) in [(0x00, 0x00), (0x5a, 0xc3), (0x1234, 0x7f)] {
+			let event = Event::Nec { address, command };
+			assert_eq!(Ok(event), decode(&pulses(&event.waveform())));
+		}
+		assert_eq!(Err(Error::Checksum), decode(&pulses(&nec(0x1234_ff00))));
+		let mut bad = pulses(&nec(0xfe01_ff00));
+		bad[10] = 2 * NEC_UNIT;
+		assert_eq!(Err(Error::Timing), decode(&bad));
+		assert_eq!(Err(Error::Timing), decode(&bad[..20]));
+	}
+
+	#[test]
+	fn rc5() {
+		for toggle in [false, true] {
+			for (address, command) in [(0, 0), (0x1f, 0x3f), (0x05, 0x35), (0x14, 0x7f)] {
+				let event = Event::Rc5 {
+					address,
+					command,
+					toggle,
+				};
+				let waveform = event.waveform();
+				assert_eq!(RC5_PERIOD, waveform.duration());
+				assert_eq!(Ok(event), decode(&pulses(&waveform)));
+			}
+		}
+		let mut bad = pulses(
+			&Event::Rc5 {
+				address: 0,
+				command: 0,
+				toggle: false,
+			}
+			.waveform(),
+		);
+		bad[3] = 3 * RC5_HALF_BIT;
+		assert_eq!(Err(Error::Timing), decode(&bad));
+	}
+}
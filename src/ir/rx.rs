@@ -0,0 +1,164 @@
+use super::{decode, Error, Event, GAP};
+use crate::clock::VirtualClock;
+use crate::sync::{Arc, Mutex};
+use crate::wire::{Wire, WireState};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+#[derive(Debug)]
+struct Receiver {
+	/// Lengths of the marks and spaces of the current frame.
+	pulses: Vec<Duration>,
+	mark: bool,
+	/// Time and number of the last edge.
+	last: Duration,
+	edges: u64,
+	received: VecDeque<Result<Event, Error>>,
+}
+
+impl Receiver {
+	fn edge(&mut self, now: Duration, mark: bool) -> Option<u64> {
+		if mark == self.mark {
+			return None;
+		}
+		// a space before the first mark is idle time
+		if self.mark || !self.pulses.is_empty() {
+			self.pulses.push(now - self.last);
+		}
+		self.mark = mark;
+		self.last = now;
+		self.edges += 1;
+		Some(self.edges)
+	}
+
+	/// Decodes the current frame if there was no edge since edge `edges`.
+	fn timeout(&mut self, edges: u64) {
+		if self.edges != edges || self.mark || self.pulses.is_empty() {
+			return;
+		}
+		let event = decode(&self.pulses);
+		self.received.push_back(event);
+		self.pulses.clear();
+	}
+}
+
+/// An IR receiver decoding the envelope of NEC and RC5 frames on a wire.
+///
+/// The receiver measures the lengths of the marks, while the wire is low, and
+/// the spaces between them on the clock. A frame is decoded once the wire has
+/// been high for 10 ms, and the received events and errors are queued until
+/// they are read. Timings are accepted within 25 % of their nominal length.
+///
+/// See the [module documentation](`super`) for an example.
+#[derive(Clone, Debug)]
+pub struct IrReceiver {
+	receiver: Arc<Mutex<Receiver>>,
+}
+
+impl IrReceiver {
+	/// Attaches a new receiver to the given wire.
+	pub fn new(line: &Wire, clock: VirtualClock) -> Self {
+		let receiver = Arc::new(Mutex::new(Receiver {
+			pulses: vec![],
+			mark: false,
+			last: clock.now(),
+			edges: 0,
+			received: VecDeque::new(),
+		}));
+		let r = receiver.clone();
+		line.on_change(move |state| {
+			let edges = r.lock().unwrap().edge(clock.now(), state == WireState::Low);
+			if let Some(edges) = edges {
+				let r = r.clone();
+				clock.schedule_in(GAP, move || r.lock().unwrap().timeout(edges));
+			}
+		});
+		IrReceiver { receiver }
+	}
+
+	/// Returns the next received event or error, if any.
+	pub fn try_read(&mut self) -> nb::Result<Event, Error> {
+		match self.receiver.lock().unwrap().received.pop_front() {
+			Some(Ok(event)) => Ok(event),
+			Some(Err(e)) => Err(nb::Error::Other(e)),
+			None => Err(nb::Error::WouldBlock),
+		}
+	}
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::super::IrTransmitter;
+	use super::*;
+
+	fn setup() -> (VirtualClock, Wire, IrReceiver, IrTransmitter) {
+		let clock = VirtualClock::new();
+		let line = Wire::new();
+		let rx = IrReceiver::new(&line, clock.clone());
+		let tx = IrTransmitter::new(line.connect_push_pull_pin(), clock.clone());
+		(clock, line, rx, tx)
+	}
+
+	#[test]
+	fn held_key() {
+		let (clock, _, mut rx, mut tx) = setup();
+		let event = Event::Nec {
+			address: 0xbeef,
+			command: 0x42,
+		};
+		tx.send(event);
+		assert_eq!(Err(nb::Error::WouldBlock), tx.try_send(Event::NecRepeat));
+		for _ in 0..3 {
+			tx.send(Event::NecRepeat);
+		}
+		tx.flush();
+		assert_eq!(Duration::from_millis(4 * 108), clock.now());
+		assert_eq!(Ok(event), rx.try_read());
+		for _ in 0..3 {
+			assert_eq!(Ok(Event::NecRepeat), rx.try_read());
+		}
+		assert_eq!(Err(nb::Error::WouldBlock), rx.try_read());
+	}
+
+	#[test]
+	fn rc5() {
+		let (clock, _, mut rx, mut tx) = setup();
+		for toggle in [false, false, true] {
+			tx.send(Event::Rc5 {
+				address: 0x00,
+				command: 0x0c,
+				toggle,
+			});
+		}
+		tx.flush();
+		assert_eq!(Duration::from_micros(3 * 113_778), clock.now());
+		for toggle in [false, false, true] {
+			let event = Event::Rc5 {
+				address: 0x00,
+				command: 0x0c,
+				toggle,
+			};
+			assert_eq!(Ok(event), rx.try_read());
+		}
+	}
+
+	#[test]
+	fn perturbed() {
+		let clock = VirtualClock::new();
+		let line = Wire::new_with_pull(WireState::High);
+		let mut rx = IrReceiver::new(&line, clock.clone());
+		let event = Event::Nec {
+			address: 0x10,
+			command: 0x20,
+		};
+		let mut waveform = event.waveform();
+		// a remote running 10 % slow with some noise
+		waveform = waveform.scale(1.1).jitter(Duration::from_micros(50), 7);
+		// 40 % is out of tolerance
+		waveform.append(&event.waveform().scale(1.4));
+		waveform.schedule(&clock, &[&line]);
+		clock.run();
+		assert_eq!(Ok(event), rx.try_read());
+		assert_eq!(Err(nb::Error::Other(Error::Timing)), rx.try_read());
+	}
+}
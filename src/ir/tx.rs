@@ -0,0 +1,79 @@
+use super::Event;
+use crate::clock::VirtualClock;
+use crate::sync::{Arc, Mutex};
+use crate::wire::PushPullPin;
+use core::convert::Infallible;
+use embedded_hal::digital::OutputPin;
+use std::time::Duration;
+
+/// An IR remote control driving the envelope of its frames onto a wire.
+///
+/// Sending an event drives its first mark immediately and schedules all
+/// further edges on the clock. A new frame can be sent once the frame period
+/// of the previous one has passed, so repeat codes and repeated frames follow
+/// with the timing of a real remote. The wire is driven high while idle.
+///
+/// See the [module documentation](`super`) for an example.
+#[derive(Debug)]
+pub struct IrTransmitter {
+	pin: Arc<Mutex<PushPullPin>>,
+	clock: VirtualClock,
+	busy_until: Duration,
+}
+
+impl IrTransmitter {
+	/// Creates a new transmitter and drives the wire to its idle level.
+	pub fn new(mut pin: PushPullPin, clock: VirtualClock) -> Self {
+		let _ = pin.set_high();
+		IrTransmitter {
+			pin: Arc::new(Mutex::new(pin)),
+			busy_until: clock.now(),
+			clock,
+		}
+	}
+
+	fn is_idle(&self) -> bool {
+		self.clock.now() >= self.busy_until
+	}
+
+	/// Starts sending `event` unless the frame period of the previous one has
+	/// not passed yet.
+	///
+	/// # Panics
+	///
+	/// Panics if the address or command of an RC5 frame is out of range.
+	pub fn try_send(&mut self, event: Event) -> nb::Result<(), Infallible> {
+		if !self.is_idle() {
+			return Err(nb::Error::WouldBlock);
+		}
+		let waveform = event.waveform();
+		waveform.schedule_pin(&self.clock, &self.pin);
+		self.busy_until = self.clock.now() + waveform.duration();
+		Ok(())
+	}
+
+	/// Advances the clock until the previous frame period has passed and
+	/// starts sending `event`.
+	///
+	/// # Panics
+	///
+	/// Panics if the address or command of an RC5 frame is out of range.
+	pub fn send(&mut self, event: Event) {
+		self.flush();
+		let _ = self.try_send(event);
+	}
+
+	/// Returns `Ok` once the frame period of the last event has passed.
+	pub fn try_flush(&mut self) -> nb::Result<(), Infallible> {
+		if self.is_idle() {
+			Ok(())
+		} else {
+			Err(nb::Error::WouldBlock)
+		}
+	}
+
+	/// Advances the clock until the frame period of the last event has passed.
+	pub fn flush(&mut self) {
+		self.clock.advance_to(self.busy_until);
+	}
+}
@@ -0,0 +1,182 @@
+use super::State;
+use crate::clock::half_period_us;
+use crate::wire::{InputOnlyPin, PushPullPin};
+use embedded_hal::delay::DelayUs;
+use embedded_hal::digital::{InputPin, OutputPin};
+
+/// A bit-banged JTAG master.
+///
+/// The master drives TCK, TMS and TDI through [`PushPullPin`]s and samples TDO
+/// through an [`InputOnlyPin`] right before each rising edge of TCK. A floating
+/// TDO is read as `0`. It keeps track of the state of the TAP controllers,
+/// which is only known after a [`reset`](`JtagMaster::reset`).
+///
+/// Scans start and end in the Run-Test/Idle state. All timing is done using
+/// the given delay, which usually is a
+/// [`VirtualClock`](`crate::clock::VirtualClock`). Errors of the delay are
+/// passed on.
+///
+/// See [`JtagTarget`](`super::JtagTarget`) for an example.
+pub struct JtagMaster<D> {
+	tck: PushPullPin,
+	tms: PushPullPin,
+	tdi: PushPullPin,
+	tdo: InputOnlyPin,
+	delay: D,
+	state: State,
+	half_period_us: u32,
+}
+
+impl<D: DelayUs> JtagMaster<D> {
+	/// Creates a new master with a TCK frequency of 500 kHz.
+	pub fn new(
+		mut tck: PushPullPin,
+		mut tms: PushPullPin,
+		mut tdi: PushPullPin,
+		tdo: InputOnlyPin,
+		delay: D,
+	) -> Self {
+		let _ = tck.set_low();
+		let _ = tms.set_high();
+		let _ = tdi.set_high();
+		JtagMaster {
+			tck,
+			tms,
+			tdi,
+			tdo,
+			delay,
+			state: State::TestLogicReset,
+			half_period_us: 1,
+		}
+	}
+
+	/// Sets the TCK frequency.
+	///
+	/// As the delay has a resolution of one microsecond, the frequency is
+	/// rounded down to the next achievable frequency, but at most 500 kHz.
+	pub fn set_frequency(&mut self, hz: u32) {
+		self.half_period_us = half_period_us(hz);
+	}
+
+	/// Returns the state the TAP controllers are expected to be in.
+	pub fn state(&self) -> State {
+		self.state
+	}
+
+	/// Releases the pins and the delay.
+	pub fn free(self) -> (PushPullPin, PushPullPin, PushPullPin, InputOnlyPin, D) {
+		(self.tck, self.tms, self.tdi, self.tdo, self.delay)
+	}
+
+	/// Runs a single TCK cycle and returns the level of TDO before it.
+	fn clock(&mut self, tms: bool, tdi: bool) -> Result<bool, D::Error> {
+		let _ = self.tms.set_state(tms.into());
+		let _ = self.tdi.set_state(tdi.into());
+		self.delay.delay_us(self.half_period_us)?;
+		let tdo = self.tdo.is_high().unwrap_or(false);
+		let _ = self.tck.set_high();
+		self.delay.delay_us(self.half_period_us)?;
+		let _ = self.tck.set_low();
+		self.state = self.state.next(tms);
+		Ok(tdo)
+	}
+
+	/// Resets the TAP controllers with five TCK cycles with TMS high and moves
+	/// them to the Run-Test/Idle state.
+	pub fn reset(&mut self) -> Result<(), D::Error> {
+		for _ in 0..5 {
+			self.clock(true, true)?;
+		}
+		self.move_to(State::RunTestIdle)
+	}
+
+	/// Moves the TAP controllers to `state` on the shortest path.
+	pub fn move_to(&mut self, state: State) -> Result<(), D::Error> {
+		for tms in self.state.path(state) {
+			self.clock(tms, true)?;
+		}
+		Ok(())
+	}
+
+	/// Runs `cycles` TCK cycles in the Run-Test/Idle state.
+	pub fn idle(&mut self, cycles: u32) -> Result<(), D::Error> {
+		self.move_to(State::RunTestIdle)?;
+		for _ in 0..cycles {
+			self.clock(false, true)?;
+		}
+		Ok(())
+	}
+
+	fn scan(&mut self, shift: State, bits: &[bool]) -> Result<Vec<bool>, D::Error> {
+		let mut read = Vec::with_capacity(bits.len());
+		match bits.split_last() {
+			Some((last, bits)) => {
+				self.move_to(shift)?;
+				for bit in bits {
+					read.push(self.clock(false, *bit)?);
+				}
+				// leave the shift state with the last bit
+				read.push(self.clock(true, *last)?);
+			}
+			// capture and update without shifting
+			None => self.move_to(shift.next(true))?,
+		}
+		self.move_to(State::RunTestIdle)?;
+		Ok(read)
+	}
+
+	/// Shifts `bits` into the instruction registers and returns the bits
+	/// shifted out, both in shift order.
+	pub fn shift_ir(&mut self, bits: &[bool]) -> Result<Vec<bool>, D::Error> {
+		self.scan(State::ShiftIr, bits)
+	}
+
+	/// Shifts `bits` into the data registers selected by the current
+	/// instructions and returns the bits shifted out, both in shift order.
+	pub fn shift_dr(&mut self, bits: &[bool]) -> Result<Vec<bool>, D::Error> {
+		self.scan(State::ShiftDr, bits)
+	}
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::*;
+	use crate::clock::VirtualClock;
+	use crate::wire::{Wire, WireState};
+	use std::sync::{Arc, Mutex};
+	use std::time::Duration;
+
+	#[test]
+	fn timing() {
+		let (tck, tms, tdi, tdo) = (Wire::new(), Wire::new(), Wire::new(), Wire::new());
+		let clock = VirtualClock::new();
+		let mut master = JtagMaster::new(
+			tck.connect_push_pull_pin(),
+			tms.connect_push_pull_pin(),
+			tdi.connect_push_pull_pin(),
+			tdo.connect_input_pin(),
+			clock.clone(),
+		);
+		master.set_frequency(100_000);
+		let log = Arc::new(Mutex::new(vec![]));
+		let (l, c, s) = (log.clone(), clock.clone(), tms.clone());
+		tck.on_change(move |state| {
+			if state == WireState::High {
+				l.lock().unwrap().push((c.now(), s.get_state()));
+			}
+		});
+		assert_eq!(Ok(()), master.reset());
+		assert_eq!(Ok(vec![]), master.shift_dr(&[]));
+		assert_eq!(State::RunTestIdle, master.state());
+		let us = |t| Duration::from_micros(t);
+		let levels: Vec<_> = log.lock().unwrap().iter().map(|(_, s)| *s).collect();
+		use WireState::*;
+		assert_eq!(
+			vec![High, High, High, High, High, Low, High, Low, High, High, Low],
+			levels
+		);
+		assert_eq!(us(5), log.lock().unwrap()[0].0);
+		assert_eq!(us(15), log.lock().unwrap()[1].0);
+		assert_eq!(us(110), clock.now());
+	}
+}
@@ -0,0 +1,168 @@
+//! Simulated JTAG scan chains
+//!
+//! JTAG uses four push-pull lines: the clock (TCK), the mode select (TMS) and
+//! the data input (TDI) driven by the master, and the data output (TDO) of the
+//! last device in the chain. Each line maps onto a [`Wire`](`crate::wire::Wire`).
+//! Devices are daisy chained by connecting the TDO of one device to the TDI of
+//! the next one, while TCK and TMS are shared.
+//!
+//! Every device contains a test access port (TAP), a 16 [`State`] machine
+//! advanced by TMS on the rising edges of TCK. It selects whether the
+//! instruction register (IR) or the data register (DR) chosen by the current
+//! instruction is captured, shifted from TDI to TDO, and updated.
+//!
+//! [`JtagMaster`] bit-bangs the lines to move the TAP between states and shift
+//! registers. [`JtagTarget`] is a TAP attached to the wires, which implements
+//! the mandatory BYPASS and an optional IDCODE instruction and passes all
+//! other instructions on to a [`TargetHandler`].
+//!
+//! Register contents are passed as bits in shift order, i.e. the bit closest to
+//! TDO first, which is the least significant bit of a value. [`to_bits`] and
+//! [`from_bits`] convert between both representations.
+
+mod master;
+mod target;
+
+pub use master::JtagMaster;
+pub use target::{JtagTarget, TargetHandler};
+
+/// A state of the TAP controller.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum State {
+	TestLogicReset,
+	RunTestIdle,
+	SelectDrScan,
+	CaptureDr,
+	ShiftDr,
+	Exit1Dr,
+	PauseDr,
+	Exit2Dr,
+	UpdateDr,
+	SelectIrScan,
+	CaptureIr,
+	ShiftIr,
+	Exit1Ir,
+	PauseIr,
+	Exit2Ir,
+	UpdateIr,
+}
+
+impl State {
+	/// All states.
+	pub const ALL: [State; 16] = [
+		State::TestLogicReset,
+		State::RunTestIdle,
+		State::SelectDrScan,
+		State::CaptureDr,
+		State::ShiftDr,
+		State::Exit1Dr,
+		State::PauseDr,
+		State::Exit2Dr,
+		State::UpdateDr,
+		State::SelectIrScan,
+		State::CaptureIr,
+		State::ShiftIr,
+		State::Exit1Ir,
+		State::PauseIr,
+		State::Exit2Ir,
+		State::UpdateIr,
+	];
+
+	/// Returns the state following this one on a rising edge of TCK with the
+	/// given level of TMS.
+	pub fn next(self, tms: bool) -> State {
+		use State::*;
+		match (self, tms) {
+			(TestLogicReset, false) => RunTestIdle,
+			(TestLogicReset, true) => TestLogicReset,
+			(RunTestIdle, false) => RunTestIdle,
+			(RunTestIdle, true) => SelectDrScan,
+			(SelectDrScan, false) => CaptureDr,
+			(SelectDrScan, true) => SelectIrScan,
+			(CaptureDr, false) | (ShiftDr, false) | (Exit2Dr, false) => ShiftDr,
+			(CaptureDr, true) | (ShiftDr, true) => Exit1Dr,
+			(Exit1Dr, false) | (PauseDr, false) => PauseDr,
+			(Exit1Dr, true) | (Exit2Dr, true) => UpdateDr,
+			(PauseDr, true) => Exit2Dr,
+			(UpdateDr, false) | (UpdateIr, false) => RunTestIdle,
+			(UpdateDr, true) | (UpdateIr, true) => SelectDrScan,
+			(SelectIrScan, false) => CaptureIr,
+			(SelectIrScan, true) => TestLogicReset,
+			(CaptureIr, false) | (ShiftIr, false) | (Exit2Ir, false) => ShiftIr,
+			(CaptureIr, true) | (ShiftIr, true) => Exit1Ir,
+			(Exit1Ir, false) | (PauseIr, false) => PauseIr,
+			(Exit1Ir, true) | (Exit2Ir, true) => UpdateIr,
+			(PauseIr, true) => Exit2Ir,
+		}
+	}
+
+	/// Returns the shortest sequence of TMS levels leading from this state to
+	/// `to`, which is empty if both are the same.
+	pub fn path(self, to: State) -> Vec<bool> {
+		// breadth first search, there are only 16 states
+		let mut paths = vec![(self, vec![])];
+		let mut i = 0;
+		while paths[i].0 != to {
+			for tms in [false, true] {
+				let next = paths[i].0.next(tms);
+				if paths.iter().all(|(state, _)| *state != next) {
+					let mut path = paths[i].1.clone();
+					path.push(tms);
+					paths.push((next, path));
+				}
+			}
+			i += 1;
+		}
+		paths.swap_remove(i).1
+	}
+
+	pub(crate) fn is_shift(self) -> bool {
+		matches!(self, State::ShiftDr | State::ShiftIr)
+	}
+}
+
+/// Returns the lowest `len` bits of `value` in shift order.
+pub fn to_bits(value: u64, len: usize) -> Vec<bool> {
+	(0..len).map(|i| i < 64 && value & (1 << i) != 0).collect()
+}
+
+/// Returns the value of up to 64 bits given in shift order.
+pub fn from_bits(bits: &[bool]) -> u64 {
+	bits.iter()
+		.take(64)
+		.enumerate()
+		.fold(0, |value, (i, bit)| value | (*bit as u64) << i)
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn state_machine() {
+		for state in State::ALL {
+			// five clocks with TMS high reset the TAP from any state
+			let reset = (0..5).fold(state, |state, _| state.next(true));
+			assert_eq!(State::TestLogicReset, reset, "{:?}", state);
+			for to in State::ALL {
+				let path = state.path(to);
+				assert!(path.len() <= 8, "{:?} -> {:?}", state, to);
+				let end = path.iter().fold(state, |state, tms| state.next(*tms));
+				assert_eq!(to, end);
+			}
+		}
+		assert_eq!(
+			vec![true, false, false],
+			State::RunTestIdle.path(State::ShiftDr)
+		);
+		assert_eq!(vec![true, true], State::Exit1Ir.path(State::SelectDrScan));
+	}
+
+	#[test]
+	fn bits() {
+		assert_eq!(vec![true, false, true, true], to_bits(0b1101, 4));
+		assert_eq!(0b1101, from_bits(&to_bits(0b1101, 4)));
+		assert_eq!(u64::MAX, from_bits(&to_bits(u64::MAX, 70)));
+		assert!(!to_bits(u64::MAX, 70)[64]);
+	}
+}
@@ -0,0 +1,437 @@
+use super::{from_bits, to_bits, State};
+use crate::sync::{Arc, Mutex};
+use crate::wire::{PushPullPin, Wire, WireState};
+use embedded_hal::digital::OutputPin;
+
+/// Behaviour of a simulated JTAG device.
+///
+/// The methods are called by a [`JtagTarget`] for all instructions except
+/// BYPASS and IDCODE, which the target implements itself.
+pub trait TargetHandler {
+	/// Called in the Capture-DR state, returns the bits loaded into the data
+	/// register selected by `instruction`.
+	///
+	/// The number of bits is the length of the register. Returning `None` or
+	/// no bits selects the one bit bypass register instead.
+	fn capture_dr(&mut self, instruction: u32) -> Option<Vec<bool>>;
+
+	/// Called in the Update-DR state with the bits shifted into the data
+	/// register selected by `instruction`.
+	fn update_dr(&mut self, instruction: u32, bits: &[bool]);
+
+	/// Called in the Update-IR state when `instruction` becomes the current
+	/// instruction.
+	fn update_ir(&mut self, _instruction: u32) {}
+
+	/// Called for every clock cycle staying in the Run-Test/Idle state, e.g.
+	/// to run a self test or program a flash page.
+	fn run_test_idle(&mut self, _instruction: u32) {}
+
+	/// Called when the TAP enters the Test-Logic-Reset state.
+	fn reset(&mut self) {}
+}
+
+#[derive(Debug)]
+struct Tap<H> {
+	handler: H,
+	tdo: PushPullPin,
+	state: State,
+	ir_length: usize,
+	instruction: u32,
+	idcode: Option<(u32, u32)>,
+	/// The register being captured, shifted and updated, TDO end first.
+	shift: Vec<bool>,
+}
+
+impl<H: TargetHandler> Tap<H> {
+	fn bypass(&self) -> u32 {
+		(u64::MAX >> (64 - self.ir_length)) as u32
+	}
+
+	fn reset(&mut self) {
+		self.instruction = match self.idcode {
+			Some((instruction, _)) => instruction,
+			None => self.bypass(),
+		};
+		self.handler.reset();
+	}
+
+	fn capture_dr(&mut self) -> Vec<bool> {
+		match self.idcode {
+			_ if self.instruction == self.bypass() => vec![false],
+			Some((instruction, code)) if instruction == self.instruction => {
+				to_bits(code.into(), 32)
+			}
+			_ => match self.handler.capture_dr(self.instruction) {
+				Some(bits) if !bits.is_empty() => bits,
+				_ => vec![false],
+			},
+		}
+	}
+
+	fn update_dr(&mut self) {
+		let builtin = self.instruction == self.bypass()
+			|| matches!(self.idcode, Some((instruction, _)) if instruction == self.instruction);
+		if !builtin {
+			self.handler.update_dr(self.instruction, &self.shift);
+		}
+	}
+
+	/// Samples TMS and TDI on a rising edge of TCK.
+	fn rising(&mut self, tms: bool, tdi: bool) {
+		match self.state {
+			// the two lowest bits of the captured IR are fixed to 0b01
+			State::CaptureIr => self.shift = to_bits(1, self.ir_length),
+			State::CaptureDr => self.shift = self.capture_dr(),
+			State::ShiftIr | State::ShiftDr => {
+				self.shift.remove(0);
+				self.shift.push(tdi);
+			}
+			State::RunTestIdle if !tms => self.handler.run_test_idle(self.instruction),
+			_ => {}
+		}
+		let state = self.state.next(tms);
+		if state == State::TestLogicReset && self.state != State::TestLogicReset {
+			self.reset();
+		}
+		self.state = state;
+	}
+
+	/// Drives TDO and updates registers on a falling edge of TCK.
+	fn falling(&mut self) {
+		match self.state {
+			State::UpdateIr => {
+				self.instruction = from_bits(&self.shift) as u32;
+				self.handler.update_ir(self.instruction);
+			}
+			State::UpdateDr => self.update_dr(),
+			_ => {}
+		}
+		let _ = match self.state {
+			state if state.is_shift() && self.shift[0] => self.tdo.set_high(),
+			state if state.is_shift() => self.tdo.set_low(),
+			_ => {
+				self.tdo.release();
+				Ok(())
+			}
+		};
+	}
+}
+
+/// A simulated JTAG device attached to TCK, TMS, TDI and TDO wires.
+///
+/// The TAP controller samples TMS and TDI on rising edges of TCK and changes
+/// TDO on falling edges. TDO is driven through its own [`PushPullPin`] in the
+/// Shift-IR and Shift-DR states only and released otherwise. A floating TMS
+/// or TDI is read as high, like with the pull-ups required by the standard.
+///
+/// The instruction register has a length of up to 32 bits. The instruction
+/// with all bits set is BYPASS, which selects a one bit register captured as
+/// `0`. If an IDCODE instruction was set with
+/// [`set_idcode`](`JtagTarget::set_idcode`), it selects a 32 bit register
+/// with the device identification and is loaded on reset, otherwise BYPASS
+/// is. All other instructions are passed on to the [`TargetHandler`].
+///
+/// # Examples
+///
+/// ```
+/// use embedded_hal_sync_pins::clock::VirtualClock;
+/// use embedded_hal_sync_pins::jtag::{from_bits, to_bits, JtagMaster, JtagTarget, TargetHandler};
+/// use embedded_hal_sync_pins::wire::Wire;
+///
+/// /// An 8 bit scratch register selected by instruction 0x2.
+/// struct Scratch(u8);
+///
+/// impl TargetHandler for Scratch {
+///     fn capture_dr(&mut self, instruction: u32) -> Option<Vec<bool>> {
+///         (instruction == 0x2).then(|| to_bits(self.0.into(), 8))
+///     }
+///
+///     fn update_dr(&mut self, _instruction: u32, bits: &[bool]) {
+///         self.0 = from_bits(bits) as u8;
+///     }
+/// }
+///
+/// let (tck, tms, tdi, tdo) = (Wire::new(), Wire::new(), Wire::new(), Wire::new());
+/// let target = JtagTarget::new(&tck, &tms, &tdi, &tdo, 4, Scratch(0x5a));
+/// target.set_idcode(0x1, 0x4ba0_0477);
+/// let mut master = JtagMaster::new(
+///     tck.connect_push_pull_pin(),
+///     tms.connect_push_pull_pin(),
+///     tdi.connect_push_pull_pin(),
+///     tdo.connect_input_pin(),
+///     VirtualClock::new(),
+/// );
+/// master.reset().unwrap();
+/// let idcode = master.shift_dr(&[false; 32]).unwrap();
+/// assert_eq!(0x4ba0_0477, from_bits(&idcode));
+/// master.shift_ir(&to_bits(0x2, 4)).unwrap();
+/// let old = master.shift_dr(&to_bits(0xc3, 8)).unwrap();
+/// assert_eq!(0x5a, from_bits(&old));
+/// assert_eq!(0xc3, target.with_handler(|scratch| scratch.0));
+/// ```
+#[derive(Debug)]
+pub struct JtagTarget<H> {
+	tap: Arc<Mutex<Tap<H>>>,
+}
+
+impl<H> Clone for JtagTarget<H> {
+	fn clone(&self) -> Self {
+		JtagTarget {
+			tap: self.tap.clone(),
+		}
+	}
+}
+
+impl<H: TargetHandler + Send + 'static> JtagTarget<H> {
+	/// Attaches a new target with an instruction register of `ir_length`
+	/// bits to the given wires. The TAP starts in the Test-Logic-Reset state.
+	///
+	/// # Panics
+	///
+	/// Panics if `ir_length` is not in `2..=32`.
+	pub fn new(
+		tck: &Wire,
+		tms: &Wire,
+		tdi: &Wire,
+		tdo: &Wire,
+		ir_length: usize,
+		handler: H,
+	) -> Self {
+		assert!(
+			(2..=32).contains(&ir_length),
+			"invalid instruction register length: {}",
+			ir_length
+		);
+		let mut tap = Tap {
+			handler,
+			tdo: tdo.connect_push_pull_pin(),
+			state: State::TestLogicReset,
+			ir_length,
+			instruction: 0,
+			idcode: None,
+			shift: vec![],
+		};
+		tap.reset();
+		let tap = Arc::new(Mutex::new(tap));
+		let (t, tms, tdi) = (tap.clone(), tms.clone(), tdi.clone());
+		tck.on_change(move |state| match state {
+			WireState::High => t.lock().unwrap().rising(
+				tms.get_state() != WireState::Low,
+				tdi.get_state() != WireState::Low,
+			),
+			WireState::Low => t.lock().unwrap().falling(),
+			WireState::Floating => {}
+		});
+		JtagTarget { tap }
+	}
+}
+
+impl<H> JtagTarget<H> {
+	/// Adds an IDCODE instruction selecting a register with `code`, which is
+	/// loaded into the instruction register on reset.
+	///
+	/// The least significant bit of an IDCODE is `1`, so it can be told apart
+	/// from the bypass register after a reset.
+	pub fn set_idcode(&self, instruction: u32, code: u32) {
+		let mut tap = self.tap.lock().unwrap();
+		if tap.state == State::TestLogicReset {
+			tap.instruction = instruction;
+		}
+		tap.idcode = Some((instruction, code));
+	}
+
+	pub fn state(&self) -> State {
+		self.tap.lock().unwrap().state
+	}
+
+	/// Returns the current instruction.
+	pub fn instruction(&self) -> u32 {
+		self.tap.lock().unwrap().instruction
+	}
+
+	/// Calls `f` with the handler of this target.
+	pub fn with_handler<F, R>(&self, f: F) -> R
+	where
+		F: FnOnce(&mut H) -> R,
+	{
+		f(&mut self.tap.lock().unwrap().handler)
+	}
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::*;
+	use crate::clock::VirtualClock;
+	use crate::jtag::JtagMaster;
+
+	const EXTEST: u32 = 0x0;
+	const SAMPLE: u32 = 0x2;
+
+	/// A boundary scan cell per pin, recording all calls.
+	#[derive(Default)]
+	struct Boundary {
+		inputs: Vec<bool>,
+		outputs: Vec<bool>,
+		log: Vec<String>,
+	}
+
+	impl TargetHandler for Boundary {
+		fn capture_dr(&mut self, instruction: u32) -> Option<Vec<bool>> {
+			self.log.push(format!("capture {:#x}", instruction));
+			match instruction {
+				EXTEST | SAMPLE => Some(self.inputs.clone()),
+				_ => None,
+			}
+		}
+
+		fn update_dr(&mut self, instruction: u32, bits: &[bool]) {
+			self.log.push(format!("update {:#x}", instruction));
+			if instruction == EXTEST {
+				self.outputs = bits.to_vec();
+			}
+		}
+
+		fn update_ir(&mut self, instruction: u32) {
+			self.log.push(format!("instruction {:#x}", instruction));
+		}
+
+		fn run_test_idle(&mut self, _instruction: u32) {
+			self.log.push("idle".into());
+		}
+
+		fn reset(&mut self) {
+			self.log.push("reset".into());
+		}
+	}
+
+	struct Chain {
+		tck: Wire,
+		tms: Wire,
+		tdi: Wire,
+		tdo: Wire,
+	}
+
+	impl Chain {
+		fn new() -> Self {
+			Chain {
+				tck: Wire::new(),
+				tms: Wire::new(),
+				tdi: Wire::new(),
+				tdo: Wire::new(),
+			}
+		}
+
+		fn master(&self) -> JtagMaster<VirtualClock> {
+			JtagMaster::new(
+				self.tck.connect_push_pull_pin(),
+				self.tms.connect_push_pull_pin(),
+				self.tdi.connect_push_pull_pin(),
+				self.tdo.connect_input_pin(),
+				VirtualClock::new(),
+			)
+		}
+
+		fn attach(&self, tdi: &Wire, tdo: &Wire, pins: usize) -> JtagTarget<Boundary> {
+			let boundary = Boundary {
+				inputs: to_bits(0b1011, pins),
+				..Default::default()
+			};
+			JtagTarget::new(&self.tck, &self.tms, tdi, tdo, 4, boundary)
+		}
+	}
+
+	#[test]
+	fn instructions() {
+		let chain = Chain::new();
+		let target = chain.attach(&chain.tdi, &chain.tdo, 6);
+		let mut master = chain.master();
+		assert_eq!(Ok(()), master.reset());
+		assert_eq!(State::RunTestIdle, target.state());
+		assert_eq!(0xf, target.instruction());
+		// the captured instruction register reads 0b01 in its lowest bits
+		assert_eq!(
+			Ok(to_bits(0b0001, 4)),
+			master.shift_ir(&to_bits(SAMPLE.into(), 4))
+		);
+		assert_eq!(SAMPLE, target.instruction());
+		assert_eq!(Ok(to_bits(0b1011, 6)), master.shift_dr(&[true; 6]));
+		master.shift_ir(&to_bits(EXTEST.into(), 4)).unwrap();
+		assert_eq!(Ok(to_bits(0b1011, 6)), master.shift_dr(&to_bits(0b110, 6)));
+		assert_eq!(
+			to_bits(0b110, 6),
+			target.with_handler(|b| b.outputs.clone())
+		);
+		assert_eq!(Ok(()), master.idle(2));
+		assert_eq!(Ok(()), master.reset());
+		assert_eq!(
+			vec![
+				"reset",
+				"instruction 0x2",
+				"capture 0x2",
+				"update 0x2",
+				"instruction 0x0",
+				"capture 0x0",
+				"update 0x0",
+				"idle",
+				"idle",
+				"reset"
+			],
+			target.with_handler(|b| b.log.clone())
+		);
+		assert_eq!(0xf, target.instruction());
+	}
+
+	#[test]
+	fn bypass_and_idcode() {
+		let chain = Chain::new();
+		let target = chain.attach(&chain.tdi, &chain.tdo, 6);
+		let mut master = chain.master();
+		assert_eq!(Ok(()), master.reset());
+		// the bypass register delays the data by one bit
+		let bits = to_bits(0b1101, 4);
+		assert_eq!(
+			Ok(vec![false, true, false, true, true]),
+			master.shift_dr(&[&bits[..], &[false]].concat())
+		);
+		target.set_idcode(0x1, 0x1234_5677);
+		assert_eq!(Ok(()), master.reset());
+		assert_eq!(0x1, target.instruction());
+		let idcode = master.shift_dr(&[false; 32]).unwrap();
+		assert_eq!(0x1234_5677, from_bits(&idcode));
+		// unknown instructions select the bypass register as well
+		master.shift_ir(&to_bits(0x5, 4)).unwrap();
+		assert_eq!(Ok(vec![false, true]), master.shift_dr(&[true, false]));
+		// the handler never sees the builtin instructions
+		assert_eq!(
+			vec![
+				"reset",
+				"reset",
+				"instruction 0x5",
+				"capture 0x5",
+				"update 0x5"
+			],
+			target.with_handler(|b| b.log.clone())
+		);
+	}
+
+	#[test]
+	fn daisy_chain() {
+		let chain = Chain::new();
+		let between = Wire::new();
+		let first = chain.attach(&chain.tdi, &between, 2);
+		let second = chain.attach(&between, &chain.tdo, 4);
+		let mut master = chain.master();
+		assert_eq!(Ok(()), master.reset());
+		// TDO is only driven while shifting
+		assert_eq!(WireState::Floating, chain.tdo.get_state());
+		// instructions are shifted through both registers, the second target
+		// is closest to TDO
+		let ir = [to_bits(SAMPLE.into(), 4), to_bits(0xf, 4)].concat();
+		assert_eq!(Ok(to_bits(0x11, 8)), master.shift_ir(&ir));
+		assert_eq!(0xf, first.instruction());
+		assert_eq!(SAMPLE, second.instruction());
+		assert_eq!(Ok(to_bits(0b1011, 5)), master.shift_dr(&[false; 5]));
+		assert_eq!(WireState::Floating, chain.tdo.get_state());
+	}
+}
@@ -1,2 +1,46 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "std")]
+pub mod can;
+#[cfg(feature = "std")]
+pub mod clock;
+#[cfg(feature = "cs-wire")]
+pub mod cs_wire;
+#[cfg(feature = "std")]
+pub mod dht;
+#[cfg(feature = "std")]
+pub mod encoder;
+#[cfg(feature = "std")]
+pub mod hcsr04;
+#[cfg(feature = "std")]
+pub mod i2c;
+pub mod inverted;
+#[cfg(feature = "std")]
+pub mod ir;
+#[cfg(feature = "std")]
+pub mod jtag;
+#[cfg(feature = "std")]
+pub mod manchester;
+pub mod net;
+#[cfg(feature = "std")]
+pub mod onewire;
 pub mod pins;
+#[cfg(feature = "std")]
+pub mod ps2;
+#[cfg(feature = "std")]
+pub mod rs485;
+#[cfg(feature = "std")]
+pub mod spi;
+mod state;
+#[cfg(feature = "std")]
+pub mod stimulus;
+#[cfg(feature = "std")]
+pub mod swd;
+#[cfg(feature = "std")]
+pub mod sync;
+#[cfg(feature = "std")]
+pub mod uart;
+#[cfg(feature = "std")]
 pub mod wire;
+#[cfg(feature = "std")]
+pub mod ws2812;
@@ -0,0 +1,201 @@
+//! Manchester coded links
+//!
+//! Manchester coding puts a transition in the middle of every bit, so the
+//! receiver can recover the clock from the data. Three codings are supported:
+//!
+//! - [`Coding::Ieee`], as in IEEE 802.3, sends a one as a rising and a zero as
+//!   a falling edge in the middle of the bit.
+//! - [`Coding::Thomas`], the original convention of G. E. Thomas, sends a one
+//!   as a falling and a zero as a rising edge.
+//! - [`Coding::Differential`] has an edge in the middle of every bit and an
+//!   additional one at the start of a zero, so only the presence of edges
+//!   matters and not their direction.
+//!
+//! The line idles low. A frame starts with a start bit whose first half is low,
+//! followed by the bytes of the frame, LSB first, and ends when the line has
+//! been idle for more than a bit period. [`ManchesterTx`] sends frames on a
+//! wire and [`ManchesterRx`] decodes them, both timed on a
+//! [`VirtualClock`](`crate::clock::VirtualClock`). [`waveform`] returns the
+//! levels of a frame, e.g. to perturb it with the functions in
+//! [`stimulus`](`crate::stimulus`).
+//!
+//! # Examples
+//!
+//! ```
+//! use embedded_hal_sync_pins::clock::VirtualClock;
+//! use embedded_hal_sync_pins::manchester::{self, Coding, Config, ManchesterRx};
+//! use embedded_hal_sync_pins::wire::Wire;
+//! use std::time::Duration;
+//!
+//! let clock = VirtualClock::new();
+//! let line = Wire::new();
+//! let config = Config {
+//!     coding: Coding::Differential,
+//!     jitter: Duration::from_micros(10),
+//!     ..Default::default()
+//! };
+//! let mut rx = ManchesterRx::new(&line, clock.clone(), config);
+//! // a sensor with a noisy clock
+//! manchester::waveform(&config, b"\x42\x17")
+//!     .jitter(Duration::from_micros(10), 1)
+//!     .schedule(&clock, &[&line]);
+//! clock.run();
+//! assert_eq!(Ok(vec![0x42, 0x17]), rx.try_read());
+//! ```
+
+mod rx;
+mod tx;
+
+pub use rx::ManchesterRx;
+pub use tx::ManchesterTx;
+
+use crate::stimulus::Waveform;
+use crate::wire::WireState;
+use std::fmt;
+use std::time::Duration;
+
+/// Meaning of the edges of a Manchester coded signal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Coding {
+	/// A one is a rising edge in the middle of the bit.
+	Ieee,
+	/// A one is a falling edge in the middle of the bit.
+	Thomas,
+	/// A zero has an edge at the start of the bit, a one does not.
+	Differential,
+}
+
+/// Coding, bit rate and clock recovery tolerance of a link.
+///
+/// The default is IEEE coding at 10 kbit/s, accepting edges up to 10 µs from
+/// their nominal time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+	pub coding: Coding,
+	pub bit_rate: u32,
+	/// Maximum time an edge may be off from its nominal time, less than an
+	/// eighth of a bit. As the receiver recovers the clock from the last edge
+	/// in the middle of a bit, it accepts intervals between edges that are off
+	/// by up to twice this time.
+	pub jitter: Duration,
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Config {
+			coding: Coding::Ieee,
+			bit_rate: 10_000,
+			jitter: Duration::from_micros(10),
+		}
+	}
+}
+
+impl Config {
+	pub(crate) fn validate(&self) {
+		assert!(self.bit_rate > 0, "invalid bit rate: {}", self.bit_rate);
+		assert!(
+			self.jitter * 8 < self.offset(2),
+			"jitter too large: {:?}",
+			self.jitter
+		);
+	}
+
+	/// Time from the start of a frame to `half_bits` half bit periods later.
+	pub(crate) fn offset(&self, half_bits: u32) -> Duration {
+		Duration::from_nanos(half_bits as u64 * 500_000_000 / self.bit_rate as u64)
+	}
+}
+
+/// An error while receiving a frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+	/// An edge was too far off from where the recovered clock expected it.
+	CodeViolation,
+	/// The frame was empty or did not end after a whole byte.
+	Framing,
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Error::CodeViolation => write!(f, "code violation"),
+			Error::Framing => write!(f, "framing error"),
+		}
+	}
+}
+
+impl std::error::Error for Error {}
+
+/// Returns the levels of a frame with the given bytes, including the start
+/// bit, followed by two bit periods of idle time.
+///
+/// # Panics
+///
+/// Panics if the configuration is invalid.
+pub fn waveform(config: &Config, frame: &[u8]) -> Waveform {
+	config.validate();
+	let start = config.coding != Coding::Thomas;
+	let mut bits = vec![start];
+	for byte in frame {
+		bits.extend((0..8).map(|i| byte & (1 << i) != 0));
+	}
+	let mut waveform = Waveform::new(1);
+	let mut level = false;
+	for (i, bit) in bits.iter().enumerate() {
+		let first = match config.coding {
+			Coding::Ieee => !bit,
+			Coding::Thomas => *bit,
+			Coding::Differential => level ^ !bit,
+		};
+		level = !first;
+		let i = 2 * i as u32;
+		waveform.set(config.offset(i), 0, state(first));
+		waveform.set(config.offset(i + 1), 0, state(level));
+	}
+	let end = 2 * bits.len() as u32;
+	waveform.set(config.offset(end), 0, WireState::Low);
+	waveform.hold(config.offset(end + 4));
+	waveform
+}
+
+fn state(high: bool) -> WireState {
+	if high {
+		WireState::High
+	} else {
+		WireState::Low
+	}
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::*;
+
+	/// Returns the levels in the middle of every half bit.
+	fn halves(config: &Config, frame: &[u8]) -> Vec<bool> {
+		let waveform = waveform(config, frame);
+		let quarter = config.offset(1) / 2;
+		(0..2 + 16 * frame.len() as u32)
+			.map(|i| waveform.state_at(config.offset(i) + quarter, 0) == WireState::High)
+			.collect()
+	}
+
+	#[test]
+	fn codings() {
+		let mut config = Config::default();
+		let bits = |s: &str| {
+			s.chars()
+				.filter(|c| *c != ' ')
+				.map(|c| c == '1')
+				.collect::<Vec<_>>()
+		};
+		// the start bit, then 0x05 LSB first
+		assert_eq!(bits("01 01 10 01 10 10 10 10 10"), halves(&config, &[0x05]));
+		config.coding = Coding::Thomas;
+		assert_eq!(bits("01 10 01 10 01 01 01 01 01"), halves(&config, &[0x05]));
+		config.coding = Coding::Differential;
+		assert_eq!(bits("01 10 10 01 01 01 01 01 01"), halves(&config, &[0x05]));
+		let waveform = waveform(&config, &[0x05]);
+		assert_eq!(config.offset(22), waveform.duration());
+		assert_eq!(WireState::Low, waveform.state_at(config.offset(18), 0));
+	}
+}
@@ -0,0 +1,235 @@
+use super::{Coding, Config, Error};
+use crate::clock::VirtualClock;
+use crate::sync::{Arc, Mutex};
+use crate::wire::{Wire, WireState};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+#[derive(Debug)]
+enum State {
+	Idle,
+	Receiving {
+		/// Time of the last edge in the middle of a bit.
+		mid: Duration,
+		/// Whether there was an edge at the start of the current bit.
+		boundary: bool,
+		bits: Vec<bool>,
+	},
+	/// Waiting for the end of a frame with a code violation.
+	Discarding,
+}
+
+#[derive(Debug)]
+struct Receiver {
+	config: Config,
+	state: State,
+	edges: u64,
+	received: VecDeque<Result<Vec<u8>, Error>>,
+}
+
+impl Receiver {
+	/// Handles an edge and returns its number.
+	fn edge(&mut self, now: Duration, rising: bool) -> u64 {
+		self.edges += 1;
+		let tolerance = 2 * self.config.jitter;
+		let near = |interval: Duration, nominal: Duration| {
+			interval + tolerance >= nominal && interval <= nominal + tolerance
+		};
+		let (half, bit) = (self.config.offset(1), self.config.offset(2));
+		let coding = self.config.coding;
+		match &mut self.state {
+			State::Idle if rising => {
+				// the middle of the start bit
+				self.state = State::Receiving {
+					mid: now,
+					boundary: false,
+					bits: vec![],
+				};
+			}
+			State::Receiving {
+				mid,
+				boundary,
+				bits,
+			} => {
+				let interval = now - *mid;
+				if near(interval, half) && !*boundary {
+					*boundary = true;
+				} else if near(interval, bit) {
+					bits.push(match coding {
+						Coding::Ieee => rising,
+						Coding::Thomas => !rising,
+						Coding::Differential => !*boundary,
+					});
+					*mid = now;
+					*boundary = false;
+				} else {
+					self.received.push_back(Err(Error::CodeViolation));
+					self.state = State::Discarding;
+				}
+			}
+			_ => {}
+		}
+		self.edges
+	}
+
+	/// Ends the current frame if there was no edge since edge `edges`.
+	fn timeout(&mut self, edges: u64) {
+		if self.edges != edges {
+			return;
+		}
+		if let State::Receiving { bits, .. } = std::mem::replace(&mut self.state, State::Idle) {
+			let frame = if !bits.is_empty() && bits.len() % 8 == 0 {
+				Ok(bits
+					.chunks(8)
+					.map(|byte| byte.iter().rev().fold(0, |b, bit| (b << 1) | *bit as u8))
+					.collect())
+			} else {
+				Err(Error::Framing)
+			};
+			self.received.push_back(frame);
+		}
+	}
+}
+
+/// A Manchester decoder recovering the clock and frames from a wire.
+///
+/// The receiver synchronizes to the rising edge in the middle of the start
+/// bit and expects every following edge half a bit or a whole bit after the
+/// last edge in the middle of a bit, within twice the configured jitter. Edges
+/// in the middle of a bit resynchronize the recovered clock, so the bit rates
+/// of both sides may differ as long as the edges stay within the tolerance. A
+/// frame ends once no edge followed for a bit and a quarter, received frames
+/// and errors are queued until they are read. After a code violation, the
+/// rest of the frame is discarded. A start bit without any data, e.g. from a
+/// glitch, is reported as [`Error::Framing`].
+#[derive(Clone, Debug)]
+pub struct ManchesterRx {
+	receiver: Arc<Mutex<Receiver>>,
+}
+
+impl ManchesterRx {
+	/// Attaches a new decoder to the given wire.
+	///
+	/// # Panics
+	///
+	/// Panics if the configuration is invalid.
+	pub fn new(line: &Wire, clock: VirtualClock, config: Config) -> Self {
+		config.validate();
+		let receiver = Arc::new(Mutex::new(Receiver {
+			config,
+			state: State::Idle,
+			edges: 0,
+			received: VecDeque::new(),
+		}));
+		let timeout = config.offset(2) + config.offset(1) / 2;
+		let r = receiver.clone();
+		line.on_change(move |state| {
+			let edges = r
+				.lock()
+				.unwrap()
+				.edge(clock.now(), state == WireState::High);
+			let r = r.clone();
+			clock.schedule_in(timeout, move || r.lock().unwrap().timeout(edges));
+		});
+		ManchesterRx { receiver }
+	}
+
+	pub fn config(&self) -> Config {
+		self.receiver.lock().unwrap().config
+	}
+
+	/// Returns the next received frame or error, if any.
+	pub fn try_read(&mut self) -> nb::Result<Vec<u8>, Error> {
+		match self.receiver.lock().unwrap().received.pop_front() {
+			Some(Ok(frame)) => Ok(frame),
+			Some(Err(e)) => Err(nb::Error::Other(e)),
+			None => Err(nb::Error::WouldBlock),
+		}
+	}
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::super::{waveform, ManchesterTx};
+	use super::*;
+	use crate::stimulus::Waveform;
+
+	fn config(coding: Coding) -> Config {
+		Config {
+			coding,
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn codings() {
+		for coding in [Coding::Ieee, Coding::Thomas, Coding::Differential] {
+			let clock = VirtualClock::new();
+			let line = Wire::new();
+			let mut rx = ManchesterRx::new(&line, clock.clone(), config(coding));
+			let mut tx = ManchesterTx::new(line.connect_push_pull_pin(), clock, config(coding));
+			for frame in [&[0x00][..], &[0xff, 0x00, 0xa5], b"hello"] {
+				tx.write(frame);
+			}
+			tx.flush();
+			for frame in [&[0x00][..], &[0xff, 0x00, 0xa5], b"hello"] {
+				assert_eq!(Ok(frame.to_vec()), rx.try_read(), "{:?}", coding);
+			}
+			assert_eq!(Err(nb::Error::WouldBlock), rx.try_read());
+		}
+	}
+
+	#[test]
+	fn clock_recovery() {
+		let clock = VirtualClock::new();
+		let line = Wire::new();
+		let mut rx = ManchesterRx::new(&line, clock.clone(), config(Coding::Ieee));
+		let frame: Vec<u8> = (0..64).collect();
+		let sent = waveform(&config(Coding::Ieee), &frame);
+		let mut waveform = Waveform::new(1);
+		// senders 5 % off, which a fixed clock would lose after a few bits
+		for (factor, seed) in [(1.05, 1), (0.95, 2)] {
+			waveform.append(&sent.scale(factor).jitter(Duration::from_micros(4), seed));
+		}
+		// 22 % is too far off
+		waveform.append(&sent.scale(1.22));
+		waveform.schedule(&clock, &[&line]);
+		clock.run();
+		assert_eq!(Ok(frame.clone()), rx.try_read());
+		assert_eq!(Ok(frame), rx.try_read());
+		assert_eq!(Err(nb::Error::Other(Error::CodeViolation)), rx.try_read());
+		assert_eq!(Err(nb::Error::WouldBlock), rx.try_read());
+	}
+
+	#[test]
+	fn errors() {
+		let config = config(Coding::Differential);
+		let clock = VirtualClock::new();
+		let line = Wire::new();
+		let mut rx = ManchesterRx::new(&line, clock.clone(), config);
+		let sent = waveform(&config, &[0x12]);
+		// a start bit without data
+		let empty = waveform(&config, &[]);
+		// jitter beyond the tolerance
+		let mut waveform = sent.jitter(Duration::from_micros(24), 3);
+		// a frame cut off after the fourth bit
+		let mut cut = Waveform::new(1);
+		for edge in sent
+			.edges()
+			.iter()
+			.filter(|edge| edge.at < config.offset(10))
+		{
+			cut.set(edge.at, 0, edge.state);
+		}
+		cut.set(config.offset(10), 0, WireState::Low);
+		cut.hold(config.offset(14));
+		waveform.append(&cut);
+		waveform.append(&empty);
+		waveform.schedule(&clock, &[&line]);
+		clock.run();
+		assert_eq!(Err(nb::Error::Other(Error::CodeViolation)), rx.try_read());
+		assert_eq!(Err(nb::Error::Other(Error::Framing)), rx.try_read());
+		assert_eq!(Err(nb::Error::Other(Error::Framing)), rx.try_read());
+		assert_eq!(Err(nb::Error::WouldBlock), rx.try_read());
+	}
+}
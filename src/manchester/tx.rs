@@ -0,0 +1,78 @@
+use super::{waveform, Config};
+use crate::clock::VirtualClock;
+use crate::sync::{Arc, Mutex};
+use crate::wire::PushPullPin;
+use core::convert::Infallible;
+use embedded_hal::digital::OutputPin;
+use std::time::Duration;
+
+/// A Manchester encoder driving a wire.
+///
+/// Writing a frame drives its first level immediately and schedules all
+/// further edges on the clock. A new frame can be written once the previous
+/// one and two bit periods of idle time after it have been sent.
+#[derive(Debug)]
+pub struct ManchesterTx {
+	pin: Arc<Mutex<PushPullPin>>,
+	clock: VirtualClock,
+	config: Config,
+	busy_until: Duration,
+}
+
+impl ManchesterTx {
+	/// Creates a new encoder and drives the line to its idle level.
+	///
+	/// # Panics
+	///
+	/// Panics if the configuration is invalid.
+	pub fn new(mut pin: PushPullPin, clock: VirtualClock, config: Config) -> Self {
+		config.validate();
+		let _ = pin.set_low();
+		ManchesterTx {
+			pin: Arc::new(Mutex::new(pin)),
+			busy_until: clock.now(),
+			clock,
+			config,
+		}
+	}
+
+	pub fn config(&self) -> Config {
+		self.config
+	}
+
+	fn is_idle(&self) -> bool {
+		self.clock.now() >= self.busy_until
+	}
+
+	/// Starts sending `frame` unless the previous frame is still being sent.
+	pub fn try_write(&mut self, frame: &[u8]) -> nb::Result<(), Infallible> {
+		if !self.is_idle() {
+			return Err(nb::Error::WouldBlock);
+		}
+		let waveform = waveform(&self.config, frame);
+		waveform.schedule_pin(&self.clock, &self.pin);
+		self.busy_until = self.clock.now() + waveform.duration();
+		Ok(())
+	}
+
+	/// Advances the clock until the previous frame has been sent and starts
+	/// sending `frame`.
+	pub fn write(&mut self, frame: &[u8]) {
+		self.flush();
+		let _ = self.try_write(frame);
+	}
+
+	/// Returns `Ok` once the last frame has been sent completely.
+	pub fn try_flush(&mut self) -> nb::Result<(), Infallible> {
+		if self.is_idle() {
+			Ok(())
+		} else {
+			Err(nb::Error::WouldBlock)
+		}
+	}
+
+	/// Advances the clock until the last frame has been sent.
+	pub fn flush(&mut self) {
+		self.clock.advance_to(self.busy_until);
+	}
+}
@@ -0,0 +1,131 @@
+//! Pin backends
+//!
+//! The pin types in [`pins`](`crate::pins`) do not care where their level is
+//! stored. They are generic over a backend that implements the traits in this
+//! module, so the same pin implementation can be used with an
+//! [`AtomicPinState`](`crate::pins::AtomicPinState`), a connection to a
+//! [`Wire`](`crate::wire::Wire`) or any user defined backend, e.g. a net that
+//! is attached over the network or one that records all changes.
+//!
+//! Backends are shared between several pins and threads, so all methods take
+//! `&self` and implementations are expected to use interior mutability.
+
+pub use crate::state::WireState;
+
+/// A net whose resolved level can be read.
+pub trait Net {
+	/// Returns the resolved level of the net, as seen by an input.
+	fn level(&self) -> WireState;
+}
+
+/// A connection to a net that can drive a level onto it.
+///
+/// The level driven by a backend is not necessarily the level of the net, e.g.
+/// a wire may be driven by several pins or pulled to a level if no pin drives
+/// it. [`level`](`Net::level`) always returns the resolved level, while
+/// [`driven`](`PinBackend::driven`) returns the level of this connection only.
+pub trait PinBackend: Net {
+	/// Returns the level this backend drives onto the net.
+	fn driven(&self) -> WireState;
+
+	/// Drives `state` onto the net.
+	fn drive(&self, state: WireState);
+
+	/// Stops driving the net.
+	fn release(&self) {
+		self.drive(WireState::Floating);
+	}
+
+	/// Drives a new level based on the currently driven level.
+	///
+	/// Implementations have to ensure that no other change of the driven level
+	/// happens between reading the current and storing the new level.
+	fn update_driven<F>(&self, f: F)
+	where
+		F: FnMut(WireState) -> WireState;
+}
+
+impl<T: Net + ?Sized> Net for &T {
+	fn level(&self) -> WireState {
+		(**self).level()
+	}
+}
+
+impl<T: PinBackend + ?Sized> PinBackend for &T {
+	fn driven(&self) -> WireState {
+		(**self).driven()
+	}
+
+	fn drive(&self, state: WireState) {
+		(**self).drive(state)
+	}
+
+	fn release(&self) {
+		(**self).release()
+	}
+
+	fn update_driven<F>(&self, f: F)
+	where
+		F: FnMut(WireState) -> WireState,
+	{
+		(**self).update_driven(f)
+	}
+}
+
+#[cfg(feature = "std")]
+impl<T: Net + ?Sized> Net for std::sync::Arc<T> {
+	fn level(&self) -> WireState {
+		(**self).level()
+	}
+}
+
+#[cfg(feature = "std")]
+impl<T: PinBackend + ?Sized> PinBackend for std::sync::Arc<T> {
+	fn driven(&self) -> WireState {
+		(**self).driven()
+	}
+
+	fn drive(&self, state: WireState) {
+		(**self).drive(state)
+	}
+
+	fn release(&self) {
+		(**self).release()
+	}
+
+	fn update_driven<F>(&self, f: F)
+	where
+		F: FnMut(WireState) -> WireState,
+	{
+		(**self).update_driven(f)
+	}
+}
+
+#[cfg(loom)]
+impl<T: Net + ?Sized> Net for loom::sync::Arc<T> {
+	fn level(&self) -> WireState {
+		(**self).level()
+	}
+}
+
+#[cfg(loom)]
+impl<T: PinBackend + ?Sized> PinBackend for loom::sync::Arc<T> {
+	fn driven(&self) -> WireState {
+		(**self).driven()
+	}
+
+	fn drive(&self, state: WireState) {
+		(**self).drive(state)
+	}
+
+	fn release(&self) {
+		(**self).release()
+	}
+
+	fn update_driven<F>(&self, f: F)
+	where
+		F: FnMut(WireState) -> WireState,
+	{
+		(**self).update_driven(f)
+	}
+}
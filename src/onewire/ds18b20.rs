@@ -0,0 +1,324 @@
+use super::{crc8, SlaveHandler};
+use crate::clock::VirtualClock;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+const CONVERT_T: u8 = 0x44;
+const WRITE_SCRATCHPAD: u8 = 0x4e;
+const READ_SCRATCHPAD: u8 = 0xbe;
+const COPY_SCRATCHPAD: u8 = 0x48;
+const RECALL_E2: u8 = 0xb8;
+const READ_POWER_SUPPLY: u8 = 0xb4;
+
+/// A model of a DS18B20 digital thermometer.
+///
+/// The model implements all function commands of the sensor. Temperature
+/// conversions take as long as specified for the configured resolution, from
+/// 93.75 ms at 9 bits to 750 ms at 12 bits, measured on the clock. Until then
+/// read time slots return `0`. The scratchpad holds the power-on value of
+/// 85 °C until the first conversion has finished.
+///
+/// The family code of the DS18B20 is [`Ds18b20::FAMILY`].
+///
+/// # Examples
+///
+/// ```
+/// use embedded_hal_sync_pins::clock::VirtualClock;
+/// use embedded_hal_sync_pins::onewire::{crc8, Ds18b20, OneWireMaster, OneWireSlave, Rom};
+/// use embedded_hal_sync_pins::wire::{Wire, WireState};
+///
+/// let clock = VirtualClock::new();
+/// let line = Wire::new_with_pull(WireState::High);
+/// let rom = Rom::new(Ds18b20::FAMILY, [1, 2, 3, 4, 5, 6]);
+/// let sensor = OneWireSlave::new(&line, clock.clone(), rom, Ds18b20::new(clock.clone()));
+/// sensor.with_handler(|s| s.set_temperature(21.5));
+/// let mut master = OneWireMaster::new(line.connect_open_drain_pin(), clock);
+/// // start a conversion and wait until it is finished
+/// master.select(&rom).unwrap();
+/// master.write_byte(0x44).unwrap();
+/// while !master.read_bit().unwrap() {}
+/// // read the scratchpad
+/// master.select(&rom).unwrap();
+/// master.write_byte(0xbe).unwrap();
+/// let mut scratchpad = [0; 9];
+/// master.read_bytes(&mut scratchpad).unwrap();
+/// assert_eq!(0, crc8(&scratchpad));
+/// let raw = i16::from_le_bytes([scratchpad[0], scratchpad[1]]);
+/// assert_eq!(21.5, raw as f32 / 16.0);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Ds18b20 {
+	clock: VirtualClock,
+	temperature: f32,
+	parasite: bool,
+	scratchpad: [u8; 9],
+	/// TH, TL and configuration register.
+	eeprom: [u8; 3],
+	converting_until: Option<Duration>,
+	command: Option<u8>,
+	received: usize,
+	send: VecDeque<bool>,
+}
+
+impl Ds18b20 {
+	pub const FAMILY: u8 = 0x28;
+
+	/// Creates a new sensor with a temperature of 25 °C and 12 bit resolution.
+	pub fn new(clock: VirtualClock) -> Self {
+		let eeprom = [0x4b, 0x46, 0x7f];
+		let mut sensor = Ds18b20 {
+			clock,
+			temperature: 25.0,
+			parasite: false,
+			scratchpad: [0x50, 0x05, 0, 0, 0, 0xff, 0x0c, 0x10, 0],
+			eeprom,
+			converting_until: None,
+			command: None,
+			received: 0,
+			send: VecDeque::new(),
+		};
+		sensor.recall();
+		sensor
+	}
+
+	/// Sets the temperature measured by the next conversion in °C.
+	pub fn set_temperature(&mut self, celsius: f32) {
+		self.temperature = celsius;
+	}
+
+	pub fn temperature(&self) -> f32 {
+		self.temperature
+	}
+
+	/// Sets whether the sensor is powered parasitically through the data line.
+	pub fn set_parasite_power(&mut self, parasite: bool) {
+		self.parasite = parasite;
+	}
+
+	/// Returns the scratchpad including its CRC.
+	pub fn scratchpad(&mut self) -> [u8; 9] {
+		self.update();
+		self.scratchpad
+	}
+
+	/// Returns TH, TL and the configuration register stored in EEPROM.
+	pub fn eeprom(&self) -> [u8; 3] {
+		self.eeprom
+	}
+
+	/// Resolution in bits as configured in the scratchpad.
+	pub fn resolution(&self) -> u8 {
+		9 + ((self.scratchpad[4] >> 5) & 0x03)
+	}
+
+	fn conversion_time(&self) -> Duration {
+		Duration::from_micros(93_750 << (self.resolution() - 9))
+	}
+
+	fn update_crc(&mut self) {
+		self.scratchpad[8] = crc8(&self.scratchpad[..8]);
+	}
+
+	fn recall(&mut self) {
+		self.scratchpad[2..5].copy_from_slice(&self.eeprom);
+		self.update_crc();
+	}
+
+	/// Finishes a conversion once its time has passed.
+	fn update(&mut self) {
+		match self.converting_until {
+			Some(until) if self.clock.now() >= until => {}
+			_ => return,
+		}
+		self.converting_until = None;
+		let unused = 12 - self.resolution();
+		let raw = ((self.temperature * 16.0).round() as i16) & !((1 << unused) - 1);
+		self.scratchpad[..2].copy_from_slice(&raw.to_le_bytes());
+		self.update_crc();
+	}
+}
+
+impl SlaveHandler for Ds18b20 {
+	fn reset(&mut self) {
+		self.command = None;
+		self.received = 0;
+		self.send.clear();
+	}
+
+	fn write(&mut self, byte: u8) {
+		self.update();
+		match self.command {
+			None => {
+				self.command = Some(byte);
+				match byte {
+					CONVERT_T => {
+						self.converting_until = Some(self.clock.now() + self.conversion_time())
+					}
+					READ_SCRATCHPAD => {
+						self.send = self
+							.scratchpad
+							.iter()
+							.flat_map(|byte| (0..8).map(move |i| byte & (1 << i) != 0))
+							.collect()
+					}
+					COPY_SCRATCHPAD => self.eeprom.copy_from_slice(&self.scratchpad[2..5]),
+					RECALL_E2 => self.recall(),
+					_ => {}
+				}
+			}
+			Some(WRITE_SCRATCHPAD) if self.received < 3 => {
+				let byte = if self.received == 2 {
+					// only the resolution bits of the configuration are writable
+					(byte & 0x60) | 0x1f
+				} else {
+					byte
+				};
+				self.scratchpad[2 + self.received] = byte;
+				self.received += 1;
+				self.update_crc();
+			}
+			_ => {}
+		}
+	}
+
+	fn read_bit(&mut self) -> Option<bool> {
+		self.update();
+		match self.command {
+			Some(CONVERT_T) => Some(self.converting_until.is_none()),
+			Some(READ_SCRATCHPAD) => self.send.pop_front(),
+			Some(RECALL_E2) => Some(true),
+			Some(READ_POWER_SUPPLY) => Some(!self.parasite),
+			_ => None,
+		}
+	}
+
+	fn alarm(&mut self) -> bool {
+		self.update();
+		let temperature = i16::from_le_bytes([self.scratchpad[0], self.scratchpad[1]]) >> 4;
+		let (high, low) = (self.scratchpad[2] as i8, self.scratchpad[3] as i8);
+		temperature >= high as i16 || temperature <= low as i16
+	}
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::*;
+	use crate::onewire::{OneWireMaster, OneWireSlave, Rom};
+	use crate::wire::{Wire, WireState};
+
+	struct Bus {
+		clock: VirtualClock,
+		sensor: OneWireSlave<Ds18b20>,
+		master: OneWireMaster<VirtualClock>,
+		rom: Rom,
+	}
+
+	fn bus() -> Bus {
+		let clock = VirtualClock::new();
+		let line = Wire::new_with_pull(WireState::High);
+		let rom = Rom::new(Ds18b20::FAMILY, [0xaa, 0, 0, 0, 0, 0]);
+		let sensor = OneWireSlave::new(&line, clock.clone(), rom, Ds18b20::new(clock.clone()));
+		let master = OneWireMaster::new(line.connect_open_drain_pin(), clock.clone());
+		Bus {
+			clock,
+			sensor,
+			master,
+			rom,
+		}
+	}
+
+	impl Bus {
+		fn command(&mut self, command: u8) {
+			self.master.select(&self.rom).unwrap();
+			self.master.write_byte(command).unwrap();
+		}
+
+		fn read_scratchpad(&mut self) -> [u8; 9] {
+			self.command(READ_SCRATCHPAD);
+			let mut scratchpad = [0; 9];
+			self.master.read_bytes(&mut scratchpad).unwrap();
+			assert_eq!(0, crc8(&scratchpad));
+			scratchpad
+		}
+
+		/// Converts and returns the raw temperature and the conversion time in ms.
+		fn convert(&mut self) -> (i16, u128) {
+			self.command(CONVERT_T);
+			let start = self.clock.now();
+			while !self.master.read_bit().unwrap() {}
+			let time = (self.clock.now() - start).as_millis();
+			let scratchpad = self.read_scratchpad();
+			(i16::from_le_bytes([scratchpad[0], scratchpad[1]]), time)
+		}
+	}
+
+	#[test]
+	fn power_on() {
+		let mut bus = bus();
+		assert_eq!(
+			[0x50, 0x05, 0x4b, 0x46, 0x7f, 0xff, 0x0c, 0x10],
+			bus.read_scratchpad()[..8]
+		);
+		assert_eq!(Ok(bus.rom), bus.master.read_rom());
+	}
+
+	#[test]
+	fn conversion() {
+		let mut bus = bus();
+		for (celsius, raw) in [(25.0625, 0x0191), (-10.125, -162), (-55.0, -880), (0.5, 8)] {
+			bus.sensor.with_handler(|s| s.set_temperature(celsius));
+			let (measured, time) = bus.convert();
+			assert_eq!(raw, measured, "{}", celsius);
+			assert_eq!(750, time);
+		}
+	}
+
+	#[test]
+	fn resolution() {
+		let mut bus = bus();
+		bus.sensor.with_handler(|s| s.set_temperature(25.0625));
+		for (config, raw, time) in [(0x1f, 0x0190, 93), (0x3f, 0x0190, 187), (0x5f, 0x0190, 375)] {
+			bus.command(WRITE_SCRATCHPAD);
+			bus.master.write_bytes(&[0x20, 0x10, config]).unwrap();
+			let (measured, conversion_time) = bus.convert();
+			assert_eq!(raw, measured);
+			assert!(conversion_time >= time && conversion_time <= time + 1);
+		}
+		bus.sensor.with_handler(|s| s.set_temperature(25.125));
+		let (measured, _) = bus.convert();
+		assert_eq!(0x0192, measured);
+	}
+
+	#[test]
+	fn eeprom() {
+		let mut bus = bus();
+		bus.command(WRITE_SCRATCHPAD);
+		bus.master.write_bytes(&[0x20, 0x10, 0xff]).unwrap();
+		assert_eq!([0x20, 0x10, 0x7f], bus.read_scratchpad()[2..5]);
+		bus.command(COPY_SCRATCHPAD);
+		assert_eq!([0x20, 0x10, 0x7f], bus.sensor.with_handler(|s| s.eeprom()));
+		bus.command(WRITE_SCRATCHPAD);
+		bus.master.write_bytes(&[0x00, 0x00, 0x1f]).unwrap();
+		bus.command(RECALL_E2);
+		assert!(bus.master.read_bit().unwrap());
+		assert_eq!([0x20, 0x10, 0x7f], bus.read_scratchpad()[2..5]);
+	}
+
+	#[test]
+	fn alarm_and_power_supply() {
+		let mut bus = bus();
+		bus.command(WRITE_SCRATCHPAD);
+		bus.master.write_bytes(&[30, 10, 0x7f]).unwrap();
+		bus.sensor.with_handler(|s| s.set_temperature(20.0));
+		bus.convert();
+		assert_eq!(Ok(vec![]), bus.master.alarm_search());
+		bus.sensor.with_handler(|s| s.set_temperature(30.5));
+		bus.convert();
+		assert_eq!(Ok(vec![bus.rom]), bus.master.alarm_search());
+		bus.command(READ_POWER_SUPPLY);
+		assert_eq!(Ok(true), bus.master.read_bit());
+		bus.sensor.with_handler(|s| s.set_parasite_power(true));
+		bus.command(READ_POWER_SUPPLY);
+		assert_eq!(Ok(false), bus.master.read_bit());
+	}
+}
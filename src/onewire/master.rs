@@ -0,0 +1,292 @@
+use super::{Error, Rom, ALARM_SEARCH, MATCH_ROM, READ_ROM, SEARCH_ROM, SKIP_ROM};
+use crate::wire::OpenDrainPin;
+use embedded_hal::delay::DelayUs;
+use embedded_hal::digital::{InputPin, OutputPin};
+
+/// A bit-banged 1-Wire master.
+///
+/// The master drives the line through an [`OpenDrainPin`] using standard speed
+/// timing: reset pulses of 480 µs and time slots of 70 µs. The line has to be
+/// pulled high, a floating line is read as low.
+///
+/// # Examples
+///
+/// ```
+/// use embedded_hal_sync_pins::clock::VirtualClock;
+/// use embedded_hal_sync_pins::onewire::{Ds18b20, OneWireMaster, OneWireSlave, Rom};
+/// use embedded_hal_sync_pins::wire::{Wire, WireState};
+///
+/// let clock = VirtualClock::new();
+/// let line = Wire::new_with_pull(WireState::High);
+/// let rom = Rom::new(0x28, [1, 2, 3, 4, 5, 6]);
+/// let _sensor = OneWireSlave::new(&line, clock.clone(), rom, Ds18b20::new(clock.clone()));
+/// let mut master = OneWireMaster::new(line.connect_open_drain_pin(), clock);
+/// assert_eq!(Ok(vec![rom]), master.search());
+/// assert_eq!(Ok(rom), master.read_rom());
+/// ```
+pub struct OneWireMaster<D> {
+	pin: OpenDrainPin,
+	delay: D,
+}
+
+impl<D: DelayUs> OneWireMaster<D> {
+	/// Creates a new master and releases the line.
+	pub fn new(mut pin: OpenDrainPin, delay: D) -> Self {
+		// an open drain pin pulls the wire low when set high
+		let _ = pin.set_low();
+		OneWireMaster { pin, delay }
+	}
+
+	/// Releases the pin and the delay.
+	pub fn free(self) -> (OpenDrainPin, D) {
+		(self.pin, self.delay)
+	}
+
+	fn wait(&mut self, us: u32) -> Result<(), Error> {
+		self.delay.delay_us(us).map_err(|_| Error::Delay)
+	}
+
+	fn pull_low(&mut self, us: u32) -> Result<(), Error> {
+		let _ = self.pin.set_high();
+		self.wait(us)?;
+		let _ = self.pin.set_low();
+		Ok(())
+	}
+
+	fn sample(&self) -> bool {
+		self.pin.is_high().unwrap_or(false)
+	}
+
+	/// Sends a reset pulse and returns whether any device answered with a
+	/// presence pulse.
+	pub fn reset(&mut self) -> Result<bool, Error> {
+		self.pull_low(480)?;
+		self.wait(70)?;
+		let presence = !self.sample();
+		self.wait(410)?;
+		Ok(presence)
+	}
+
+	pub fn write_bit(&mut self, bit: bool) -> Result<(), Error> {
+		if bit {
+			self.pull_low(6)?;
+			self.wait(64)
+		} else {
+			self.pull_low(60)?;
+			self.wait(10)
+		}
+	}
+
+	pub fn read_bit(&mut self) -> Result<bool, Error> {
+		self.pull_low(6)?;
+		self.wait(9)?;
+		let bit = self.sample();
+		self.wait(55)?;
+		Ok(bit)
+	}
+
+	/// Writes `byte` LSB first.
+	pub fn write_byte(&mut self, byte: u8) -> Result<(), Error> {
+		for i in 0..8 {
+			self.write_bit(byte & (1 << i) != 0)?;
+		}
+		Ok(())
+	}
+
+	/// Reads a byte LSB first.
+	pub fn read_byte(&mut self) -> Result<u8, Error> {
+		let mut byte = 0;
+		for i in 0..8 {
+			if self.read_bit()? {
+				byte |= 1 << i;
+			}
+		}
+		Ok(byte)
+	}
+
+	pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+		bytes.iter().try_for_each(|byte| self.write_byte(*byte))
+	}
+
+	pub fn read_bytes(&mut self, bytes: &mut [u8]) -> Result<(), Error> {
+		for byte in bytes.iter_mut() {
+			*byte = self.read_byte()?;
+		}
+		Ok(())
+	}
+
+	fn reset_present(&mut self) -> Result<(), Error> {
+		if self.reset()? {
+			Ok(())
+		} else {
+			Err(Error::NoPresence)
+		}
+	}
+
+	/// Resets the bus and reads the ROM code of the only device on it.
+	pub fn read_rom(&mut self) -> Result<Rom, Error> {
+		self.reset_present()?;
+		self.write_byte(READ_ROM)?;
+		let mut rom = Rom([0; 8]);
+		self.read_bytes(&mut rom.0)?;
+		if rom.is_valid() {
+			Ok(rom)
+		} else {
+			Err(Error::Crc)
+		}
+	}
+
+	/// Resets the bus and selects the device with the given ROM code.
+	pub fn select(&mut self, rom: &Rom) -> Result<(), Error> {
+		self.reset_present()?;
+		self.write_byte(MATCH_ROM)?;
+		self.write_bytes(&rom.0)
+	}
+
+	/// Resets the bus and selects all devices on it.
+	pub fn skip_rom(&mut self) -> Result<(), Error> {
+		self.reset_present()?;
+		self.write_byte(SKIP_ROM)
+	}
+
+	/// Returns the ROM codes of all devices on the bus.
+	pub fn search(&mut self) -> Result<Vec<Rom>, Error> {
+		self.search_all(SEARCH_ROM)
+	}
+
+	/// Returns the ROM codes of all devices with an alarm condition.
+	pub fn alarm_search(&mut self) -> Result<Vec<Rom>, Error> {
+		self.search_all(ALARM_SEARCH)
+	}
+
+	fn search_all(&mut self, command: u8) -> Result<Vec<Rom>, Error> {
+		let mut roms = vec![];
+		let mut rom = Rom([0; 8]);
+		let mut last_discrepancy = None;
+		loop {
+			if !self.reset()? {
+				return Ok(roms);
+			}
+			self.write_byte(command)?;
+			let mut last_zero = None;
+			for n in 0..64 {
+				let bit = self.read_bit()?;
+				let complement = self.read_bit()?;
+				let direction = match (bit, complement) {
+					(true, true) if n == 0 => return Ok(roms),
+					(true, true) => return Err(Error::Search),
+					(false, false) => {
+						// devices with both values are left
+						let direction = match last_discrepancy {
+							Some(d) if n < d => rom.bit(n),
+							Some(d) => n == d,
+							None => false,
+						};
+						if !direction {
+							last_zero = Some(n);
+						}
+						direction
+					}
+					(bit, _) => bit,
+				};
+				rom.set_bit(n, direction);
+				self.write_bit(direction)?;
+			}
+			if !rom.is_valid() {
+				return Err(Error::Crc);
+			}
+			roms.push(rom);
+			last_discrepancy = last_zero;
+			if last_discrepancy.is_none() {
+				return Ok(roms);
+			}
+		}
+	}
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::*;
+	use crate::clock::VirtualClock;
+	use crate::onewire::{OneWireSlave, SlaveHandler};
+	use crate::wire::{Wire, WireState};
+	use std::sync::{Arc, Mutex};
+
+	struct Alarm(bool);
+
+	impl SlaveHandler for Alarm {
+		fn write(&mut self, _: u8) {}
+
+		fn alarm(&mut self) -> bool {
+			self.0
+		}
+	}
+
+	fn bus() -> (Wire, VirtualClock, OneWireMaster<VirtualClock>) {
+		let clock = VirtualClock::new();
+		let line = Wire::new_with_pull(WireState::High);
+		let master = OneWireMaster::new(line.connect_open_drain_pin(), clock.clone());
+		(line, clock, master)
+	}
+
+	#[test]
+	fn empty_bus() {
+		let (_, clock, mut master) = bus();
+		assert_eq!(Ok(false), master.reset());
+		assert_eq!(960, clock.now().as_micros());
+		assert_eq!(Err(Error::NoPresence), master.read_rom());
+		assert_eq!(Ok(vec![]), master.search());
+	}
+
+	#[test]
+	fn time_slots() {
+		let (line, clock, mut master) = bus();
+		let lows = Arc::new(Mutex::new(vec![]));
+		let (l, c) = (lows.clone(), clock.clone());
+		let fell = Mutex::new(clock.now());
+		line.on_change(move |state| {
+			if state == WireState::Low {
+				*fell.lock().unwrap() = c.now();
+			} else {
+				let low = c.now() - *fell.lock().unwrap();
+				l.lock().unwrap().push(low.as_micros());
+			}
+		});
+		assert_eq!(Ok(()), master.write_byte(0x02));
+		assert_eq!(Ok(true), master.read_bit());
+		assert_eq!(
+			vec![60, 6, 60, 60, 60, 60, 60, 60, 6],
+			*lows.lock().unwrap()
+		);
+		assert_eq!(9 * 70, clock.now().as_micros());
+	}
+
+	#[test]
+	fn search() {
+		let (line, clock, mut master) = bus();
+		let mut roms = vec![
+			Rom::new(0x28, [0x01, 0, 0, 0, 0, 0]),
+			Rom::new(0x28, [0x03, 0, 0, 0, 0, 0]),
+			Rom::new(0x28, [0x83, 0, 0, 0, 0, 0x80]),
+			Rom::new(0x10, [0xff, 0, 0, 0, 0, 0]),
+		];
+		for (i, rom) in roms.iter().enumerate() {
+			OneWireSlave::new(&line, clock.clone(), *rom, Alarm(i % 2 == 1));
+		}
+		let mut found = master.search().unwrap();
+		found.sort();
+		roms.sort();
+		assert_eq!(roms, found);
+		let mut alarms = master.alarm_search().unwrap();
+		alarms.sort();
+		assert_eq!(
+			vec![
+				Rom::new(0x10, [0xff, 0, 0, 0, 0, 0]),
+				Rom::new(0x28, [0x03, 0, 0, 0, 0, 0])
+			],
+			alarms
+		);
+		// several devices answering READ ROM produce a wired-AND of their codes
+		assert_eq!(Err(Error::Crc), master.read_rom());
+	}
+}
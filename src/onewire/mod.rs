@@ -0,0 +1,144 @@
+//! Simulated 1-Wire buses
+//!
+//! 1-Wire uses a single open drain line pulled high, which maps directly onto a
+//! [`Wire`](`crate::wire::Wire`) created with
+//! [`Wire::new_with_pull(WireState::High)`](`crate::wire::Wire::new_with_pull`).
+//! All communication is initiated by the master pulling the line low: a long
+//! reset pulse is answered by a presence pulse of every slave, while short
+//! pulses start time slots to write or read a single bit.
+//!
+//! [`OneWireMaster`] generates reset pulses and time slots through an
+//! [`OpenDrainPin`](`crate::wire::OpenDrainPin`) and implements the ROM
+//! commands including the ROM search. [`OneWireSlave`] is the other side of the
+//! bus: it answers resets and ROM commands with its [`Rom`] code and passes
+//! function commands on to a [`SlaveHandler`], e.g. a [`Ds18b20`] temperature
+//! sensor. Slaves time their responses on a
+//! [`VirtualClock`](`crate::clock::VirtualClock`), which the master should use
+//! as its delay.
+
+mod ds18b20;
+mod master;
+mod slave;
+
+pub use ds18b20::Ds18b20;
+pub use master::OneWireMaster;
+pub use slave::{OneWireSlave, SlaveHandler};
+
+use std::fmt;
+
+pub(crate) const READ_ROM: u8 = 0x33;
+pub(crate) const MATCH_ROM: u8 = 0x55;
+pub(crate) const SKIP_ROM: u8 = 0xcc;
+pub(crate) const SEARCH_ROM: u8 = 0xf0;
+pub(crate) const ALARM_SEARCH: u8 = 0xec;
+
+/// Calculates the Dallas/Maxim CRC-8 of `data`.
+///
+/// The CRC of data followed by its CRC is `0`.
+pub fn crc8(data: &[u8]) -> u8 {
+	let mut crc = 0;
+	for byte in data {
+		let mut byte = *byte;
+		for _ in 0..8 {
+			let mix = (crc ^ byte) & 1;
+			crc >>= 1;
+			if mix != 0 {
+				crc ^= 0x8c;
+			}
+			byte >>= 1;
+		}
+	}
+	crc
+}
+
+/// The 64-bit ROM code identifying a 1-Wire device.
+///
+/// The code consists of a family code, a 48-bit serial number and a CRC of
+/// both. It is transferred LSB first, starting with the family code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Rom(pub [u8; 8]);
+
+impl Rom {
+	/// Creates a ROM code with a valid CRC.
+	pub fn new(family: u8, serial: [u8; 6]) -> Self {
+		let mut rom = [family, 0, 0, 0, 0, 0, 0, 0];
+		rom[1..7].copy_from_slice(&serial);
+		rom[7] = crc8(&rom[..7]);
+		Rom(rom)
+	}
+
+	pub fn family(&self) -> u8 {
+		self.0[0]
+	}
+
+	pub fn serial(&self) -> [u8; 6] {
+		let mut serial = [0; 6];
+		serial.copy_from_slice(&self.0[1..7]);
+		serial
+	}
+
+	/// Returns whether the last byte is the CRC of the others.
+	pub fn is_valid(&self) -> bool {
+		crc8(&self.0) == 0
+	}
+
+	/// Returns bit `n` in transfer order.
+	pub(crate) fn bit(&self, n: u8) -> bool {
+		self.0[n as usize / 8] & (1 << (n % 8)) != 0
+	}
+
+	pub(crate) fn set_bit(&mut self, n: u8, bit: bool) {
+		let mask = 1 << (n % 8);
+		if bit {
+			self.0[n as usize / 8] |= mask;
+		} else {
+			self.0[n as usize / 8] &= !mask;
+		}
+	}
+}
+
+/// An error of a [`OneWireMaster`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+	/// No device answered a reset with a presence pulse.
+	NoPresence,
+	/// A ROM code read from the bus has an invalid CRC.
+	Crc,
+	/// No device took part in the ROM search after the first bit.
+	Search,
+	/// The delay failed.
+	Delay,
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Error::NoPresence => write!(f, "no presence pulse"),
+			Error::Crc => write!(f, "invalid ROM code CRC"),
+			Error::Search => write!(f, "devices left the ROM search"),
+			Error::Delay => write!(f, "delay failed"),
+		}
+	}
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn rom() {
+		// example from Maxim application note 27
+		let rom = Rom::new(0x02, [0x1c, 0xb8, 0x01, 0x00, 0x00, 0x00]);
+		assert_eq!(0xa2, rom.0[7]);
+		assert!(rom.is_valid());
+		assert_eq!(0x02, rom.family());
+		assert_eq!([0x1c, 0xb8, 0x01, 0x00, 0x00, 0x00], rom.serial());
+		assert!(!rom.bit(0));
+		assert!(rom.bit(1));
+		let mut invalid = rom;
+		invalid.set_bit(63, false);
+		assert!(!invalid.is_valid());
+	}
+}
@@ -0,0 +1,344 @@
+use super::{Rom, ALARM_SEARCH, MATCH_ROM, READ_ROM, SEARCH_ROM, SKIP_ROM};
+use crate::clock::VirtualClock;
+use crate::net::PinBackend;
+use crate::sync::{Arc, Mutex};
+use crate::wire::{Driver, InputOnlyPin, Wire, WireState};
+use embedded_hal::digital::InputPin;
+use std::time::Duration;
+
+/// Minimum length of a reset pulse.
+const RESET: Duration = Duration::from_micros(480);
+/// Time after the start of a time slot at which a slave samples the line or
+/// releases it after sending a zero.
+const SAMPLE: Duration = Duration::from_micros(30);
+/// Delay between the end of a reset pulse and the presence pulse.
+const PRESENCE_DELAY: Duration = Duration::from_micros(30);
+const PRESENCE: Duration = Duration::from_micros(120);
+
+/// Behaviour of a simulated 1-Wire device.
+///
+/// ROM commands are handled by the [`OneWireSlave`], the handler only sees the
+/// function commands following them. The methods are called while the master
+/// clocks the bus, so they should return quickly.
+pub trait SlaveHandler {
+	/// Called on every reset pulse.
+	fn reset(&mut self) {}
+
+	/// Called when the device was selected by a ROM command.
+	fn select(&mut self) {}
+
+	/// Called for every byte written by the master to the selected device.
+	fn write(&mut self, byte: u8);
+
+	/// Called at the start of every time slot while the device is selected.
+	///
+	/// Returns the bit to send in a read time slot, or `None` if the device
+	/// expects the master to write. Sending a `1` leaves the line untouched,
+	/// so it is indistinguishable from receiving.
+	fn read_bit(&mut self) -> Option<bool> {
+		None
+	}
+
+	/// Returns whether the device takes part in an alarm search.
+	fn alarm(&mut self) -> bool {
+		false
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+	/// Waiting for a reset pulse.
+	Idle,
+	/// Receiving a ROM command.
+	RomCommand,
+	/// Sending the given bit of the ROM code.
+	ReadRom(u8),
+	/// Receiving the given bit of a ROM code.
+	MatchRom(u8),
+	/// Sending the given bit of the ROM code, then its complement, then
+	/// receiving the direction chosen by the master.
+	Search(u8, u8),
+	/// Selected, passing function commands on to the handler.
+	Function,
+}
+
+#[derive(Debug)]
+struct Engine<H> {
+	rom: Rom,
+	handler: H,
+	line: InputOnlyPin,
+	state: State,
+	shift: u8,
+	bits: u8,
+	fell_at: Duration,
+	/// Edges are ignored until the presence pulses after a reset are over.
+	recovery_until: Duration,
+}
+
+impl<H: SlaveHandler> Engine<H> {
+	fn reset(&mut self, now: Duration) {
+		self.state = State::RomCommand;
+		self.shift = 0;
+		self.bits = 0;
+		self.recovery_until = now + RESET;
+		self.handler.reset();
+	}
+
+	fn select(&mut self) {
+		self.state = State::Function;
+		self.shift = 0;
+		self.bits = 0;
+		self.handler.select();
+	}
+
+	/// Returns the bit to send in the time slot starting now.
+	fn slot(&mut self) -> Option<bool> {
+		let bit = match self.state {
+			State::ReadRom(n) => {
+				if n == 63 {
+					self.select();
+				} else {
+					self.state = State::ReadRom(n + 1);
+				}
+				Some(self.rom.bit(n))
+			}
+			State::Search(n, 0) => {
+				self.state = State::Search(n, 1);
+				Some(self.rom.bit(n))
+			}
+			State::Search(n, 1) => {
+				self.state = State::Search(n, 2);
+				Some(!self.rom.bit(n))
+			}
+			State::Function => self.handler.read_bit(),
+			_ => None,
+		};
+		if bit.is_some() {
+			// a partially received byte is discarded
+			self.shift = 0;
+			self.bits = 0;
+		}
+		bit
+	}
+
+	fn receive(&mut self, bit: bool) {
+		match self.state {
+			State::RomCommand | State::Function => {
+				self.shift |= (bit as u8) << self.bits;
+				self.bits += 1;
+				if self.bits < 8 {
+					return;
+				}
+				let byte = self.shift;
+				self.shift = 0;
+				self.bits = 0;
+				if self.state == State::Function {
+					self.handler.write(byte);
+					return;
+				}
+				match byte {
+					READ_ROM => self.state = State::ReadRom(0),
+					MATCH_ROM => self.state = State::MatchRom(0),
+					SEARCH_ROM => self.state = State::Search(0, 0),
+					ALARM_SEARCH if self.handler.alarm() => self.state = State::Search(0, 0),
+					SKIP_ROM => self.select(),
+					_ => self.state = State::Idle,
+				}
+			}
+			State::MatchRom(n) | State::Search(n, 2) => {
+				if bit != self.rom.bit(n) {
+					self.state = State::Idle;
+				} else if n == 63 {
+					self.select();
+				} else if let State::MatchRom(_) = self.state {
+					self.state = State::MatchRom(n + 1);
+				} else {
+					self.state = State::Search(n + 1, 0);
+				}
+			}
+			_ => {}
+		}
+	}
+}
+
+/// A simulated 1-Wire device attached to a wire.
+///
+/// The slave listens for reset pulses and time slots on the wire. It answers
+/// resets with a presence pulse and handles the ROM commands READ ROM, MATCH
+/// ROM, SKIP ROM, SEARCH ROM and ALARM SEARCH with its [`Rom`] code. Function
+/// commands of a selected slave are passed on to a [`SlaveHandler`]. The line
+/// is pulled low through the slave's own [`Driver`], so several slaves form a
+/// wired-AND as required by the ROM search.
+#[derive(Debug)]
+pub struct OneWireSlave<H> {
+	engine: Arc<Mutex<Engine<H>>>,
+}
+
+impl<H> Clone for OneWireSlave<H> {
+	fn clone(&self) -> Self {
+		OneWireSlave {
+			engine: self.engine.clone(),
+		}
+	}
+}
+
+/// Drives `state` onto the line at `at`.
+fn drive_at(clock: &VirtualClock, output: &Arc<Driver>, at: Duration, state: WireState) {
+	let output = output.clone();
+	clock.schedule_at(at, move || output.drive(state));
+}
+
+impl<H: SlaveHandler + Send + 'static> OneWireSlave<H> {
+	/// Attaches a new slave to the given wire.
+	pub fn new(line: &Wire, clock: VirtualClock, rom: Rom, handler: H) -> Self {
+		let engine = Arc::new(Mutex::new(Engine {
+			rom,
+			handler,
+			line: line.connect_input_pin(),
+			state: State::Idle,
+			shift: 0,
+			bits: 0,
+			fell_at: Duration::ZERO,
+			recovery_until: Duration::ZERO,
+		}));
+		let output = Arc::new(line.connect_driver());
+		let e = engine.clone();
+		line.on_change(move |state| {
+			let now = clock.now();
+			let mut engine = e.lock().unwrap();
+			if now < engine.recovery_until {
+				return;
+			}
+			if state == WireState::Low {
+				engine.fell_at = now;
+				if engine.state == State::Idle {
+					return;
+				}
+				match engine.slot() {
+					Some(false) => {
+						output.drive(WireState::Low);
+						drive_at(&clock, &output, now + SAMPLE, WireState::Floating);
+					}
+					Some(true) => {}
+					None => {
+						let e = e.clone();
+						clock.schedule_at(now + SAMPLE, move || {
+							let mut engine = e.lock().unwrap();
+							let bit = engine.line.is_high().unwrap_or(false);
+							engine.receive(bit);
+						});
+					}
+				}
+			} else if now - engine.fell_at >= RESET {
+				engine.reset(now);
+				let presence = now + PRESENCE_DELAY;
+				drive_at(&clock, &output, presence, WireState::Low);
+				drive_at(&clock, &output, presence + PRESENCE, WireState::Floating);
+			}
+		});
+		OneWireSlave { engine }
+	}
+}
+
+impl<H> OneWireSlave<H> {
+	pub fn rom(&self) -> Rom {
+		self.engine.lock().unwrap().rom
+	}
+
+	/// Calls `f` with the handler of this slave.
+	///
+	/// This is useful to inspect or change the state of a simulated device
+	/// between transactions.
+	pub fn with_handler<F, R>(&self, f: F) -> R
+	where
+		F: FnOnce(&mut H) -> R,
+	{
+		f(&mut self.engine.lock().unwrap().handler)
+	}
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::*;
+	use crate::onewire::OneWireMaster;
+
+	#[derive(Default)]
+	struct Log {
+		log: Vec<String>,
+		send: Vec<bool>,
+	}
+
+	impl SlaveHandler for Log {
+		fn reset(&mut self) {
+			self.log.push("reset".into());
+		}
+
+		fn select(&mut self) {
+			self.log.push("select".into());
+		}
+
+		fn write(&mut self, byte: u8) {
+			self.log.push(format!("write {:#x}", byte));
+		}
+
+		fn read_bit(&mut self) -> Option<bool> {
+			if self.send.is_empty() {
+				None
+			} else {
+				Some(self.send.remove(0))
+			}
+		}
+	}
+
+	fn bus(roms: &[Rom]) -> (Vec<OneWireSlave<Log>>, OneWireMaster<VirtualClock>) {
+		let clock = VirtualClock::new();
+		let line = Wire::new_with_pull(WireState::High);
+		let slaves = roms
+			.iter()
+			.map(|rom| OneWireSlave::new(&line, clock.clone(), *rom, Log::default()))
+			.collect();
+		let master = OneWireMaster::new(line.connect_open_drain_pin(), clock);
+		(slaves, master)
+	}
+
+	fn log(slave: &OneWireSlave<Log>) -> Vec<String> {
+		slave.with_handler(|h| h.log.clone())
+	}
+
+	#[test]
+	fn match_rom() {
+		let a = Rom::new(0x28, [1, 0, 0, 0, 0, 0]);
+		let b = Rom::new(0x28, [2, 0, 0, 0, 0, 0]);
+		let (slaves, mut master) = bus(&[a, b]);
+		assert_eq!(Ok(()), master.select(&b));
+		assert_eq!(Ok(()), master.write_bytes(&[0x12, 0x34]));
+		assert_eq!(vec!["reset"], log(&slaves[0]));
+		assert_eq!(
+			vec!["reset", "select", "write 0x12", "write 0x34"],
+			log(&slaves[1])
+		);
+	}
+
+	#[test]
+	fn read_slots() {
+		let rom = Rom::new(0x28, [1, 0, 0, 0, 0, 0]);
+		let (slaves, mut master) = bus(&[rom]);
+		slaves[0].with_handler(|h| h.send = vec![false, true, true, false, true, true, true, true]);
+		assert_eq!(Ok(()), master.skip_rom());
+		// bits are sent LSB first
+		assert_eq!(Ok(0xf6), master.read_byte());
+		assert_eq!(Ok(()), master.write_byte(0xa5));
+		assert_eq!(vec!["reset", "select", "write 0xa5"], log(&slaves[0]));
+	}
+
+	#[test]
+	fn unknown_rom_command() {
+		let rom = Rom::new(0x28, [1, 0, 0, 0, 0, 0]);
+		let (slaves, mut master) = bus(&[rom]);
+		assert_eq!(Ok(true), master.reset());
+		assert_eq!(Ok(()), master.write_bytes(&[0x00, 0x01]));
+		assert_eq!(vec!["reset"], log(&slaves[0]));
+		assert_eq!(Ok(rom), master.read_rom());
+		assert_eq!(vec!["reset", "reset", "select"], log(&slaves[0]));
+	}
+}
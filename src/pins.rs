@@ -5,17 +5,31 @@
 //! [`Input`-](`embedded_hal::digital::InputPin`) or
 //! [`OutputPin`s](`embedded_hal::digital::OutputPin`).
 //!
-//! As atomic types these pins use primitive [`atomic`](`std::sync::atomic`) types,
+//! As atomic types these pins use primitive [`atomic`](`core::sync::atomic`) types,
 //! so that these pins can be shared safely between threads. Especially useful
 //! for integration testing.
+//!
+//! The pin types are also available without the `std` feature, e.g. for the
+//! wires in [`cs_wire`](`crate::cs_wire`). Pins then refer to an
+//! [`AtomicPinState`] by a `&'static` reference instead of an `Arc` by default.
 
+use crate::net::{Net, PinBackend, WireState};
+#[cfg(feature = "std")]
+use crate::sync::{Arc, AtomicUsize};
 use core::convert::Infallible;
+#[cfg(not(feature = "std"))]
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
 use embedded_hal::digital as hal;
 use embedded_hal::digital::ErrorType;
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::{FromPrimitive, ToPrimitive};
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
+
+/// Backend of the pin types if none is given.
+#[cfg(feature = "std")]
+type DefaultBackend = Arc<AtomicPinState>;
+#[cfg(not(feature = "std"))]
+type DefaultBackend = &'static AtomicPinState;
 
 /// A digital pin state.
 #[derive(Clone, Debug, PartialEq, Eq, FromPrimitive, ToPrimitive)]
@@ -28,13 +42,70 @@ pub enum PinState {
 	Floating,
 }
 
+/// Memory orderings used by pins to access an [`AtomicPinState`].
+///
+/// Pins only read, write or toggle the state, so instead of choosing an
+/// [`Ordering`] for every operation, one of these variants selects a
+/// consistent set of orderings for all of them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MemoryOrdering {
+	/// Use [`SeqCst`](`Ordering::SeqCst`) for all operations.
+	#[default]
+	SeqCst,
+	/// Use [`Acquire`](`Ordering::Acquire`) for loads,
+	/// [`Release`](`Ordering::Release`) for stores and
+	/// [`AcqRel`](`Ordering::AcqRel`) for updates.
+	///
+	/// Everything a thread did before setting a pin is visible to a thread
+	/// that reads the new pin state.
+	AcquireRelease,
+	/// Use [`Relaxed`](`Ordering::Relaxed`) for all operations.
+	///
+	/// Only the pin state itself is synchronized, so drivers must not rely
+	/// on a pin change to publish other data.
+	Relaxed,
+}
+
+impl MemoryOrdering {
+	/// Ordering used to load the state.
+	pub fn load(self) -> Ordering {
+		match self {
+			MemoryOrdering::SeqCst => Ordering::SeqCst,
+			MemoryOrdering::AcquireRelease => Ordering::Acquire,
+			MemoryOrdering::Relaxed => Ordering::Relaxed,
+		}
+	}
+
+	/// Ordering used to store the state.
+	pub fn store(self) -> Ordering {
+		match self {
+			MemoryOrdering::SeqCst => Ordering::SeqCst,
+			MemoryOrdering::AcquireRelease => Ordering::Release,
+			MemoryOrdering::Relaxed => Ordering::Relaxed,
+		}
+	}
+
+	/// Ordering used to store the state on a successful update.
+	pub fn update(self) -> Ordering {
+		match self {
+			MemoryOrdering::SeqCst => Ordering::SeqCst,
+			MemoryOrdering::AcquireRelease => Ordering::AcqRel,
+			MemoryOrdering::Relaxed => Ordering::Relaxed,
+		}
+	}
+}
+
 /// A digital [pin state](`PinState`) which can be safely shared between threads.
 ///
-/// This type is based on [`AtomicUsize`], so the same limitations and platform
-/// support apply.
+/// This type is based on [`AtomicUsize`](`core::sync::atomic::AtomicUsize`), so the
+/// same limitations and platform support apply.
+///
+/// Pins accessing this state use the [`MemoryOrdering`] given on creation, which
+/// defaults to [`SeqCst`](`MemoryOrdering::SeqCst`).
 #[derive(Debug)]
 pub struct AtomicPinState {
 	state: AtomicUsize,
+	ordering: MemoryOrdering,
 }
 
 impl AtomicPinState {
@@ -43,6 +114,28 @@ impl AtomicPinState {
 		Self::new_with_state(PinState::Floating)
 	}
 
+	/// Creates a new atomic pin state with a given state and memory ordering.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use embedded_hal_sync_pins::pins::{AtomicPinState, MemoryOrdering, PinState};
+	///
+	/// let state = AtomicPinState::new_with_ordering(PinState::Low, MemoryOrdering::AcquireRelease);
+	/// assert_eq!(MemoryOrdering::AcquireRelease, state.ordering());
+	/// ```
+	pub fn new_with_ordering(state: PinState, ordering: MemoryOrdering) -> Self {
+		AtomicPinState {
+			state: AtomicUsize::new(state.to_usize().unwrap()),
+			ordering,
+		}
+	}
+
+	/// Returns the memory ordering used by pins to access this state.
+	pub fn ordering(&self) -> MemoryOrdering {
+		self.ordering
+	}
+
 	/// Creates a new atomic pin state with a given state.
 	///
 	/// # Examples
@@ -54,9 +147,7 @@ impl AtomicPinState {
 	/// let low = AtomicPinState::new_with_state(PinState::Low);
 	/// ```
 	pub fn new_with_state(state: PinState) -> Self {
-		AtomicPinState {
-			state: AtomicUsize::new(state.to_usize().unwrap()),
-		}
+		Self::new_with_ordering(state, MemoryOrdering::default())
 	}
 
 	/// Loads a state from the atomic pin state.
@@ -96,151 +187,205 @@ impl Default for AtomicPinState {
 	}
 }
 
+impl From<PinState> for WireState {
+	fn from(state: PinState) -> WireState {
+		match state {
+			PinState::High => WireState::High,
+			PinState::Low => WireState::Low,
+			PinState::Floating => WireState::Floating,
+		}
+	}
+}
+
+impl From<WireState> for PinState {
+	fn from(state: WireState) -> PinState {
+		match state {
+			WireState::High => PinState::High,
+			WireState::Low => PinState::Low,
+			WireState::Floating => PinState::Floating,
+		}
+	}
+}
+
+impl Net for AtomicPinState {
+	fn level(&self) -> WireState {
+		self.load(self.ordering.load()).into()
+	}
+}
+
+impl PinBackend for AtomicPinState {
+	fn driven(&self) -> WireState {
+		self.load(self.ordering.load()).into()
+	}
+
+	fn drive(&self, state: WireState) {
+		self.store(state.into(), self.ordering.store());
+	}
+
+	fn update_driven<F>(&self, mut f: F)
+	where
+		F: FnMut(WireState) -> WireState,
+	{
+		let order = self.ordering;
+		self.fetch_update(order.update(), order.load(), |x| Some(f(x.into()).into()));
+	}
+}
+
 /// A mutable [input pin](`hal::InputPin`) that can be safely shared between threads.
 ///
 /// This pin implements [`embedded_hal::InputPin`](`hal::InputPin`) and can be used
-/// to share an [`AtomicPinState`] with an [`embedded_hal`] implementation.
+/// to share an [`AtomicPinState`] or any other [`Net`] with an [`embedded_hal`]
+/// implementation.
 ///
 /// # Examples
 ///
 /// ```
 /// use embedded_hal_sync_pins::pins::{AtomicPinState, InputPin, PinState};
 /// use embedded_hal::digital::InputPin as HalInputPin;
-/// use std::sync::{Arc, atomic::Ordering};
+/// use core::sync::atomic::Ordering;
 ///
-/// let state = Arc::new(AtomicPinState::new_with_state(PinState::Low));
-/// let pin = InputPin::new(state.clone());
+/// let state = AtomicPinState::new_with_state(PinState::Low);
+/// let pin = InputPin::new(&state);
 /// assert_eq!(Ok(true), pin.is_low());
 /// state.store(PinState::High, Ordering::SeqCst);
 /// assert_eq!(Ok(true), pin.is_high());
 /// ```
 #[derive(Clone, Debug)]
-pub struct InputPin {
-	state: Arc<AtomicPinState>,
+pub struct InputPin<N = DefaultBackend> {
+	net: N,
 }
 
-impl InputPin {
-	/// Creates a new input pin with a given [`PinState`].
-	pub fn new(state: Arc<AtomicPinState>) -> Self {
-		InputPin { state }
+impl<N: Net> InputPin<N> {
+	/// Creates a new input pin reading the level of a given [`Net`].
+	pub fn new(net: N) -> Self {
+		InputPin { net }
 	}
 }
 
-impl ErrorType for InputPin {
+impl<N: Net> ErrorType for InputPin<N> {
 	type Error = Infallible;
 }
 
-impl hal::InputPin for InputPin {
+impl<N: Net> hal::InputPin for InputPin<N> {
 	fn is_high(&self) -> Result<bool, Self::Error> {
-		Ok(self.state.load(Ordering::SeqCst) == PinState::High)
+		Ok(self.net.level() == WireState::High)
 	}
 
 	fn is_low(&self) -> Result<bool, Self::Error> {
-		Ok(self.state.load(Ordering::SeqCst) == PinState::Low)
+		Ok(self.net.level() == WireState::Low)
 	}
 }
 
 /// A mutable [output pin](`hal::OutputPin`) that can be safely shared between threads.
 ///
 /// This pin implements [`embedded_hal::OutputPin`](`hal::OutputPin`) and can be used
-/// to share an [`AtomicPinState`] with an [`embedded_hal`] implementation.
+/// to share an [`AtomicPinState`] or any other [`PinBackend`] with an [`embedded_hal`]
+/// implementation.
 ///
 /// It also implements [`embedded_hal::InputPin`](`hal::InputPin`), so it is possible
-/// to also read the internal state.
+/// to also read the resolved level of the backend.
 ///
 /// # Examples
 ///
 /// ```
 /// use embedded_hal_sync_pins::pins::{AtomicPinState, PushPullPin, PinState};
 /// use embedded_hal::digital::{InputPin as HalInputPin, OutputPin};
-/// use std::sync::Arc;
 ///
-/// let state = Arc::new(AtomicPinState::new());
-/// let mut pin = PushPullPin::new(state.clone());
+/// let state = AtomicPinState::new();
+/// let mut pin = PushPullPin::new(&state);
 /// pin.set_low().unwrap();
 /// assert_eq!(Ok(true), pin.is_low());
 /// pin.set_high().unwrap();
 /// assert_eq!(Ok(true), pin.is_high());
 /// ```
 #[derive(Clone, Debug)]
-pub struct PushPullPin {
-	state: Arc<AtomicPinState>,
+pub struct PushPullPin<B = DefaultBackend> {
+	backend: B,
 }
 
-impl PushPullPin {
-	pub fn new(state: Arc<AtomicPinState>) -> Self {
-		PushPullPin { state }
+impl<B: PinBackend> PushPullPin<B> {
+	pub fn new(backend: B) -> Self {
+		PushPullPin { backend }
+	}
+
+	/// Stops driving the backend, i.e. puts the pin into high impedance state.
+	///
+	/// The next call to [`set_high`](`hal::OutputPin::set_high`) or
+	/// [`set_low`](`hal::OutputPin::set_low`) drives the backend again. This is
+	/// useful for shared lines like MISO, which a device only drives while it is
+	/// selected.
+	pub fn release(&mut self) {
+		self.backend.release();
 	}
 }
 
-impl ErrorType for PushPullPin {
+impl<B: PinBackend> ErrorType for PushPullPin<B> {
 	type Error = Infallible;
 }
 
-impl hal::OutputPin for PushPullPin {
+impl<B: PinBackend> hal::OutputPin for PushPullPin<B> {
 	fn set_high(&mut self) -> Result<(), Self::Error> {
-		self.state.store(PinState::High, Ordering::SeqCst);
+		self.backend.drive(WireState::High);
 		Ok(())
 	}
 
 	fn set_low(&mut self) -> Result<(), Self::Error> {
-		self.state.store(PinState::Low, Ordering::SeqCst);
+		self.backend.drive(WireState::Low);
 		Ok(())
 	}
 }
 
-impl hal::StatefulOutputPin for PushPullPin {
+impl<B: PinBackend> hal::StatefulOutputPin for PushPullPin<B> {
 	fn is_set_high(&self) -> Result<bool, Self::Error> {
-		Ok(self.state.load(Ordering::SeqCst) == PinState::High)
+		Ok(self.backend.driven() == WireState::High)
 	}
 
 	fn is_set_low(&self) -> Result<bool, Self::Error> {
-		Ok(self.state.load(Ordering::SeqCst) == PinState::Low)
+		Ok(self.backend.driven() == WireState::Low)
 	}
 }
 
-impl hal::ToggleableOutputPin for PushPullPin {
+impl<B: PinBackend> hal::ToggleableOutputPin for PushPullPin<B> {
 	fn toggle(&mut self) -> Result<(), Self::Error> {
-		self.state
-			.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |x| match x {
-				PinState::Low => Some(PinState::High),
-				PinState::High => Some(PinState::Low),
-				PinState::Floating => Some(PinState::Low),
-			});
+		self.backend.update_driven(|x| match x {
+			WireState::Low => WireState::High,
+			WireState::High => WireState::Low,
+			WireState::Floating => WireState::Low,
+		});
 		Ok(())
 	}
 }
 
-impl hal::InputPin for PushPullPin {
+impl<B: PinBackend> hal::InputPin for PushPullPin<B> {
 	fn is_high(&self) -> Result<bool, Self::Error> {
-		Ok(self.state.load(Ordering::SeqCst) == PinState::High)
+		Ok(self.backend.level() == WireState::High)
 	}
 
 	fn is_low(&self) -> Result<bool, Self::Error> {
-		Ok(self.state.load(Ordering::SeqCst) == PinState::Low)
+		Ok(self.backend.level() == WireState::Low)
 	}
 }
 
 /// A mutable [output pin](`hal::OutputPin`) in open drain configuration that can be safely shared between threads.
 ///
 /// This pin implements [`embedded_hal::OutputPin`](`hal::OutputPin`) and can be used
-/// to share an [`AtomicPinState`] with an [`embedded_hal`] implementation. In open drain
-/// configuration this pin is in a floating state (not connected) if it is set to low and
-/// logical low ("pull to GND") if it is set to high.
+/// to share an [`AtomicPinState`] or any other [`PinBackend`] with an [`embedded_hal`]
+/// implementation. In open drain configuration this pin is in a floating state (not
+/// connected) if it is set to low and logical low ("pull to GND") if it is set to high.
 ///
 /// It also implements [`embedded_hal::InputPin`](`hal::InputPin`), so it is possible
-/// to also read the internal state, which will be either [`Floating`](`PinState::Floating`)
-/// or [`Low`](`PinState::Low`).
+/// to also read the resolved level of the backend. For an [`AtomicPinState`] this will
+/// be either [`Floating`](`PinState::Floating`) or [`Low`](`PinState::Low`).
 ///
 /// # Examples
 ///
 /// ```
 /// use embedded_hal_sync_pins::pins::{AtomicPinState, OpenDrainPin, PinState};
 /// use embedded_hal::digital::{InputPin as HalInputPin, OutputPin};
-/// use std::sync::{Arc, atomic::Ordering};
+/// use core::sync::atomic::Ordering;
 ///
-/// let state = Arc::new(AtomicPinState::new());
-/// let mut pin = OpenDrainPin::new(state.clone());
+/// let state = AtomicPinState::new();
+/// let mut pin = OpenDrainPin::new(&state);
 /// pin.set_low().unwrap();
 /// assert_eq!(Ok(false), pin.is_low());
 /// assert_eq!(Ok(false), pin.is_high());
@@ -251,61 +396,60 @@ impl hal::InputPin for PushPullPin {
 /// ```
 
 #[derive(Clone, Debug)]
-pub struct OpenDrainPin {
-	state: Arc<AtomicPinState>,
+pub struct OpenDrainPin<B = DefaultBackend> {
+	backend: B,
 }
 
-impl OpenDrainPin {
-	pub fn new(state: Arc<AtomicPinState>) -> Self {
-		OpenDrainPin { state }
+impl<B: PinBackend> OpenDrainPin<B> {
+	pub fn new(backend: B) -> Self {
+		OpenDrainPin { backend }
 	}
 }
 
-impl ErrorType for OpenDrainPin {
+impl<B: PinBackend> ErrorType for OpenDrainPin<B> {
 	type Error = Infallible;
 }
 
-impl hal::OutputPin for OpenDrainPin {
+impl<B: PinBackend> hal::OutputPin for OpenDrainPin<B> {
 	fn set_high(&mut self) -> Result<(), Self::Error> {
-		self.state.store(PinState::Low, Ordering::SeqCst);
+		self.backend.drive(WireState::Low);
 		Ok(())
 	}
 
 	fn set_low(&mut self) -> Result<(), Self::Error> {
-		self.state.store(PinState::Floating, Ordering::SeqCst);
+		self.backend.drive(WireState::Floating);
 		Ok(())
 	}
 }
 
-impl hal::StatefulOutputPin for OpenDrainPin {
+impl<B: PinBackend> hal::StatefulOutputPin for OpenDrainPin<B> {
 	fn is_set_high(&self) -> Result<bool, Self::Error> {
-		Ok(self.state.load(Ordering::SeqCst) == PinState::Low)
+		Ok(self.backend.driven() == WireState::Low)
 	}
 
 	fn is_set_low(&self) -> Result<bool, Self::Error> {
-		Ok(self.state.load(Ordering::SeqCst) == PinState::Floating)
+		Ok(self.backend.driven() == WireState::Floating)
 	}
 }
 
-impl hal::ToggleableOutputPin for OpenDrainPin {
+impl<B: PinBackend> hal::ToggleableOutputPin for OpenDrainPin<B> {
 	fn toggle(&mut self) -> Result<(), Self::Error> {
-		self.state
-			.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |x| match x {
-				PinState::Floating => Some(PinState::Low),
-				PinState::Low => Some(PinState::Floating),
-				PinState::High => Some(PinState::Floating),
-			});
+		self.backend.update_driven(|x| match x {
+			WireState::Floating => WireState::Low,
+			WireState::Low => WireState::Floating,
+			WireState::High => WireState::Floating,
+		});
 		Ok(())
 	}
 }
 
-impl hal::InputPin for OpenDrainPin {
+impl<B: PinBackend> hal::InputPin for OpenDrainPin<B> {
 	fn is_high(&self) -> Result<bool, Self::Error> {
-		Ok(self.state.load(Ordering::SeqCst) == PinState::High)
+		Ok(self.backend.level() == WireState::High)
 	}
 
 	fn is_low(&self) -> Result<bool, Self::Error> {
-		Ok(self.state.load(Ordering::SeqCst) == PinState::Low)
+		Ok(self.backend.level() == WireState::Low)
 	}
 }
 
@@ -335,7 +479,7 @@ impl From<PinState> for vcd::Value {
 	}
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std", not(loom)))]
 mod tests {
 	use super::*;
 
@@ -415,6 +559,9 @@ mod tests {
 		assert_eq!(High, state.load(Ordering::SeqCst));
 		assert_eq!(Ok(()), pin.toggle());
 		assert_eq!(Low, state.load(Ordering::SeqCst));
+		pin.release();
+		assert_eq!(Floating, state.load(Ordering::SeqCst));
+		assert_eq!(Ok(false), pin.is_set_low());
 	}
 
 	#[test]
@@ -444,4 +591,110 @@ mod tests {
 		assert_eq!(Ok(()), pin.toggle());
 		assert_eq!(Floating, state.load(Ordering::SeqCst));
 	}
+
+	#[test]
+	fn custom_backend() {
+		use hal::OutputPin as HalOutputPin;
+		use hal::ToggleableOutputPin;
+		use std::sync::Mutex;
+		use WireState::*;
+
+		/// A backend recording every level driven onto it.
+		#[derive(Default)]
+		struct Recorder {
+			levels: Mutex<Vec<WireState>>,
+		}
+
+		impl Net for Recorder {
+			fn level(&self) -> WireState {
+				self.driven()
+			}
+		}
+
+		impl PinBackend for Recorder {
+			fn driven(&self) -> WireState {
+				*self.levels.lock().unwrap().last().unwrap_or(&Floating)
+			}
+
+			fn drive(&self, state: WireState) {
+				self.levels.lock().unwrap().push(state);
+			}
+
+			fn update_driven<F>(&self, mut f: F)
+			where
+				F: FnMut(WireState) -> WireState,
+			{
+				let mut levels = self.levels.lock().unwrap();
+				let next = f(*levels.last().unwrap_or(&Floating));
+				levels.push(next);
+			}
+		}
+
+		let recorder = Arc::new(Recorder::default());
+		let mut pin = OpenDrainPin::new(recorder.clone());
+		assert_eq!(Ok(()), pin.set_high());
+		assert_eq!(Ok(()), pin.toggle());
+		assert_eq!(Ok(()), pin.set_low());
+		let input = InputPin::new(recorder.clone());
+		assert_eq!(Ok(false), hal::InputPin::is_low(&input));
+		assert_eq!(
+			vec![Low, Floating, Floating],
+			*recorder.levels.lock().unwrap()
+		);
+	}
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+	use super::*;
+	use crate::sync::{model, thread};
+	use hal::InputPin as HalInputPin;
+	use hal::OutputPin as HalOutputPin;
+	use hal::ToggleableOutputPin;
+	use loom::cell::UnsafeCell;
+
+	#[test]
+	fn acquire_release_publishes_data() {
+		model(|| {
+			let data = Arc::new(UnsafeCell::new(0));
+			let state = Arc::new(AtomicPinState::new_with_ordering(
+				PinState::Low,
+				MemoryOrdering::AcquireRelease,
+			));
+			let mut ready = PushPullPin::new(state.clone());
+			let input = InputPin::new(state);
+			let writer = {
+				let data = data.clone();
+				thread::spawn(move || {
+					data.with_mut(|x| unsafe { *x = 42 });
+					ready.set_high().unwrap();
+				})
+			};
+			if input.is_high().unwrap() {
+				// setting the pin happens-before observing it
+				assert_eq!(42, data.with(|x| unsafe { *x }));
+			}
+			writer.join().unwrap();
+		});
+	}
+
+	#[test]
+	fn relaxed_toggles_are_not_lost() {
+		model(|| {
+			let state = Arc::new(AtomicPinState::new_with_ordering(
+				PinState::Low,
+				MemoryOrdering::Relaxed,
+			));
+			let handles: Vec<_> = (0..2)
+				.map(|_| {
+					let mut pin = PushPullPin::new(state.clone());
+					thread::spawn(move || pin.toggle().unwrap())
+				})
+				.collect();
+			for handle in handles {
+				handle.join().unwrap();
+			}
+			assert_eq!(PinState::Low, state.load(Ordering::Relaxed));
+		});
+	}
 }
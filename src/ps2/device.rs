@@ -0,0 +1,390 @@
+use super::{decode, frame, open_drain, HALF_PERIOD, RESEND};
+use crate::clock::VirtualClock;
+use crate::net::PinBackend;
+use crate::sync::{Arc, Mutex};
+use crate::wire::{Driver, InputOnlyPin, Wire, WireState};
+use embedded_hal::digital::InputPin;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Time the lines have to be high before the device starts sending.
+const IDLE: Duration = Duration::from_micros(50);
+/// Time from setting DATA to pulling CLK low.
+const SETUP: Duration = Duration::from_micros(20);
+
+/// Behaviour of a simulated PS/2 device.
+pub trait DeviceHandler {
+	/// Called for every byte received from the host, returns the bytes to
+	/// answer with.
+	///
+	/// Requests to resend the last byte are handled by the [`Ps2Device`].
+	fn receive(&mut self, byte: u8) -> Vec<u8>;
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum State {
+	Idle,
+	/// Sending the given bit of a frame with the byte.
+	Sending(u8, usize),
+	/// Receiving a frame, with the levels after the start bit so far.
+	Receiving(Vec<bool>),
+	/// Waiting for the host to release CLK.
+	Inhibited,
+}
+
+#[derive(Debug)]
+struct Engine<H> {
+	handler: H,
+	clk: InputOnlyPin,
+	data: InputOnlyPin,
+	state: State,
+	queue: VecDeque<u8>,
+	/// The last byte sent, for resend requests.
+	last: u8,
+}
+
+impl<H> Engine<H> {
+	fn is_clk_high(&self) -> bool {
+		self.clk.is_high().unwrap_or(true)
+	}
+
+	/// Stops sending because the host inhibits the device, the byte is sent
+	/// again later.
+	fn abort(&mut self, byte: u8) {
+		self.state = State::Inhibited;
+		self.queue.push_front(byte);
+	}
+}
+
+/// The drivers of the device on CLK and DATA.
+#[derive(Debug)]
+struct Pins {
+	clk: Driver,
+	data: Driver,
+}
+
+/// A simulated PS/2 device attached to CLK and DATA wires.
+///
+/// The device sends queued bytes whenever the host does not inhibit it and
+/// generates the clock for requests-to-send of the host. If the host pulls
+/// CLK low while a frame is being sent, the frame is aborted and sent again
+/// once the host releases CLK. Bytes from the host are acknowledged and passed
+/// on to the [`DeviceHandler`], whose answers are sent before all other
+/// queued bytes. A frame with a parity or framing error is answered with
+/// [`RESEND`](`super::RESEND`), and a resend request from the host sends the
+/// last byte again.
+///
+/// # Examples
+///
+/// ```
+/// use embedded_hal_sync_pins::clock::VirtualClock;
+/// use embedded_hal_sync_pins::ps2::{Keyboard, Ps2Device, Ps2Host};
+/// use embedded_hal_sync_pins::wire::{Wire, WireState};
+///
+/// let clock = VirtualClock::new();
+/// let clk = Wire::new_with_pull(WireState::High);
+/// let data = Wire::new_with_pull(WireState::High);
+/// let keyboard = Ps2Device::new(&clk, &data, clock.clone(), Keyboard::new());
+/// let mut host = Ps2Host::new(&clk, &data, clock.clone());
+/// // turn on caps lock
+/// host.write(0xed).unwrap();
+/// assert_eq!(Ok(0xfa), host.read());
+/// host.write(0x04).unwrap();
+/// assert_eq!(Ok(0xfa), host.read());
+/// assert_eq!(0x04, keyboard.with_handler(|keyboard| keyboard.leds()));
+/// // press and release A
+/// keyboard.send(&[0x1c, 0xf0, 0x1c]);
+/// let mut codes = [0; 3];
+/// for code in codes.iter_mut() {
+///     *code = host.read().unwrap();
+/// }
+/// assert_eq!([0x1c, 0xf0, 0x1c], codes);
+/// ```
+#[derive(Debug)]
+pub struct Ps2Device<H> {
+	engine: Arc<Mutex<Engine<H>>>,
+	pins: Arc<Pins>,
+	clock: VirtualClock,
+}
+
+impl<H> Clone for Ps2Device<H> {
+	fn clone(&self) -> Self {
+		Ps2Device {
+			engine: self.engine.clone(),
+			pins: self.pins.clone(),
+			clock: self.clock.clone(),
+		}
+	}
+}
+
+impl<H: DeviceHandler + Send + 'static> Ps2Device<H> {
+	/// Attaches a new device to the given wires.
+	pub fn new(clk: &Wire, data: &Wire, clock: VirtualClock, handler: H) -> Self {
+		let pins = Pins {
+			clk: clk.connect_driver(),
+			data: data.connect_driver(),
+		};
+		let device = Ps2Device {
+			engine: Arc::new(Mutex::new(Engine {
+				handler,
+				clk: clk.connect_input_pin(),
+				data: data.connect_input_pin(),
+				state: State::Idle,
+				queue: VecDeque::new(),
+				last: 0,
+			})),
+			pins: Arc::new(pins),
+			clock,
+		};
+		let d = device.clone();
+		clk.on_change(move |state| {
+			if state == WireState::High {
+				d.clk_released();
+			}
+		});
+		device
+	}
+
+	/// Queues `bytes` to be sent to the host.
+	pub fn send(&self, bytes: &[u8]) {
+		self.engine.lock().unwrap().queue.extend(bytes);
+		let d = self.clone();
+		self.clock.schedule_in(Duration::ZERO, move || d.start());
+	}
+
+	fn schedule<F>(&self, delay: Duration, f: F)
+	where
+		F: FnOnce(&Self) + Send + 'static,
+	{
+		let d = self.clone();
+		self.clock.schedule_in(delay, move || f(&d));
+	}
+
+	fn clk_released(&self) {
+		let mut engine = self.engine.lock().unwrap();
+		if !matches!(engine.state, State::Idle | State::Inhibited) {
+			return;
+		}
+		if engine.data.is_low().unwrap_or(false) {
+			// request-to-send
+			engine.state = State::Receiving(vec![]);
+			self.schedule(HALF_PERIOD, |d| d.receive_clock(0));
+		} else {
+			engine.state = State::Idle;
+			self.schedule(IDLE, Self::start);
+		}
+	}
+
+	/// Starts sending the next queued byte if the lines are idle.
+	fn start(&self) {
+		{
+			let mut engine = self.engine.lock().unwrap();
+			let idle = engine.is_clk_high() && engine.data.is_high().unwrap_or(true);
+			if engine.state != State::Idle || !idle {
+				return;
+			}
+			match engine.queue.pop_front() {
+				Some(byte) => engine.state = State::Sending(byte, 0),
+				None => return,
+			}
+		}
+		self.send_bit();
+	}
+
+	/// Sends the current bit, pulling CLK low after the setup time.
+	fn send_bit(&self) {
+		let level = {
+			let mut engine = self.engine.lock().unwrap();
+			match engine.state {
+				State::Sending(byte, _) if !engine.is_clk_high() => {
+					engine.abort(byte);
+					None
+				}
+				State::Sending(byte, i) => Some(frame(byte)[i]),
+				_ => None,
+			}
+		};
+		match level {
+			Some(level) => self.pins.data.drive(open_drain(level)),
+			None => return self.pins.data.release(),
+		}
+		self.schedule(SETUP, |d| {
+			let clocking = {
+				let mut engine = d.engine.lock().unwrap();
+				match engine.state {
+					State::Sending(byte, _) if !engine.is_clk_high() => {
+						engine.abort(byte);
+						false
+					}
+					State::Sending(..) => true,
+					_ => false,
+				}
+			};
+			if !clocking {
+				return d.pins.data.release();
+			}
+			d.pins.clk.drive(WireState::Low);
+			d.schedule(HALF_PERIOD, |d| d.send_clock_released());
+		});
+	}
+
+	fn send_clock_released(&self) {
+		self.pins.clk.release();
+		let mut engine = self.engine.lock().unwrap();
+		match engine.state {
+			State::Sending(byte, 10) => {
+				engine.last = byte;
+				engine.state = State::Idle;
+				self.schedule(IDLE, Self::start);
+			}
+			State::Sending(byte, i) => {
+				engine.state = State::Sending(byte, i + 1);
+				self.schedule(HALF_PERIOD - SETUP, Self::send_bit);
+			}
+			_ => {}
+		}
+	}
+
+	/// Generates clock pulse `n` of a frame sent by the host. The host changes
+	/// DATA while CLK is low, the device samples it in the middle of the high
+	/// period and acknowledges the stop bit in the last pulse.
+	fn receive_clock(&self, n: usize) {
+		self.pins.clk.drive(WireState::Low);
+		self.schedule(HALF_PERIOD, move |d| {
+			d.pins.clk.release();
+			d.schedule(HALF_PERIOD / 2, move |d| d.receive_sample(n));
+		});
+	}
+
+	fn receive_sample(&self, n: usize) {
+		if n == 10 {
+			self.pins.data.release();
+			return self.received();
+		}
+		let ack = {
+			let mut engine = self.engine.lock().unwrap();
+			let level = engine.data.is_high().unwrap_or(true);
+			match &mut engine.state {
+				State::Receiving(levels) => levels.push(level),
+				_ => return,
+			}
+			n == 9
+		};
+		if ack {
+			self.pins.data.drive(WireState::Low);
+		}
+		self.schedule(HALF_PERIOD / 2, move |d| d.receive_clock(n + 1));
+	}
+
+	fn received(&self) {
+		let mut engine = self.engine.lock().unwrap();
+		let levels = match std::mem::replace(&mut engine.state, State::Idle) {
+			State::Receiving(levels) => levels,
+			_ => return,
+		};
+		let frame: Vec<bool> = std::iter::once(false).chain(levels).collect();
+		let answer = match decode(&frame) {
+			Ok(RESEND) => vec![engine.last],
+			Ok(byte) => engine.handler.receive(byte),
+			Err(_) => vec![RESEND],
+		};
+		for byte in answer.into_iter().rev() {
+			engine.queue.push_front(byte);
+		}
+		self.schedule(IDLE, Self::start);
+	}
+}
+
+impl<H> Ps2Device<H> {
+	/// Calls `f` with the handler of this device.
+	pub fn with_handler<F, R>(&self, f: F) -> R
+	where
+		F: FnOnce(&mut H) -> R,
+	{
+		f(&mut self.engine.lock().unwrap().handler)
+	}
+
+	/// Returns the number of bytes waiting to be sent.
+	pub fn pending(&self) -> usize {
+		self.engine.lock().unwrap().queue.len()
+	}
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::super::{Keyboard, Ps2Host, ACK};
+	use super::*;
+
+	fn setup() -> (VirtualClock, Ps2Device<Keyboard>, Ps2Host) {
+		let clock = VirtualClock::new();
+		let clk = Wire::new_with_pull(WireState::High);
+		let data = Wire::new_with_pull(WireState::High);
+		let device = Ps2Device::new(&clk, &data, clock.clone(), Keyboard::new());
+		let host = Ps2Host::new(&clk, &data, clock.clone());
+		(clock, device, host)
+	}
+
+	fn command(host: &mut Ps2Host, byte: u8, answers: usize) -> Vec<u8> {
+		host.write(byte).unwrap();
+		(0..answers).map(|_| host.read().unwrap()).collect()
+	}
+
+	#[test]
+	fn keyboard() {
+		let (_, device, mut host) = setup();
+		assert_eq!(vec![ACK, 0xaa], command(&mut host, 0xff, 2));
+		assert_eq!(vec![0xee], command(&mut host, 0xee, 1));
+		assert_eq!(vec![ACK, 0xab, 0x83], command(&mut host, 0xf2, 3));
+		assert_eq!(vec![ACK], command(&mut host, 0xf0, 1));
+		assert_eq!(vec![ACK], command(&mut host, 0x03, 1));
+		assert_eq!(vec![ACK], command(&mut host, 0xf0, 1));
+		assert_eq!(vec![ACK, 0x03], command(&mut host, 0x00, 2));
+		assert_eq!(vec![ACK], command(&mut host, 0xf3, 1));
+		assert_eq!(vec![ACK], command(&mut host, 0x20, 1));
+		assert_eq!(3, device.with_handler(|keyboard| keyboard.scan_code_set()));
+		assert_eq!(vec![ACK], command(&mut host, 0xf5, 1));
+		device.with_handler(|keyboard| {
+			assert!(!keyboard.is_scanning());
+			assert_eq!(2, keyboard.scan_code_set());
+			assert_eq!(0x2b, keyboard.typematic());
+		});
+		assert_eq!(Err(nb::Error::WouldBlock), host.try_read());
+	}
+
+	#[test]
+	fn inhibit() {
+		let (clock, device, mut host) = setup();
+		device.send(&[0x1c, 0xf0]);
+		clock.advance(Duration::from_micros(300));
+		host.inhibit(true);
+		clock.advance(Duration::from_millis(2));
+		assert_eq!(2, device.pending());
+		assert_eq!(Err(nb::Error::WouldBlock), host.try_read());
+		host.inhibit(false);
+		assert_eq!(Ok(0x1c), host.read());
+		assert_eq!(Ok(0xf0), host.read());
+		assert_eq!(0, device.pending());
+	}
+
+	#[test]
+	fn request_to_send() {
+		let (clock, device, mut host) = setup();
+		device.send(&[0x1c, 0xf0]);
+		clock.advance(Duration::from_micros(300));
+		// the answer to the echo is sent first, then the aborted byte
+		host.write(0xee).unwrap();
+		assert_eq!(Ok(0xee), host.read());
+		assert_eq!(Ok(0x1c), host.read());
+		assert_eq!(Ok(0xf0), host.read());
+	}
+
+	#[test]
+	fn resend() {
+		let (_, device, mut host) = setup();
+		device.send(&[0x1c]);
+		assert_eq!(Ok(0x1c), host.read());
+		host.write(RESEND).unwrap();
+		assert_eq!(Ok(0x1c), host.read());
+		assert_eq!(Err(super::super::Error::Timeout), host.read());
+	}
+}
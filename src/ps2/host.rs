@@ -0,0 +1,225 @@
+use super::{decode, frame, open_drain, Error, REQUEST_TO_SEND};
+use crate::clock::VirtualClock;
+use crate::net::PinBackend;
+use crate::sync::{Arc, Mutex};
+use crate::wire::{Driver, InputOnlyPin, Wire, WireState};
+use embedded_hal::digital::InputPin;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Time a device has to start clocking after a request-to-send.
+const TIMEOUT: Duration = Duration::from_millis(15);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum State {
+	/// Receiving a frame, with the levels so far.
+	Receiving(Vec<bool>),
+	/// Sending the given bit of a frame with the byte.
+	Sending(u8, usize),
+	Inhibited,
+}
+
+#[derive(Debug)]
+struct Engine {
+	data: InputOnlyPin,
+	state: State,
+	received: VecDeque<Result<u8, Error>>,
+	/// Whether the last frame sent was acknowledged.
+	ack: Option<bool>,
+}
+
+impl Engine {
+	/// Handles a falling edge of CLK generated by the device, returns the
+	/// level to send next.
+	fn falling(&mut self) -> Option<bool> {
+		let level = self.data.is_high().unwrap_or(true);
+		match &mut self.state {
+			State::Receiving(levels) => {
+				levels.push(level);
+				if levels.len() == 11 {
+					let byte = decode(levels);
+					self.received.push_back(byte);
+					levels.clear();
+				}
+				None
+			}
+			State::Sending(_, 10) => {
+				self.ack = Some(!level);
+				self.state = State::Receiving(vec![]);
+				None
+			}
+			State::Sending(byte, i) => {
+				// the start bit was sent with the request-to-send
+				*i += 1;
+				Some(frame(*byte)[*i])
+			}
+			State::Inhibited => None,
+		}
+	}
+}
+
+/// The host side of a PS/2 connection.
+///
+/// The host receives frames on the falling edges of CLK generated by the
+/// device and queues the received bytes and errors until they are read. It
+/// sends bytes with a request-to-send, changing DATA on the falling edges of
+/// CLK while the device clocks the frame in. Both lines are only ever pulled
+/// low or released.
+///
+/// Blocking calls advance the clock until they are done. As the clock is
+/// virtual, a read only returns [`Error::Timeout`] instead of waiting forever
+/// when nothing was received and no activity is scheduled on the clock.
+///
+/// See [`Ps2Device`](`super::Ps2Device`) for an example.
+#[derive(Debug)]
+pub struct Ps2Host {
+	engine: Arc<Mutex<Engine>>,
+	clk: Driver,
+	data: Arc<Driver>,
+	clock: VirtualClock,
+}
+
+impl Ps2Host {
+	/// Attaches a new host to the given wires.
+	pub fn new(clk: &Wire, data: &Wire, clock: VirtualClock) -> Self {
+		let engine = Arc::new(Mutex::new(Engine {
+			data: data.connect_input_pin(),
+			state: State::Receiving(vec![]),
+			received: VecDeque::new(),
+			ack: None,
+		}));
+		let host = Ps2Host {
+			engine,
+			clk: clk.connect_driver(),
+			data: Arc::new(data.connect_driver()),
+			clock,
+		};
+		let (e, d) = (host.engine.clone(), host.data.clone());
+		clk.on_change(move |state| {
+			if state == WireState::Low {
+				let level = e.lock().unwrap().falling();
+				if let Some(level) = level {
+					d.drive(open_drain(level));
+				}
+			}
+		});
+		host
+	}
+
+	/// Inhibits the device by holding CLK low, or releases CLK again.
+	///
+	/// A partially received frame is discarded.
+	pub fn inhibit(&mut self, inhibit: bool) {
+		self.engine.lock().unwrap().state = if inhibit {
+			State::Inhibited
+		} else {
+			State::Receiving(vec![])
+		};
+		self.clk.drive(open_drain(!inhibit));
+	}
+
+	/// Returns the next received byte or error, if any.
+	pub fn try_read(&mut self) -> nb::Result<u8, Error> {
+		match self.engine.lock().unwrap().received.pop_front() {
+			Some(Ok(byte)) => Ok(byte),
+			Some(Err(e)) => Err(nb::Error::Other(e)),
+			None => Err(nb::Error::WouldBlock),
+		}
+	}
+
+	/// Advances the clock until a byte or error was received.
+	pub fn read(&mut self) -> Result<u8, Error> {
+		loop {
+			match self.try_read() {
+				Ok(byte) => return Ok(byte),
+				Err(nb::Error::Other(e)) => return Err(e),
+				Err(nb::Error::WouldBlock) => {}
+			}
+			match self.clock.next_event() {
+				Some(at) => self.clock.advance_to(at),
+				None => return Err(Error::Timeout),
+			}
+		}
+	}
+
+	/// Sends `byte` to the device, advancing the clock until it was
+	/// acknowledged.
+	///
+	/// A frame being received is aborted by the request-to-send, the device
+	/// sends it again afterwards.
+	pub fn write(&mut self, byte: u8) -> Result<(), Error> {
+		self.inhibit(true);
+		self.clock.advance(REQUEST_TO_SEND);
+		{
+			let mut engine = self.engine.lock().unwrap();
+			engine.ack = None;
+			engine.state = State::Sending(byte, 0);
+		}
+		self.data.drive(WireState::Low);
+		self.clk.release();
+		let start = self.clock.now();
+		loop {
+			if let Some(ack) = self.engine.lock().unwrap().ack {
+				return if ack { Ok(()) } else { Err(Error::NoAck) };
+			}
+			match self.clock.next_event() {
+				Some(at) if at <= start + TIMEOUT => self.clock.advance_to(at),
+				_ => break,
+			}
+		}
+		self.engine.lock().unwrap().state = State::Receiving(vec![]);
+		self.data.release();
+		Err(Error::Timeout)
+	}
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::super::HALF_PERIOD;
+	use super::*;
+
+	/// Clocks the levels of a frame out like a device would.
+	fn send_frame(clock: &VirtualClock, clk: &Wire, data: &Wire, levels: &[bool]) {
+		let clk = clk.connect_driver();
+		let data = data.connect_driver();
+		for level in levels {
+			data.drive(open_drain(*level));
+			clock.advance(HALF_PERIOD / 2);
+			clk.drive(WireState::Low);
+			clock.advance(HALF_PERIOD);
+			clk.release();
+			clock.advance(HALF_PERIOD / 2);
+		}
+		data.release();
+	}
+
+	#[test]
+	fn receive() {
+		let clock = VirtualClock::new();
+		let clk = Wire::new_with_pull(WireState::High);
+		let data = Wire::new_with_pull(WireState::High);
+		let mut host = Ps2Host::new(&clk, &data, clock.clone());
+		send_frame(&clock, &clk, &data, &frame(0x5a));
+		let mut bad_parity = frame(0x5a);
+		bad_parity[9] = !bad_parity[9];
+		send_frame(&clock, &clk, &data, &bad_parity);
+		let mut bad_stop = frame(0x5a);
+		bad_stop[10] = false;
+		send_frame(&clock, &clk, &data, &bad_stop);
+		assert_eq!(Ok(0x5a), host.try_read());
+		assert_eq!(Err(nb::Error::Other(Error::Parity)), host.try_read());
+		assert_eq!(Err(nb::Error::Other(Error::Framing)), host.try_read());
+		assert_eq!(Err(Error::Timeout), host.read());
+	}
+
+	#[test]
+	fn no_device() {
+		let clock = VirtualClock::new();
+		let clk = Wire::new_with_pull(WireState::High);
+		let data = Wire::new_with_pull(WireState::High);
+		let mut host = Ps2Host::new(&clk, &data, clock.clone());
+		assert_eq!(Err(Error::Timeout), host.write(0xff));
+		assert_eq!(WireState::High, clk.get_state());
+		assert_eq!(WireState::High, data.get_state());
+	}
+}
@@ -0,0 +1,98 @@
+use super::{DeviceHandler, ACK};
+
+/// Answer to the echo command.
+const ECHO: u8 = 0xee;
+/// Byte sent after a successful self-test.
+const SELF_TEST_PASSED: u8 = 0xaa;
+
+/// A model of a PS/2 keyboard, to be used with a [`Ps2Device`](`super::Ps2Device`).
+///
+/// The keyboard answers the reset, echo, identify, LED, typematic rate, scan
+/// code set and scanning commands. Other commands are acknowledged and
+/// ignored. Scan codes of key presses are sent with
+/// [`Ps2Device::send`](`super::Ps2Device::send`).
+///
+/// See [`Ps2Device`](`super::Ps2Device`) for an example.
+#[derive(Clone, Debug)]
+pub struct Keyboard {
+	/// The command waiting for its argument.
+	command: Option<u8>,
+	leds: u8,
+	typematic: u8,
+	scan_code_set: u8,
+	scanning: bool,
+}
+
+impl Default for Keyboard {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Keyboard {
+	/// Creates a keyboard with the defaults after power-up.
+	pub fn new() -> Self {
+		Keyboard {
+			command: None,
+			leds: 0,
+			typematic: 0x2b,
+			scan_code_set: 2,
+			scanning: true,
+		}
+	}
+
+	/// Returns the LED state set by the host, bit 0 is scroll lock, bit 1 num
+	/// lock and bit 2 caps lock.
+	pub fn leds(&self) -> u8 {
+		self.leds
+	}
+
+	/// Returns the typematic rate and delay set by the host.
+	pub fn typematic(&self) -> u8 {
+		self.typematic
+	}
+
+	/// Returns the scan code set selected by the host.
+	pub fn scan_code_set(&self) -> u8 {
+		self.scan_code_set
+	}
+
+	/// Returns whether scanning is enabled.
+	pub fn is_scanning(&self) -> bool {
+		self.scanning
+	}
+
+	fn set_defaults(&mut self) {
+		self.typematic = 0x2b;
+		self.scan_code_set = 2;
+	}
+}
+
+impl DeviceHandler for Keyboard {
+	fn receive(&mut self, byte: u8) -> Vec<u8> {
+		match self.command.take() {
+			Some(0xed) => self.leds = byte & 0x07,
+			Some(0xf3) => self.typematic = byte & 0x7f,
+			Some(0xf0) if byte == 0 => return vec![ACK, self.scan_code_set],
+			Some(0xf0) if (1..=3).contains(&byte) => self.scan_code_set = byte,
+			Some(_) => {}
+			None => match byte {
+				0xed | 0xf0 | 0xf3 => self.command = Some(byte),
+				ECHO => return vec![ECHO],
+				0xf2 => return vec![ACK, 0xab, 0x83],
+				0xf4 => self.scanning = true,
+				0xf5 => {
+					self.scanning = false;
+					self.set_defaults();
+				}
+				0xf6 => self.set_defaults(),
+				0xff => {
+					*self = Keyboard::new();
+					return vec![ACK, SELF_TEST_PASSED];
+				}
+				_ => {}
+			},
+		}
+		vec![ACK]
+	}
+}
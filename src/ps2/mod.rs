@@ -0,0 +1,112 @@
+//! Simulated PS/2 keyboards and mice
+//!
+//! PS/2 uses two open drain lines pulled high, a clock (CLK) and a data line
+//! (DATA), which map onto [`Wire`](`crate::wire::Wire`)s created with
+//! [`Wire::new_with_pull(WireState::High)`](`crate::wire::Wire::new_with_pull`).
+//! The clock is always generated by the device, at about 12.5 kHz here.
+//!
+//! Bytes are sent in frames of 11 bits: a low start bit, eight data bits LSB
+//! first, an odd parity bit and a high stop bit. The device sends a frame
+//! when both lines are high, changing DATA while CLK is high so the host can
+//! read it on the falling edges. The host can inhibit the device by holding
+//! CLK low. To send a byte itself, the host holds CLK low for at least 100 µs,
+//! pulls DATA low and releases CLK, a request-to-send the device answers by
+//! clocking in the frame and acknowledging it by pulling DATA low once more.
+//!
+//! [`Ps2Device`] implements the device side of the protocol and passes bytes
+//! from the host on to a [`DeviceHandler`], e.g. a [`Keyboard`] or a [`Mouse`].
+//! [`Ps2Host`] is the other side, as implemented by the firmware of a
+//! computer. Both time their actions on a
+//! [`VirtualClock`](`crate::clock::VirtualClock`).
+
+mod device;
+mod host;
+mod keyboard;
+mod mouse;
+
+pub use device::{DeviceHandler, Ps2Device};
+pub use host::Ps2Host;
+pub use keyboard::Keyboard;
+pub use mouse::Mouse;
+
+use crate::wire::WireState;
+use std::fmt;
+use std::time::Duration;
+
+/// Half a clock period, the time CLK is low or high.
+pub(crate) const HALF_PERIOD: Duration = Duration::from_micros(40);
+/// Minimum time the host holds CLK low for a request-to-send.
+pub(crate) const REQUEST_TO_SEND: Duration = Duration::from_micros(100);
+
+/// Byte a device sends after receiving a command.
+pub const ACK: u8 = 0xfa;
+/// Byte requesting the last byte to be sent again.
+pub const RESEND: u8 = 0xfe;
+
+/// An error of a [`Ps2Host`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+	/// The parity bit of a received frame did not match the data.
+	Parity,
+	/// The start bit of a received frame was high or its stop bit low.
+	Framing,
+	/// The device did not acknowledge a frame sent by the host.
+	NoAck,
+	/// The device did not clock a frame in, or a blocking read found no
+	/// received data and no pending activity on the clock.
+	Timeout,
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Error::Parity => write!(f, "parity error"),
+			Error::Framing => write!(f, "framing error"),
+			Error::NoAck => write!(f, "frame not acknowledged"),
+			Error::Timeout => write!(f, "device not responding"),
+		}
+	}
+}
+
+impl std::error::Error for Error {}
+
+/// Returns the odd parity bit of `byte`.
+pub(crate) fn parity(byte: u8) -> bool {
+	byte.count_ones() & 1 == 0
+}
+
+/// Returns the levels of a frame with `byte`.
+pub(crate) fn frame(byte: u8) -> [bool; 11] {
+	let mut frame = [true; 11];
+	frame[0] = false;
+	for (i, level) in frame[1..9].iter_mut().enumerate() {
+		*level = byte & (1 << i) != 0;
+	}
+	frame[9] = parity(byte);
+	frame
+}
+
+/// Returns the state an open drain output drives for `level`, both lines are
+/// pulled high.
+pub(crate) fn open_drain(level: bool) -> WireState {
+	if level {
+		WireState::Floating
+	} else {
+		WireState::Low
+	}
+}
+
+/// Decodes the levels of a frame.
+pub(crate) fn decode(levels: &[bool]) -> Result<u8, Error> {
+	let byte = levels[1..9]
+		.iter()
+		.rev()
+		.fold(0, |byte, level| (byte << 1) | *level as u8);
+	if levels[0] || !levels[10] {
+		Err(Error::Framing)
+	} else if levels[9] != parity(byte) {
+		Err(Error::Parity)
+	} else {
+		Ok(byte)
+	}
+}
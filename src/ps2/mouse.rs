@@ -0,0 +1,163 @@
+use super::{DeviceHandler, ACK};
+
+/// Byte sent after a successful self-test.
+const SELF_TEST_PASSED: u8 = 0xaa;
+/// Device ID of a standard mouse.
+const ID: u8 = 0x00;
+
+/// A model of a standard PS/2 mouse, to be used with a
+/// [`Ps2Device`](`super::Ps2Device`).
+///
+/// The mouse answers the reset, identify, status request, defaults, sample
+/// rate, resolution and data reporting commands. Other commands are
+/// acknowledged and ignored. Movement packets built with [`Mouse::packet`]
+/// are sent with [`Ps2Device::send`](`super::Ps2Device::send`).
+///
+/// # Examples
+///
+/// ```
+/// use embedded_hal_sync_pins::clock::VirtualClock;
+/// use embedded_hal_sync_pins::ps2::{Mouse, Ps2Device, Ps2Host};
+/// use embedded_hal_sync_pins::wire::{Wire, WireState};
+///
+/// let clock = VirtualClock::new();
+/// let clk = Wire::new_with_pull(WireState::High);
+/// let data = Wire::new_with_pull(WireState::High);
+/// let mouse = Ps2Device::new(&clk, &data, clock.clone(), Mouse::new());
+/// let mut host = Ps2Host::new(&clk, &data, clock.clone());
+/// // enable data reporting
+/// host.write(0xf4).unwrap();
+/// assert_eq!(Ok(0xfa), host.read());
+/// assert!(mouse.with_handler(|mouse| mouse.is_reporting()));
+/// // move left with the left button pressed
+/// mouse.send(&Mouse::packet(-3, 0, 0b001));
+/// let mut packet = [0; 3];
+/// for byte in packet.iter_mut() {
+///     *byte = host.read().unwrap();
+/// }
+/// assert_eq!([0x19, 0xfd, 0x00], packet);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Mouse {
+	/// The command waiting for its argument.
+	command: Option<u8>,
+	sample_rate: u8,
+	resolution: u8,
+	reporting: bool,
+}
+
+impl Default for Mouse {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Mouse {
+	/// Creates a mouse with the defaults after power-up.
+	pub fn new() -> Self {
+		Mouse {
+			command: None,
+			sample_rate: 100,
+			resolution: 2,
+			reporting: false,
+		}
+	}
+
+	/// Returns the sample rate set by the host in samples per second.
+	pub fn sample_rate(&self) -> u8 {
+		self.sample_rate
+	}
+
+	/// Returns the resolution set by the host, from `0` for one count per
+	/// millimetre to `3` for eight.
+	pub fn resolution(&self) -> u8 {
+		self.resolution
+	}
+
+	/// Returns whether data reporting is enabled.
+	pub fn is_reporting(&self) -> bool {
+		self.reporting
+	}
+
+	/// Returns the movement packet for a movement by `dx` and `dy` with the
+	/// given buttons pressed, bit 0 is the left, bit 1 the right and bit 2 the
+	/// middle button.
+	///
+	/// Movements out of the range of nine bits set the overflow bits and are
+	/// clamped.
+	pub fn packet(dx: i16, dy: i16, buttons: u8) -> [u8; 3] {
+		let mut flags = 0x08 | (buttons & 0x07);
+		let x = dx.clamp(-256, 255);
+		let y = dy.clamp(-256, 255);
+		if x < 0 {
+			flags |= 0x10;
+		}
+		if y < 0 {
+			flags |= 0x20;
+		}
+		if x != dx {
+			flags |= 0x40;
+		}
+		if y != dy {
+			flags |= 0x80;
+		}
+		[flags, x as u8, y as u8]
+	}
+}
+
+impl DeviceHandler for Mouse {
+	fn receive(&mut self, byte: u8) -> Vec<u8> {
+		match self.command.take() {
+			Some(0xf3) => self.sample_rate = byte,
+			Some(0xe8) => self.resolution = byte & 0x03,
+			Some(_) => {}
+			None => match byte {
+				0xe8 | 0xf3 => self.command = Some(byte),
+				0xe9 => {
+					let status = (self.reporting as u8) << 5;
+					return vec![ACK, status, self.resolution, self.sample_rate];
+				}
+				0xf2 => return vec![ACK, ID],
+				0xf4 => self.reporting = true,
+				0xf5 => self.reporting = false,
+				0xf6 => {
+					*self = Mouse::new();
+				}
+				0xff => {
+					*self = Mouse::new();
+					return vec![ACK, SELF_TEST_PASSED, ID];
+				}
+				_ => {}
+			},
+		}
+		vec![ACK]
+	}
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn packet() {
+		assert_eq!([0x08, 0x00, 0x00], Mouse::packet(0, 0, 0));
+		assert_eq!([0x0f, 0x05, 0x0a], Mouse::packet(5, 10, 0xff));
+		assert_eq!([0x38, 0xff, 0x00], Mouse::packet(-1, -256, 0));
+		assert_eq!([0x48, 0xff, 0x01], Mouse::packet(300, 1, 0));
+		assert_eq!([0xa8, 0x01, 0x00], Mouse::packet(1, -300, 0));
+	}
+
+	#[test]
+	fn commands() {
+		let mut mouse = Mouse::new();
+		assert_eq!(vec![ACK, SELF_TEST_PASSED, ID], mouse.receive(0xff));
+		assert_eq!(vec![ACK], mouse.receive(0xf3));
+		assert_eq!(vec![ACK], mouse.receive(40));
+		assert_eq!(vec![ACK], mouse.receive(0xe8));
+		assert_eq!(vec![ACK], mouse.receive(3));
+		assert_eq!(vec![ACK], mouse.receive(0xf4));
+		assert_eq!(vec![ACK, 0x20, 3, 40], mouse.receive(0xe9));
+		assert_eq!(vec![ACK], mouse.receive(0xf6));
+		assert_eq!(vec![ACK, 0x00, 2, 100], mouse.receive(0xe9));
+	}
+}
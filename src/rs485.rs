@@ -0,0 +1,323 @@
+//! Simulated RS-485 buses
+//!
+//! RS-485 transmits a signal as the voltage difference between the two lines
+//! A and B of a twisted pair, a one when A is above B and a zero otherwise.
+//! A [`Bus`] models the pair as two [`Wire`]s, where a one is A high and B
+//! low. Idle-bus failsafe biasing, a pull up on A and a pull down on B that
+//! keep an idle bus at a one, is represented by the pulls of the wires.
+//!
+//! Nodes connect to the bus through [`Transceiver`]s, which like a MAX485
+//! have a driver input DI, a driver enable DE, an active low receiver enable
+//! /RE and a receiver output RO on the logic side. The driver drives both
+//! lines with push-pull outputs while DE is high, so two nodes enabling their
+//! drivers at the same time with different levels cause a short circuit on
+//! the wires. As on a half-duplex bus, DE and /RE are often tied together, the
+//! same wire can be passed for both.
+//!
+//! # Examples
+//!
+//! ```
+//! use embedded_hal_sync_pins::clock::VirtualClock;
+//! use embedded_hal_sync_pins::rs485::{Bus, Transceiver};
+//! use embedded_hal_sync_pins::uart::{Config, UartRx, UartTx};
+//! use embedded_hal_sync_pins::wire::Wire;
+//! use embedded_hal::digital::OutputPin;
+//! use embedded_io::{Read, Write};
+//!
+//! let clock = VirtualClock::new();
+//! let bus = Bus::new();
+//! let config = Config::default();
+//! // a sending node with DE and /RE tied together
+//! let (ro, enable, di) = (Wire::new(), Wire::new(), Wire::new());
+//! Transceiver::new(&bus, &ro, &enable, &enable, &di);
+//! let mut enable = enable.connect_push_pull_pin();
+//! let mut tx = UartTx::new(di.connect_push_pull_pin(), clock.clone(), config);
+//! // a listening node
+//! let (ro, re, de, di) = (Wire::new(), Wire::new(), Wire::new(), Wire::new());
+//! Transceiver::new(&bus, &ro, &re, &de, &di);
+//! re.connect_push_pull_pin().set_low().unwrap();
+//! let mut rx = UartRx::new(&ro, clock.clone(), config);
+//!
+//! enable.set_high().unwrap();
+//! tx.write_all(b"ping").unwrap();
+//! tx.flush().unwrap();
+//! enable.set_low().unwrap();
+//! let mut buffer = [0; 4];
+//! rx.read_exact(&mut buffer).unwrap();
+//! assert_eq!(b"ping", &buffer);
+//! ```
+
+use crate::net::PinBackend;
+use crate::sync::Arc;
+use crate::wire::{Driver, Wire, WireState};
+
+/// The two lines of an RS-485 bus.
+#[derive(Clone, Debug)]
+pub struct Bus {
+	a: Wire,
+	b: Wire,
+}
+
+impl Default for Bus {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Bus {
+	/// Creates a bus with failsafe biasing, A is pulled high and B low, so
+	/// receivers read a one while no driver is enabled.
+	pub fn new() -> Self {
+		Bus {
+			a: Wire::new_with_pull(WireState::High),
+			b: Wire::new_with_pull(WireState::Low),
+		}
+	}
+
+	/// Creates a bus without biasing, the level of an idle bus is undefined.
+	pub fn new_unbiased() -> Self {
+		Bus {
+			a: Wire::new(),
+			b: Wire::new(),
+		}
+	}
+
+	pub fn a(&self) -> &Wire {
+		&self.a
+	}
+
+	pub fn b(&self) -> &Wire {
+		&self.b
+	}
+
+	/// Returns the differential level of the bus, `High` if A is high and B
+	/// low, `Low` if A is low and B high and `Floating` otherwise.
+	pub fn get_state(&self) -> WireState {
+		match (self.a.get_state(), self.b.get_state()) {
+			(WireState::High, WireState::Low) => WireState::High,
+			(WireState::Low, WireState::High) => WireState::Low,
+			_ => WireState::Floating,
+		}
+	}
+}
+
+#[derive(Debug)]
+struct Inner {
+	bus: Bus,
+	a: Driver,
+	b: Driver,
+	ro: Driver,
+	re: Wire,
+	de: Wire,
+	di: Wire,
+}
+
+impl Inner {
+	fn is_driving(&self) -> bool {
+		self.de.get_state() == WireState::High
+	}
+
+	fn is_receiving(&self) -> bool {
+		self.re.get_state() == WireState::Low
+	}
+
+	/// Drives the bus and RO according to the current inputs.
+	fn update(&self) {
+		if self.is_driving() {
+			let (a, b) = match self.di.get_state() {
+				WireState::High => (WireState::High, WireState::Low),
+				_ => (WireState::Low, WireState::High),
+			};
+			self.a.drive(a);
+			self.b.drive(b);
+		} else {
+			self.a.release();
+			self.b.release();
+		}
+		if self.is_receiving() {
+			self.ro.drive(self.bus.get_state());
+		} else {
+			self.ro.release();
+		}
+	}
+}
+
+/// A half-duplex RS-485 transceiver connecting a node to a [`Bus`].
+///
+/// While DE is high, the transceiver drives DI onto the bus. While /RE is
+/// low, it drives the differential level of the bus onto RO, which floats if
+/// the level is undefined, e.g. on an idle bus without biasing. DI and DE are
+/// read as low and /RE as high unless they are driven, so an unconnected
+/// transceiver neither drives the bus nor RO.
+///
+/// See the [module documentation](`self`) for an example.
+#[derive(Clone, Debug)]
+pub struct Transceiver {
+	inner: Arc<Inner>,
+}
+
+impl Transceiver {
+	/// Connects a new transceiver to `bus` with the given logic side wires,
+	/// in the pin order of a MAX485.
+	///
+	/// # Panics
+	///
+	/// Panics if the driver is enabled on a bus already driven to a different
+	/// level by another transceiver.
+	pub fn new(bus: &Bus, ro: &Wire, re: &Wire, de: &Wire, di: &Wire) -> Self {
+		let inner = Arc::new(Inner {
+			bus: bus.clone(),
+			a: bus.a.connect_driver(),
+			b: bus.b.connect_driver(),
+			ro: ro.connect_driver(),
+			re: re.clone(),
+			de: de.clone(),
+			di: di.clone(),
+		});
+		for wire in [&bus.a, &bus.b, re, de, di] {
+			let inner = inner.clone();
+			wire.on_change(move |_| inner.update());
+		}
+		inner.update();
+		Transceiver { inner }
+	}
+
+	/// Returns whether the driver is enabled.
+	pub fn is_driving(&self) -> bool {
+		self.inner.is_driving()
+	}
+
+	/// Returns whether the receiver is enabled.
+	pub fn is_receiving(&self) -> bool {
+		self.inner.is_receiving()
+	}
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::*;
+	use crate::clock::VirtualClock;
+	use crate::uart::{Config, UartRx, UartTx};
+	use embedded_hal::digital::OutputPin;
+	use embedded_io::{Read, Write};
+
+	struct Node {
+		ro: Wire,
+		re: Wire,
+		de: Wire,
+		di: Wire,
+		transceiver: Transceiver,
+	}
+
+	impl Node {
+		fn new(bus: &Bus) -> Self {
+			let (ro, re, de, di) = (Wire::new(), Wire::new(), Wire::new(), Wire::new());
+			let transceiver = Transceiver::new(bus, &ro, &re, &de, &di);
+			Node {
+				ro,
+				re,
+				de,
+				di,
+				transceiver,
+			}
+		}
+	}
+
+	#[test]
+	fn drive_and_receive() {
+		let bus = Bus::new();
+		let (first, second) = (Node::new(&bus), Node::new(&bus));
+		assert!(!first.transceiver.is_driving());
+		assert!(!first.transceiver.is_receiving());
+		assert_eq!(WireState::Floating, first.ro.get_state());
+		let mut re = second.re.connect_push_pull_pin();
+		re.set_low().unwrap();
+		assert!(second.transceiver.is_receiving());
+		// failsafe biasing
+		assert_eq!(WireState::High, bus.get_state());
+		assert_eq!(WireState::High, second.ro.get_state());
+		let mut de = first.de.connect_push_pull_pin();
+		let mut di = first.di.connect_push_pull_pin();
+		de.set_high().unwrap();
+		assert_eq!(WireState::Low, bus.a().get_state());
+		assert_eq!(WireState::High, bus.b().get_state());
+		assert_eq!(WireState::Low, second.ro.get_state());
+		di.set_high().unwrap();
+		assert_eq!(WireState::High, second.ro.get_state());
+		di.set_low().unwrap();
+		assert_eq!(WireState::Low, second.ro.get_state());
+		re.set_high().unwrap();
+		assert_eq!(WireState::Floating, second.ro.get_state());
+		re.set_low().unwrap();
+		de.set_low().unwrap();
+		assert_eq!(WireState::High, second.ro.get_state());
+	}
+
+	#[test]
+	fn unbiased() {
+		let bus = Bus::new_unbiased();
+		let node = Node::new(&bus);
+		node.re.connect_push_pull_pin().set_low().unwrap();
+		assert_eq!(WireState::Floating, bus.get_state());
+		assert_eq!(WireState::Floating, node.ro.get_state());
+		node.de.connect_push_pull_pin().set_high().unwrap();
+		node.di.connect_push_pull_pin().set_high().unwrap();
+		assert_eq!(WireState::High, node.ro.get_state());
+	}
+
+	#[test]
+	fn same_level() {
+		let bus = Bus::new();
+		let (first, second) = (Node::new(&bus), Node::new(&bus));
+		for node in [&first, &second] {
+			node.de.connect_push_pull_pin().set_high().unwrap();
+		}
+		assert_eq!(WireState::Low, bus.get_state());
+	}
+
+	#[test]
+	#[should_panic(expected = "short circuit")]
+	fn contention() {
+		let bus = Bus::new();
+		let (first, second) = (Node::new(&bus), Node::new(&bus));
+		first.de.connect_push_pull_pin().set_high().unwrap();
+		second.di.connect_push_pull_pin().set_high().unwrap();
+		second.de.connect_push_pull_pin().set_high().unwrap();
+	}
+
+	#[test]
+	fn half_duplex() {
+		let clock = VirtualClock::new();
+		let bus = Bus::new();
+		let config = Config {
+			baud: 115_200,
+			..Default::default()
+		};
+		let nodes: Vec<_> = (0..2).map(|_| Node::new(&bus)).collect();
+		let mut enables = vec![];
+		let mut uarts = vec![];
+		for node in nodes.iter() {
+			// DE and /RE are switched together
+			let mut de = node.de.connect_push_pull_pin();
+			let mut re = node.re.connect_push_pull_pin();
+			de.set_low().unwrap();
+			re.set_low().unwrap();
+			enables.push((de, re));
+			let tx = UartTx::new(node.di.connect_push_pull_pin(), clock.clone(), config);
+			let rx = UartRx::new(&node.ro, clock.clone(), config);
+			uarts.push((tx, rx));
+		}
+		for (from, message) in [(0, b"ping"), (1, b"pong")] {
+			enables[from].0.set_high().unwrap();
+			enables[from].1.set_high().unwrap();
+			uarts[from].0.write_all(message).unwrap();
+			uarts[from].0.flush().unwrap();
+			enables[from].0.set_low().unwrap();
+			enables[from].1.set_low().unwrap();
+			let mut buffer = [0; 4];
+			uarts[1 - from].1.read_exact(&mut buffer).unwrap();
+			assert_eq!(message, &buffer);
+			assert_eq!(Err(nb::Error::WouldBlock), uarts[from].1.try_read());
+		}
+	}
+}
@@ -0,0 +1,448 @@
+use super::BitOrder;
+use crate::clock::half_period_us;
+use crate::wire::{InputOnlyPin, PushPullPin};
+use embedded_hal::delay::DelayUs;
+use embedded_hal::digital::{InputPin, OutputPin};
+use embedded_hal::spi::{
+	ErrorKind, ErrorType, Mode, Phase, Polarity, SpiBus, SpiBusFlush, SpiBusRead, SpiBusWrite,
+	SpiDevice, MODE_0,
+};
+
+/// A bit-banged SPI master.
+///
+/// The master drives SCK and MOSI through [`PushPullPin`]s and samples MISO
+/// through an [`InputOnlyPin`]. Words of up to 32 bits are supported; only the
+/// lowest [`word_size`](`SpiMaster::set_word_size`) bits of each word are
+/// transferred. A floating MISO line is read as `0`.
+///
+/// All timing is done using the given delay, which usually is a
+/// [`VirtualClock`](`crate::clock::VirtualClock`).
+///
+/// # Examples
+///
+/// ```
+/// use embedded_hal_sync_pins::clock::VirtualClock;
+/// use embedded_hal_sync_pins::spi::SpiMaster;
+/// use embedded_hal_sync_pins::wire::Wire;
+/// use embedded_hal::spi::{SpiBus, MODE_3};
+///
+/// let sck = Wire::new();
+/// let mosi = Wire::new();
+/// // loop MOSI back to MISO
+/// let mut spi = SpiMaster::new(
+///     sck.connect_push_pull_pin(),
+///     mosi.connect_push_pull_pin(),
+///     mosi.connect_input_pin(),
+///     VirtualClock::new(),
+/// );
+/// spi.set_mode(MODE_3);
+/// let mut read = [0u8; 2];
+/// spi.transfer(&mut read, &[0xca, 0xfe]).unwrap();
+/// assert_eq!([0xca, 0xfe], read);
+/// ```
+pub struct SpiMaster<D> {
+	sck: PushPullPin,
+	mosi: PushPullPin,
+	miso: InputOnlyPin,
+	delay: D,
+	mode: Mode,
+	bit_order: BitOrder,
+	word_size: u8,
+	half_period_us: u32,
+}
+
+impl<D: DelayUs> SpiMaster<D> {
+	/// Creates a new master in mode 0, sending 8 bit words MSB first at 500 kHz.
+	pub fn new(sck: PushPullPin, mosi: PushPullPin, miso: InputOnlyPin, delay: D) -> Self {
+		let mut master = SpiMaster {
+			sck,
+			mosi,
+			miso,
+			delay,
+			mode: MODE_0,
+			bit_order: BitOrder::MsbFirst,
+			word_size: 8,
+			half_period_us: 1,
+		};
+		master.set_mode(MODE_0);
+		let _ = master.mosi.set_low();
+		master
+	}
+
+	/// Sets the SPI mode and moves SCK to its idle level.
+	pub fn set_mode(&mut self, mode: Mode) {
+		self.mode = mode;
+		self.set_sck(false);
+	}
+
+	pub fn set_bit_order(&mut self, bit_order: BitOrder) {
+		self.bit_order = bit_order;
+	}
+
+	/// Sets the number of bits transferred per word.
+	///
+	/// # Panics
+	///
+	/// Panics if `bits` is not in `1..=32`. Transfers panic if the word type is
+	/// smaller than the word size, e.g. `u8` words for a word size of 9 bits.
+	pub fn set_word_size(&mut self, bits: u8) {
+		assert!((1..=32).contains(&bits), "invalid word size: {}", bits);
+		self.word_size = bits;
+	}
+
+	/// Sets the SCK frequency.
+	///
+	/// As the delay has a resolution of one microsecond, the frequency is
+	/// rounded down to the next achievable frequency, but at most 500 kHz.
+	pub fn set_frequency(&mut self, hz: u32) {
+		self.half_period_us = half_period_us(hz);
+	}
+
+	/// Releases the pins and the delay.
+	pub fn free(self) -> (PushPullPin, PushPullPin, InputOnlyPin, D) {
+		(self.sck, self.mosi, self.miso, self.delay)
+	}
+
+	/// Drives SCK to its active (`true`) or idle (`false`) level.
+	fn set_sck(&mut self, active: bool) {
+		let high = active != (self.mode.polarity == Polarity::IdleHigh);
+		let _ = if high {
+			self.sck.set_high()
+		} else {
+			self.sck.set_low()
+		};
+	}
+
+	fn half_period(&mut self) -> Result<(), ErrorKind> {
+		self.delay
+			.delay_us(self.half_period_us)
+			.map_err(|_| ErrorKind::Other)
+	}
+
+	fn set_mosi(&mut self, bit: bool) {
+		let _ = if bit {
+			self.mosi.set_high()
+		} else {
+			self.mosi.set_low()
+		};
+	}
+
+	fn miso(&self) -> bool {
+		self.miso.is_high().unwrap_or(false)
+	}
+
+	fn transfer_bit(&mut self, bit: bool) -> Result<bool, ErrorKind> {
+		match self.mode.phase {
+			Phase::CaptureOnFirstTransition => {
+				self.set_mosi(bit);
+				self.half_period()?;
+				self.set_sck(true);
+				let read = self.miso();
+				self.half_period()?;
+				self.set_sck(false);
+				Ok(read)
+			}
+			Phase::CaptureOnSecondTransition => {
+				self.set_sck(true);
+				self.set_mosi(bit);
+				self.half_period()?;
+				self.set_sck(false);
+				let read = self.miso();
+				self.half_period()?;
+				Ok(read)
+			}
+		}
+	}
+
+	/// Panics if words of `type_bits` bits can not hold the word size.
+	fn check_word_type(&self, type_bits: u32) {
+		assert!(
+			self.word_size as u32 <= type_bits,
+			"word size {} exceeds the word type of {} bits",
+			self.word_size,
+			type_bits
+		);
+	}
+
+	fn transfer_word(&mut self, word: u32) -> Result<u32, ErrorKind> {
+		let bits = self.word_size as u32;
+		let mut read = 0;
+		for i in 0..bits {
+			let bit = match self.bit_order {
+				BitOrder::MsbFirst => bits - 1 - i,
+				BitOrder::LsbFirst => i,
+			};
+			if self.transfer_bit(word & (1 << bit) != 0)? {
+				read |= 1 << bit;
+			}
+		}
+		Ok(read)
+	}
+}
+
+impl<D> ErrorType for SpiMaster<D> {
+	type Error = ErrorKind;
+}
+
+impl<D: DelayUs> SpiBusFlush for SpiMaster<D> {
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		// all transfers are blocking
+		Ok(())
+	}
+}
+
+macro_rules! impl_spi {
+	($word:ty) => {
+		impl<D: DelayUs> SpiBusRead<$word> for SpiMaster<D> {
+			fn read(&mut self, words: &mut [$word]) -> Result<(), Self::Error> {
+				self.check_word_type(<$word>::BITS);
+				for word in words.iter_mut() {
+					*word = self.transfer_word(0)? as $word;
+				}
+				Ok(())
+			}
+		}
+
+		impl<D: DelayUs> SpiBusWrite<$word> for SpiMaster<D> {
+			fn write(&mut self, words: &[$word]) -> Result<(), Self::Error> {
+				self.check_word_type(<$word>::BITS);
+				for word in words {
+					self.transfer_word(*word as u32)?;
+				}
+				Ok(())
+			}
+		}
+
+		impl<D: DelayUs> SpiBus<$word> for SpiMaster<D> {
+			fn transfer(&mut self, read: &mut [$word], write: &[$word]) -> Result<(), Self::Error> {
+				self.check_word_type(<$word>::BITS);
+				for i in 0..read.len().max(write.len()) {
+					let word = self.transfer_word(write.get(i).copied().unwrap_or(0) as u32)?;
+					if let Some(r) = read.get_mut(i) {
+						*r = word as $word;
+					}
+				}
+				Ok(())
+			}
+
+			fn transfer_in_place(&mut self, words: &mut [$word]) -> Result<(), Self::Error> {
+				self.check_word_type(<$word>::BITS);
+				for word in words.iter_mut() {
+					*word = self.transfer_word(*word as u32)? as $word;
+				}
+				Ok(())
+			}
+		}
+	};
+}
+
+impl_spi!(u8);
+impl_spi!(u16);
+impl_spi!(u32);
+
+/// A [`SpiMaster`] with an active low chip select.
+///
+/// This implements [`SpiDevice`], so the chip select is asserted for the
+/// duration of each transaction.
+pub struct SpiMasterDevice<D> {
+	bus: SpiMaster<D>,
+	cs: PushPullPin,
+}
+
+impl<D: DelayUs> SpiMasterDevice<D> {
+	/// Creates a new device and deasserts its chip select.
+	pub fn new(bus: SpiMaster<D>, cs: PushPullPin) -> Self {
+		let mut device = SpiMasterDevice { bus, cs };
+		let _ = device.cs.set_high();
+		device
+	}
+
+	/// Returns the underlying bus, e.g. to change its configuration.
+	pub fn bus_mut(&mut self) -> &mut SpiMaster<D> {
+		&mut self.bus
+	}
+
+	/// Releases the bus and the chip select pin.
+	pub fn free(self) -> (SpiMaster<D>, PushPullPin) {
+		(self.bus, self.cs)
+	}
+}
+
+impl<D> ErrorType for SpiMasterDevice<D> {
+	type Error = ErrorKind;
+}
+
+impl<D: DelayUs> SpiDevice for SpiMasterDevice<D> {
+	type Bus = SpiMaster<D>;
+
+	fn transaction<R>(
+		&mut self,
+		f: impl FnOnce(&mut Self::Bus) -> Result<R, <Self::Bus as ErrorType>::Error>,
+	) -> Result<R, Self::Error> {
+		let _ = self.cs.set_low();
+		let result = f(&mut self.bus);
+		let flush = self.bus.flush();
+		let _ = self.cs.set_high();
+		let result = result?;
+		flush?;
+		Ok(result)
+	}
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::*;
+	use crate::clock::VirtualClock;
+	use crate::wire::{Wire, WireState};
+	use embedded_hal::spi::{MODE_1, MODE_2, MODE_3};
+	use std::sync::{Arc, Mutex};
+	use std::time::Duration;
+
+	struct Bus {
+		sck: Wire,
+		mosi: Wire,
+		spi: SpiMaster<VirtualClock>,
+	}
+
+	fn loopback() -> Bus {
+		let sck = Wire::new();
+		let mosi = Wire::new();
+		let spi = SpiMaster::new(
+			sck.connect_push_pull_pin(),
+			mosi.connect_push_pull_pin(),
+			mosi.connect_input_pin(),
+			VirtualClock::new(),
+		);
+		Bus { sck, mosi, spi }
+	}
+
+	/// Records MOSI on every SCK edge that captures data in `mode`.
+	fn capture(bus: &Bus, mode: Mode) -> Arc<Mutex<String>> {
+		let log = Arc::new(Mutex::new(String::new()));
+		let (l, mosi) = (log.clone(), bus.mosi.clone());
+		let idle = if mode.polarity == Polarity::IdleLow {
+			WireState::Low
+		} else {
+			WireState::High
+		};
+		let first = mode.phase == Phase::CaptureOnFirstTransition;
+		bus.sck.on_change(move |state| {
+			if (state != idle) == first {
+				let bit = mosi.get_state() == WireState::High;
+				l.lock().unwrap().push(if bit { '1' } else { '0' });
+			}
+		});
+		log
+	}
+
+	#[test]
+	fn modes() {
+		for mode in [MODE_0, MODE_1, MODE_2, MODE_3] {
+			let mut bus = loopback();
+			bus.spi.set_mode(mode);
+			let idle = if mode.polarity == Polarity::IdleLow {
+				WireState::Low
+			} else {
+				WireState::High
+			};
+			assert_eq!(idle, bus.sck.get_state());
+			let log = capture(&bus, mode);
+			let mut words = [0xa5u8, 0x0f];
+			assert_eq!(Ok(()), bus.spi.transfer_in_place(&mut words));
+			assert_eq!([0xa5, 0x0f], words, "{:?}", mode);
+			assert_eq!("1010010100001111", *log.lock().unwrap(), "{:?}", mode);
+			assert_eq!(idle, bus.sck.get_state());
+		}
+	}
+
+	#[test]
+	fn frequency() {
+		let (sck, mosi) = (Wire::new(), Wire::new());
+		let clock = VirtualClock::new();
+		let mut spi = SpiMaster::new(
+			sck.connect_push_pull_pin(),
+			mosi.connect_push_pull_pin(),
+			mosi.connect_input_pin(),
+			clock.clone(),
+		);
+		let rising = Arc::new(Mutex::new(vec![]));
+		let (r, c) = (rising.clone(), clock.clone());
+		sck.on_change(move |state| {
+			if state == WireState::High {
+				r.lock().unwrap().push(c.now());
+			}
+		});
+		// 2 µs half periods, as 1 µs would be too fast
+		spi.set_frequency(300_000);
+		assert_eq!(Ok(()), spi.write(&[0xa5u8]));
+		let rising = rising.lock().unwrap();
+		let period = rising.windows(2).map(|w| w[1] - w[0]).min().unwrap();
+		assert_eq!(Duration::from_micros(4), period);
+	}
+
+	#[test]
+	fn bit_order_and_word_size() {
+		let mut bus = loopback();
+		let log = capture(&bus, MODE_0);
+		bus.spi.set_bit_order(BitOrder::LsbFirst);
+		bus.spi.set_word_size(9);
+		let mut read = [0u16; 2];
+		assert_eq!(Ok(()), bus.spi.transfer(&mut read, &[0x101, 0xfff]));
+		// only the lowest 9 bits are transferred
+		assert_eq!([0x101, 0x1ff], read);
+		assert_eq!("100000001111111111", *log.lock().unwrap());
+		log.lock().unwrap().clear();
+		bus.spi.set_word_size(32);
+		bus.spi.set_bit_order(BitOrder::MsbFirst);
+		let mut words = [0x8000_0001u32];
+		assert_eq!(Ok(()), bus.spi.transfer_in_place(&mut words));
+		assert_eq!([0x8000_0001], words);
+		assert_eq!(format!("1{:0>30}1", ""), *log.lock().unwrap());
+	}
+
+	#[test]
+	#[should_panic(expected = "word size 9 exceeds")]
+	fn word_type_too_small() {
+		let mut bus = loopback();
+		bus.spi.set_word_size(9);
+		let _ = bus.spi.write(&[0x01u8]);
+	}
+
+	#[test]
+	fn uneven_transfer() {
+		let mut bus = loopback();
+		let mut read = [0xffu8; 3];
+		assert_eq!(Ok(()), bus.spi.transfer(&mut read, &[0x12]));
+		assert_eq!([0x12, 0x00, 0x00], read);
+		let mut read = [0u8; 1];
+		assert_eq!(Ok(()), bus.spi.transfer(&mut read, &[0x34, 0x56]));
+		assert_eq!([0x34], read);
+		assert_eq!(Ok(()), SpiBusRead::read(&mut bus.spi, &mut read));
+		assert_eq!([0x00], read);
+	}
+
+	#[test]
+	fn device() {
+		let sck = Wire::new();
+		let mosi = Wire::new();
+		let cs = Wire::new();
+		let bus = SpiMaster::new(
+			sck.connect_push_pull_pin(),
+			mosi.connect_push_pull_pin(),
+			mosi.connect_input_pin(),
+			VirtualClock::new(),
+		);
+		let mut device = SpiMasterDevice::new(bus, cs.connect_push_pull_pin());
+		assert_eq!(WireState::High, cs.get_state());
+		let selected = Arc::new(Mutex::new(vec![]));
+		let (s, c) = (selected.clone(), cs.clone());
+		sck.on_change(move |_| s.lock().unwrap().push(c.get_state()));
+		let mut buffer = [0x42u8];
+		assert_eq!(Ok(()), device.transfer_in_place(&mut buffer));
+		assert_eq!([0x42], buffer);
+		assert_eq!(WireState::High, cs.get_state());
+		let selected = selected.lock().unwrap();
+		assert_eq!(16, selected.len());
+		assert!(selected.iter().all(|state| *state == WireState::Low));
+	}
+}
@@ -0,0 +1,107 @@
+//! Simulated SPI buses
+//!
+//! SPI uses push-pull lines for the clock (SCK), the data from the master
+//! (MOSI) and the data from the selected slave (MISO), plus an active low chip
+//! select per slave. Each line maps onto a [`Wire`](`crate::wire::Wire`).
+//!
+//! [`SpiMaster`] implements [`embedded_hal::spi::SpiBus`] by bit-banging these
+//! wires in any of the four SPI modes, and [`SpiMasterDevice`] adds a chip
+//! select to implement [`embedded_hal::spi::SpiDevice`].
+//!
+//! Simulated devices are attached to the wires as [`SpiTarget`]s, which pass
+//! all transfers on to a [`TargetHandler`]. An [`SpiMonitor`] passively
+//! decodes the traffic into [`Frame`]s per chip select.
+
+mod master;
+mod monitor;
+mod target;
+
+pub use master::{SpiMaster, SpiMasterDevice};
+pub use monitor::{Frame, SpiMonitor};
+pub use target::{SpiTarget, TargetHandler};
+
+use crate::wire::WireState;
+use embedded_hal::spi::{Mode, Phase, Polarity};
+
+/// Order in which the bits of a word are transferred.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BitOrder {
+	MsbFirst,
+	LsbFirst,
+}
+
+/// Shift register for the words of a single SPI data line.
+#[derive(Clone, Debug)]
+pub(crate) struct Shifter {
+	pub(crate) mode: Mode,
+	pub(crate) bit_order: BitOrder,
+	word_size: u8,
+	/// Number of bits of the current word shifted in so far.
+	bits: u8,
+	word: u32,
+}
+
+impl Shifter {
+	pub(crate) fn new(mode: Mode) -> Self {
+		Shifter {
+			mode,
+			bit_order: BitOrder::MsbFirst,
+			word_size: 8,
+			bits: 0,
+			word: 0,
+		}
+	}
+
+	pub(crate) fn set_word_size(&mut self, bits: u8) {
+		assert!((1..=32).contains(&bits), "invalid word size: {}", bits);
+		self.word_size = bits;
+		self.reset();
+	}
+
+	/// Discards an incomplete word.
+	pub(crate) fn reset(&mut self) {
+		self.bits = 0;
+		self.word = 0;
+	}
+
+	/// Number of bits of an incomplete word shifted in so far.
+	pub(crate) fn bits(&self) -> u8 {
+		self.bits
+	}
+
+	/// Returns whether SCK changing to `sck` is the leading edge of a clock pulse.
+	pub(crate) fn is_leading(&self, sck: WireState) -> bool {
+		(sck == WireState::High) != (self.mode.polarity == Polarity::IdleHigh)
+	}
+
+	/// Returns whether data is captured on the leading or trailing edge.
+	pub(crate) fn is_capture(&self, leading: bool) -> bool {
+		leading == (self.mode.phase == Phase::CaptureOnFirstTransition)
+	}
+
+	fn position(&self) -> u8 {
+		match self.bit_order {
+			BitOrder::MsbFirst => self.word_size - 1 - self.bits,
+			BitOrder::LsbFirst => self.bits,
+		}
+	}
+
+	/// Returns the bit of `word` to be transferred next.
+	pub(crate) fn bit(&self, word: u32) -> bool {
+		word & (1 << self.position()) != 0
+	}
+
+	/// Shifts in `bit` and returns the word once it is complete.
+	pub(crate) fn shift(&mut self, bit: bool) -> Option<u32> {
+		if bit {
+			self.word |= 1 << self.position();
+		}
+		self.bits += 1;
+		if self.bits < self.word_size {
+			return None;
+		}
+		let word = self.word;
+		self.reset();
+		Some(word)
+	}
+}
@@ -0,0 +1,289 @@
+use super::{BitOrder, Shifter};
+use crate::sync::{Arc, Mutex};
+use crate::wire::{Wire, WireState};
+use embedded_hal::spi::Mode;
+
+/// All words transferred while a chip select was asserted.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Frame {
+	/// Index of the chip select as returned by [`SpiMonitor::add_chip_select`].
+	pub chip_select: usize,
+	/// Words sent by the master.
+	pub mosi: Vec<u32>,
+	/// Words sent by the selected device, a floating MISO line is read as `0`.
+	pub miso: Vec<u32>,
+	/// Number of bits clocked after the last complete word.
+	pub incomplete_bits: u8,
+}
+
+#[derive(Debug)]
+struct Selection {
+	frame: Frame,
+	mosi: Shifter,
+	miso: Shifter,
+}
+
+#[derive(Debug)]
+struct Decoder {
+	/// Configuration for new selections.
+	shifter: Shifter,
+	selections: Vec<Option<Selection>>,
+	frames: Vec<Frame>,
+}
+
+impl Decoder {
+	fn select(&mut self, chip_select: usize, selected: bool) {
+		let selection = &mut self.selections[chip_select];
+		match (selected, selection.take()) {
+			(true, None) => {
+				*selection = Some(Selection {
+					frame: Frame {
+						chip_select,
+						..Default::default()
+					},
+					mosi: self.shifter.clone(),
+					miso: self.shifter.clone(),
+				})
+			}
+			(false, Some(mut selection)) => {
+				selection.frame.incomplete_bits = selection.mosi.bits();
+				self.frames.push(selection.frame);
+			}
+			(_, current) => *selection = current,
+		}
+	}
+
+	fn clock(&mut self, sck: WireState, mosi: bool, miso: bool) {
+		for selection in self.selections.iter_mut().flatten() {
+			let leading = selection.mosi.is_leading(sck);
+			if !selection.mosi.is_capture(leading) {
+				continue;
+			}
+			if let Some(word) = selection.mosi.shift(mosi) {
+				selection.frame.mosi.push(word);
+			}
+			if let Some(word) = selection.miso.shift(miso) {
+				selection.frame.miso.push(word);
+			}
+		}
+	}
+}
+
+/// A passive SPI bus monitor.
+///
+/// The monitor attaches to SCK, MOSI and MISO and samples both data lines on
+/// the capture edges of SCK for its [`Mode`]. Any number of active low chip
+/// selects can be added; all words transferred while one of them is asserted
+/// are logged as a [`Frame`] once it is deasserted again.
+///
+/// # Examples
+///
+/// ```
+/// use embedded_hal_sync_pins::clock::VirtualClock;
+/// use embedded_hal_sync_pins::spi::{Frame, SpiMaster, SpiMasterDevice, SpiMonitor};
+/// use embedded_hal_sync_pins::wire::Wire;
+/// use embedded_hal::spi::{SpiDevice, MODE_0};
+///
+/// let (sck, mosi, miso, cs) = (Wire::new(), Wire::new(), Wire::new(), Wire::new());
+/// let monitor = SpiMonitor::new(&sck, &mosi, &miso, MODE_0);
+/// let flash = monitor.add_chip_select(&cs);
+/// let bus = SpiMaster::new(
+///     sck.connect_push_pull_pin(),
+///     mosi.connect_push_pull_pin(),
+///     miso.connect_input_pin(),
+///     VirtualClock::new(),
+/// );
+/// let mut device = SpiMasterDevice::new(bus, cs.connect_push_pull_pin());
+/// device.write(&[0x9fu8]).unwrap();
+/// assert_eq!(
+///     vec![Frame {
+///         chip_select: flash,
+///         mosi: vec![0x9f],
+///         miso: vec![0x00],
+///         incomplete_bits: 0,
+///     }],
+///     monitor.frames(),
+/// );
+/// ```
+#[derive(Clone, Debug)]
+pub struct SpiMonitor {
+	decoder: Arc<Mutex<Decoder>>,
+}
+
+impl SpiMonitor {
+	/// Attaches a new monitor to the given wires.
+	///
+	/// The monitor decodes 8 bit words MSB first. A one is the asserted level of
+	/// MOSI and MISO, low if a wire is declared
+	/// [active low](`Wire::set_active_low`).
+	pub fn new(sck: &Wire, mosi: &Wire, miso: &Wire, mode: Mode) -> Self {
+		let decoder = Arc::new(Mutex::new(Decoder {
+			shifter: Shifter::new(mode),
+			selections: vec![],
+			frames: vec![],
+		}));
+		let (d, mosi, miso) = (decoder.clone(), mosi.clone(), miso.clone());
+		sck.on_change(move |state| {
+			d.lock()
+				.unwrap()
+				.clock(state, mosi.is_asserted(), miso.is_asserted())
+		});
+		SpiMonitor { decoder }
+	}
+
+	/// Starts monitoring the chip select `cs` and returns its index.
+	pub fn add_chip_select(&self, cs: &Wire) -> usize {
+		let index = {
+			let mut decoder = self.decoder.lock().unwrap();
+			decoder.selections.push(None);
+			decoder.selections.len() - 1
+		};
+		let d = self.decoder.clone();
+		cs.on_change(move |state| d.lock().unwrap().select(index, state == WireState::Low));
+		if cs.get_state() == WireState::Low {
+			self.decoder.lock().unwrap().select(index, true);
+		}
+		index
+	}
+
+	/// Sets the bit order for frames starting after this call.
+	pub fn set_bit_order(&self, bit_order: BitOrder) {
+		self.decoder.lock().unwrap().shifter.bit_order = bit_order;
+	}
+
+	/// Sets the word size for frames starting after this call.
+	///
+	/// # Panics
+	///
+	/// Panics if `bits` is not in `1..=32`.
+	pub fn set_word_size(&self, bits: u8) {
+		self.decoder.lock().unwrap().shifter.set_word_size(bits);
+	}
+
+	/// Returns all frames finished so far.
+	pub fn frames(&self) -> Vec<Frame> {
+		self.decoder.lock().unwrap().frames.clone()
+	}
+
+	/// Removes all frames finished so far.
+	pub fn clear(&self) {
+		self.decoder.lock().unwrap().frames.clear();
+	}
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::*;
+	use crate::clock::VirtualClock;
+	use crate::spi::{SpiMaster, SpiMasterDevice, SpiTarget, TargetHandler};
+	use embedded_hal::digital::OutputPin;
+	use embedded_hal::spi::{SpiBusWrite, SpiDevice, MODE_0, MODE_1, MODE_2, MODE_3};
+
+	/// Answers every word with its complement.
+	struct Complement(u32);
+
+	impl TargetHandler for Complement {
+		fn write(&mut self, word: u32) {
+			self.0 = !word & 0xff;
+		}
+
+		fn read(&mut self) -> u32 {
+			self.0
+		}
+	}
+
+	#[test]
+	fn frames_per_chip_select() {
+		for mode in [MODE_0, MODE_1, MODE_2, MODE_3] {
+			let (sck, mosi, miso) = (Wire::new(), Wire::new(), Wire::new());
+			let (cs1, cs2) = (Wire::new(), Wire::new());
+			let monitor = SpiMonitor::new(&sck, &mosi, &miso, mode);
+			assert_eq!(0, monitor.add_chip_select(&cs1));
+			assert_eq!(1, monitor.add_chip_select(&cs2));
+			let _target = SpiTarget::new(&sck, &mosi, &miso, &cs2, mode, Complement(0x42));
+			let mut bus = SpiMaster::new(
+				sck.connect_push_pull_pin(),
+				mosi.connect_push_pull_pin(),
+				miso.connect_input_pin(),
+				VirtualClock::new(),
+			);
+			bus.set_mode(mode);
+			let mut device1 = SpiMasterDevice::new(bus, cs1.connect_push_pull_pin());
+			assert_eq!(Ok(()), device1.write(&[0x01u8, 0x02]));
+			let (bus, _) = device1.free();
+			let mut device2 = SpiMasterDevice::new(bus, cs2.connect_push_pull_pin());
+			let mut buffer = [0x0fu8, 0xf0];
+			assert_eq!(Ok(()), device2.transfer_in_place(&mut buffer));
+			assert_eq!(
+				vec![
+					Frame {
+						chip_select: 0,
+						mosi: vec![0x01, 0x02],
+						miso: vec![0x00, 0x00],
+						incomplete_bits: 0,
+					},
+					Frame {
+						chip_select: 1,
+						mosi: vec![0x0f, 0xf0],
+						miso: vec![0x42, 0xf0],
+						incomplete_bits: 0,
+					},
+				],
+				monitor.frames(),
+				"{:?}",
+				mode
+			);
+			monitor.clear();
+			assert!(monitor.frames().is_empty());
+		}
+	}
+
+	#[test]
+	fn word_size_and_incomplete_bits() {
+		let (sck, mosi, miso, cs) = (Wire::new(), Wire::new(), Wire::new(), Wire::new());
+		let monitor = SpiMonitor::new(&sck, &mosi, &miso, MODE_0);
+		monitor.set_word_size(12);
+		monitor.set_bit_order(BitOrder::LsbFirst);
+		monitor.add_chip_select(&cs);
+		let mut bus = SpiMaster::new(
+			sck.connect_push_pull_pin(),
+			mosi.connect_push_pull_pin(),
+			mosi.connect_input_pin(),
+			VirtualClock::new(),
+		);
+		bus.set_bit_order(BitOrder::LsbFirst);
+		let mut select = cs.connect_push_pull_pin();
+		select.set_low().unwrap();
+		// three bytes are two 12 bit words
+		assert_eq!(Ok(()), bus.write(&[0x21u8, 0x43, 0x65]));
+		bus.set_word_size(4);
+		assert_eq!(Ok(()), bus.write(&[0x7u8]));
+		select.set_high().unwrap();
+		assert_eq!(
+			vec![Frame {
+				chip_select: 0,
+				mosi: vec![0x321, 0x654],
+				miso: vec![0, 0],
+				incomplete_bits: 4,
+			}],
+			monitor.frames()
+		);
+	}
+
+	#[test]
+	fn active_low_data() {
+		let (sck, mosi, miso, cs) = (Wire::new(), Wire::new(), Wire::new(), Wire::new());
+		mosi.set_active_low(true);
+		let monitor = SpiMonitor::new(&sck, &mosi, &miso, MODE_0);
+		monitor.add_chip_select(&cs);
+		let bus = SpiMaster::new(
+			sck.connect_push_pull_pin(),
+			mosi.connect_push_pull_pin(),
+			miso.connect_input_pin(),
+			VirtualClock::new(),
+		);
+		let mut device = SpiMasterDevice::new(bus, cs.connect_push_pull_pin());
+		assert_eq!(Ok(()), device.write(&[0x9fu8]));
+		assert_eq!(vec![0x60], monitor.frames()[0].mosi);
+	}
+}
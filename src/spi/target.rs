@@ -0,0 +1,359 @@
+use super::{BitOrder, Shifter};
+use crate::sync::{Arc, Mutex};
+use crate::wire::{PushPullPin, Wire, WireState};
+use embedded_hal::digital::OutputPin;
+use embedded_hal::spi::Mode;
+
+/// Behaviour of a simulated SPI device.
+///
+/// The methods are called by an [`SpiTarget`] while the master clocks the bus,
+/// so they should return quickly and must not wait for other bus activity.
+pub trait TargetHandler {
+	/// Called when the chip select of the target is asserted.
+	fn select(&mut self) {}
+
+	/// Called for every word written by the master.
+	fn write(&mut self, word: u32);
+
+	/// Called whenever the target needs the next word to send.
+	///
+	/// The first word is requested when the target is selected, every further
+	/// word after the previous one was written. As SPI is full duplex, the
+	/// target has to provide a word before it knows whether the master clocks
+	/// it, so the last word requested in a transaction may never be sent.
+	fn read(&mut self) -> u32;
+
+	/// Called when the chip select of the target is deasserted.
+	///
+	/// An incomplete word received before is discarded.
+	fn deselect(&mut self) {}
+}
+
+#[derive(Debug)]
+struct Engine<H> {
+	handler: H,
+	miso: PushPullPin,
+	shifter: Shifter,
+	selected: bool,
+	/// The word shifted out on MISO.
+	tx: u32,
+	/// Whether `tx` still has to be requested from the handler.
+	empty: bool,
+}
+
+impl<H: TargetHandler> Engine<H> {
+	fn drive(&mut self) {
+		if self.empty {
+			self.tx = self.handler.read();
+			self.empty = false;
+		}
+		let _ = if self.shifter.bit(self.tx) {
+			self.miso.set_high()
+		} else {
+			self.miso.set_low()
+		};
+	}
+
+	fn sample(&mut self, mosi: bool) {
+		if let Some(word) = self.shifter.shift(mosi) {
+			self.empty = true;
+			self.handler.write(word);
+		}
+	}
+
+	fn select(&mut self, selected: bool) {
+		if selected == self.selected {
+			return;
+		}
+		self.selected = selected;
+		self.shifter.reset();
+		if selected {
+			self.handler.select();
+			self.empty = true;
+			self.drive();
+		} else {
+			self.miso.release();
+			self.handler.deselect();
+		}
+	}
+
+	fn clock(&mut self, leading: bool, mosi: bool) {
+		if !self.selected {
+			return;
+		}
+		if self.shifter.is_capture(leading) {
+			self.sample(mosi);
+		} else {
+			self.drive();
+		}
+	}
+}
+
+/// A simulated SPI device attached to SCK, MOSI, MISO and chip select wires.
+///
+/// The target samples MOSI on the capture edges of SCK for its [`Mode`] while
+/// its active low chip select is asserted and passes all received words on to
+/// a [`TargetHandler`]. Words from the handler are driven onto MISO through its
+/// own [`PushPullPin`], which is released while the target is not selected, so
+/// several targets can share MISO. Selecting more than one of them at the same
+/// time causes a short circuit on MISO.
+///
+/// # Examples
+///
+/// ```
+/// use embedded_hal_sync_pins::clock::VirtualClock;
+/// use embedded_hal_sync_pins::spi::{SpiMaster, SpiMasterDevice, SpiTarget, TargetHandler};
+/// use embedded_hal_sync_pins::wire::Wire;
+/// use embedded_hal::spi::{SpiDevice, MODE_0};
+///
+/// /// Answers every word with the previous one plus one.
+/// struct Increment(u32);
+///
+/// impl TargetHandler for Increment {
+///     fn write(&mut self, word: u32) {
+///         self.0 = word + 1;
+///     }
+///
+///     fn read(&mut self) -> u32 {
+///         self.0
+///     }
+/// }
+///
+/// let (sck, mosi, miso, cs) = (Wire::new(), Wire::new(), Wire::new(), Wire::new());
+/// let _target = SpiTarget::new(&sck, &mosi, &miso, &cs, MODE_0, Increment(0));
+/// let bus = SpiMaster::new(
+///     sck.connect_push_pull_pin(),
+///     mosi.connect_push_pull_pin(),
+///     miso.connect_input_pin(),
+///     VirtualClock::new(),
+/// );
+/// let mut device = SpiMasterDevice::new(bus, cs.connect_push_pull_pin());
+/// let mut buffer = [1u8, 5, 7];
+/// device.transfer_in_place(&mut buffer).unwrap();
+/// assert_eq!([0, 2, 6], buffer);
+/// ```
+#[derive(Debug)]
+pub struct SpiTarget<H> {
+	engine: Arc<Mutex<Engine<H>>>,
+}
+
+impl<H> Clone for SpiTarget<H> {
+	fn clone(&self) -> Self {
+		SpiTarget {
+			engine: self.engine.clone(),
+		}
+	}
+}
+
+impl<H: TargetHandler + Send + 'static> SpiTarget<H> {
+	/// Attaches a new target to the given wires.
+	///
+	/// The target transfers 8 bit words MSB first.
+	pub fn new(sck: &Wire, mosi: &Wire, miso: &Wire, cs: &Wire, mode: Mode, handler: H) -> Self {
+		let engine = Arc::new(Mutex::new(Engine {
+			handler,
+			miso: miso.connect_push_pull_pin(),
+			shifter: Shifter::new(mode),
+			selected: false,
+			tx: 0,
+			empty: true,
+		}));
+		let e = engine.clone();
+		cs.on_change(move |state| e.lock().unwrap().select(state == WireState::Low));
+		let (e, m) = (engine.clone(), mosi.clone());
+		sck.on_change(move |state| {
+			let mut engine = e.lock().unwrap();
+			let leading = engine.shifter.is_leading(state);
+			engine.clock(leading, m.get_state() == WireState::High);
+		});
+		SpiTarget { engine }
+	}
+}
+
+impl<H> SpiTarget<H> {
+	pub fn set_bit_order(&self, bit_order: BitOrder) {
+		self.engine.lock().unwrap().shifter.bit_order = bit_order;
+	}
+
+	/// Sets the number of bits transferred per word.
+	///
+	/// # Panics
+	///
+	/// Panics if `bits` is not in `1..=32`.
+	pub fn set_word_size(&self, bits: u8) {
+		self.engine.lock().unwrap().shifter.set_word_size(bits);
+	}
+
+	/// Calls `f` with the handler of this target.
+	///
+	/// This is useful to inspect or change the state of a simulated device
+	/// between transactions.
+	pub fn with_handler<F, R>(&self, f: F) -> R
+	where
+		F: FnOnce(&mut H) -> R,
+	{
+		f(&mut self.engine.lock().unwrap().handler)
+	}
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::*;
+	use crate::clock::VirtualClock;
+	use crate::spi::{SpiMaster, SpiMasterDevice};
+	use embedded_hal::spi::{Phase, SpiBus, SpiDevice, MODE_0, MODE_1, MODE_2, MODE_3};
+
+	/// Records all calls and answers with a fixed sequence of words.
+	#[derive(Default)]
+	struct Recorder {
+		answers: Vec<u32>,
+		log: Vec<String>,
+	}
+
+	impl TargetHandler for Recorder {
+		fn select(&mut self) {
+			self.log.push("select".into());
+		}
+
+		fn write(&mut self, word: u32) {
+			self.log.push(format!("write {:#x}", word));
+		}
+
+		fn read(&mut self) -> u32 {
+			self.log.push("read".into());
+			if self.answers.is_empty() {
+				0
+			} else {
+				self.answers.remove(0)
+			}
+		}
+
+		fn deselect(&mut self) {
+			self.log.push("deselect".into());
+		}
+	}
+
+	struct Bus {
+		sck: Wire,
+		mosi: Wire,
+		miso: Wire,
+	}
+
+	impl Bus {
+		fn new() -> Self {
+			Bus {
+				sck: Wire::new(),
+				mosi: Wire::new(),
+				miso: Wire::new(),
+			}
+		}
+
+		fn master(&self, mode: Mode) -> SpiMaster<VirtualClock> {
+			let mut master = SpiMaster::new(
+				self.sck.connect_push_pull_pin(),
+				self.mosi.connect_push_pull_pin(),
+				self.miso.connect_input_pin(),
+				VirtualClock::new(),
+			);
+			master.set_mode(mode);
+			master
+		}
+
+		fn attach(&self, cs: &Wire, mode: Mode, answers: &[u32]) -> SpiTarget<Recorder> {
+			let recorder = Recorder {
+				answers: answers.to_vec(),
+				..Default::default()
+			};
+			SpiTarget::new(&self.sck, &self.mosi, &self.miso, cs, mode, recorder)
+		}
+	}
+
+	#[test]
+	fn modes() {
+		for mode in [MODE_0, MODE_1, MODE_2, MODE_3] {
+			let bus = Bus::new();
+			let cs = Wire::new();
+			let target = bus.attach(&cs, mode, &[0xa5, 0x3c]);
+			let mut device = SpiMasterDevice::new(bus.master(mode), cs.connect_push_pull_pin());
+			assert_eq!(WireState::Floating, bus.miso.get_state());
+			let mut words = [0x12u8, 0x34];
+			assert_eq!(Ok(()), device.transfer_in_place(&mut words));
+			assert_eq!([0xa5, 0x3c], words, "{:?}", mode);
+			assert_eq!(WireState::Floating, bus.miso.get_state());
+			let mut expected = vec!["select", "read", "write 0x12", "read", "write 0x34"];
+			// the next word is only requested early if it is driven before the first edge
+			if mode.phase == Phase::CaptureOnFirstTransition {
+				expected.push("read");
+			}
+			expected.push("deselect");
+			assert_eq!(
+				expected,
+				target.with_handler(|h| h.log.clone()),
+				"{:?}",
+				mode
+			);
+		}
+	}
+
+	#[test]
+	fn bit_order_and_word_size() {
+		let bus = Bus::new();
+		let cs = Wire::new();
+		let target = bus.attach(&cs, MODE_0, &[0x1ab]);
+		target.set_bit_order(BitOrder::LsbFirst);
+		target.set_word_size(9);
+		let mut master = bus.master(MODE_0);
+		master.set_bit_order(BitOrder::LsbFirst);
+		master.set_word_size(9);
+		let mut device = SpiMasterDevice::new(master, cs.connect_push_pull_pin());
+		let mut words = [0x155u16];
+		assert_eq!(Ok(()), device.transfer_in_place(&mut words));
+		assert_eq!([0x1ab], words);
+		assert_eq!(
+			vec!["select", "read", "write 0x155", "read", "deselect"],
+			target.with_handler(|h| h.log.clone())
+		);
+	}
+
+	#[test]
+	fn incomplete_word() {
+		let bus = Bus::new();
+		let cs = Wire::new();
+		let target = bus.attach(&cs, MODE_0, &[]);
+		let mut master = bus.master(MODE_0);
+		master.set_word_size(4);
+		let mut device = SpiMasterDevice::new(master, cs.connect_push_pull_pin());
+		assert_eq!(Ok(()), device.write(&[0xfu8]));
+		assert_eq!(
+			vec!["select", "read", "deselect"],
+			target.with_handler(|h| h.log.clone())
+		);
+	}
+
+	#[test]
+	fn shared_miso() {
+		let bus = Bus::new();
+		let (cs1, cs2) = (Wire::new(), Wire::new());
+		let _t1 = bus.attach(&cs1, MODE_0, &[0x11]);
+		let _t2 = bus.attach(&cs2, MODE_0, &[0x22]);
+		let mut master = bus.master(MODE_0);
+		let mut read = [0u8];
+		let mut select2 = cs2.connect_push_pull_pin();
+		select2.set_low().unwrap();
+		assert_eq!(Ok(()), master.transfer(&mut read, &[0]));
+		select2.set_high().unwrap();
+		assert_eq!([0x22], read);
+		assert_eq!(WireState::Floating, bus.miso.get_state());
+	}
+
+	#[test]
+	#[should_panic]
+	fn contention() {
+		let bus = Bus::new();
+		let (cs1, cs2) = (Wire::new(), Wire::new());
+		let _t1 = bus.attach(&cs1, MODE_0, &[0x80]);
+		let _t2 = bus.attach(&cs2, MODE_0, &[0x00]);
+		cs1.connect_push_pull_pin().set_low().unwrap();
+		cs2.connect_push_pull_pin().set_low().unwrap();
+	}
+}
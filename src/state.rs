@@ -0,0 +1,33 @@
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WireState {
+	Low,
+	High,
+	Floating,
+}
+
+impl Copy for WireState {}
+
+/// Resolves the state of a wire from the states of all connected drivers.
+///
+/// Floating drivers are ignored. If no driver is active the wire is pulled
+/// to `pull`. Two drivers actively driving different levels are a short
+/// circuit, which causes a panic.
+#[cfg(any(feature = "std", feature = "cs-wire"))]
+pub(crate) fn resolve(states: &[WireState], pull: WireState) -> WireState {
+	use WireState::*;
+	let mut s = Floating;
+	for state in states.iter() {
+		if *state == Floating {
+			continue;
+		}
+		if s != Floating && *state != s {
+			panic!("short circuit: {:?}", states);
+		}
+		s = *state;
+	}
+	if s == Floating {
+		pull
+	} else {
+		s
+	}
+}
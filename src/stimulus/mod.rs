@@ -0,0 +1,420 @@
+//! Canned waveforms for test vectors
+//!
+//! Sometimes a test only needs the other side of a protocol to send something
+//! fixed, without simulating a whole device. A [`Waveform`] is a list of timed
+//! [`Edge`]s on one or more channels, which is played on [`Wire`]s, either
+//! scheduled on a [`VirtualClock`] or in real time.
+//!
+//! The generators [`uart`], [`spi`], [`i2c`], [`quadrature`] and [`pwm`] create
+//! waveforms for common protocols and signals. Waveforms can be perturbed
+//! before they are played, e.g. with [`Waveform::scale`] for a clock running
+//! too fast or too slow and [`Waveform::jitter`] for randomly shifted edges.
+//!
+//! # Examples
+//!
+//! ```
+//! use embedded_hal_sync_pins::clock::VirtualClock;
+//! use embedded_hal_sync_pins::stimulus;
+//! use embedded_hal_sync_pins::uart::{Config, UartRx};
+//! use embedded_hal_sync_pins::wire::Wire;
+//!
+//! let clock = VirtualClock::new();
+//! let line = Wire::new();
+//! let mut rx = UartRx::new(&line, clock.clone(), Config::default());
+//! // a transmitter running 2 % slow
+//! stimulus::uart(&Config::default(), b"hi")
+//!     .scale(1.02)
+//!     .schedule(&clock, &[&line]);
+//! clock.run();
+//! assert_eq!(Ok(b'h'), rx.try_read());
+//! assert_eq!(Ok(b'i'), rx.try_read());
+//! ```
+
+mod protocols;
+
+pub use protocols::{i2c, pwm, quadrature, spi, uart, I2cOp};
+
+use crate::clock::VirtualClock;
+use crate::net::PinBackend;
+use crate::sync::{Arc, Mutex};
+use crate::wire::{Driver, PushPullPin, Wire, WireState};
+use embedded_hal::digital::OutputPin;
+use std::time::{Duration, Instant};
+
+/// A channel changing its state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Edge {
+	/// Time since the start of the waveform.
+	pub at: Duration,
+	pub channel: usize,
+	pub state: WireState,
+}
+
+/// Timed changes of the states of one or more channels.
+///
+/// All channels are floating until their first edge. A waveform also has a
+/// duration, which can be longer than the time of its last edge, e.g. to
+/// include the stop bit of a UART frame.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Waveform {
+	channels: usize,
+	edges: Vec<Edge>,
+	duration: Duration,
+}
+
+/// A xorshift64* generator, good enough for reproducible jitter.
+struct Rng(u64);
+
+impl Rng {
+	fn next(&mut self) -> u64 {
+		self.0 ^= self.0 >> 12;
+		self.0 ^= self.0 << 25;
+		self.0 ^= self.0 >> 27;
+		self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+	}
+}
+
+impl Waveform {
+	/// Creates an empty waveform with the given number of channels.
+	pub fn new(channels: usize) -> Self {
+		Waveform {
+			channels,
+			edges: vec![],
+			duration: Duration::ZERO,
+		}
+	}
+
+	pub fn channels(&self) -> usize {
+		self.channels
+	}
+
+	/// Returns all edges ordered by time.
+	pub fn edges(&self) -> &[Edge] {
+		&self.edges
+	}
+
+	pub fn duration(&self) -> Duration {
+		self.duration
+	}
+
+	/// Returns the state of `channel` right after `at`.
+	pub fn state_at(&self, at: Duration, channel: usize) -> WireState {
+		self.edges
+			.iter()
+			.take_while(|edge| edge.at <= at)
+			.filter(|edge| edge.channel == channel)
+			.last()
+			.map_or(WireState::Floating, |edge| edge.state)
+	}
+
+	/// Sets `channel` to `state` at `at`, after all other edges at that time.
+	///
+	/// Edges that do not change the state of the channel are left out.
+	///
+	/// # Panics
+	///
+	/// Panics if `channel` is out of range.
+	pub fn set(&mut self, at: Duration, channel: usize, state: WireState) -> &mut Self {
+		assert!(channel < self.channels, "invalid channel: {}", channel);
+		self.duration = self.duration.max(at);
+		if self.state_at(at, channel) != state {
+			let index = self.edges.partition_point(|edge| edge.at <= at);
+			self.edges.insert(index, Edge { at, channel, state });
+		}
+		self
+	}
+
+	/// Extends the duration of the waveform to at least `duration`.
+	pub fn hold(&mut self, duration: Duration) -> &mut Self {
+		self.duration = self.duration.max(duration);
+		self
+	}
+
+	/// Appends `other` after the end of this waveform.
+	///
+	/// # Panics
+	///
+	/// Panics if the waveforms have a different number of channels.
+	pub fn append(&mut self, other: &Waveform) -> &mut Self {
+		assert_eq!(self.channels, other.channels, "channel count mismatch");
+		let offset = self.duration;
+		for edge in &other.edges {
+			self.set(offset + edge.at, edge.channel, edge.state);
+		}
+		self.hold(offset + other.duration)
+	}
+
+	/// Returns this waveform repeated `count` times.
+	pub fn repeat(&self, count: usize) -> Self {
+		let mut waveform = Waveform::new(self.channels);
+		for _ in 0..count {
+			waveform.append(self);
+		}
+		waveform
+	}
+
+	fn map_times<F>(&self, mut f: F) -> Self
+	where
+		F: FnMut(&Edge) -> Duration,
+	{
+		let mut edges: Vec<Edge> = self
+			.edges
+			.iter()
+			.map(|edge| Edge {
+				at: f(edge),
+				..*edge
+			})
+			.collect();
+		// keep the order of the edges of each channel
+		let mut last = vec![Duration::ZERO; self.channels];
+		for edge in edges.iter_mut() {
+			edge.at = edge.at.max(last[edge.channel]);
+			last[edge.channel] = edge.at;
+		}
+		edges.sort_by_key(|edge| edge.at);
+		let end = edges.last().map_or(Duration::ZERO, |edge| edge.at);
+		Waveform {
+			channels: self.channels,
+			edges,
+			duration: self.duration.max(end),
+		}
+	}
+
+	/// Returns this waveform with all times multiplied by `factor`.
+	///
+	/// A factor of `1.02` simulates a clock running 2 % slow.
+	pub fn scale(&self, factor: f64) -> Self {
+		let mut waveform = self.map_times(|edge| edge.at.mul_f64(factor));
+		waveform.duration = self.duration.mul_f64(factor);
+		waveform
+	}
+
+	/// Returns this waveform with every edge shifted by a random time of up to
+	/// `max` in either direction.
+	///
+	/// The same `seed` always results in the same waveform. Edges of a channel
+	/// stay in order, an edge that would overtake the previous one is moved to
+	/// the same time instead.
+	pub fn jitter(&self, max: Duration, seed: u64) -> Self {
+		// xorshift gets stuck at zero
+		let mut rng = Rng(seed.wrapping_add(0x9e37_79b9_7f4a_7c15).max(1));
+		let range = 2 * max.as_nanos() as u64 + 1;
+		self.map_times(|edge| {
+			let offset = Duration::from_nanos(rng.next() % range);
+			(edge.at + offset).saturating_sub(max)
+		})
+	}
+
+	/// Returns this waveform with all edges of `channel` delayed by `delay`,
+	/// e.g. to violate setup or hold times.
+	pub fn skew(&self, channel: usize, delay: Duration) -> Self {
+		self.map_times(|edge| {
+			if edge.channel == channel {
+				edge.at + delay
+			} else {
+				edge.at
+			}
+		})
+	}
+
+	fn connect(&self, wires: &[&Wire]) -> Vec<Driver> {
+		assert_eq!(self.channels, wires.len(), "one wire per channel required");
+		wires.iter().map(|wire| wire.connect_driver()).collect()
+	}
+
+	/// Schedules the waveform on `clock`, starting now.
+	///
+	/// A new driver is connected to each wire, channel `i` drives `wires[i]`.
+	/// Edges at the start of the waveform are applied right away, all others
+	/// once the clock reaches them. The drivers stay connected and keep their
+	/// last state.
+	///
+	/// # Panics
+	///
+	/// Panics if the number of wires does not match the number of channels.
+	pub fn schedule(&self, clock: &VirtualClock, wires: &[&Wire]) {
+		let drivers = Arc::new(self.connect(wires));
+		let start = clock.now();
+		for edge in &self.edges {
+			let (channel, state) = (edge.channel, edge.state);
+			if edge.at == Duration::ZERO {
+				drivers[channel].drive(state);
+				continue;
+			}
+			let drivers = drivers.clone();
+			clock.schedule_at(start + edge.at, move || drivers[channel].drive(state));
+		}
+	}
+
+	/// Schedules the first channel of the waveform on `clock` to drive `pin`,
+	/// starting now, for components that own the pin they drive.
+	pub(crate) fn schedule_pin(&self, clock: &VirtualClock, pin: &Arc<Mutex<PushPullPin>>) {
+		let drive = |pin: &Mutex<PushPullPin>, state| {
+			let mut pin = pin.lock().unwrap();
+			let _ = if state == WireState::Low {
+				pin.set_low()
+			} else {
+				pin.set_high()
+			};
+		};
+		let start = clock.now();
+		for edge in self.edges.iter().filter(|edge| edge.channel == 0) {
+			let state = edge.state;
+			if edge.at == Duration::ZERO {
+				drive(pin, state);
+				continue;
+			}
+			let pin = pin.clone();
+			clock.schedule_at(start + edge.at, move || drive(&pin, state));
+		}
+	}
+
+	/// Plays the waveform on `wires` in real time, blocking until its duration
+	/// has passed.
+	///
+	/// Like [`schedule`](`Waveform::schedule`), this connects a new driver to
+	/// each wire. Run it in its own thread to stimulate a driver under test
+	/// running against the wall clock.
+	///
+	/// # Panics
+	///
+	/// Panics if the number of wires does not match the number of channels.
+	pub fn play(&self, wires: &[&Wire]) {
+		let drivers = self.connect(wires);
+		let start = Instant::now();
+		let wait = |at: Duration| {
+			let elapsed = start.elapsed();
+			if at > elapsed {
+				std::thread::sleep(at - elapsed);
+			}
+		};
+		for edge in &self.edges {
+			wait(edge.at);
+			drivers[edge.channel].drive(edge.state);
+		}
+		wait(self.duration);
+	}
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::*;
+	use std::sync::Mutex;
+	use WireState::*;
+
+	fn us(us: u64) -> Duration {
+		Duration::from_micros(us)
+	}
+
+	fn square() -> Waveform {
+		let mut waveform = Waveform::new(2);
+		waveform
+			.set(us(0), 0, Low)
+			.set(us(0), 1, High)
+			.set(us(10), 0, High)
+			.set(us(10), 0, High)
+			.set(us(20), 0, Low)
+			.hold(us(30));
+		waveform
+	}
+
+	#[test]
+	fn edges() {
+		let waveform = square();
+		assert_eq!(4, waveform.edges().len());
+		assert_eq!(us(30), waveform.duration());
+		assert_eq!(High, waveform.state_at(us(15), 0));
+		assert_eq!(Floating, Waveform::new(1).state_at(us(15), 0));
+		let repeated = waveform.repeat(2);
+		assert_eq!(us(60), repeated.duration());
+		// the second period starts with a low level, which does not change
+		// channel 0, and channel 1 is already high
+		assert_eq!(6, repeated.edges().len());
+		assert_eq!(
+			Edge {
+				at: us(40),
+				channel: 0,
+				state: High
+			},
+			repeated.edges()[4]
+		);
+	}
+
+	#[test]
+	fn perturbations() {
+		let waveform = square();
+		let scaled = waveform.scale(1.5);
+		assert_eq!(us(45), scaled.duration());
+		assert_eq!(us(30), scaled.edges()[3].at);
+		let skewed = waveform.skew(1, us(5));
+		assert_eq!(
+			Edge {
+				at: us(5),
+				channel: 1,
+				state: High
+			},
+			skewed.edges()[1]
+		);
+		let jittered = waveform.jitter(us(2), 42);
+		assert_eq!(jittered, waveform.jitter(us(2), 42));
+		assert_ne!(jittered, waveform.jitter(us(2), 43));
+		for (a, b) in waveform.edges().iter().zip(jittered.edges()) {
+			assert_eq!((a.channel, a.state), (b.channel, b.state));
+			assert!(b.at + us(2) >= a.at && b.at <= a.at + us(2));
+		}
+		// edges of a channel are never reordered
+		let jittered = waveform.jitter(us(100), 1);
+		let levels: Vec<WireState> = jittered
+			.edges()
+			.iter()
+			.filter(|edge| edge.channel == 0)
+			.map(|edge| edge.state)
+			.collect();
+		assert_eq!(vec![Low, High, Low], levels);
+	}
+
+	fn record(
+		wire: &Wire,
+		now: impl Fn() -> Duration + Send + 'static,
+	) -> Arc<Mutex<Vec<(Duration, WireState)>>> {
+		let log = Arc::new(Mutex::new(vec![]));
+		let l = log.clone();
+		wire.on_change(move |state| l.lock().unwrap().push((now(), state)));
+		log
+	}
+
+	#[test]
+	fn schedule() {
+		let clock = VirtualClock::new();
+		let (a, b) = (Wire::new(), Wire::new());
+		clock.advance(us(100));
+		let c = clock.clone();
+		let log = record(&a, move || c.now());
+		square().schedule(&clock, &[&a, &b]);
+		assert_eq!(Low, a.get_state());
+		assert_eq!(High, b.get_state());
+		clock.run();
+		assert_eq!(
+			vec![(us(100), Low), (us(110), High), (us(120), Low)],
+			*log.lock().unwrap()
+		);
+	}
+
+	#[test]
+	fn play() {
+		let wire = Wire::new();
+		let start = Instant::now();
+		let log = record(&wire, move || start.elapsed());
+		let mut waveform = Waveform::new(1);
+		waveform
+			.set(Duration::ZERO, 0, Low)
+			.set(Duration::from_millis(2), 0, High)
+			.hold(Duration::from_millis(4));
+		waveform.play(&[&wire]);
+		assert!(start.elapsed() >= Duration::from_millis(4));
+		let log = log.lock().unwrap();
+		assert_eq!(2, log.len());
+		assert!(log[1].0 >= Duration::from_millis(2));
+		assert_eq!(High, log[1].1);
+	}
+}
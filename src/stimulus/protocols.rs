@@ -0,0 +1,427 @@
+use super::Waveform;
+use crate::spi::BitOrder;
+use crate::uart::Config;
+use crate::wire::WireState::{self, *};
+use embedded_hal::spi::{Mode, Phase, Polarity};
+use std::time::Duration;
+
+fn level(high: bool) -> WireState {
+	if high {
+		High
+	} else {
+		Low
+	}
+}
+
+fn half_period(hz: u32) -> Duration {
+	assert!(hz > 0, "frequency must not be zero");
+	Duration::from_nanos(500_000_000 / hz as u64)
+}
+
+/// Returns a UART byte stream on a single channel.
+///
+/// The line is idle high for one bit time before the first frame, frames
+/// follow each other without gaps. Bits above the configured number of data
+/// bits are ignored.
+///
+/// # Panics
+///
+/// Panics if the configuration is invalid.
+pub fn uart(config: &Config, data: &[u8]) -> Waveform {
+	config.validate();
+	let mut waveform = Waveform::new(1);
+	waveform.set(Duration::ZERO, 0, High);
+	let mut start = config.offset(2);
+	let data_bits = config.data_bits;
+	for byte in data {
+		let byte = if data_bits < 8 {
+			byte & ((1 << data_bits) - 1)
+		} else {
+			*byte
+		};
+		let mut levels = vec![false];
+		levels.extend((0..data_bits).map(|i| byte & (1 << i) != 0));
+		levels.extend(config.parity_bit(byte));
+		levels.resize(config.frame_bits() as usize, true);
+		for (i, high) in levels.into_iter().enumerate() {
+			waveform.set(start + config.offset(2 * i as u32), 0, level(high));
+		}
+		start += config.offset(2 * config.frame_bits());
+	}
+	waveform.hold(start);
+	waveform
+}
+
+/// Returns an SPI frame sent by a master, on the channels SCK, MOSI and chip
+/// select in this order.
+///
+/// Chip select is asserted half a clock period before the first clock edge
+/// and deasserted half a clock period after the last one.
+///
+/// # Panics
+///
+/// Panics if `hz` is zero.
+pub fn spi(mode: Mode, hz: u32, bit_order: BitOrder, data: &[u8]) -> Waveform {
+	const SCK: usize = 0;
+	const MOSI: usize = 1;
+	const CS: usize = 2;
+	let half = half_period(hz);
+	let idle = level(mode.polarity == Polarity::IdleHigh);
+	let active = level(mode.polarity == Polarity::IdleLow);
+	let mut waveform = Waveform::new(3);
+	waveform
+		.set(Duration::ZERO, SCK, idle)
+		.set(Duration::ZERO, MOSI, Low)
+		.set(Duration::ZERO, CS, High)
+		.set(half, CS, Low);
+	let bits = data.iter().flat_map(|byte| {
+		(0..8).map(move |i| {
+			let i = match bit_order {
+				BitOrder::MsbFirst => 7 - i,
+				BitOrder::LsbFirst => i,
+			};
+			byte & (1 << i) != 0
+		})
+	});
+	let mut t = half;
+	for bit in bits {
+		// data changes on the edge before the one it is captured on
+		let change = match mode.phase {
+			Phase::CaptureOnFirstTransition => t,
+			Phase::CaptureOnSecondTransition => t + half,
+		};
+		waveform
+			.set(change, MOSI, level(bit))
+			.set(t + half, SCK, active)
+			.set(t + 2 * half, SCK, idle);
+		t += 2 * half;
+	}
+	waveform.set(t + half, CS, High).hold(t + 2 * half);
+	waveform
+}
+
+/// An operation of an I2C transaction generated by [`i2c`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum I2cOp<'a> {
+	/// Writes the bytes to the target.
+	Write(&'a [u8]),
+	/// Clocks the given number of bytes out of the target.
+	Read(usize),
+}
+
+struct I2cBuilder {
+	waveform: Waveform,
+	half: Duration,
+	t: Duration,
+}
+
+impl I2cBuilder {
+	const SDA: usize = 0;
+	const SCL: usize = 1;
+
+	/// Pulls `channel` low or releases it `halves` half periods from now.
+	fn set(&mut self, halves: u32, channel: usize, high: bool) {
+		let state = if high { Floating } else { Low };
+		self.waveform
+			.set(self.t + self.half * halves, channel, state);
+	}
+
+	fn start(&mut self) {
+		self.set(1, Self::SDA, false);
+		self.set(2, Self::SCL, false);
+		self.t += self.half * 2;
+	}
+
+	fn repeated_start(&mut self) {
+		self.set(0, Self::SDA, true);
+		self.set(1, Self::SCL, true);
+		self.set(2, Self::SDA, false);
+		self.set(3, Self::SCL, false);
+		self.t += self.half * 3;
+	}
+
+	fn stop(&mut self) {
+		self.set(0, Self::SDA, false);
+		self.set(1, Self::SCL, true);
+		self.set(2, Self::SDA, true);
+		self.t += self.half * 3;
+	}
+
+	fn bit(&mut self, high: bool) {
+		self.set(0, Self::SDA, high);
+		self.set(1, Self::SCL, true);
+		self.set(2, Self::SCL, false);
+		self.t += self.half * 2;
+	}
+
+	/// Sends `byte` and releases SDA for the acknowledgement of the target.
+	fn byte(&mut self, byte: u8) {
+		for i in (0..8).rev() {
+			self.bit(byte & (1 << i) != 0);
+		}
+		self.bit(true);
+	}
+}
+
+/// Returns an I2C transaction with a 7-bit address sent by a master, on the
+/// channels SDA and SCL in this order.
+///
+/// Both channels are open drain, they are either low or floating and have to
+/// be played on wires pulled high. SDA is released whenever the target sends,
+/// i.e. for acknowledgements and read data, so a simulated target can answer.
+/// The generator does not react to it, a missing acknowledgement does not
+/// abort the transaction. Like [`embedded_hal::i2c::I2c::transaction`],
+/// adjacent operations of the same kind are merged, and the last byte of a
+/// read is not acknowledged.
+///
+/// # Panics
+///
+/// Panics if `hz` is zero.
+pub fn i2c(hz: u32, address: u8, ops: &[I2cOp]) -> Waveform {
+	let mut i2c = I2cBuilder {
+		waveform: Waveform::new(2),
+		half: half_period(hz),
+		t: Duration::ZERO,
+	};
+	i2c.start();
+	if ops.is_empty() {
+		i2c.byte(address << 1);
+	}
+	for (i, op) in ops.iter().enumerate() {
+		let previous = i.checked_sub(1).map(|i| &ops[i]);
+		let next = ops.get(i + 1);
+		match *op {
+			I2cOp::Write(bytes) => {
+				match previous {
+					Some(I2cOp::Write(_)) => {}
+					Some(_) => {
+						i2c.repeated_start();
+						i2c.byte(address << 1);
+					}
+					None => i2c.byte(address << 1),
+				}
+				for byte in bytes {
+					i2c.byte(*byte);
+				}
+			}
+			I2cOp::Read(len) => {
+				match previous {
+					Some(I2cOp::Read(_)) => {}
+					Some(_) => {
+						i2c.repeated_start();
+						i2c.byte((address << 1) | 1);
+					}
+					None => i2c.byte((address << 1) | 1),
+				}
+				let last = !matches!(next, Some(I2cOp::Read(_)));
+				for n in 0..len {
+					for _ in 0..8 {
+						i2c.bit(true);
+					}
+					i2c.bit(last && n + 1 == len);
+				}
+			}
+		}
+	}
+	i2c.stop();
+	let end = i2c.t;
+	i2c.waveform.hold(end);
+	i2c.waveform
+}
+
+/// Returns the signals of a quadrature encoder on the channels A and B.
+///
+/// Both channels start low. Each move is a number of steps, positive in the
+/// direction in which A leads B, negative in the other one. Every step is one
+/// change of either channel, taking `interval`, so a full cycle of both
+/// channels takes four steps.
+pub fn quadrature(moves: &[i32], interval: Duration) -> Waveform {
+	const STATES: [(WireState, WireState); 4] =
+		[(Low, Low), (High, Low), (High, High), (Low, High)];
+	let mut waveform = Waveform::new(2);
+	waveform
+		.set(Duration::ZERO, 0, Low)
+		.set(Duration::ZERO, 1, Low);
+	let mut position = 0i64;
+	let mut t = Duration::ZERO;
+	for steps in moves {
+		for _ in 0..steps.unsigned_abs() {
+			t += interval;
+			position += steps.signum() as i64;
+			let (a, b) = STATES[position.rem_euclid(4) as usize];
+			waveform.set(t, 0, a).set(t, 1, b);
+		}
+	}
+	waveform.hold(t + interval);
+	waveform
+}
+
+/// Returns `cycles` periods of a PWM signal on a single channel, each high for
+/// `high` and low for the rest of `period`.
+pub fn pwm(period: Duration, high: Duration, cycles: u32) -> Waveform {
+	let mut waveform = Waveform::new(1);
+	if high.is_zero() {
+		waveform.set(Duration::ZERO, 0, Low);
+	}
+	for i in 0..cycles {
+		let start = period * i;
+		if !high.is_zero() {
+			waveform.set(start, 0, High);
+		}
+		if high < period {
+			waveform.set(start + high, 0, Low);
+		}
+	}
+	waveform.hold(period * cycles);
+	waveform
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::*;
+	use crate::clock::VirtualClock;
+	use crate::i2c::{Direction, I2cMonitor, I2cTarget, RegisterMap, Transaction, Transfer};
+	use crate::spi::SpiMonitor;
+	use crate::stimulus::Edge;
+	use crate::uart::{Parity, StopBits, UartRx};
+	use crate::wire::Wire;
+	use embedded_hal::spi::{MODE_0, MODE_1, MODE_2, MODE_3};
+
+	fn us(us: u64) -> Duration {
+		Duration::from_micros(us)
+	}
+
+	#[test]
+	fn uart_frames() {
+		let config = Config {
+			baud: 10_000,
+			data_bits: 7,
+			parity: Parity::Odd,
+			stop_bits: StopBits::Two,
+		};
+		let waveform = uart(&config, &[0x41, 0x7f, 0xc1]);
+		// one idle bit and three frames of 11 bits
+		assert_eq!(us(100 + 3 * 1100), waveform.duration());
+		let clock = VirtualClock::new();
+		let line = Wire::new();
+		let mut rx = UartRx::new(&line, clock.clone(), config);
+		waveform.jitter(us(10), 7).schedule(&clock, &[&line]);
+		clock.run();
+		assert_eq!(Ok(0x41), rx.try_read());
+		assert_eq!(Ok(0x7f), rx.try_read());
+		// the eighth bit is not sent and does not count for the parity
+		assert_eq!(Ok(0x41), rx.try_read());
+	}
+
+	#[test]
+	fn spi_modes() {
+		for mode in [MODE_0, MODE_1, MODE_2, MODE_3] {
+			for bit_order in [BitOrder::MsbFirst, BitOrder::LsbFirst] {
+				let clock = VirtualClock::new();
+				let (sck, mosi, miso, cs) = (Wire::new(), Wire::new(), Wire::new(), Wire::new());
+				let monitor = SpiMonitor::new(&sck, &mosi, &miso, mode);
+				monitor.set_bit_order(bit_order);
+				monitor.add_chip_select(&cs);
+				spi(mode, 1_000_000, bit_order, &[0x81, 0x3c])
+					.schedule(&clock, &[&sck, &mosi, &cs]);
+				clock.run();
+				let frames = monitor.frames();
+				assert_eq!(1, frames.len(), "{:?}", mode);
+				assert_eq!(
+					vec![0x81, 0x3c],
+					frames[0].mosi,
+					"{:?} {:?}",
+					mode,
+					bit_order
+				);
+				assert_eq!(0, frames[0].incomplete_bits);
+			}
+		}
+	}
+
+	#[test]
+	fn i2c_transaction() {
+		let clock = VirtualClock::new();
+		let sda = Wire::new_with_pull(High);
+		let scl = Wire::new_with_pull(High);
+		let monitor = I2cMonitor::new(&sda, &scl);
+		let target = I2cTarget::new(&sda, &scl, 0x50, RegisterMap::new(4));
+		target.with_handler(|r| r.registers_mut()[3] = 0xa5);
+		// the writes are merged, the read continues at register 3 and wraps
+		let ops = [
+			I2cOp::Write(&[0x01]),
+			I2cOp::Write(&[0x11, 0x22]),
+			I2cOp::Read(2),
+		];
+		let waveform = i2c(100_000, 0x50, &ops);
+		waveform.schedule(&clock, &[&sda, &scl]);
+		clock.run();
+		let transfer = |direction, data| Transfer {
+			address: 0x50,
+			direction,
+			ack: true,
+			data,
+		};
+		assert_eq!(
+			vec![Transaction {
+				transfers: vec![
+					transfer(Direction::Write, vec![0x01, 0x11, 0x22]),
+					transfer(Direction::Read, vec![0xa5, 0xff]),
+				]
+			}],
+			monitor.transactions()
+		);
+		assert!(monitor.violations().is_empty());
+		assert_eq!(0x22, target.with_handler(|r| r.registers()[2]));
+		assert_eq!(High, sda.get_state());
+		assert_eq!(High, scl.get_state());
+	}
+
+	#[test]
+	fn encoder() {
+		let waveform = quadrature(&[2, -3], us(10));
+		let states: Vec<(u64, WireState, WireState)> = (1..=5)
+			.map(|i| {
+				let at = us(10 * i);
+				(i, waveform.state_at(at, 0), waveform.state_at(at, 1))
+			})
+			.collect();
+		assert_eq!(
+			vec![
+				(1, High, Low),
+				(2, High, High),
+				(3, High, Low),
+				(4, Low, Low),
+				(5, Low, High)
+			],
+			states
+		);
+		assert_eq!(us(60), waveform.duration());
+		// every step changes a single channel
+		assert_eq!(2 + 5, waveform.edges().len());
+	}
+
+	#[test]
+	fn pwm_train() {
+		let waveform = pwm(us(100), us(25), 3);
+		assert_eq!(us(300), waveform.duration());
+		assert_eq!(6, waveform.edges().len());
+		assert_eq!(
+			Edge {
+				at: us(225),
+				channel: 0,
+				state: Low
+			},
+			waveform.edges()[5]
+		);
+		assert_eq!(
+			vec![Edge {
+				at: Duration::ZERO,
+				channel: 0,
+				state: High
+			}],
+			pwm(us(100), us(100), 3).edges()
+		);
+		assert_eq!(1, pwm(us(100), Duration::ZERO, 3).edges().len());
+	}
+}
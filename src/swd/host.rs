@@ -0,0 +1,212 @@
+use super::{parity, request, Ack, Error};
+use crate::clock::half_period_us;
+use crate::wire::PushPullPin;
+use embedded_hal::delay::DelayUs;
+use embedded_hal::digital::{InputPin, OutputPin};
+
+/// A bit-banged SWD host.
+///
+/// The host drives SWCLK through a [`PushPullPin`] and SWDIO through another
+/// one, which is released while the target sends. It changes SWDIO while
+/// SWCLK is low and samples it right before each rising edge, a floating SWDIO
+/// is read as `0`. Between transactions SWDIO is driven low.
+///
+/// Errors are returned as reported by the target, the host does not retry
+/// requests answered with `WAIT`. All timing is done using the given delay,
+/// which usually is a [`VirtualClock`](`crate::clock::VirtualClock`).
+///
+/// See [`SwdTarget`](`super::SwdTarget`) for an example.
+pub struct SwdHost<D> {
+	swclk: PushPullPin,
+	swdio: PushPullPin,
+	delay: D,
+	half_period_us: u32,
+	turnaround: usize,
+}
+
+impl<D: DelayUs> SwdHost<D> {
+	/// Creates a new host with a SWCLK frequency of 500 kHz and a turnaround
+	/// period of one cycle.
+	pub fn new(mut swclk: PushPullPin, mut swdio: PushPullPin, delay: D) -> Self {
+		let _ = swclk.set_low();
+		let _ = swdio.set_low();
+		SwdHost {
+			swclk,
+			swdio,
+			delay,
+			half_period_us: 1,
+			turnaround: 1,
+		}
+	}
+
+	/// Sets the SWCLK frequency.
+	///
+	/// As the delay has a resolution of one microsecond, the frequency is
+	/// rounded down to the next achievable frequency, but at most 500 kHz.
+	pub fn set_frequency(&mut self, hz: u32) {
+		self.half_period_us = half_period_us(hz);
+	}
+
+	/// Sets the turnaround period in clock cycles, which has to match the
+	/// one configured in the `DLCR` of the target.
+	///
+	/// # Panics
+	///
+	/// Panics if `cycles` is not in `1..=4`.
+	pub fn set_turnaround(&mut self, cycles: usize) {
+		assert!((1..=4).contains(&cycles), "invalid turnaround: {}", cycles);
+		self.turnaround = cycles;
+	}
+
+	/// Releases the pins and the delay.
+	pub fn free(self) -> (PushPullPin, PushPullPin, D) {
+		(self.swclk, self.swdio, self.delay)
+	}
+
+	/// Runs a single clock cycle, driving `bit` or releasing SWDIO, and
+	/// returns the level of SWDIO before the rising edge.
+	fn cycle(&mut self, bit: Option<bool>) -> Result<bool, Error> {
+		let _ = match bit {
+			Some(true) => self.swdio.set_high(),
+			Some(false) => self.swdio.set_low(),
+			None => {
+				self.swdio.release();
+				Ok(())
+			}
+		};
+		self.delay
+			.delay_us(self.half_period_us)
+			.map_err(|_| Error::Delay)?;
+		let level = self.swdio.is_high().unwrap_or(false);
+		let _ = self.swclk.set_high();
+		self.delay
+			.delay_us(self.half_period_us)
+			.map_err(|_| Error::Delay)?;
+		let _ = self.swclk.set_low();
+		Ok(level)
+	}
+
+	fn turnaround(&mut self) -> Result<(), Error> {
+		for _ in 0..self.turnaround {
+			self.cycle(None)?;
+		}
+		Ok(())
+	}
+
+	/// Runs `cycles` idle clock cycles with SWDIO low.
+	pub fn idle(&mut self, cycles: u32) -> Result<(), Error> {
+		for _ in 0..cycles {
+			self.cycle(Some(false))?;
+		}
+		Ok(())
+	}
+
+	/// Resets the line with 56 cycles with SWDIO high, followed by two idle
+	/// cycles.
+	///
+	/// The target answers to nothing but reading
+	/// [`DPIDR`](`super::DPIDR`) afterwards.
+	pub fn line_reset(&mut self) -> Result<(), Error> {
+		for _ in 0..56 {
+			self.cycle(Some(true))?;
+		}
+		self.idle(2)
+	}
+
+	/// Sends a request and checks the ACK of the target. The turnaround back
+	/// to the host follows unless data is read.
+	fn request(&mut self, ap: bool, read: bool, address: u8) -> Result<(), Error> {
+		for bit in request(ap, read, address).iter() {
+			self.cycle(Some(*bit))?;
+		}
+		self.turnaround()?;
+		let mut bits = [false; 3];
+		for bit in bits.iter_mut() {
+			*bit = self.cycle(None)?;
+		}
+		let ack = [Ack::Ok, Ack::Wait, Ack::Fault]
+			.iter()
+			.find(|ack| ack.bits() == bits);
+		if ack != Some(&Ack::Ok) || !read {
+			self.turnaround()?;
+		}
+		let result = match ack {
+			Some(Ack::Ok) => return Ok(()),
+			Some(Ack::Wait) => Err(Error::Wait),
+			Some(Ack::Fault) => Err(Error::Fault),
+			None => Err(Error::Protocol),
+		};
+		let _ = self.swdio.set_low();
+		result
+	}
+
+	fn read(&mut self, ap: bool, address: u8) -> Result<u32, Error> {
+		self.request(ap, true, address)?;
+		let mut value = 0;
+		for i in 0..32 {
+			if self.cycle(None)? {
+				value |= 1 << i;
+			}
+		}
+		let bit = self.cycle(None)?;
+		self.turnaround()?;
+		let _ = self.swdio.set_low();
+		if bit == parity(value) {
+			Ok(value)
+		} else {
+			Err(Error::Parity)
+		}
+	}
+
+	fn write(&mut self, ap: bool, address: u8, value: u32) -> Result<(), Error> {
+		self.request(ap, false, address)?;
+		for i in 0..32 {
+			self.cycle(Some(value & (1 << i) != 0))?;
+		}
+		self.cycle(Some(parity(value)))?;
+		let _ = self.swdio.set_low();
+		Ok(())
+	}
+
+	/// Reads the DP register at `address`.
+	///
+	/// # Panics
+	///
+	/// Panics if `address` is not one of `0x0`, `0x4`, `0x8` and `0xc`.
+	pub fn read_dp(&mut self, address: u8) -> Result<u32, Error> {
+		self.read(false, address)
+	}
+
+	/// Writes the DP register at `address`.
+	///
+	/// # Panics
+	///
+	/// Panics if `address` is not one of `0x0`, `0x4`, `0x8` and `0xc`.
+	pub fn write_dp(&mut self, address: u8, value: u32) -> Result<(), Error> {
+		self.write(false, address, value)
+	}
+
+	/// Reads the register at `address` in the AP and bank selected in
+	/// `SELECT`.
+	///
+	/// AP reads are posted, the returned value is the result of the previous
+	/// AP read. The result of the last one can be read from
+	/// [`RDBUFF`](`super::RDBUFF`).
+	///
+	/// # Panics
+	///
+	/// Panics if `address` is not one of `0x0`, `0x4`, `0x8` and `0xc`.
+	pub fn read_ap(&mut self, address: u8) -> Result<u32, Error> {
+		self.read(true, address)
+	}
+
+	/// Writes the register at `address` in the AP and bank selected in
+	/// `SELECT`.
+	///
+	/// # Panics
+	///
+	/// Panics if `address` is not one of `0x0`, `0x4`, `0x8` and `0xc`.
+	pub fn write_ap(&mut self, address: u8, value: u32) -> Result<(), Error> {
+		self.write(true, address, value)
+	}
+}
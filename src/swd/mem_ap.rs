@@ -0,0 +1,123 @@
+use super::AccessPort;
+use std::convert::TryInto;
+
+const CSW: u8 = 0x00;
+const TAR: u8 = 0x04;
+const DRW: u8 = 0x0c;
+const BD0: u8 = 0x10;
+const BD3: u8 = 0x1c;
+const IDR: u8 = 0xfc;
+
+/// `CSW` bits of a word sized access.
+const SIZE_WORD: u32 = 0b010;
+/// Mask of the `AddrInc` field of `CSW`.
+const ADDR_INC: u32 = 0b11 << 4;
+/// `AddrInc` value incrementing `TAR` after each `DRW` access.
+const INCREMENT_SINGLE: u32 = 0b01 << 4;
+
+/// A memory access port with a block of RAM.
+///
+/// The AP implements the `CSW`, `TAR`, `DRW`, `BD0` to `BD3` and `IDR`
+/// registers for word sized accesses. `TAR` is incremented after a `DRW`
+/// access if `AddrInc` in `CSW` is set to single increment. Accesses outside
+/// of the memory or not aligned to a word fail.
+///
+/// See [`SwdTarget`](`super::SwdTarget`) for an example.
+#[derive(Clone, Debug)]
+pub struct MemAp {
+	base: u32,
+	memory: Vec<u8>,
+	csw: u32,
+	tar: u32,
+}
+
+impl MemAp {
+	/// The identification of an AHB-AP.
+	pub const IDR: u32 = 0x2477_0011;
+
+	/// Creates an AP with `size` bytes of memory starting at `base`, all
+	/// initialized to zero.
+	pub fn new(base: u32, size: usize) -> Self {
+		MemAp {
+			base,
+			memory: vec![0; size],
+			csw: SIZE_WORD,
+			tar: 0,
+		}
+	}
+
+	pub fn memory(&self) -> &[u8] {
+		&self.memory
+	}
+
+	pub fn memory_mut(&mut self) -> &mut [u8] {
+		&mut self.memory
+	}
+
+	fn offset(&self, address: u32) -> Option<usize> {
+		let offset = address.checked_sub(self.base)? as usize;
+		if address & 0x3 == 0 && offset + 4 <= self.memory.len() {
+			Some(offset)
+		} else {
+			None
+		}
+	}
+
+	/// Returns the word at `address`.
+	///
+	/// # Panics
+	///
+	/// Panics if the word is not in the memory of the AP.
+	pub fn read_word(&self, address: u32) -> u32 {
+		let offset = self.offset(address).expect("invalid address");
+		u32::from_le_bytes(self.memory[offset..offset + 4].try_into().unwrap())
+	}
+
+	/// Returns the address a data register accesses and increments `TAR`
+	/// after `DRW` accesses.
+	fn access(&mut self, address: u8) -> Option<usize> {
+		let target = match address {
+			DRW => {
+				let target = self.tar;
+				if self.csw & ADDR_INC == INCREMENT_SINGLE {
+					self.tar = self.tar.wrapping_add(4);
+				}
+				target
+			}
+			_ => (self.tar & !0xf) | (address - BD0) as u32,
+		};
+		self.offset(target)
+	}
+}
+
+impl AccessPort for MemAp {
+	fn read(&mut self, address: u8) -> Option<u32> {
+		match address {
+			CSW => Some(self.csw),
+			TAR => Some(self.tar),
+			DRW | BD0..=BD3 => {
+				let offset = self.access(address)?;
+				let word = self.memory[offset..offset + 4].try_into().unwrap();
+				Some(u32::from_le_bytes(word))
+			}
+			IDR => Some(MemAp::IDR),
+			_ => Some(0),
+		}
+	}
+
+	fn write(&mut self, address: u8, value: u32) -> bool {
+		match address {
+			// only word sized accesses are supported
+			CSW => self.csw = (value & !0x7) | SIZE_WORD,
+			TAR => self.tar = value,
+			DRW | BD0..=BD3 => match self.access(address) {
+				Some(offset) => {
+					self.memory[offset..offset + 4].copy_from_slice(&value.to_le_bytes())
+				}
+				None => return false,
+			},
+			_ => {}
+		}
+		true
+	}
+}
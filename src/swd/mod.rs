@@ -0,0 +1,127 @@
+//! Simulated Serial Wire Debug (SWD)
+//!
+//! SWD uses a clock (SWCLK) driven by the host and a single bidirectional data
+//! line (SWDIO), both mapping onto a [`Wire`](`crate::wire::Wire`). SWDIO should
+//! be pulled high, like on real boards.
+//!
+//! Every transaction starts with an 8 bit request from the host, selecting a
+//! register of the debug port (DP) or of an access port (AP) and whether it is
+//! read or written. After a turnaround period, in which neither side drives
+//! SWDIO, the target answers with a three bit [`Ack`]. On `OK` 32 data bits and
+//! a parity bit follow, sent by the target for a read or by the host after
+//! another turnaround for a write. A line reset of at least 50 clock cycles
+//! with SWDIO high puts the target into a reset state, which is left by
+//! reading [`DPIDR`].
+//!
+//! [`SwdHost`] bit-bangs transactions through a
+//! [`PushPullPin`](`crate::wire::PushPullPin`) that is released during
+//! turnarounds. [`SwdTarget`] implements the debug port and passes accesses to
+//! its access port on to an [`AccessPort`], e.g. a [`MemAp`]. Both drive SWDIO
+//! only while it is their turn, so a turnaround mismatch or two targets
+//! answering at the same time cause a short circuit on the wire.
+
+mod host;
+mod mem_ap;
+mod target;
+
+pub use host::SwdHost;
+pub use mem_ap::MemAp;
+pub use target::{AccessPort, SwdTarget};
+
+use std::fmt;
+
+/// Address of the read-only DP identification register.
+pub const DPIDR: u8 = 0x0;
+/// Address of the write-only DP register clearing sticky error flags.
+pub const ABORT: u8 = 0x0;
+/// Address of the DP control and status register, in DP bank 0.
+pub const CTRL_STAT: u8 = 0x4;
+/// Address of the DP data link control register, in DP bank 1.
+pub const DLCR: u8 = 0x4;
+/// Address of the read-only DP register returning the last read data again.
+pub const RESEND: u8 = 0x8;
+/// Address of the write-only DP register selecting the AP and register banks.
+pub const SELECT: u8 = 0x8;
+/// Address of the read-only DP register holding the result of the last AP read.
+pub const RDBUFF: u8 = 0xc;
+
+/// `ABORT` bit clearing the `STICKYERR` flag.
+pub const STKERRCLR: u32 = 1 << 2;
+/// `ABORT` bit clearing the `WDATAERR` flag.
+pub const WDERRCLR: u32 = 1 << 3;
+/// `CTRL/STAT` flag set when an AP access failed.
+pub const STICKYERR: u32 = 1 << 5;
+/// `CTRL/STAT` flag set when the parity of written data was wrong.
+pub const WDATAERR: u32 = 1 << 7;
+/// `CTRL/STAT` bit requesting debug power up, acknowledged in the next bit.
+pub const CDBGPWRUPREQ: u32 = 1 << 28;
+/// `CTRL/STAT` bit requesting system power up, acknowledged in the next bit.
+pub const CSYSPWRUPREQ: u32 = 1 << 30;
+
+/// The acknowledgement of a request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ack {
+	Ok,
+	/// The target is busy and the request should be repeated.
+	Wait,
+	/// A sticky error flag is set.
+	Fault,
+}
+
+impl Ack {
+	/// Returns the three ACK bits in transfer order.
+	pub(crate) fn bits(self) -> [bool; 3] {
+		match self {
+			Ack::Ok => [true, false, false],
+			Ack::Wait => [false, true, false],
+			Ack::Fault => [false, false, true],
+		}
+	}
+}
+
+/// An error of an [`SwdHost`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+	/// The target answered with `WAIT`.
+	Wait,
+	/// The target answered with `FAULT`.
+	Fault,
+	/// The target answered with an invalid ACK or did not answer at all,
+	/// which requires a line reset.
+	Protocol,
+	/// The parity of the data read was wrong.
+	Parity,
+	/// The delay failed.
+	Delay,
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Error::Wait => write!(f, "target answered WAIT"),
+			Error::Fault => write!(f, "target answered FAULT"),
+			Error::Protocol => write!(f, "invalid or missing acknowledgement"),
+			Error::Parity => write!(f, "read data parity error"),
+			Error::Delay => write!(f, "delay failed"),
+		}
+	}
+}
+
+impl std::error::Error for Error {}
+
+/// Returns the bits of a request in transfer order.
+pub(crate) fn request(ap: bool, read: bool, address: u8) -> [bool; 8] {
+	assert!(
+		address & !0xc == 0,
+		"invalid register address: {:#x}",
+		address
+	);
+	let (a2, a3) = (address & 0x4 != 0, address & 0x8 != 0);
+	let parity = ap ^ read ^ a2 ^ a3;
+	[true, ap, read, a2, a3, parity, false, true]
+}
+
+/// Returns the even parity bit of `value`.
+pub(crate) fn parity(value: u32) -> bool {
+	value.count_ones() % 2 == 1
+}
@@ -0,0 +1,489 @@
+use super::{parity, Ack, CDBGPWRUPREQ, CSYSPWRUPREQ, STICKYERR, STKERRCLR, WDATAERR, WDERRCLR};
+use crate::sync::{Arc, Mutex};
+use crate::wire::{PushPullPin, Wire, WireState};
+use embedded_hal::digital::OutputPin;
+use std::collections::VecDeque;
+
+/// Number of clock cycles with SWDIO high resetting the line.
+const LINE_RESET: u32 = 50;
+
+/// Behaviour of a simulated access port.
+///
+/// Register addresses combine the bank selected in `SELECT` with the address
+/// of the request, e.g. `0xfc` for the identification register.
+pub trait AccessPort {
+	/// Returns whether the AP is still busy, which makes the target answer
+	/// `WAIT` to all AP accesses.
+	fn is_busy(&mut self) -> bool {
+		false
+	}
+
+	/// Reads the register at `address`, returning `None` if the access
+	/// failed, which sets the `STICKYERR` flag.
+	fn read(&mut self, address: u8) -> Option<u32>;
+
+	/// Writes the register at `address`, returning `false` if the access
+	/// failed, which sets the `STICKYERR` flag.
+	fn write(&mut self, address: u8, value: u32) -> bool;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+	/// Waiting for an idle cycle after a line reset.
+	Reset,
+	/// Waiting for the start bit of a request.
+	Idle,
+	/// Receiving the request bits shifted in so far.
+	Request(u8, u8),
+	/// Ignoring everything but a line reset after a protocol error.
+	Lockout,
+}
+
+/// What the target does on a rising edge of SWCLK.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Cycle {
+	Skip,
+	Drive(bool),
+	Release,
+	Sample,
+}
+
+#[derive(Debug)]
+struct Port<A> {
+	ap: A,
+	swdio: PushPullPin,
+	phase: Phase,
+	schedule: VecDeque<Cycle>,
+	/// Whether SWDIO is driven by the target.
+	driving: bool,
+	/// Consecutive high levels sampled while not driving.
+	ones: u32,
+	/// Register of the write whose data is sampled.
+	write: Option<(bool, u8)>,
+	data: Vec<bool>,
+	/// Whether the line was reset and DPIDR not read yet.
+	reset: bool,
+	turnaround: usize,
+	dpidr: u32,
+	power: u32,
+	sticky_error: bool,
+	write_error: bool,
+	select: u32,
+	/// Result of the last AP read.
+	posted: u32,
+	/// Data returned by the last read.
+	last: u32,
+}
+
+impl<A: AccessPort> Port<A> {
+	fn rising(&mut self, level: bool) {
+		if !self.driving {
+			self.ones = if level { self.ones + 1 } else { 0 };
+			if self.ones >= LINE_RESET {
+				self.phase = Phase::Reset;
+				self.schedule.clear();
+				self.reset = true;
+				return;
+			}
+		}
+		match self.schedule.pop_front() {
+			Some(Cycle::Skip) => {}
+			Some(Cycle::Drive(bit)) => {
+				self.driving = true;
+				let _ = if bit {
+					self.swdio.set_high()
+				} else {
+					self.swdio.set_low()
+				};
+			}
+			Some(Cycle::Release) => {
+				self.driving = false;
+				self.swdio.release();
+			}
+			Some(Cycle::Sample) => {
+				self.data.push(level);
+				if self.data.len() == 33 {
+					self.write_data();
+				}
+			}
+			None => match self.phase {
+				Phase::Reset if !level => self.phase = Phase::Idle,
+				Phase::Idle if level => self.phase = Phase::Request(1, 1),
+				Phase::Request(bits, n) => {
+					let bits = bits | (level as u8) << n;
+					if n == 7 {
+						self.phase = Phase::Idle;
+						self.request(bits);
+					} else {
+						self.phase = Phase::Request(bits, n + 1);
+					}
+				}
+				_ => {}
+			},
+		}
+	}
+
+	fn request(&mut self, bits: u8) {
+		let bit = |n: u8| bits & (1 << n) != 0;
+		let (ap, read, address) = (bit(1), bit(2), (bits >> 1) & 0xc);
+		let valid = !bit(6) && bit(7) && bit(5) == (bit(1) ^ bit(2) ^ bit(3) ^ bit(4));
+		// after a line reset, only reading DPIDR is allowed
+		if !valid || (self.reset && (ap || !read || address != 0)) {
+			self.phase = Phase::Lockout;
+			return;
+		}
+		let ack = if ap && (self.sticky_error || self.write_error) {
+			Ack::Fault
+		} else if ap && self.select >> 24 == 0 && self.ap.is_busy() {
+			Ack::Wait
+		} else {
+			Ack::Ok
+		};
+		let turnaround = self.turnaround;
+		self.schedule.extend(vec![Cycle::Skip; turnaround - 1]);
+		self.schedule
+			.extend(ack.bits().iter().map(|bit| Cycle::Drive(*bit)));
+		if ack == Ack::Ok && read {
+			let value = if ap {
+				self.read_ap(address)
+			} else {
+				self.read_dp(address)
+			};
+			self.last = value;
+			self.schedule
+				.extend((0..32).map(|i| Cycle::Drive(value & (1 << i) != 0)));
+			self.schedule.push_back(Cycle::Drive(parity(value)));
+		}
+		self.schedule.push_back(Cycle::Release);
+		self.schedule.extend(vec![Cycle::Skip; turnaround]);
+		if ack == Ack::Ok && !read {
+			self.write = Some((ap, address));
+			self.data.clear();
+			self.schedule.extend(vec![Cycle::Sample; 33]);
+		}
+	}
+
+	fn ap_address(&self, address: u8) -> u8 {
+		(self.select & 0xf0) as u8 | address
+	}
+
+	fn read_ap(&mut self, address: u8) -> u32 {
+		let result = if self.select >> 24 == 0 {
+			self.ap.read(self.ap_address(address))
+		} else {
+			// there is no other AP
+			Some(0)
+		};
+		self.sticky_error |= result.is_none();
+		// AP reads are posted
+		std::mem::replace(&mut self.posted, result.unwrap_or(0))
+	}
+
+	fn read_dp(&mut self, address: u8) -> u32 {
+		match address {
+			0x0 => {
+				self.reset = false;
+				self.dpidr
+			}
+			0x4 if self.select & 0xf == 1 => (self.turnaround as u32 - 1) << 8,
+			0x4 => {
+				let mut value = self.power | (self.power << 1);
+				if self.sticky_error {
+					value |= STICKYERR;
+				}
+				if self.write_error {
+					value |= WDATAERR;
+				}
+				value
+			}
+			0x8 => self.last,
+			_ => self.posted,
+		}
+	}
+
+	fn write_data(&mut self) {
+		let value = self.data[..32]
+			.iter()
+			.enumerate()
+			.fold(0, |value, (i, bit)| value | (*bit as u32) << i);
+		let (ap, address) = self.write.take().unwrap();
+		if self.data[32] != parity(value) {
+			self.write_error = true;
+			return;
+		}
+		match (ap, address) {
+			(true, _) if self.select >> 24 == 0 => {
+				let address = self.ap_address(address);
+				self.sticky_error |= !self.ap.write(address, value);
+			}
+			(true, _) => {}
+			(false, 0x0) => {
+				if value & STKERRCLR != 0 {
+					self.sticky_error = false;
+				}
+				if value & WDERRCLR != 0 {
+					self.write_error = false;
+				}
+			}
+			(false, 0x4) if self.select & 0xf == 1 => {
+				self.turnaround = ((value >> 8) & 0x3) as usize + 1;
+			}
+			(false, 0x4) => self.power = value & (CDBGPWRUPREQ | CSYSPWRUPREQ),
+			(false, 0x8) => self.select = value,
+			_ => {}
+		}
+	}
+}
+
+/// A simulated SWD debug port attached to SWCLK and SWDIO wires.
+///
+/// The target samples SWDIO on rising edges of SWCLK and changes it right after
+/// them while it is its turn, through its own [`PushPullPin`]. It implements
+/// the DP registers `DPIDR`, `ABORT`, `CTRL/STAT`, `DLCR`, `SELECT`, `RESEND`
+/// and `RDBUFF`, including the power up handshake and a configurable
+/// turnaround period. Accesses to the AP selected with `APSEL` zero are passed
+/// on to the [`AccessPort`], other APs read as zero.
+///
+/// AP reads are posted like on real hardware: the data returned by an AP read
+/// is the result of the previous one, the result of the last one can be read
+/// from [`RDBUFF`](`super::RDBUFF`). While `STICKYERR` or `WDATAERR` is set,
+/// AP accesses are answered with `FAULT`. A request with an invalid parity or
+/// any request but reading `DPIDR` after a line reset is not answered at all,
+/// the target then ignores everything until the next line reset.
+///
+/// # Examples
+///
+/// ```
+/// use embedded_hal_sync_pins::clock::VirtualClock;
+/// use embedded_hal_sync_pins::swd::{MemAp, SwdHost, SwdTarget, DPIDR, RDBUFF, SELECT};
+/// use embedded_hal_sync_pins::wire::{Wire, WireState};
+///
+/// let swclk = Wire::new();
+/// let swdio = Wire::new_with_pull(WireState::High);
+/// let target = SwdTarget::new(&swclk, &swdio, 0x2ba0_1477, MemAp::new(0x2000_0000, 64));
+/// let mut host = SwdHost::new(
+///     swclk.connect_push_pull_pin(),
+///     swdio.connect_push_pull_pin(),
+///     VirtualClock::new(),
+/// );
+/// host.line_reset().unwrap();
+/// assert_eq!(Ok(0x2ba0_1477), host.read_dp(DPIDR));
+/// host.write_dp(SELECT, 0).unwrap();
+/// // write a word through TAR and DRW
+/// host.write_ap(0x4, 0x2000_0010).unwrap();
+/// host.write_ap(0xc, 0xcafe_f00d).unwrap();
+/// assert_eq!([0x0d, 0xf0, 0xfe, 0xca], target.with_ap(|ap| ap.memory()[0x10..0x14].to_vec())[..]);
+/// // read it back, the AP read is posted
+/// host.read_ap(0xc).unwrap();
+/// assert_eq!(Ok(0xcafe_f00d), host.read_dp(RDBUFF));
+/// ```
+#[derive(Debug)]
+pub struct SwdTarget<A> {
+	port: Arc<Mutex<Port<A>>>,
+}
+
+impl<A> Clone for SwdTarget<A> {
+	fn clone(&self) -> Self {
+		SwdTarget {
+			port: self.port.clone(),
+		}
+	}
+}
+
+impl<A: AccessPort + Send + 'static> SwdTarget<A> {
+	/// Attaches a new target with the given DP identification to the wires.
+	///
+	/// The target only answers after a line reset.
+	pub fn new(swclk: &Wire, swdio: &Wire, dpidr: u32, ap: A) -> Self {
+		let port = Arc::new(Mutex::new(Port {
+			ap,
+			swdio: swdio.connect_push_pull_pin(),
+			phase: Phase::Lockout,
+			schedule: VecDeque::new(),
+			driving: false,
+			ones: 0,
+			write: None,
+			data: vec![],
+			reset: true,
+			turnaround: 1,
+			dpidr,
+			power: 0,
+			sticky_error: false,
+			write_error: false,
+			select: 0,
+			posted: 0,
+			last: 0,
+		}));
+		let (p, swdio) = (port.clone(), swdio.clone());
+		swclk.on_change(move |state| {
+			if state == WireState::High {
+				p.lock()
+					.unwrap()
+					.rising(swdio.get_state() == WireState::High);
+			}
+		});
+		SwdTarget { port }
+	}
+}
+
+impl<A> SwdTarget<A> {
+	/// Calls `f` with the access port of this target.
+	pub fn with_ap<F, R>(&self, f: F) -> R
+	where
+		F: FnOnce(&mut A) -> R,
+	{
+		f(&mut self.port.lock().unwrap().ap)
+	}
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::*;
+	use crate::clock::VirtualClock;
+	use crate::swd::{
+		Error, MemAp, SwdHost, ABORT, CTRL_STAT, DLCR, DPIDR, RDBUFF, RESEND, SELECT,
+	};
+
+	const IDCODE: u32 = 0x0bc1_1477;
+
+	/// An AP that is busy for a number of accesses.
+	struct Slow {
+		busy: u32,
+		value: u32,
+	}
+
+	impl AccessPort for Slow {
+		fn is_busy(&mut self) -> bool {
+			self.busy = self.busy.saturating_sub(1);
+			self.busy > 0
+		}
+
+		fn read(&mut self, _address: u8) -> Option<u32> {
+			Some(self.value)
+		}
+
+		fn write(&mut self, _address: u8, value: u32) -> bool {
+			self.value = value;
+			true
+		}
+	}
+
+	fn connect<A: AccessPort + Send + 'static>(
+		ap: A,
+	) -> (SwdTarget<A>, SwdHost<VirtualClock>, Wire) {
+		let swclk = Wire::new();
+		let swdio = Wire::new_with_pull(WireState::High);
+		let target = SwdTarget::new(&swclk, &swdio, IDCODE, ap);
+		let host = SwdHost::new(
+			swclk.connect_push_pull_pin(),
+			swdio.connect_push_pull_pin(),
+			VirtualClock::new(),
+		);
+		(target, host, swdio)
+	}
+
+	#[test]
+	fn debug_port() {
+		let (_target, mut host, swdio) = connect(MemAp::new(0, 16));
+		// no answer before a line reset
+		assert_eq!(Err(Error::Protocol), host.read_dp(DPIDR));
+		assert_eq!(Ok(()), host.line_reset());
+		// and only to reading DPIDR after it
+		assert_eq!(Err(Error::Protocol), host.read_dp(CTRL_STAT));
+		assert_eq!(Ok(()), host.line_reset());
+		assert_eq!(Ok(IDCODE), host.read_dp(DPIDR));
+		assert_eq!(WireState::Low, swdio.get_state());
+		assert_eq!(Ok(0), host.read_dp(CTRL_STAT));
+		assert_eq!(
+			Ok(()),
+			host.write_dp(CTRL_STAT, CDBGPWRUPREQ | CSYSPWRUPREQ)
+		);
+		assert_eq!(Ok(0xf000_0000), host.read_dp(CTRL_STAT));
+		assert_eq!(Ok(0xf000_0000), host.read_dp(RESEND));
+		// longer turnaround
+		assert_eq!(Ok(()), host.write_dp(SELECT, 1));
+		assert_eq!(Ok(0), host.read_dp(DLCR));
+		assert_eq!(Ok(()), host.write_dp(DLCR, 1 << 8));
+		host.set_turnaround(2);
+		assert_eq!(Ok(1 << 8), host.read_dp(DLCR));
+		// the ACK comes too late for the host
+		host.set_turnaround(1);
+		assert_eq!(Err(Error::Protocol), host.write_dp(SELECT, 0));
+		// a line reset does not reset the turnaround
+		assert_eq!(Ok(()), host.line_reset());
+		host.set_turnaround(2);
+		assert_eq!(Ok(IDCODE), host.read_dp(DPIDR));
+	}
+
+	#[test]
+	fn access_port() {
+		let (target, mut host, _) = connect(MemAp::new(0x100, 16));
+		assert_eq!(Ok(()), host.line_reset());
+		assert_eq!(Ok(IDCODE), host.read_dp(DPIDR));
+		assert_eq!(Ok(()), host.write_dp(SELECT, 0xf0));
+		assert_eq!(Ok(0), host.read_ap(0xc));
+		assert_eq!(Ok(MemAp::IDR), host.read_dp(RDBUFF));
+		assert_eq!(Ok(()), host.write_dp(SELECT, 0));
+		// auto-increment
+		assert_eq!(Ok(()), host.write_ap(0x0, 0x12));
+		assert_eq!(Ok(()), host.write_ap(0x4, 0x104));
+		for word in [0x1111_1111, 0x2222_2222] {
+			assert_eq!(Ok(()), host.write_ap(0xc, word));
+		}
+		assert_eq!(Ok(0x10c), host.read_ap(0x4).and(host.read_dp(RDBUFF)));
+		assert_eq!(0x2222_2222, target.with_ap(|ap| ap.read_word(0x108)));
+		assert_eq!(Ok(()), host.write_ap(0x4, 0x104));
+		assert_eq!(Ok(0x10c), host.read_ap(0xc));
+		assert_eq!(Ok(0x1111_1111), host.read_ap(0xc));
+		assert_eq!(Ok(0x2222_2222), host.read_dp(RDBUFF));
+		// other APs read as zero
+		assert_eq!(Ok(()), host.write_dp(SELECT, 0x0100_00f0));
+		assert_eq!(Ok(0x2222_2222), host.read_ap(0xc));
+		assert_eq!(Ok(0), host.read_dp(RDBUFF));
+	}
+
+	#[test]
+	fn errors() {
+		let (_target, mut host, _) = connect(MemAp::new(0x100, 16));
+		assert_eq!(Ok(()), host.line_reset());
+		assert_eq!(Ok(IDCODE), host.read_dp(DPIDR));
+		assert_eq!(Ok(()), host.write_ap(0x4, 0x200));
+		// the failing read itself is acknowledged
+		assert_eq!(Ok(0), host.read_ap(0xc));
+		assert_eq!(Err(Error::Fault), host.read_ap(0xc));
+		assert_eq!(Err(Error::Fault), host.write_ap(0x4, 0x100));
+		assert_eq!(Ok(STICKYERR), host.read_dp(CTRL_STAT));
+		assert_eq!(Ok(()), host.write_dp(ABORT, STKERRCLR));
+		assert_eq!(Ok(0), host.read_dp(CTRL_STAT));
+		assert_eq!(Ok(()), host.write_ap(0x4, 0x100));
+	}
+
+	#[test]
+	fn wait() {
+		let (_target, mut host, _) = connect(Slow { busy: 3, value: 0 });
+		assert_eq!(Ok(()), host.line_reset());
+		assert_eq!(Ok(IDCODE), host.read_dp(DPIDR));
+		assert_eq!(Err(Error::Wait), host.write_ap(0x0, 5));
+		assert_eq!(Err(Error::Wait), host.write_ap(0x0, 5));
+		assert_eq!(Ok(()), host.write_ap(0x0, 5));
+		assert_eq!(Ok(0), host.read_ap(0x0));
+		assert_eq!(Ok(5), host.read_dp(RDBUFF));
+	}
+
+	#[test]
+	#[should_panic(expected = "short circuit")]
+	fn contention() {
+		let swclk = Wire::new();
+		let swdio = Wire::new_with_pull(WireState::High);
+		let _t1 = SwdTarget::new(&swclk, &swdio, 0x1, MemAp::new(0, 4));
+		let _t2 = SwdTarget::new(&swclk, &swdio, 0x2, MemAp::new(0, 4));
+		let mut host = SwdHost::new(
+			swclk.connect_push_pull_pin(),
+			swdio.connect_push_pull_pin(),
+			VirtualClock::new(),
+		);
+		host.line_reset().unwrap();
+		// both targets answer with different data
+		let _ = host.read_dp(DPIDR);
+	}
+}
@@ -0,0 +1,55 @@
+//! Synchronization primitives
+//!
+//! All types in [`pins`](`crate::pins`) and [`wire`](`crate::wire`) are built on
+//! the primitives re-exported here. When compiled with `--cfg loom` these are the
+//! primitives of [`loom`] instead of the ones from [`std`], so that all
+//! interleavings of concurrent accesses to pins and wires can be model checked.
+//!
+//! Downstream crates can use the same re-exports in their own multi-threaded
+//! driver tests. Together with [`model`] such a test runs once during a normal
+//! test run and is explored exhaustively when compiled with `--cfg loom`.
+//!
+//! # Examples
+//!
+//! ```
+//! use embedded_hal_sync_pins::sync::{model, thread};
+//! use embedded_hal_sync_pins::wire::{Wire, WireState};
+//! use embedded_hal::digital::OutputPin;
+//!
+//! model(|| {
+//!     let wire = Wire::new_with_pull(WireState::High);
+//!     let mut pin = wire.connect_open_drain_pin();
+//!     let handle = thread::spawn(move || pin.set_high().unwrap());
+//!     handle.join().unwrap();
+//!     assert_eq!(WireState::Low, wire.get_state());
+//! });
+//! ```
+
+#[cfg(loom)]
+pub use loom::sync::{
+	atomic::{AtomicUsize, Ordering},
+	Arc, Condvar, Mutex, MutexGuard,
+};
+#[cfg(loom)]
+pub use loom::thread;
+#[cfg(not(loom))]
+pub use std::sync::{
+	atomic::{AtomicUsize, Ordering},
+	Arc, Condvar, Mutex, MutexGuard,
+};
+#[cfg(not(loom))]
+pub use std::thread;
+
+/// Runs a concurrent test.
+///
+/// With `--cfg loom` all possible interleavings of the threads spawned by `f`
+/// are explored using [`loom::model`]. Otherwise `f` is just called once.
+pub fn model<F>(f: F)
+where
+	F: Fn() + Sync + Send + 'static,
+{
+	#[cfg(loom)]
+	loom::model(f);
+	#[cfg(not(loom))]
+	f();
+}
@@ -0,0 +1,161 @@
+//! Simulated UARTs
+//!
+//! A UART line idles high. Every frame starts with a low start bit followed by
+//! the data bits (LSB first), an optional parity bit and one or two high stop
+//! bits. The line maps onto a single [`Wire`](`crate::wire::Wire`).
+//!
+//! [`UartTx`] drives frames onto a wire and [`UartRx`] decodes them, both
+//! implementing the [`embedded_io`] traits. All timing runs on a
+//! [`VirtualClock`](`crate::clock::VirtualClock`), so tests at any baud rate
+//! finish instantly and do not depend on the wall clock. Both sides also
+//! provide non-blocking methods returning [`nb::Result`], and with the `async`
+//! feature they implement the `embedded-io-async` traits.
+//!
+//! # Examples
+//!
+//! ```
+//! use embedded_hal_sync_pins::clock::VirtualClock;
+//! use embedded_hal_sync_pins::uart::{Config, UartRx, UartTx};
+//! use embedded_hal_sync_pins::wire::Wire;
+//! use embedded_io::{Read, Write};
+//!
+//! let clock = VirtualClock::new();
+//! let line = Wire::new();
+//! let config = Config {
+//!     baud: 115_200,
+//!     ..Default::default()
+//! };
+//! let mut rx = UartRx::new(&line, clock.clone(), config);
+//! let mut tx = UartTx::new(line.connect_push_pull_pin(), clock, config);
+//! tx.write_all(b"hello").unwrap();
+//! let mut buffer = [0; 5];
+//! rx.read_exact(&mut buffer).unwrap();
+//! assert_eq!(b"hello", &buffer);
+//! ```
+
+mod rx;
+mod tx;
+
+pub use rx::UartRx;
+pub use tx::UartTx;
+
+use std::fmt;
+use std::time::Duration;
+
+/// Parity bit of a frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Parity {
+	None,
+	/// The number of ones in the data and parity bits is even.
+	Even,
+	/// The number of ones in the data and parity bits is odd.
+	Odd,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopBits {
+	One,
+	Two,
+}
+
+/// Frame format and baud rate of a UART.
+///
+/// The default is 9600 baud with 8 data bits, no parity and one stop bit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+	pub baud: u32,
+	/// Number of data bits in `5..=8`.
+	pub data_bits: u8,
+	pub parity: Parity,
+	pub stop_bits: StopBits,
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Config {
+			baud: 9600,
+			data_bits: 8,
+			parity: Parity::None,
+			stop_bits: StopBits::One,
+		}
+	}
+}
+
+impl Config {
+	pub(crate) fn validate(&self) {
+		assert!(self.baud > 0, "invalid baud rate: {}", self.baud);
+		assert!(
+			(5..=8).contains(&self.data_bits),
+			"invalid data bits: {}",
+			self.data_bits
+		);
+	}
+
+	/// Number of bits in a frame, including start and stop bits.
+	pub(crate) fn frame_bits(&self) -> u32 {
+		let parity = match self.parity {
+			Parity::None => 0,
+			_ => 1,
+		};
+		let stop = match self.stop_bits {
+			StopBits::One => 1,
+			StopBits::Two => 2,
+		};
+		1 + self.data_bits as u32 + parity + stop
+	}
+
+	/// Time from the start of a frame to `half_bits` half bit periods later.
+	///
+	/// This is calculated from the start of each frame, so rounding errors do
+	/// not accumulate over the bits of a frame.
+	pub(crate) fn offset(&self, half_bits: u32) -> Duration {
+		Duration::from_nanos(half_bits as u64 * 500_000_000 / self.baud as u64)
+	}
+
+	/// Returns the parity bit for `data`, if any.
+	pub(crate) fn parity_bit(&self, data: u8) -> Option<bool> {
+		let odd = data.count_ones() % 2 == 1;
+		match self.parity {
+			Parity::None => None,
+			Parity::Even => Some(odd),
+			Parity::Odd => Some(!odd),
+		}
+	}
+}
+
+/// An error while receiving a frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+	/// A stop bit was low.
+	Framing,
+	/// The parity bit did not match the data.
+	Parity,
+	/// The line was held low for a whole frame.
+	Break,
+	/// A blocking read found no received data and no pending activity on the
+	/// clock, so it would wait forever.
+	Timeout,
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Error::Framing => write!(f, "framing error"),
+			Error::Parity => write!(f, "parity error"),
+			Error::Break => write!(f, "break condition"),
+			Error::Timeout => write!(f, "no data and no pending activity"),
+		}
+	}
+}
+
+impl std::error::Error for Error {}
+
+impl embedded_io::Error for Error {
+	fn kind(&self) -> embedded_io::ErrorKind {
+		match self {
+			Error::Framing | Error::Parity => embedded_io::ErrorKind::InvalidData,
+			Error::Break => embedded_io::ErrorKind::Other,
+			Error::Timeout => embedded_io::ErrorKind::TimedOut,
+		}
+	}
+}
@@ -0,0 +1,372 @@
+use super::{Config, Error};
+use crate::clock::VirtualClock;
+use crate::sync::{Arc, Mutex};
+use crate::wire::{Wire, WireState};
+use std::collections::VecDeque;
+
+/// Returns whether `state` is a mark, the level of a one and of the idle line,
+/// on `line`. Marks are high unless the line is active low, a floating line is
+/// idle.
+fn is_mark(line: &Wire, state: WireState) -> bool {
+	state == WireState::Floating || (state == WireState::High) != line.is_active_low()
+}
+
+#[derive(Debug)]
+struct Receiver {
+	config: Config,
+	line: Wire,
+	/// Levels sampled in the middle of each bit of the current frame.
+	levels: Vec<bool>,
+	receiving: bool,
+	/// Counts the frames started, samples scheduled for earlier frames are
+	/// dropped.
+	generation: u64,
+	received: VecDeque<Result<u8, Error>>,
+}
+
+impl Receiver {
+	fn start(receiver: &Arc<Mutex<Receiver>>, clock: &VirtualClock) {
+		let (config, generation) = {
+			let mut rx = receiver.lock().unwrap();
+			if rx.receiving {
+				return;
+			}
+			rx.receiving = true;
+			rx.generation += 1;
+			rx.levels.clear();
+			(rx.config, rx.generation)
+		};
+		let start = clock.now();
+		for i in 0..config.frame_bits() {
+			let r = receiver.clone();
+			clock.schedule_at(start + config.offset(2 * i + 1), move || {
+				r.lock().unwrap().sample(generation)
+			});
+		}
+	}
+
+	fn sample(&mut self, generation: u64) {
+		if !self.receiving || generation != self.generation {
+			return;
+		}
+		self.levels.push(is_mark(&self.line, self.line.get_state()));
+		if self.levels[0] {
+			// the start bit was a glitch
+			self.receiving = false;
+		} else if self.levels.len() == self.config.frame_bits() as usize {
+			self.receiving = false;
+			let frame = self.decode();
+			self.received.push_back(frame);
+		}
+	}
+
+	fn decode(&self) -> Result<u8, Error> {
+		if self.levels.iter().all(|level| !level) {
+			return Err(Error::Break);
+		}
+		let data_bits = self.config.data_bits as usize;
+		let data = self.levels[1..=data_bits]
+			.iter()
+			.rev()
+			.fold(0, |data, bit| (data << 1) | *bit as u8);
+		let mut rest = self.levels[data_bits + 1..].iter();
+		if let Some(parity) = self.config.parity_bit(data) {
+			if rest.next() != Some(&parity) {
+				return Err(Error::Parity);
+			}
+		}
+		if rest.all(|level| *level) {
+			Ok(data)
+		} else {
+			Err(Error::Framing)
+		}
+	}
+}
+
+/// A UART receiver decoding frames from a wire.
+///
+/// The receiver waits for the falling edge of a start bit and samples the line
+/// in the middle of every bit of the frame, as scheduled on the clock. Received
+/// words and errors are queued until they are read. A floating line is read as
+/// high. On a wire declared [active low](`Wire::set_active_low`) the levels are
+/// inverted, as for a UART without a line driver.
+///
+/// Blocking reads advance the clock until a word was received. As the clock is
+/// virtual, a read only returns [`Error::Timeout`] instead of waiting forever
+/// when nothing was received and no activity is scheduled on the clock.
+#[derive(Clone, Debug)]
+pub struct UartRx {
+	receiver: Arc<Mutex<Receiver>>,
+	clock: VirtualClock,
+}
+
+impl UartRx {
+	/// Attaches a new receiver to the given wire.
+	///
+	/// # Panics
+	///
+	/// Panics if the configuration is invalid.
+	pub fn new(line: &Wire, clock: VirtualClock, config: Config) -> Self {
+		config.validate();
+		let receiver = Arc::new(Mutex::new(Receiver {
+			config,
+			line: line.clone(),
+			levels: vec![],
+			receiving: false,
+			generation: 0,
+			received: VecDeque::new(),
+		}));
+		let (r, c, l) = (receiver.clone(), clock.clone(), line.clone());
+		line.on_change(move |state| {
+			if !is_mark(&l, state) {
+				Receiver::start(&r, &c);
+			}
+		});
+		UartRx { receiver, clock }
+	}
+
+	pub fn config(&self) -> Config {
+		self.receiver.lock().unwrap().config
+	}
+
+	/// Returns the next received word or error, if any.
+	pub fn try_read(&mut self) -> nb::Result<u8, Error> {
+		match self.receiver.lock().unwrap().received.pop_front() {
+			Some(Ok(word)) => Ok(word),
+			Some(Err(e)) => Err(nb::Error::Other(e)),
+			None => Err(nb::Error::WouldBlock),
+		}
+	}
+}
+
+impl embedded_io::ErrorType for UartRx {
+	type Error = Error;
+}
+
+impl embedded_io::Read for UartRx {
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+		if buf.is_empty() {
+			return Ok(0);
+		}
+		loop {
+			let mut rx = self.receiver.lock().unwrap();
+			if !rx.received.is_empty() {
+				let mut n = 0;
+				while n < buf.len() {
+					match rx.received.front() {
+						Some(Ok(word)) => buf[n] = *word,
+						// errors are returned after the words received before them
+						Some(Err(e)) if n == 0 => {
+							let e = *e;
+							rx.received.pop_front();
+							return Err(e);
+						}
+						_ => break,
+					}
+					rx.received.pop_front();
+					n += 1;
+				}
+				return Ok(n);
+			}
+			drop(rx);
+			match self.clock.next_event() {
+				Some(at) => self.clock.advance_to(at),
+				None => return Err(Error::Timeout),
+			}
+		}
+	}
+}
+
+impl embedded_io::ReadReady for UartRx {
+	fn read_ready(&mut self) -> Result<bool, Self::Error> {
+		Ok(!self.receiver.lock().unwrap().received.is_empty())
+	}
+}
+
+#[cfg(feature = "async")]
+impl embedded_io_async::Read for UartRx {
+	async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+		embedded_io::Read::read(self, buf)
+	}
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::*;
+	use crate::stimulus::{self, Waveform};
+	use crate::uart::{Parity, StopBits, UartTx};
+	use embedded_hal::digital::OutputPin;
+	use embedded_io::{Read, ReadReady, Write};
+	use std::time::Duration;
+
+	fn pair(config: Config) -> (UartTx, UartRx, Wire, VirtualClock) {
+		let clock = VirtualClock::new();
+		let line = Wire::new();
+		let rx = UartRx::new(&line, clock.clone(), config);
+		let tx = UartTx::new(line.connect_push_pull_pin(), clock.clone(), config);
+		(tx, rx, line, clock)
+	}
+
+	#[test]
+	fn formats() {
+		for data_bits in 5..=8 {
+			for parity in [Parity::None, Parity::Even, Parity::Odd] {
+				for stop_bits in [StopBits::One, StopBits::Two] {
+					let config = Config {
+						baud: 115_200,
+						data_bits,
+						parity,
+						stop_bits,
+					};
+					let (mut tx, mut rx, _, _) = pair(config);
+					let data: Vec<u8> = (0..32).map(|i| i * 7).collect();
+					assert_eq!(Ok(()), tx.write_all(&data));
+					let mut buffer = [0; 32];
+					assert_eq!(Ok(()), rx.read_exact(&mut buffer), "{:?}", config);
+					let mask = ((1u16 << data_bits) - 1) as u8;
+					let expected: Vec<u8> = data.iter().map(|d| d & mask).collect();
+					assert_eq!(expected, buffer, "{:?}", config);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn active_low() {
+		let clock = VirtualClock::new();
+		let line = Wire::new();
+		line.set_active_low(true);
+		let mut rx = UartRx::new(&line, clock.clone(), Config::default());
+		let sent = stimulus::uart(&Config::default(), b"hi");
+		let mut inverted = Waveform::new(1);
+		for edge in sent.edges() {
+			let state = match edge.state {
+				WireState::High => WireState::Low,
+				_ => WireState::High,
+			};
+			inverted.set(edge.at, 0, state);
+		}
+		inverted.hold(sent.duration());
+		inverted.schedule(&clock, &[&line]);
+		clock.run();
+		assert_eq!(Ok(b'h'), rx.try_read());
+		assert_eq!(Ok(b'i'), rx.try_read());
+		assert_eq!(Err(nb::Error::WouldBlock), rx.try_read());
+	}
+
+	#[test]
+	fn non_blocking() {
+		let (mut tx, mut rx, _, clock) = pair(Config::default());
+		assert_eq!(Err(nb::Error::WouldBlock), rx.try_read());
+		assert_eq!(Ok(()), tx.try_write(0x42));
+		assert_eq!(Ok(false), rx.read_ready());
+		clock.run();
+		assert_eq!(Ok(true), rx.read_ready());
+		assert_eq!(Ok(0x42), rx.try_read());
+		assert_eq!(Err(nb::Error::WouldBlock), rx.try_read());
+		let mut buffer = [0; 4];
+		assert_eq!(Err(Error::Timeout), rx.read(&mut buffer));
+	}
+
+	#[test]
+	fn baud_mismatch_is_detected() {
+		let clock = VirtualClock::new();
+		let line = Wire::new();
+		let mut rx = UartRx::new(
+			&line,
+			clock.clone(),
+			Config {
+				baud: 19_200,
+				parity: Parity::Even,
+				..Default::default()
+			},
+		);
+		let mut tx = UartTx::new(
+			line.connect_push_pull_pin(),
+			clock,
+			Config {
+				baud: 9600,
+				parity: Parity::Even,
+				..Default::default()
+			},
+		);
+		// the receiver samples the fourth and fifth bit of 0x0f as parity and stop bit
+		assert_eq!(Ok(()), tx.write_all(&[0x0f]));
+		let mut buffer = [0; 1];
+		assert_eq!(Err(Error::Framing), rx.read(&mut buffer));
+	}
+
+	#[test]
+	fn errors() {
+		let config = Config {
+			baud: 1000,
+			data_bits: 8,
+			parity: Parity::Odd,
+			stop_bits: StopBits::One,
+		};
+		let clock = VirtualClock::new();
+		let line = Wire::new();
+		let mut rx = UartRx::new(&line, clock.clone(), config);
+		let mut pin = line.connect_push_pull_pin();
+		let mut send = |levels: &str| {
+			for level in levels.chars() {
+				let _ = if level == '1' {
+					pin.set_high()
+				} else {
+					pin.set_low()
+				};
+				clock.delay_ns(1_000_000);
+			}
+		};
+		// 0x01 with correct parity, with wrong parity and with a low stop bit
+		send("1");
+		send("01000000001");
+		send("01000000011");
+		send("01000000000");
+		// break and 0x03
+		send("1");
+		send("000000000000");
+		send("1");
+		send("01100000011");
+		let mut buffer = [0; 4];
+		assert_eq!(Ok(1), rx.read(&mut buffer));
+		assert_eq!(0x01, buffer[0]);
+		assert_eq!(Err(Error::Parity), rx.read(&mut buffer));
+		assert_eq!(Err(Error::Framing), rx.read(&mut buffer));
+		assert_eq!(Err(Error::Break), rx.read(&mut buffer));
+		assert_eq!(Ok(1), rx.read(&mut buffer));
+		assert_eq!(0x03, buffer[0]);
+		assert_eq!(Err(nb::Error::WouldBlock), rx.try_read());
+	}
+
+	#[test]
+	fn glitch() {
+		let clock = VirtualClock::new();
+		let line = Wire::new_with_pull(WireState::High);
+		let mut rx = UartRx::new(&line, clock.clone(), Config::default());
+		let mut pin = line.connect_push_pull_pin();
+		// a low pulse shorter than half a bit is not a start bit
+		let _ = pin.set_low();
+		clock.delay_ns(10_000);
+		let _ = pin.set_high();
+		clock.run();
+		assert_eq!(Err(nb::Error::WouldBlock), rx.try_read());
+	}
+
+	#[test]
+	fn glitch_before_frame() {
+		let clock = VirtualClock::new();
+		let line = Wire::new_with_pull(WireState::High);
+		let mut rx = UartRx::new(&line, clock.clone(), Config::default());
+		// the frame starts before the samples of the glitch are done
+		let mut waveform = Waveform::new(1);
+		waveform
+			.set(Duration::ZERO, 0, WireState::Low)
+			.set(Duration::from_micros(10), 0, WireState::High)
+			.append(&stimulus::uart(&Config::default(), b"U"));
+		waveform.schedule(&clock, &[&line]);
+		clock.run();
+		assert_eq!(Ok(b'U'), rx.try_read());
+		assert_eq!(Err(nb::Error::WouldBlock), rx.try_read());
+	}
+}
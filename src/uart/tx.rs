@@ -0,0 +1,234 @@
+use super::Config;
+use crate::clock::VirtualClock;
+use crate::sync::{Arc, Mutex};
+use crate::wire::PushPullPin;
+use core::convert::Infallible;
+use embedded_hal::digital::OutputPin;
+use std::time::Duration;
+
+/// A UART transmitter driving a wire.
+///
+/// Writing a word drives its start bit immediately and schedules all further
+/// bits on the clock, so the frame is only complete once the clock has
+/// advanced by the frame time. Like a hardware UART with a single byte buffer,
+/// a new word can be written once the previous frame has been sent.
+#[derive(Debug)]
+pub struct UartTx {
+	pin: Arc<Mutex<PushPullPin>>,
+	clock: VirtualClock,
+	config: Config,
+	busy_until: Duration,
+}
+
+fn drive(pin: &Mutex<PushPullPin>, high: bool) {
+	let mut pin = pin.lock().unwrap();
+	let _ = if high { pin.set_high() } else { pin.set_low() };
+}
+
+impl UartTx {
+	/// Creates a new transmitter and drives the line to its idle level.
+	///
+	/// # Panics
+	///
+	/// Panics if the configuration is invalid.
+	pub fn new(mut pin: PushPullPin, clock: VirtualClock, config: Config) -> Self {
+		config.validate();
+		let _ = pin.set_high();
+		UartTx {
+			pin: Arc::new(Mutex::new(pin)),
+			busy_until: clock.now(),
+			clock,
+			config,
+		}
+	}
+
+	pub fn config(&self) -> Config {
+		self.config
+	}
+
+	fn is_idle(&self) -> bool {
+		self.clock.now() >= self.busy_until
+	}
+
+	/// Schedules `levels` one bit after another, starting now.
+	fn send(&mut self, levels: Vec<bool>) {
+		let start = self.clock.now();
+		drive(&self.pin, levels[0]);
+		for (i, level) in levels.iter().enumerate().skip(1) {
+			let pin = self.pin.clone();
+			let level = *level;
+			let at = start + self.config.offset(2 * i as u32);
+			self.clock.schedule_at(at, move || drive(&pin, level));
+		}
+		self.busy_until = start + self.config.offset(2 * levels.len() as u32);
+	}
+
+	/// Starts sending `word` unless a frame is still being sent.
+	///
+	/// Bits above the configured number of data bits are ignored.
+	pub fn try_write(&mut self, word: u8) -> nb::Result<(), Infallible> {
+		if !self.is_idle() {
+			return Err(nb::Error::WouldBlock);
+		}
+		let data_bits = self.config.data_bits;
+		let word = if data_bits < 8 {
+			word & ((1 << data_bits) - 1)
+		} else {
+			word
+		};
+		let mut levels = vec![false];
+		levels.extend((0..data_bits).map(|i| word & (1 << i) != 0));
+		levels.extend(self.config.parity_bit(word));
+		// stop bits
+		levels.resize(self.config.frame_bits() as usize, true);
+		self.send(levels);
+		Ok(())
+	}
+
+	/// Returns `Ok` once the last frame has been sent completely.
+	pub fn try_flush(&mut self) -> nb::Result<(), Infallible> {
+		if self.is_idle() {
+			Ok(())
+		} else {
+			Err(nb::Error::WouldBlock)
+		}
+	}
+
+	/// Holds the line low for `bits` bit periods, followed by one stop bit.
+	///
+	/// This waits for a frame that is still being sent first. The break itself
+	/// is scheduled like a frame.
+	pub fn send_break(&mut self, bits: u32) {
+		self.wait();
+		let mut levels = vec![false; bits.max(1) as usize];
+		levels.push(true);
+		self.send(levels);
+	}
+
+	/// Advances the clock until the last frame has been sent.
+	fn wait(&mut self) {
+		self.clock.advance_to(self.busy_until);
+	}
+}
+
+impl embedded_io::ErrorType for UartTx {
+	type Error = Infallible;
+}
+
+impl embedded_io::Write for UartTx {
+	fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+		for word in buf {
+			self.wait();
+			let _ = self.try_write(*word);
+		}
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> Result<(), Self::Error> {
+		self.wait();
+		Ok(())
+	}
+}
+
+impl embedded_io::WriteReady for UartTx {
+	fn write_ready(&mut self) -> Result<bool, Self::Error> {
+		Ok(self.is_idle())
+	}
+}
+
+#[cfg(feature = "async")]
+impl embedded_io_async::Write for UartTx {
+	async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+		embedded_io::Write::write(self, buf)
+	}
+
+	async fn flush(&mut self) -> Result<(), Self::Error> {
+		embedded_io::Write::flush(self)
+	}
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::*;
+	use crate::uart::{Parity, StopBits};
+	use crate::wire::{Wire, WireState};
+	use embedded_io::{Write, WriteReady};
+
+	/// Records all edges on `wire` with their time.
+	fn edges(wire: &Wire, clock: &VirtualClock) -> Arc<Mutex<Vec<(u64, WireState)>>> {
+		let edges = Arc::new(Mutex::new(vec![]));
+		let (e, c) = (edges.clone(), clock.clone());
+		wire.on_change(move |state| e.lock().unwrap().push((c.now().as_micros() as u64, state)));
+		edges
+	}
+
+	#[test]
+	fn frame_timing() {
+		use WireState::*;
+		let clock = VirtualClock::new();
+		let line = Wire::new();
+		let config = Config {
+			baud: 100_000,
+			data_bits: 7,
+			parity: Parity::Even,
+			stop_bits: StopBits::Two,
+		};
+		let mut tx = UartTx::new(line.connect_push_pull_pin(), clock.clone(), config);
+		let edges = edges(&line, &clock);
+		// 0x9a is truncated to 0b0011010, even parity adds a one
+		assert_eq!(Ok(()), tx.try_write(0x9a));
+		assert_eq!(Err(nb::Error::WouldBlock), tx.try_write(0x00));
+		assert_eq!(Ok(false), tx.write_ready());
+		assert_eq!(Err(nb::Error::WouldBlock), tx.try_flush());
+		assert_eq!(Ok(()), tx.flush());
+		assert_eq!(Ok(()), tx.try_flush());
+		assert_eq!(110, clock.now().as_micros());
+		assert_eq!(
+			vec![
+				(0, Low),
+				(20, High),
+				(30, Low),
+				(40, High),
+				(60, Low),
+				(80, High)
+			],
+			*edges.lock().unwrap()
+		);
+	}
+
+	#[test]
+	fn back_to_back() {
+		let clock = VirtualClock::new();
+		let line = Wire::new();
+		let config = Config {
+			baud: 10_000,
+			..Default::default()
+		};
+		let mut tx = UartTx::new(line.connect_push_pull_pin(), clock.clone(), config);
+		assert_eq!(WireState::High, line.get_state());
+		assert_eq!(Ok(3), tx.write(&[0x55, 0x55, 0x55]));
+		// the last frame is still being sent
+		assert_eq!(WireState::Low, line.get_state());
+		assert_eq!(Ok(()), tx.flush());
+		assert_eq!(WireState::High, line.get_state());
+		// 30 bits at 10000 baud
+		assert_eq!(3000, clock.now().as_micros());
+	}
+
+	#[test]
+	fn send_break() {
+		use WireState::*;
+		let clock = VirtualClock::new();
+		let line = Wire::new();
+		let config = Config {
+			baud: 1000,
+			..Default::default()
+		};
+		let mut tx = UartTx::new(line.connect_push_pull_pin(), clock.clone(), config);
+		let edges = edges(&line, &clock);
+		tx.send_break(12);
+		assert_eq!(Ok(()), tx.flush());
+		assert_eq!(vec![(0, Low), (12_000, High)], *edges.lock().unwrap());
+		assert_eq!(13_000, clock.now().as_micros());
+	}
+}
@@ -1,24 +1,78 @@
-use embedded_hal::digital::ErrorType;
-use embedded_hal::digital::{InputPin, OutputPin, StatefulOutputPin, ToggleableOutputPin};
-use std::convert::Infallible;
-use std::sync::Arc;
-use std::sync::Mutex;
+use crate::net::{Net, PinBackend};
+use crate::pins;
+use crate::sync::{Arc, Mutex};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+
+pub use crate::state::WireState;
 
 type PinId = usize;
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub enum WireState {
-	Low,
-	High,
-	Floating,
+type Listener = Box<dyn FnMut(WireState) + Send>;
+
+#[derive(Default)]
+struct Listeners(Vec<Listener>);
+
+impl fmt::Debug for Listeners {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "Listeners({})", self.0.len())
+	}
+}
+
+#[cfg(loom)]
+use loom::thread_local;
+
+thread_local! {
+	static DISPATCH: RefCell<Dispatch> = RefCell::new(Dispatch::default());
 }
 
-impl Copy for WireState {}
+/// Pending change notifications of the current thread.
+///
+/// Listeners are never called recursively: if a listener changes a wire, the
+/// resulting notifications are queued and delivered in order after the
+/// listener returned.
+#[derive(Default)]
+struct Dispatch {
+	active: bool,
+	queue: VecDeque<(Arc<Mutex<Listeners>>, WireState)>,
+}
+
+/// Resets the dispatch state, even if a listener panics.
+struct DispatchGuard;
+
+impl Drop for DispatchGuard {
+	fn drop(&mut self) {
+		DISPATCH.with(|d| {
+			let mut d = d.borrow_mut();
+			d.active = false;
+			d.queue.clear();
+		});
+	}
+}
+
+fn notify(listeners: &Arc<Mutex<Listeners>>, state: WireState) {
+	let start = DISPATCH.with(|d| {
+		let mut d = d.borrow_mut();
+		d.queue.push_back((listeners.clone(), state));
+		!std::mem::replace(&mut d.active, true)
+	});
+	if !start {
+		return;
+	}
+	let _guard = DispatchGuard;
+	while let Some((listeners, state)) = DISPATCH.with(|d| d.borrow_mut().queue.pop_front()) {
+		for listener in listeners.lock().unwrap().0.iter_mut() {
+			listener(state);
+		}
+	}
+}
 
 #[derive(Debug)]
 struct WireWrapper {
 	pub state: Vec<WireState>,
 	pub pull: WireState,
+	pub active_low: bool,
 }
 
 impl WireWrapper {
@@ -30,6 +84,7 @@ impl WireWrapper {
 		WireWrapper {
 			state: vec![],
 			pull,
+			active_low: false,
 		}
 	}
 }
@@ -43,6 +98,7 @@ impl Default for WireWrapper {
 #[derive(Clone, Debug)]
 pub struct Wire {
 	wire: Arc<Mutex<WireWrapper>>,
+	listeners: Arc<Mutex<Listeners>>,
 }
 
 impl Wire {
@@ -53,28 +109,64 @@ impl Wire {
 	pub fn new_with_pull(pull: WireState) -> Self {
 		Self {
 			wire: Arc::new(Mutex::new(WireWrapper::new_with_pull(pull))),
+			listeners: Arc::new(Mutex::new(Listeners::default())),
 		}
 	}
 
-	pub fn set_state(&mut self, id: PinId, state: WireState) {
-		let mut wire = self.wire.lock().unwrap();
-		wire.state[id] = state;
-		// check for short circuit
-		let _ = Self::wire_state(&wire);
+	pub fn set_state(&self, id: PinId, state: WireState) {
+		self.update_pin_state(id, |_| state);
 	}
 
 	pub fn get_pin_state(&self, id: PinId) -> WireState {
 		self.wire.lock().unwrap().state[id]
 	}
 
-	pub fn update_pin_state<F>(&mut self, id: PinId, mut f: F)
+	pub fn update_pin_state<F>(&self, id: PinId, mut f: F)
 	where
 		F: FnMut(WireState) -> WireState,
 	{
-		let mut wire = self.wire.lock().unwrap();
-		wire.state[id] = f(wire.state[id]);
-		// check for short circuit
-		let _ = Self::wire_state(&wire);
+		let (old, new) = {
+			let mut wire = self.wire.lock().unwrap();
+			let old = Self::wire_state(&wire);
+			wire.state[id] = f(wire.state[id]);
+			// check for short circuit
+			(old, Self::wire_state(&wire))
+		};
+		if old != new {
+			notify(&self.listeners, new);
+		}
+	}
+
+	/// Registers a listener that is called with the new state whenever the
+	/// resolved state of this wire changes.
+	///
+	/// Listeners are called on the thread that changed the wire. If a listener
+	/// changes a wire itself, the resulting notifications are delivered after
+	/// it returned, so listeners are never called recursively. Listeners must
+	/// not register further listeners on the same wire.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use embedded_hal_sync_pins::wire::{Wire, WireState};
+	/// use embedded_hal::digital::OutputPin;
+	/// use std::sync::{Arc, Mutex};
+	///
+	/// let wire = Wire::new_with_pull(WireState::High);
+	/// let edges = Arc::new(Mutex::new(vec![]));
+	/// let log = edges.clone();
+	/// wire.on_change(move |state| log.lock().unwrap().push(state));
+	/// let mut pin = wire.connect_open_drain_pin();
+	/// pin.set_high().unwrap();
+	/// pin.set_high().unwrap();
+	/// pin.set_low().unwrap();
+	/// assert_eq!(vec![WireState::Low, WireState::High], *edges.lock().unwrap());
+	/// ```
+	pub fn on_change<F>(&self, f: F)
+	where
+		F: FnMut(WireState) + Send + 'static,
+	{
+		self.listeners.lock().unwrap().0.push(Box::new(f));
 	}
 
 	pub fn get_state(&self) -> WireState {
@@ -82,47 +174,62 @@ impl Wire {
 		Self::wire_state(&wire)
 	}
 
-	fn wire_state(wire: &WireWrapper) -> WireState {
-		use WireState::*;
-		let mut s = Floating;
-		for state in wire.state.iter() {
-			if *state == Floating {
-				continue;
-			}
-			if s != Floating && *state != Floating && *state != s {
-				panic!("short circuit: {:?}", wire.state);
-			}
-			s = *state;
-		}
-		if s == WireState::Floating {
-			wire.pull
+	/// Declares whether the signal on this wire is active low.
+	///
+	/// This does not change the electrical level of the wire, but how it is
+	/// reported by [`is_asserted`](`Wire::is_asserted`) and decoded by
+	/// [`UartRx`](`crate::uart::UartRx`) and
+	/// [`SpiMonitor`](`crate::spi::SpiMonitor`).
+	pub fn set_active_low(&self, active_low: bool) {
+		self.wire.lock().unwrap().active_low = active_low;
+	}
+
+	pub fn is_active_low(&self) -> bool {
+		self.wire.lock().unwrap().active_low
+	}
+
+	/// Returns `true` if the wire is at its active level, i.e. low for an
+	/// active low wire and high otherwise.
+	///
+	/// A floating wire is never asserted.
+	pub fn is_asserted(&self) -> bool {
+		let wire = self.wire.lock().unwrap();
+		let active = if wire.active_low {
+			WireState::Low
 		} else {
-			s
-		}
+			WireState::High
+		};
+		Self::wire_state(&wire) == active
 	}
 
-	pub fn connect_push_pull_pin(&self) -> PushPullPin {
+	fn wire_state(wire: &WireWrapper) -> WireState {
+		crate::state::resolve(&wire.state, wire.pull)
+	}
+
+	/// Connects a new driver to this wire.
+	///
+	/// The returned [`Driver`] is a [`PinBackend`] that can be used with any of
+	/// the generic pin types in [`pins`](`crate::pins`).
+	pub fn connect_driver(&self) -> Driver {
 		let mut wire = self.wire.lock().unwrap();
 		let id = wire.state.len();
 		wire.state.push(WireState::Floating);
-		PushPullPin {
+		Driver {
 			id,
 			wire: self.clone(),
 		}
 	}
 
+	pub fn connect_push_pull_pin(&self) -> PushPullPin {
+		PushPullPin::new(self.connect_driver())
+	}
+
 	pub fn connect_open_drain_pin(&self) -> OpenDrainPin {
-		let mut wire = self.wire.lock().unwrap();
-		let id = wire.state.len();
-		wire.state.push(WireState::Floating);
-		OpenDrainPin {
-			id,
-			wire: self.clone(),
-		}
+		OpenDrainPin::new(self.connect_driver())
 	}
 
 	pub fn connect_input_pin(&self) -> InputOnlyPin {
-		InputOnlyPin { wire: self.clone() }
+		InputOnlyPin::new(self.clone())
 	}
 }
 
@@ -132,131 +239,52 @@ impl Default for Wire {
 	}
 }
 
-pub struct InputOnlyPin {
-	wire: Wire,
-}
-
-impl ErrorType for InputOnlyPin {
-	type Error = Infallible;
-}
-
-impl InputPin for InputOnlyPin {
-	fn is_high(&self) -> Result<bool, Self::Error> {
-		Ok(self.wire.get_state() == WireState::High)
-	}
-
-	fn is_low(&self) -> Result<bool, Self::Error> {
-		Ok(self.wire.get_state() == WireState::Low)
+impl Net for Wire {
+	fn level(&self) -> WireState {
+		self.get_state()
 	}
 }
 
-pub struct PushPullPin {
+/// A single driver connected to a [`Wire`].
+#[derive(Debug)]
+pub struct Driver {
 	wire: Wire,
 	id: PinId,
 }
 
-impl ErrorType for PushPullPin {
-	type Error = Infallible;
-}
-
-impl InputPin for PushPullPin {
-	fn is_high(&self) -> Result<bool, Self::Error> {
-		Ok(self.wire.get_state() == WireState::High)
-	}
-
-	fn is_low(&self) -> Result<bool, Self::Error> {
-		Ok(self.wire.get_state() == WireState::Low)
-	}
-}
-
-impl OutputPin for PushPullPin {
-	fn set_low(&mut self) -> Result<(), Self::Error> {
-		self.wire.set_state(self.id, WireState::Low);
-		Ok(())
-	}
-
-	fn set_high(&mut self) -> Result<(), Self::Error> {
-		self.wire.set_state(self.id, WireState::High);
-		Ok(())
+impl Net for Driver {
+	fn level(&self) -> WireState {
+		self.wire.get_state()
 	}
 }
 
-impl StatefulOutputPin for PushPullPin {
-	fn is_set_high(&self) -> Result<bool, Self::Error> {
-		Ok(self.wire.get_pin_state(self.id) == WireState::High)
-	}
-
-	fn is_set_low(&self) -> Result<bool, Self::Error> {
-		Ok(self.wire.get_pin_state(self.id) == WireState::Low)
+impl PinBackend for Driver {
+	fn driven(&self) -> WireState {
+		self.wire.get_pin_state(self.id)
 	}
-}
-
-impl ToggleableOutputPin for PushPullPin {
-	fn toggle(&mut self) -> Result<(), Self::Error> {
-		self.wire.update_pin_state(self.id, |x| match x {
-			WireState::Low => WireState::High,
-			WireState::High => WireState::Low,
-			WireState::Floating => WireState::Low,
-		});
-		Ok(())
-	}
-}
-
-pub struct OpenDrainPin {
-	wire: Wire,
-	id: PinId,
-}
-
-impl ErrorType for OpenDrainPin {
-	type Error = Infallible;
-}
 
-impl InputPin for OpenDrainPin {
-	fn is_high(&self) -> Result<bool, Self::Error> {
-		Ok(self.wire.get_state() == WireState::High)
+	fn drive(&self, state: WireState) {
+		self.wire.set_state(self.id, state);
 	}
 
-	fn is_low(&self) -> Result<bool, Self::Error> {
-		Ok(self.wire.get_state() == WireState::Low)
+	fn update_driven<F>(&self, f: F)
+	where
+		F: FnMut(WireState) -> WireState,
+	{
+		self.wire.update_pin_state(self.id, f);
 	}
 }
 
-impl OutputPin for OpenDrainPin {
-	fn set_low(&mut self) -> Result<(), Self::Error> {
-		self.wire.set_state(self.id, WireState::Floating);
-		Ok(())
-	}
+pub type InputOnlyPin = pins::InputPin<Wire>;
 
-	fn set_high(&mut self) -> Result<(), Self::Error> {
-		self.wire.set_state(self.id, WireState::Low);
-		Ok(())
-	}
-}
+pub type PushPullPin = pins::PushPullPin<Driver>;
 
-impl StatefulOutputPin for OpenDrainPin {
-	fn is_set_high(&self) -> Result<bool, Self::Error> {
-		Ok(self.wire.get_pin_state(self.id) == WireState::Low)
-	}
+pub type OpenDrainPin = pins::OpenDrainPin<Driver>;
 
-	fn is_set_low(&self) -> Result<bool, Self::Error> {
-		Ok(self.wire.get_pin_state(self.id) == WireState::Floating)
-	}
-}
-
-impl ToggleableOutputPin for OpenDrainPin {
-	fn toggle(&mut self) -> Result<(), Self::Error> {
-		self.wire.update_pin_state(self.id, |x| match x {
-			WireState::Floating => WireState::Low,
-			WireState::Low => WireState::Floating,
-			WireState::High => WireState::Floating,
-		});
-		Ok(())
-	}
-}
-
-#[cfg(test)]
+#[cfg(all(test, not(loom)))]
 mod tests {
 	use super::*;
+	use embedded_hal::digital::{InputPin, OutputPin, StatefulOutputPin, ToggleableOutputPin};
 	use WireState::*;
 
 	#[test]
@@ -303,6 +331,50 @@ mod tests {
 		assert_eq!(Low, wire.get_state());
 	}
 
+	#[test]
+	fn active_low() {
+		let wire = Wire::new_with_pull(High);
+		let mut pin = wire.connect_open_drain_pin();
+		assert!(!wire.is_active_low());
+		assert!(wire.is_asserted());
+		wire.set_active_low(true);
+		assert!(wire.is_active_low());
+		assert!(!wire.is_asserted());
+		assert_eq!(Ok(()), pin.set_high());
+		assert!(wire.is_asserted());
+		let wire = Wire::new();
+		wire.set_active_low(true);
+		assert!(!wire.is_asserted());
+	}
+
+	#[test]
+	fn listeners_are_not_reentrant() {
+		use std::sync::Mutex;
+		let a = Wire::new_with_pull(High);
+		let b = Wire::new_with_pull(High);
+		let log = Arc::new(Mutex::new(vec![]));
+		// echo every change of `a` onto `b`
+		let mut echo = b.connect_open_drain_pin();
+		a.on_change(move |state| {
+			if state == Low {
+				echo.set_high().unwrap();
+			} else {
+				echo.set_low().unwrap();
+			}
+		});
+		let l = log.clone();
+		a.on_change(move |state| l.lock().unwrap().push(("a", state)));
+		let l = log.clone();
+		b.on_change(move |state| l.lock().unwrap().push(("b", state)));
+		let mut pin = a.connect_open_drain_pin();
+		assert_eq!(Ok(()), pin.set_high());
+		assert_eq!(Ok(()), pin.set_low());
+		assert_eq!(
+			vec![("a", Low), ("b", Low), ("a", High), ("b", High)],
+			*log.lock().unwrap()
+		);
+	}
+
 	#[test]
 	fn input() {
 		let wire = Wire::new();
@@ -348,3 +420,48 @@ mod tests {
 		assert_eq!(Ok(true), pin3.is_low());
 	}
 }
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+	use super::*;
+	use crate::sync::{model, thread};
+	use embedded_hal::digital::{OutputPin, StatefulOutputPin, ToggleableOutputPin};
+	use WireState::*;
+
+	#[test]
+	fn wired_and() {
+		model(|| {
+			let wire = Wire::new_with_pull(High);
+			let handles: Vec<_> = (0..2)
+				.map(|_| {
+					let mut pin = wire.connect_open_drain_pin();
+					thread::spawn(move || {
+						pin.set_high().unwrap();
+						pin.set_low().unwrap();
+					})
+				})
+				.collect();
+			for handle in handles {
+				handle.join().unwrap();
+			}
+			assert_eq!(High, wire.get_state());
+		});
+	}
+
+	#[test]
+	fn concurrent_toggle() {
+		model(|| {
+			let wire = Wire::new();
+			let mut pin = wire.connect_push_pull_pin();
+			let mut other = wire.connect_open_drain_pin();
+			let handle = thread::spawn(move || {
+				pin.toggle().unwrap();
+				pin
+			});
+			other.set_low().unwrap();
+			let pin = handle.join().unwrap();
+			assert_eq!(Ok(true), pin.is_set_low());
+			assert_eq!(Low, wire.get_state());
+		});
+	}
+}
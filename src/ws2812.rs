@@ -0,0 +1,386 @@
+//! Simulated WS2812 LED strips
+//!
+//! WS2812 LEDs receive their colours on a single data line. Every bit starts
+//! with a high pulse, 0.4 µs for a zero and 0.8 µs for a one, followed by a low
+//! time that makes up a bit period of about 1.25 µs. Each LED takes the first
+//! 24 bits it receives, green, red and blue with the MSB first, and passes all
+//! further bits on to the next LED. Holding the line low for at least 50 µs
+//! resets the LEDs, which then show the colours they received.
+//!
+//! [`Ws2812Strip`] decodes the pulses on a [`Wire`] timed on a
+//! [`VirtualClock`] and keeps the resulting colours of all LEDs for
+//! assertions. [`waveform`] returns the pulses for a list of colours, e.g. to
+//! perturb them with the functions in [`stimulus`](`crate::stimulus`).
+//!
+//! # Examples
+//!
+//! ```
+//! use embedded_hal_sync_pins::clock::VirtualClock;
+//! use embedded_hal_sync_pins::wire::Wire;
+//! use embedded_hal_sync_pins::ws2812::{self, Rgb, Ws2812Strip};
+//! use std::time::Duration;
+//!
+//! let clock = VirtualClock::new();
+//! let din = Wire::new();
+//! let strip = Ws2812Strip::new(&din, clock.clone(), 2);
+//! let red = Rgb { r: 0xff, g: 0, b: 0 };
+//! let teal = Rgb { r: 0, g: 0x80, b: 0x80 };
+//! ws2812::waveform(&[red, teal])
+//!     .jitter(Duration::from_nanos(50), 1)
+//!     .schedule(&clock, &[&din]);
+//! clock.run();
+//! assert_eq!(vec![red, teal], strip.colors());
+//! ```
+
+use crate::clock::VirtualClock;
+use crate::stimulus::Waveform;
+use crate::sync::{Arc, Mutex};
+use crate::wire::{Wire, WireState};
+use std::fmt;
+use std::time::Duration;
+
+/// High time of a zero.
+const T0H: Duration = Duration::from_nanos(400);
+/// High time of a one.
+const T1H: Duration = Duration::from_nanos(800);
+/// Low time of a zero.
+const T0L: Duration = Duration::from_nanos(850);
+/// Low time of a one.
+const T1L: Duration = Duration::from_nanos(450);
+/// Tolerance of all pulse widths.
+const TOLERANCE: Duration = Duration::from_nanos(150);
+/// Minimum low time latching the received colours.
+const RESET: Duration = Duration::from_micros(50);
+
+/// The colour of an LED.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Rgb {
+	pub r: u8,
+	pub g: u8,
+	pub b: u8,
+}
+
+impl Rgb {
+	/// Returns the 24 bits sent for this colour, in the order they are sent.
+	fn bits(&self) -> impl Iterator<Item = bool> {
+		let grb = u32::from_be_bytes([0, self.g, self.r, self.b]);
+		(0..24).rev().map(move |i| grb & (1 << i) != 0)
+	}
+}
+
+/// A pulse that does not match the datasheet timing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+	/// The high pulse of the given bit of a frame was neither a zero nor a one.
+	HighTime(usize),
+	/// The line was low too briefly before the given bit of a frame.
+	LowTime(usize),
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Error::HighTime(bit) => write!(f, "invalid high time of bit {}", bit),
+			Error::LowTime(bit) => write!(f, "low time too short before bit {}", bit),
+		}
+	}
+}
+
+impl std::error::Error for Error {}
+
+/// Returns the pulses for `colors`, followed by the reset time.
+pub fn waveform(colors: &[Rgb]) -> Waveform {
+	let mut waveform = Waveform::new(1);
+	let mut at = Duration::ZERO;
+	for bit in colors.iter().flat_map(Rgb::bits) {
+		let (high, low) = if bit { (T1H, T1L) } else { (T0H, T0L) };
+		waveform.set(at, 0, WireState::High);
+		waveform.set(at + high, 0, WireState::Low);
+		at += high + low;
+	}
+	waveform.hold(at + RESET);
+	waveform
+}
+
+/// Returns whether `duration` is within the tolerance of `nominal`.
+fn near(duration: Duration, nominal: Duration) -> bool {
+	duration + TOLERANCE >= nominal && duration <= nominal + TOLERANCE
+}
+
+#[derive(Debug)]
+struct Strip {
+	colors: Vec<Rgb>,
+	/// Colours received since the last reset, not shown yet.
+	received: Vec<Option<Rgb>>,
+	bits: usize,
+	shift: u32,
+	/// Whether the rest of the frame is ignored after an error.
+	discarding: bool,
+	rise: Option<Duration>,
+	fall: Option<Duration>,
+	edges: u64,
+	latches: usize,
+	forwarded: usize,
+	errors: Vec<Error>,
+}
+
+impl Strip {
+	fn rising(&mut self, now: Duration) {
+		self.edges += 1;
+		if let Some(fall) = self.fall {
+			if self.bits > 0 && now - fall + TOLERANCE < T1L {
+				self.error(Error::LowTime(self.bits));
+			}
+		}
+		self.rise = Some(now);
+	}
+
+	fn falling(&mut self, now: Duration) -> u64 {
+		self.edges += 1;
+		self.fall = Some(now);
+		if let Some(rise) = self.rise.take() {
+			let high = now - rise;
+			if near(high, T0H) {
+				self.bit(false);
+			} else if near(high, T1H) {
+				self.bit(true);
+			} else {
+				self.error(Error::HighTime(self.bits));
+			}
+		}
+		self.edges
+	}
+
+	fn error(&mut self, error: Error) {
+		if !self.discarding {
+			self.errors.push(error);
+			self.discarding = true;
+		}
+	}
+
+	fn bit(&mut self, bit: bool) {
+		if self.discarding {
+			return;
+		}
+		let led = self.bits / 24;
+		self.bits += 1;
+		if led >= self.colors.len() {
+			self.forwarded += 1;
+			return;
+		}
+		self.shift = (self.shift << 1) | bit as u32;
+		if self.bits == 24 * (led + 1) {
+			let [_, g, r, b] = self.shift.to_be_bytes();
+			self.received[led] = Some(Rgb { r, g, b });
+		}
+	}
+
+	/// Shows the received colours if the line stayed low since edge `edges`.
+	fn reset(&mut self, edges: u64) {
+		if self.edges != edges {
+			return;
+		}
+		if self.bits > 0 || self.discarding {
+			self.latches += 1;
+		}
+		for (color, received) in self.colors.iter_mut().zip(self.received.iter_mut()) {
+			if let Some(received) = received.take() {
+				*color = received;
+			}
+		}
+		self.bits = 0;
+		self.shift = 0;
+		self.discarding = false;
+		self.fall = None;
+	}
+}
+
+/// A strip of WS2812 LEDs attached to a data wire.
+///
+/// The strip measures the high pulses on the wire against the datasheet
+/// timing with a tolerance of 150 ns. A low time shorter than the minimum low
+/// time of a one is an error too, while longer low times are accepted up to
+/// the reset time. After an error, the rest of the frame is ignored. LEDs
+/// keep their colour until they received all of their 24 bits and the line
+/// was reset, so a short frame only updates the first LEDs. All LEDs are off
+/// initially.
+///
+/// See the [module documentation](`self`) for an example.
+#[derive(Clone, Debug)]
+pub struct Ws2812Strip {
+	strip: Arc<Mutex<Strip>>,
+}
+
+impl Ws2812Strip {
+	/// Attaches a strip with `count` LEDs to `din`.
+	pub fn new(din: &Wire, clock: VirtualClock, count: usize) -> Self {
+		let strip = Arc::new(Mutex::new(Strip {
+			colors: vec![Rgb::default(); count],
+			received: vec![None; count],
+			bits: 0,
+			shift: 0,
+			discarding: false,
+			rise: None,
+			fall: None,
+			edges: 0,
+			latches: 0,
+			forwarded: 0,
+			errors: vec![],
+		}));
+		let s = strip.clone();
+		din.on_change(move |state| {
+			if state == WireState::High {
+				return s.lock().unwrap().rising(clock.now());
+			}
+			let edges = s.lock().unwrap().falling(clock.now());
+			let s = s.clone();
+			clock.schedule_in(RESET, move || s.lock().unwrap().reset(edges));
+		});
+		Ws2812Strip { strip }
+	}
+
+	/// Returns the colours shown by the LEDs, starting with the one closest
+	/// to the data input.
+	pub fn colors(&self) -> Vec<Rgb> {
+		self.strip.lock().unwrap().colors.clone()
+	}
+
+	/// Returns the number of frames latched by a reset.
+	pub fn latches(&self) -> usize {
+		self.strip.lock().unwrap().latches
+	}
+
+	/// Returns the number of bits passed on by the last LED since it was
+	/// powered up, i.e. the bits sent to a strip too short for them.
+	pub fn forwarded(&self) -> usize {
+		self.strip.lock().unwrap().forwarded
+	}
+
+	/// Returns all timing errors since the strip was powered up.
+	pub fn errors(&self) -> Vec<Error> {
+		self.strip.lock().unwrap().errors.clone()
+	}
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+	use super::*;
+	use embedded_hal::digital::OutputPin;
+
+	const RED: Rgb = Rgb {
+		r: 0xff,
+		g: 0,
+		b: 0,
+	};
+	const GREEN: Rgb = Rgb {
+		r: 0,
+		g: 0xff,
+		b: 0,
+	};
+	const BLUE: Rgb = Rgb {
+		r: 0,
+		g: 0,
+		b: 0xff,
+	};
+
+	fn setup(count: usize) -> (VirtualClock, Wire, Ws2812Strip) {
+		let clock = VirtualClock::new();
+		let din = Wire::new();
+		let strip = Ws2812Strip::new(&din, clock.clone(), count);
+		(clock, din, strip)
+	}
+
+	#[test]
+	fn cascade() {
+		let (clock, din, strip) = setup(2);
+		let mut frames = waveform(&[RED, GREEN, BLUE]);
+		frames.append(&waveform(&[BLUE]));
+		frames.schedule(&clock, &[&din]);
+		clock.advance(Duration::from_micros(3 * 30));
+		// not latched before the reset
+		assert_eq!(vec![Rgb::default(); 2], strip.colors());
+		clock.advance(Duration::from_micros(50));
+		assert_eq!(vec![RED, GREEN], strip.colors());
+		assert_eq!(24, strip.forwarded());
+		clock.run();
+		assert_eq!(vec![BLUE, GREEN], strip.colors());
+		assert_eq!(2, strip.latches());
+		assert!(strip.errors().is_empty());
+	}
+
+	#[test]
+	fn tolerance() {
+		let (clock, din, strip) = setup(3);
+		let colors = [Rgb { r: 1, g: 2, b: 3 }, RED, BLUE];
+		let mut frames = Waveform::new(1);
+		for factor in [0.85, 1.15] {
+			frames.append(&waveform(&colors).scale(factor));
+			// the reset time was scaled too
+			frames.hold(frames.duration() + Duration::from_micros(10));
+		}
+		frames.append(&waveform(&colors).jitter(Duration::from_nanos(70), 5));
+		frames.schedule(&clock, &[&din]);
+		clock.run();
+		assert_eq!(colors.to_vec(), strip.colors());
+		assert_eq!(3, strip.latches());
+		assert!(strip.errors().is_empty());
+	}
+
+	#[test]
+	fn errors() {
+		let (clock, din, strip) = setup(2);
+		let mut frames = waveform(&[GREEN]);
+		// high for 600 ns, neither a zero nor a one
+		let mut bad = Waveform::new(1);
+		bad.set(Duration::ZERO, 0, WireState::High)
+			.set(Duration::from_nanos(600), 0, WireState::Low)
+			.hold(Duration::from_micros(60));
+		frames.append(&bad);
+		// the same in the second LED, after the first one got its colour
+		let sent = waveform(&[BLUE, RED]);
+		let mut bad = Waveform::new(1);
+		for (i, edge) in sent.edges().iter().enumerate() {
+			let delay = if i == 2 * 30 + 1 { 200 } else { 0 };
+			bad.set(edge.at + Duration::from_nanos(delay), 0, edge.state);
+		}
+		frames.append(bad.hold(sent.duration()));
+		frames.schedule(&clock, &[&din]);
+		clock.run();
+		assert_eq!(vec![BLUE, Rgb::default()], strip.colors());
+		assert_eq!(
+			vec![Error::HighTime(0), Error::HighTime(30)],
+			strip.errors()
+		);
+		assert_eq!(3, strip.latches());
+	}
+
+	#[test]
+	fn bit_banged() {
+		let (clock, din, strip) = setup(1);
+		let mut pin = din.connect_push_pull_pin();
+		pin.set_low().unwrap();
+		for bit in BLUE.bits() {
+			pin.set_high().unwrap();
+			clock.delay_ns(if bit { 750 } else { 350 });
+			pin.set_low().unwrap();
+			// a slow loop between the bits
+			clock.delay_ns(if bit { 2000 } else { 1800 });
+		}
+		// 49 µs after the last falling edge
+		clock.delay_ns(47_000);
+		assert_eq!(vec![Rgb::default()], strip.colors());
+		clock.delay_ns(1_000);
+		assert_eq!(vec![BLUE], strip.colors());
+		// a low time of 200 ns is too short
+		pin.set_high().unwrap();
+		clock.delay_ns(400);
+		pin.set_low().unwrap();
+		clock.delay_ns(200);
+		pin.set_high().unwrap();
+		clock.delay_ns(400);
+		pin.set_low().unwrap();
+		clock.delay_ns(50_000);
+		assert_eq!(vec![Error::LowTime(1)], strip.errors());
+		assert_eq!(vec![BLUE], strip.colors());
+	}
+}